@@ -0,0 +1,602 @@
+use crate::dns::{self, RData, Record}; // Import DNS messages, which mDNS shares.
+use crate::http; // Import the HTTP client the UPnP descriptions are fetched with.
+use crate::scanner::{RateLimiter, Source}; // Import the source to send the queries from and the pace of the fetches.
+use crate::targets::{self, Network, Target}; // Import the targets the hosts found are merged into.
+use futures::stream::{self, StreamExt}; // Import streams to fetch descriptions concurrently.
+use serde::Serialize; // Import `Serialize` to report the adverts in the structured output.
+use socket2::{Domain, Socket, Type}; // Import socket2 to listen on the ports of the groups.
+use std::collections::{HashMap, HashSet}; // Import maps and sets of the names and hosts found.
+use std::io; // Import I/O errors for socket failures.
+use std::net::{IpAddr, Ipv4Addr, SocketAddr}; // Import address types for the groups and hosts.
+use std::sync::Arc; // Import `Arc` to share the sockets with the tasks reading them.
+use std::time::Duration; // Import `Duration` for the listening and fetch timeouts.
+use tokio::net::{TcpSocket, UdpSocket}; // Import sockets of the source for the queries and fetches.
+use tokio::sync::mpsc; // Import channels to hand the packets of every socket to one loop.
+use tokio::task::JoinHandle; // Import task handles to stop the readers once done.
+use tokio::time::{timeout, timeout_at, Instant}; // Import deadlines to stop listening.
+
+// Define the multicast groups and ports of mDNS and SSDP.
+const MDNS: (Ipv4Addr, u16) = (Ipv4Addr::new(224, 0, 0, 251), 5353);
+const SSDP: (Ipv4Addr, u16) = (Ipv4Addr::new(239, 255, 255, 250), 1900);
+
+// Define the name that lists the service types advertised over mDNS (RFC 6763 section 9).
+const SERVICE_TYPES: &str = "_services._dns-sd._udp.local";
+
+// Define the class of mDNS questions whose answers may come straight back instead of to the group.
+const CLASS_IN_UNICAST: u16 = 0x8000 | dns::CLASS_IN;
+
+// Define how long to listen for answers and announcements; SSDP devices wait up to the MX
+// seconds of the search, 2, before they answer.
+const LISTEN: Duration = Duration::from_secs(3);
+
+// Define the search SSDP devices answer, whatever they are.
+const M_SEARCH: &[u8] = b"M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: ssdp:all\r\n\r\n";
+
+// Define how long a UPnP device gets to send its description.
+const FETCH: Duration = Duration::from_secs(3);
+
+// A service or device a host advertised on the local network.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Advert {
+    // How it was advertised: `mDNS`, `SSDP` or `UPnP`.
+    pub via: &'static str,
+    // The service or device type, e.g. `_ipp._tcp` or `urn:schemas-upnp-org:device:MediaServer:1`.
+    pub service: String,
+    // The name it goes by, e.g. `Office Printer`, or the server that answered.
+    pub name: Option<String>,
+    // The port it is offered on, when it told.
+    pub port: Option<u16>,
+}
+
+impl Advert {
+    // Function to get the TCP port of the advertised service, if it has one: mDNS services of
+    // `_tcp` types, and the HTTP servers SSDP and UPnP descriptions come from.
+    pub fn tcp_port(&self) -> Option<u16> {
+        match self.via {
+            "mDNS" if !self.service.ends_with("._tcp") => None,
+            _ => self.port,
+        }
+    }
+}
+
+// A host found on the local network.
+#[derive(Debug, Clone)]
+pub struct Host {
+    pub addr: IpAddr,
+    // Its mDNS host name, e.g. `printer.local`.
+    pub hostname: Option<String>,
+    pub adverts: Vec<Advert>,
+}
+
+// What an SSDP device said in an answer to a search or an announcement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Notice {
+    // The search target or notification type, e.g. `upnp:rootdevice`.
+    service: String,
+    // The SERVER header, e.g. `Linux/5.4 UPnP/1.0 MiniUPnPd/2.2`.
+    server: Option<String>,
+    // The URL of the UPnP description of the device.
+    location: Option<String>,
+}
+
+// Function to find the hosts of the local IPv4 network that advertise themselves: it asks over
+// mDNS for the service types on offer and then for the instances of each, searches over SSDP
+// for every device, and listens for the announcements of both for `LISTEN`. The UPnP
+// descriptions the SSDP devices point to are fetched from them afterwards, `concurrency` at a
+// time and each once the `rate` of the scan lets it go out.
+pub async fn discover(
+    source: &Source,
+    concurrency: usize,
+    rate: Option<&RateLimiter>,
+) -> Vec<Host> {
+    let mdns_group = SocketAddr::from(MDNS);
+    let ssdp_group = SocketAddr::from(SSDP);
+    let (querier, searcher) = match (sender(source, mdns_group), sender(source, ssdp_group)) {
+        (Ok(querier), Ok(searcher)) => (querier, searcher),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!(
+                "Warning: --local-discovery cannot send to the mDNS and SSDP groups ({})",
+                e
+            );
+            return vec![];
+        }
+    };
+
+    // Read every socket into one channel, tagged with whether it carries mDNS.
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut readers = vec![
+        read(querier.clone(), true, tx.clone()),
+        read(searcher.clone(), false, tx.clone()),
+    ];
+    // Listening to the groups needs their ports, which a local responder may hold exclusively,
+    // so the announcements are only a bonus to the answers.
+    for (group, mdns) in [(MDNS, true), (SSDP, false)] {
+        if let Ok(listener) = listener(group, source) {
+            readers.push(read(Arc::new(listener), mdns, tx.clone()));
+        }
+    }
+    drop(tx);
+
+    let _ = querier
+        .send_to(&mdns_query(SERVICE_TYPES), mdns_group)
+        .await;
+    let _ = searcher.send_to(M_SEARCH, ssdp_group).await;
+
+    let deadline = Instant::now() + LISTEN;
+    let mut records: Vec<(IpAddr, Record)> = vec![];
+    let mut notices: Vec<(IpAddr, Notice)> = vec![];
+    let mut asked = HashSet::new();
+    while let Ok(Some((mdns, from, packet))) = timeout_at(deadline, rx.recv()).await {
+        if !mdns {
+            notices.extend(notice(&packet).map(|notice| (from, notice)));
+            continue;
+        }
+        // Other hosts' questions carry nothing to learn.
+        let Some(message) = dns::parse_message(&packet).filter(|m| m.flags & 0x8000 != 0) else {
+            continue;
+        };
+        for record in message.answers.into_iter().chain(message.additionals) {
+            // Ask for the instances of every type listed, once.
+            if let RData::Name(service) = &record.data {
+                if record.name.eq_ignore_ascii_case(SERVICE_TYPES)
+                    && asked.insert(service.to_ascii_lowercase())
+                {
+                    let _ = querier.send_to(&mdns_query(service), mdns_group).await;
+                }
+            }
+            records.push((from, record));
+        }
+    }
+    for reader in readers {
+        reader.abort();
+    }
+
+    let mut hosts: Vec<Host> = vec![];
+    for (addr, hostname, advert) in mdns_adverts(&records) {
+        add(&mut hosts, addr, hostname, advert);
+    }
+    let mut locations = vec![];
+    for (from, notice) in notices {
+        let url = notice
+            .location
+            .as_deref()
+            .and_then(|location| http::parse_url(location).ok());
+        // Only descriptions a device serves itself are fetched.
+        if let Some(url) = &url {
+            if !url.https
+                && url.host.parse() == Ok(from)
+                && !locations.contains(&(from, url.clone()))
+            {
+                locations.push((from, url.clone()));
+            }
+        }
+        // Each device also answers with its UUID, which tells nothing.
+        if !notice.service.starts_with("uuid:") {
+            let advert = Advert {
+                via: "SSDP",
+                service: notice.service,
+                name: notice.server,
+                port: url.map(|url| url.port),
+            };
+            add(&mut hosts, from, None, advert);
+        }
+    }
+    let descriptions: Vec<(IpAddr, Option<Advert>)> = stream::iter(locations)
+        .map(|(from, url)| async move {
+            if let Some(rate) = rate {
+                rate.wait().await;
+            }
+            let advert = describe(&url, from, source).await.ok().flatten();
+            (from, advert)
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+    for (from, advert) in descriptions {
+        if let Some(advert) = advert {
+            add(&mut hosts, from, None, advert);
+        }
+    }
+    hosts
+}
+
+// Function to add the hosts found to `hosts`, with their adverts: a host already among them
+// gets the adverts, and another one not in the `excluded` networks is scanned under its mDNS
+// host name.
+pub fn merge(hosts: &mut Vec<Target>, found: Vec<Host>, excluded: &[Network]) {
+    for host in found {
+        match hosts.iter_mut().find(|target| target.addr == host.addr) {
+            Some(target) => target.advertised.extend(host.adverts),
+            None if excluded.iter().any(|network| network.contains(host.addr)) => {}
+            None => {
+                let mut target = targets::ip_target(host.addr);
+                target.hostnames.extend(host.hostname);
+                target.advertised = host.adverts;
+                hosts.push(target);
+            }
+        }
+    }
+}
+
+// Function to list the TCP ports the `hosts` advertised services on, in order and once each.
+pub fn tcp_ports(hosts: &[Target]) -> Vec<u16> {
+    let mut ports: Vec<u16> = hosts
+        .iter()
+        .flat_map(|target| &target.advertised)
+        .filter_map(Advert::tcp_port)
+        .collect();
+    ports.sort_unstable();
+    ports.dedup();
+    ports
+}
+
+// Function to add `advert` to the host at `addr` in `hosts`, unless it has it already.
+fn add(hosts: &mut Vec<Host>, addr: IpAddr, hostname: Option<String>, advert: Advert) {
+    let index = match hosts.iter().position(|host| host.addr == addr) {
+        Some(index) => index,
+        None => {
+            hosts.push(Host {
+                addr,
+                hostname: None,
+                adverts: vec![],
+            });
+            hosts.len() - 1
+        }
+    };
+    let host = &mut hosts[index];
+    if host.hostname.is_none() {
+        host.hostname = hostname;
+    }
+    if !host.adverts.contains(&advert) {
+        host.adverts.push(advert);
+    }
+}
+
+// Function to open a socket of the source that sends to the IPv4 `group`.
+fn sender(source: &Source, group: SocketAddr) -> io::Result<Arc<UdpSocket>> {
+    let socket = source.socket(group, Type::DGRAM, None)?;
+    // Answers to the group must not loop back as if they came from elsewhere.
+    socket.set_multicast_loop_v4(false)?;
+    Ok(Arc::new(UdpSocket::from_std(socket.into())?))
+}
+
+// Function to listen on the port of the IPv4 `group` for what is sent to it, on the --interface
+// if one is given.
+fn listener((group, port): (Ipv4Addr, u16), source: &Source) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, None)?;
+    socket.set_nonblocking(true)?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    source.bind_interface(&socket)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)).into())?;
+    socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)?;
+    UdpSocket::from_std(socket.into())
+}
+
+// Function to read the packets `socket` receives into `tx` until stopped, with where they came
+// from and whether they are mDNS.
+fn read(
+    socket: Arc<UdpSocket>,
+    mdns: bool,
+    tx: mpsc::UnboundedSender<(bool, IpAddr, Vec<u8>)>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 9000];
+        while let Ok((len, from)) = socket.recv_from(&mut buf).await {
+            if tx.send((mdns, from.ip(), buf[..len].to_vec())).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+// Function to build an mDNS question for the PTR records of `name`, asking for the answer to
+// come straight back.
+fn mdns_query(name: &str) -> Vec<u8> {
+    dns::build_query(0, name, dns::TYPE_PTR, CLASS_IN_UNICAST, false)
+}
+
+// Function to gather the services the mDNS `records` advertise, each with the address and host
+// name of its host, or the address of the responder when they do not say. Types listed without
+// any instance are kept without a name or port.
+fn mdns_adverts(records: &[(IpAddr, Record)]) -> Vec<(IpAddr, Option<String>, Advert)> {
+    // The IPv4 address of each host name and the port and host of each instance.
+    let mut addresses: HashMap<String, IpAddr> = HashMap::new();
+    let mut instances: HashMap<String, (u16, String)> = HashMap::new();
+    for (_, record) in records {
+        match &record.data {
+            RData::Ip(addr @ IpAddr::V4(_)) => {
+                addresses
+                    .entry(record.name.to_ascii_lowercase())
+                    .or_insert(*addr);
+            }
+            RData::Service { port, target } => {
+                instances
+                    .entry(record.name.to_ascii_lowercase())
+                    .or_insert((*port, target.clone()));
+            }
+            _ => {}
+        }
+    }
+
+    let mut adverts = vec![];
+    let mut listed = HashSet::new();
+    for (from, record) in records {
+        let RData::Name(name) = &record.data else {
+            continue;
+        };
+        let owner = record.name.to_ascii_lowercase();
+        let Some(service) = service_type(&owner) else {
+            continue;
+        };
+        // `name` is a type when the owner is the list of types, and an instance of the
+        // owner's type otherwise.
+        if owner == SERVICE_TYPES {
+            if let Some(service) = service_type(&name.to_ascii_lowercase()) {
+                adverts.push((
+                    *from,
+                    None,
+                    Advert {
+                        via: "mDNS",
+                        service: service.to_string(),
+                        name: None,
+                        port: None,
+                    },
+                ));
+            }
+            continue;
+        }
+        let instance = name
+            .get(..name.len().saturating_sub(owner.len() + 1))
+            .filter(|instance| !instance.is_empty());
+        let (port, host) = match instances.get(&name.to_ascii_lowercase()) {
+            Some((port, host)) => (Some(*port), Some(host.clone())),
+            None => (None, None),
+        };
+        let addr = host
+            .as_ref()
+            .and_then(|host| addresses.get(&host.to_ascii_lowercase()))
+            .copied()
+            .unwrap_or(*from);
+        listed.insert((*from, service.to_string()));
+        adverts.push((
+            addr,
+            host,
+            Advert {
+                via: "mDNS",
+                service: service.to_string(),
+                name: instance.map(str::to_string),
+                port,
+            },
+        ));
+    }
+    // A type only stays bare if no instance of it was found on the host that listed it.
+    adverts.retain(|(addr, _, advert)| {
+        advert.name.is_some() || !listed.contains(&(*addr, advert.service.clone()))
+    });
+    adverts
+}
+
+// Function to get the service type of a lowercase DNS-SD name such as `_ipp._tcp.local`, e.g.
+// `_ipp._tcp`, or the list of types itself.
+fn service_type(name: &str) -> Option<&str> {
+    if name == SERVICE_TYPES {
+        return Some(name);
+    }
+    let service = name.strip_suffix(".local")?;
+    (service.ends_with("._tcp") || service.ends_with("._udp"))
+        .then_some(service)
+        .filter(|service| service.starts_with('_'))
+}
+
+// Function to read an SSDP answer to a search or an announcement, skipping the searches of other
+// hosts and the announcements of devices that leave.
+fn notice(packet: &[u8]) -> Option<Notice> {
+    let text = String::from_utf8_lossy(packet);
+    let mut lines = text.split("\r\n");
+    let start = lines.next()?;
+    if !start.starts_with("HTTP/1.1 200") && !start.starts_with("NOTIFY * ") {
+        return None;
+    }
+    let headers: Vec<(&str, &str)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim(), value.trim()))
+        .collect();
+    let header = |wanted: &str| {
+        headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(wanted))
+            .map(|(_, value)| value.to_string())
+            .filter(|value| !value.is_empty())
+    };
+    if header("NTS").as_deref() == Some("ssdp:byebye") {
+        return None;
+    }
+    Some(Notice {
+        service: header("ST").or_else(|| header("NT"))?,
+        server: header("SERVER"),
+        location: header("LOCATION"),
+    })
+}
+
+// Function to fetch the UPnP description at `url` from the device at `addr` and read the type
+// and names of the device.
+async fn describe(url: &http::Url, addr: IpAddr, source: &Source) -> io::Result<Option<Advert>> {
+    let addr = SocketAddr::new(addr, url.port);
+    let socket = TcpSocket::from_std_stream(source.socket(addr, Type::STREAM, None)?.into());
+    let fetch = async {
+        let mut stream = socket.connect(addr).await?;
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: port_sniffer\r\nConnection: close\r\n\r\n",
+            url.path,
+            url.authority()
+        );
+        http::send(&mut stream, request.as_bytes()).await
+    };
+    let response = timeout(FETCH, fetch)
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    if response.status != 200 {
+        return Ok(None);
+    }
+    Ok(description(
+        &String::from_utf8_lossy(&response.body),
+        url.port,
+    ))
+}
+
+// Function to read the first device of a UPnP description: its type as the service, and its
+// friendly name, maker and model as the name.
+fn description(xml: &str, port: u16) -> Option<Advert> {
+    let service = element(xml, "deviceType")?;
+    let model: Vec<String> = ["manufacturer", "modelName"]
+        .into_iter()
+        .filter_map(|tag| element(xml, tag))
+        .collect();
+    let name = match (element(xml, "friendlyName"), model.is_empty()) {
+        (Some(name), true) => Some(name),
+        (Some(name), false) => Some(format!("{} ({})", name, model.join(" "))),
+        (None, false) => Some(model.join(" ")),
+        (None, true) => None,
+    };
+    Some(Advert {
+        via: "UPnP",
+        service,
+        name,
+        port: Some(port),
+    })
+}
+
+// Function to get the text of the first `tag` element of `xml`, with its entities decoded.
+fn element(xml: &str, tag: &str) -> Option<String> {
+    let open = format!("<{}>", tag);
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find("</")?;
+    let text = xml[start..end]
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&");
+    let text: String = text.trim().chars().filter(|c| !c.is_control()).collect();
+    (!text.is_empty()).then_some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Function to build a record for the tests.
+    fn record(name: &str, data: RData) -> Record {
+        Record {
+            name: name.to_string(),
+            data,
+        }
+    }
+
+    #[test]
+    fn gathers_mdns_services() {
+        let responder: IpAddr = "192.168.1.20".parse().unwrap();
+        let printer: IpAddr = "192.168.1.30".parse().unwrap();
+        let records = vec![
+            (
+                responder,
+                record(SERVICE_TYPES, RData::Name("_ipp._tcp.local".to_string())),
+            ),
+            (
+                responder,
+                record(
+                    SERVICE_TYPES,
+                    RData::Name("_airplay._tcp.local".to_string()),
+                ),
+            ),
+            (
+                responder,
+                record(
+                    "_ipp._tcp.local",
+                    RData::Name("Office Printer._ipp._tcp.local".to_string()),
+                ),
+            ),
+            (
+                responder,
+                record(
+                    "Office Printer._ipp._tcp.local",
+                    RData::Service {
+                        port: 631,
+                        target: "printer.local".to_string(),
+                    },
+                ),
+            ),
+            (responder, record("printer.local", RData::Ip(printer))),
+        ];
+        let adverts = mdns_adverts(&records);
+        assert_eq!(
+            adverts,
+            vec![
+                (
+                    responder,
+                    None,
+                    Advert {
+                        via: "mDNS",
+                        service: "_airplay._tcp".to_string(),
+                        name: None,
+                        port: None,
+                    }
+                ),
+                (
+                    printer,
+                    Some("printer.local".to_string()),
+                    Advert {
+                        via: "mDNS",
+                        service: "_ipp._tcp".to_string(),
+                        name: Some("Office Printer".to_string()),
+                        port: Some(631),
+                    }
+                ),
+            ]
+        );
+        assert_eq!(adverts[1].2.tcp_port(), Some(631));
+        assert_eq!(service_type("4.3.2.1.in-addr.arpa"), None);
+    }
+
+    #[test]
+    fn reads_ssdp_notices() {
+        let answer = b"HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=120\r\nST: upnp:rootdevice\r\nLOCATION: http://192.168.1.1:5000/rootDesc.xml\r\nSERVER: Linux UPnP/1.0 MiniUPnPd/2.2\r\n\r\n";
+        assert_eq!(
+            notice(answer),
+            Some(Notice {
+                service: "upnp:rootdevice".to_string(),
+                server: Some("Linux UPnP/1.0 MiniUPnPd/2.2".to_string()),
+                location: Some("http://192.168.1.1:5000/rootDesc.xml".to_string()),
+            })
+        );
+        let alive = b"NOTIFY * HTTP/1.1\r\nNT: urn:schemas-upnp-org:device:MediaServer:1\r\nNTS: ssdp:alive\r\n\r\n";
+        assert_eq!(
+            notice(alive).unwrap().service,
+            "urn:schemas-upnp-org:device:MediaServer:1"
+        );
+        let byebye = b"NOTIFY * HTTP/1.1\r\nNT: upnp:rootdevice\r\nNTS: ssdp:byebye\r\n\r\n";
+        assert_eq!(notice(byebye), None);
+        assert_eq!(notice(M_SEARCH), None);
+    }
+
+    #[test]
+    fn reads_upnp_descriptions() {
+        let xml = "<?xml version=\"1.0\"?><root><device>\
+            <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>\
+            <friendlyName>Home &amp; Office Router</friendlyName>\
+            <manufacturer>Acme</manufacturer><modelName>R7000</modelName>\
+            </device></root>";
+        assert_eq!(
+            description(xml, 5000),
+            Some(Advert {
+                via: "UPnP",
+                service: "urn:schemas-upnp-org:device:InternetGatewayDevice:1".to_string(),
+                name: Some("Home & Office Router (Acme R7000)".to_string()),
+                port: Some(5000),
+            })
+        );
+        assert_eq!(description("<root></root>", 80), None);
+    }
+}
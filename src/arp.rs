@@ -0,0 +1,274 @@
+use crate::targets::Mac; // Import MAC addresses of the hosts found.
+use socket2::{Domain, Protocol, SockAddr, Socket, Type}; // Import socket2 to open packet sockets.
+use std::collections::{HashMap, HashSet}; // Import maps and sets of the hosts asked for.
+use std::ffi::CStr; // Import `CStr` to read interface names.
+use std::io; // Import I/O errors for socket failures.
+use std::mem::MaybeUninit; // Import `MaybeUninit` for the receive buffer.
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr}; // Import address types for the local networks and the hosts asked for.
+use std::time::Duration; // Import `Duration` for reply timeouts.
+use tokio::io::unix::AsyncFd; // Import `AsyncFd` to wait for replies without blocking.
+use tokio::time::{timeout_at, Instant}; // Import `timeout_at` to stop listening for replies.
+
+// Define the EtherType of ARP.
+const ETH_P_ARP: u16 = 0x0806;
+
+// Define how often hosts that did not answer are asked again.
+const ROUNDS: usize = 2;
+
+// A local Ethernet-like network: an interface that speaks ARP (or its IPv6 counterpart,
+// neighbor discovery), with one of its addresses.
+#[derive(Debug, Clone)]
+pub struct Link {
+    pub name: String,
+    pub index: u32,
+    pub mac: Mac,
+    pub addr: IpAddr,
+    netmask: IpAddr,
+}
+
+impl Link {
+    // Function to check whether `host` is on this network, as seen from the local address the
+    // kernel sends to it from.
+    pub fn reaches(&self, local: IpAddr, host: IpAddr) -> bool {
+        let prefix = |ip: IpAddr| match (ip, self.netmask) {
+            (IpAddr::V4(ip), IpAddr::V4(mask)) => Some(u128::from(u32::from(ip) & u32::from(mask))),
+            (IpAddr::V6(ip), IpAddr::V6(mask)) => Some(u128::from(ip) & u128::from(mask)),
+            _ => None,
+        };
+        local == self.addr
+            && host != self.addr
+            && prefix(host).is_some()
+            && prefix(host) == prefix(self.addr)
+    }
+}
+
+// Function to list the local networks ARP and neighbor discovery work on, skipping loopback and
+// point-to-point interfaces and those that are down.
+pub fn links() -> io::Result<Vec<Link>> {
+    let mut list: *mut libc::ifaddrs = std::ptr::null_mut();
+    // SAFETY: `list` receives the interface list, freed below.
+    if unsafe { libc::getifaddrs(&mut list) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut macs: HashMap<String, Mac> = HashMap::new();
+    let mut addrs: Vec<(String, IpAddr, IpAddr)> = vec![];
+    let mut entry = list;
+    while !entry.is_null() {
+        // SAFETY: `entry` is a node of the list returned by getifaddrs, freed only below.
+        let ifa = unsafe { &*entry };
+        entry = ifa.ifa_next;
+        let flags = ifa.ifa_flags as libc::c_int;
+        let usable = flags & libc::IFF_UP != 0
+            && flags & (libc::IFF_LOOPBACK | libc::IFF_POINTOPOINT | libc::IFF_NOARP) == 0;
+        if !usable || ifa.ifa_addr.is_null() {
+            continue;
+        }
+        // SAFETY: `ifa_name` is a NUL-terminated string owned by the list.
+        let name = unsafe { CStr::from_ptr(ifa.ifa_name) }
+            .to_string_lossy()
+            .into_owned();
+        // SAFETY: `ifa_addr` (and `ifa_netmask` with it) points to a socket address of the
+        // family in its `sa_family`.
+        match i32::from(unsafe { (*ifa.ifa_addr).sa_family }) {
+            libc::AF_PACKET => {
+                let ll = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_ll) };
+                if ll.sll_halen == 6 {
+                    let mut mac = [0; 6];
+                    mac.copy_from_slice(&ll.sll_addr[..6]);
+                    macs.insert(name, Mac(mac));
+                }
+            }
+            libc::AF_INET if !ifa.ifa_netmask.is_null() => {
+                let addr = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in) };
+                let netmask = unsafe { &*(ifa.ifa_netmask as *const libc::sockaddr_in) };
+                addrs.push((
+                    name,
+                    IpAddr::V4(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr))),
+                    IpAddr::V4(Ipv4Addr::from(u32::from_be(netmask.sin_addr.s_addr))),
+                ));
+            }
+            libc::AF_INET6 if !ifa.ifa_netmask.is_null() => {
+                let addr = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in6) };
+                let netmask = unsafe { &*(ifa.ifa_netmask as *const libc::sockaddr_in6) };
+                addrs.push((
+                    name,
+                    IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr)),
+                    IpAddr::V6(Ipv6Addr::from(netmask.sin6_addr.s6_addr)),
+                ));
+            }
+            _ => {}
+        }
+    }
+    // SAFETY: `list` came from a successful getifaddrs call and is not used afterwards.
+    unsafe { libc::freeifaddrs(list) };
+
+    Ok(addrs
+        .into_iter()
+        .filter_map(|(name, addr, netmask)| {
+            let mac = *macs.get(&name)?;
+            let c_name = std::ffi::CString::new(name.as_str()).ok()?;
+            // SAFETY: `c_name` is a valid NUL-terminated string that outlives the call.
+            let index = unsafe { libc::if_nametoindex(c_name.as_ptr()) };
+            (index != 0).then_some(Link {
+                name,
+                index,
+                mac,
+                addr,
+                netmask,
+            })
+        })
+        .collect())
+}
+
+// Function to ask every host in `hosts` on the IPv4 network `link` for its MAC address, giving
+// each round of requests `wait` to be answered. Needs root or the CAP_NET_RAW capability.
+pub async fn sweep(
+    link: &Link,
+    hosts: &[Ipv4Addr],
+    wait: Duration,
+) -> io::Result<HashMap<Ipv4Addr, Mac>> {
+    let IpAddr::V4(local) = link.addr else {
+        return Err(io::ErrorKind::InvalidInput.into());
+    };
+    let socket = Socket::new(
+        Domain::PACKET,
+        Type::DGRAM,
+        Some(Protocol::from(i32::from(ETH_P_ARP.to_be()))),
+    )?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&link_addr(link.index, Mac([0; 6])))?;
+    let socket = AsyncFd::new(socket)?;
+    let broadcast = link_addr(link.index, Mac([0xff; 6]));
+
+    let wanted: HashSet<Ipv4Addr> = hosts.iter().copied().collect();
+    let mut found = HashMap::new();
+    for _ in 0..ROUNDS {
+        for &host in hosts.iter().filter(|host| !found.contains_key(*host)) {
+            let packet = request(link.mac, local, host);
+            loop {
+                let mut ready = socket.writable().await?;
+                if let Ok(sent) =
+                    ready.try_io(|socket| socket.get_ref().send_to(&packet, &broadcast))
+                {
+                    sent?;
+                    break;
+                }
+            }
+        }
+        let listen = async {
+            let mut buffer = [MaybeUninit::<u8>::uninit(); 1500];
+            while found.len() < wanted.len() {
+                let mut ready = socket.readable().await?;
+                let Ok(length) = ready.try_io(|socket| socket.get_ref().recv(&mut buffer)) else {
+                    continue;
+                };
+                // SAFETY: `recv` initialized the first `length` bytes.
+                let packet =
+                    unsafe { std::slice::from_raw_parts(buffer.as_ptr().cast::<u8>(), length?) };
+                if let Some((host, mac)) = reply(packet).filter(|(host, _)| wanted.contains(host)) {
+                    found.insert(host, mac);
+                }
+            }
+            Ok::<(), io::Error>(())
+        };
+        if let Ok(result) = timeout_at(Instant::now() + wait, listen).await {
+            result?;
+        }
+        if found.len() == wanted.len() {
+            break;
+        }
+    }
+    Ok(found)
+}
+
+// Function to build the link-layer address of a packet socket on interface `index`.
+fn link_addr(index: u32, mac: Mac) -> SockAddr {
+    // SAFETY: an all-zero `sockaddr_ll` is valid, and the storage is large enough to hold one.
+    unsafe {
+        let mut storage: libc::sockaddr_storage = std::mem::zeroed();
+        let ll = &mut *(&mut storage as *mut libc::sockaddr_storage).cast::<libc::sockaddr_ll>();
+        ll.sll_family = libc::AF_PACKET as u16;
+        ll.sll_protocol = ETH_P_ARP.to_be();
+        ll.sll_ifindex = index as i32;
+        ll.sll_halen = 6;
+        ll.sll_addr[..6].copy_from_slice(&mac.0);
+        SockAddr::new(
+            storage,
+            std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+        )
+    }
+}
+
+// Function to build an ARP request from `mac` and `local` for the MAC address of `host`.
+fn request(mac: Mac, local: Ipv4Addr, host: Ipv4Addr) -> Vec<u8> {
+    // Ethernet hardware, IPv4 protocol addresses of 6 and 4 bytes, and a request.
+    let mut packet = vec![0, 1, 0x08, 0x00, 6, 4, 0, 1];
+    packet.extend_from_slice(&mac.0);
+    packet.extend_from_slice(&local.octets());
+    packet.extend_from_slice(&[0; 6]);
+    packet.extend_from_slice(&host.octets());
+    packet
+}
+
+// Function to read an ARP reply: the IPv4 address of the sender and its MAC address.
+fn reply(packet: &[u8]) -> Option<(Ipv4Addr, Mac)> {
+    if packet.get(..8)? != [0, 1, 0x08, 0x00, 6, 4, 0, 2] {
+        return None;
+    }
+    let mac: [u8; 6] = packet.get(8..14)?.try_into().ok()?;
+    let ip: [u8; 4] = packet.get(14..18)?.try_into().ok()?;
+    Some((Ipv4Addr::from(ip), Mac(mac)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link() -> Link {
+        Link {
+            name: "eth0".to_string(),
+            index: 2,
+            mac: Mac([2, 0, 0, 0, 0, 1]),
+            addr: IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10)),
+            netmask: IpAddr::V4(Ipv4Addr::new(255, 255, 255, 0)),
+        }
+    }
+
+    #[test]
+    fn reads_replies() {
+        let mut packet = request(
+            Mac([2, 0, 0, 0, 0, 1]),
+            Ipv4Addr::new(192, 168, 1, 10),
+            Ipv4Addr::new(192, 168, 1, 1),
+        );
+        assert_eq!(packet.len(), 28);
+        // A request is no reply.
+        assert_eq!(reply(&packet), None);
+        packet[7] = 2;
+        assert_eq!(
+            reply(&packet),
+            Some((Ipv4Addr::new(192, 168, 1, 10), Mac([2, 0, 0, 0, 0, 1])))
+        );
+        assert_eq!(reply(&packet[..17]), None);
+        assert_eq!(Mac([2, 0xfc, 0, 0, 0, 5]).to_string(), "02:fc:00:00:00:05");
+    }
+
+    #[test]
+    fn finds_hosts_on_the_link() {
+        let link = link();
+        let local = IpAddr::from([192, 168, 1, 10]);
+        assert!(link.reaches(local, IpAddr::from([192, 168, 1, 1])));
+        assert!(!link.reaches(local, local));
+        assert!(!link.reaches(local, IpAddr::from([192, 168, 2, 1])));
+        assert!(!link.reaches(IpAddr::from([10, 0, 0, 1]), IpAddr::from([192, 168, 1, 1])));
+        assert!(!link.reaches(local, "fd00::1".parse().unwrap()));
+        let link = Link {
+            addr: "fd00::2".parse().unwrap(),
+            netmask: "ffff:ffff:ffff:ffff::".parse().unwrap(),
+            ..link
+        };
+        let local = "fd00::2".parse().unwrap();
+        assert!(link.reaches(local, "fd00::1".parse().unwrap()));
+        assert!(!link.reaches(local, "fd01::1".parse().unwrap()));
+    }
+}
@@ -0,0 +1,97 @@
+use crate::scanner::{self, PortResult, PortState, Protocol, Settings}; // Import the open ports to read and the scan settings to connect with.
+use crate::targets::Target; // Import the hosts the ports belong to.
+use socket2::Type; // Import socket types for the connects.
+use std::io; // Import I/O errors for socket failures.
+use std::net::SocketAddr; // Import `SocketAddr` for the ports connected to.
+use std::time::Duration; // Import `Duration` for the connect and read timeouts.
+use tokio::io::AsyncReadExt; // Import `read` on the connected streams.
+use tokio::net::TcpSocket; // Import `TcpSocket` to connect the sockets of the source.
+use tokio::time::timeout; // Import `timeout` to give up on silent services.
+
+// Define how much of what a service sends is read, and the longest banner kept of it.
+const READ_SIZE: usize = 1024;
+const MAX_LENGTH: usize = 200;
+
+// Function to read the banner of every open TCP port in `results`, giving each connect and
+// the first bytes after it `wait`, and keep it on the result. Services that wait for the
+// client to speak first (HTTP, TLS, ...) stay without one.
+pub async fn grab_all(
+    hosts: &[Target],
+    results: &mut [PortResult],
+    settings: &Settings,
+    wait: Duration,
+) {
+    scanner::check_ports(
+        hosts,
+        results,
+        settings.concurrency,
+        |r, _| (r.protocol == Protocol::Tcp && r.state == PortState::Open).then_some(()),
+        |addr, ()| async move { grab(addr, settings, wait).await.ok().flatten() },
+        |r, banner| r.findings.banner = banner,
+    )
+    .await;
+}
+
+// Function to connect to `addr` and read what the service sends first, within `wait` each.
+// Gives `None` if it sent nothing in time.
+async fn grab(addr: SocketAddr, settings: &Settings, wait: Duration) -> io::Result<Option<String>> {
+    let socket = TcpSocket::from_std_stream(settings.socket(addr, Type::STREAM).await?.into());
+    let mut stream = timeout(wait, socket.connect(addr))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    let mut buffer = [0u8; READ_SIZE];
+    match timeout(wait, stream.read(&mut buffer)).await {
+        Ok(read) => Ok(clean(&buffer[..read?])),
+        Err(_) => Ok(None),
+    }
+}
+
+// Function to turn what a service sent into a one-line banner: its first line that is not
+// blank, trimmed, with bytes that are not printable ASCII shown as `\xNN` escapes and cut to
+// `MAX_LENGTH` characters.
+fn clean(bytes: &[u8]) -> Option<String> {
+    let line = bytes
+        .split(|&byte| byte == b'\n' || byte == b'\r')
+        .map(|line| line.trim_ascii())
+        .find(|line| !line.is_empty())?;
+    let mut banner = String::new();
+    for &byte in line {
+        match byte {
+            b' '..=b'~' => banner.push(char::from(byte)),
+            _ => banner.push_str(&format!("\\x{:02x}", byte)),
+        }
+        if banner.len() >= MAX_LENGTH {
+            banner.truncate(MAX_LENGTH);
+            banner.push_str("...");
+            break;
+        }
+    }
+    Some(banner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cleans_banners() {
+        assert_eq!(
+            clean(b"SSH-2.0-OpenSSH_9.6\r\n").as_deref(),
+            Some("SSH-2.0-OpenSSH_9.6")
+        );
+        // Only the first line that holds something is kept.
+        assert_eq!(
+            clean(b"\r\n  220 mail.example ESMTP\r\n250 OK\r\n").as_deref(),
+            Some("220 mail.example ESMTP")
+        );
+        assert_eq!(
+            clean(b"J\x00\x00\x008.0\x00").as_deref(),
+            Some("J\\x00\\x00\\x008.0\\x00")
+        );
+        assert_eq!(clean(b" \r\n\n"), None);
+        assert_eq!(clean(b""), None);
+        let long = clean(&[b'a'; 500]).unwrap();
+        assert_eq!(long.len(), MAX_LENGTH + 3);
+        assert!(long.ends_with("..."));
+    }
+}
@@ -0,0 +1,217 @@
+use crate::scanner::{Findings, PortResult, PortState, Protocol, Reason}; // Import the probe results kept in a checkpoint.
+use crate::targets::Target; // Import the scanned hosts.
+use serde::{Deserialize, Serialize}; // Import serde to read and write the checkpoint file.
+use std::collections::{HashMap, HashSet}; // Import maps and sets to track the finished probes.
+use std::fs; // Import filesystem access to read and write the checkpoint file.
+use std::net::IpAddr; // Import `IpAddr` for the family that answered a raced probe.
+use std::time::Duration; // Import `Duration` for the save interval.
+
+// Define how often the progress of a scan is written to the --checkpoint file.
+pub const INTERVAL: Duration = Duration::from_secs(10);
+
+// Finished probes of one host: the ports (of either protocol of a combined TCP and UDP scan)
+// probed so far, until all of them are.
+#[derive(Debug, Clone)]
+enum HostProgress {
+    Partial(HashSet<(u16, Protocol)>),
+    Complete,
+}
+
+// Which probes of a scan have finished.
+#[derive(Debug, Clone)]
+pub struct Progress {
+    ports: usize,
+    hosts: Vec<HostProgress>,
+    completed: usize,
+}
+
+impl Progress {
+    // Function to start tracking a scan of `ports` ports on each of `hosts` hosts.
+    pub fn new(hosts: usize, ports: usize) -> Progress {
+        Progress {
+            ports,
+            hosts: vec![HostProgress::Partial(HashSet::new()); hosts],
+            completed: 0,
+        }
+    }
+
+    // Function to record that a port of a host was probed.
+    pub fn record(&mut self, host: usize, port: u16, protocol: Protocol) {
+        let HostProgress::Partial(done) = &mut self.hosts[host] else {
+            return;
+        };
+        if done.insert((port, protocol)) {
+            self.completed += 1;
+            // Forget the single ports once the host is done, to keep long scans small.
+            if done.len() == self.ports {
+                self.hosts[host] = HostProgress::Complete;
+            }
+        }
+    }
+
+    // Function to record that every port of a host was probed.
+    fn finish(&mut self, host: usize) {
+        if let HostProgress::Partial(done) = &self.hosts[host] {
+            self.completed += self.ports - done.len();
+            self.hosts[host] = HostProgress::Complete;
+        }
+    }
+
+    // Function to check whether a port of a host was probed.
+    pub fn contains(&self, host: usize, port: u16, protocol: Protocol) -> bool {
+        match &self.hosts[host] {
+            HostProgress::Partial(done) => done.contains(&(port, protocol)),
+            HostProgress::Complete => true,
+        }
+    }
+
+    // Function to count the finished probes.
+    pub fn completed(&self) -> usize {
+        self.completed
+    }
+}
+
+// An open (or open|filtered) port found before the checkpoint was saved.
+#[derive(Debug, Serialize, Deserialize)]
+struct CheckpointPort {
+    port: u16,
+    protocol: Protocol,
+    state: PortState,
+    reason: Reason,
+    attempts: u32,
+    // Only set with --happy-eyeballs.
+    answered: Option<IpAddr>,
+    // Only set with --verify-filtered.
+    second_pass: bool,
+}
+
+// The progress of one host in the checkpoint file. Hosts not probed yet are left out.
+#[derive(Debug, Serialize, Deserialize)]
+struct CheckpointHost {
+    address: String,
+    // Set once every port was probed; `done` is then left empty.
+    complete: bool,
+    done: Vec<u16>,
+    open: Vec<CheckpointPort>,
+}
+
+// The contents of a checkpoint file.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    // The arguments the scan was started with, so resuming repeats the same scan.
+    pub args: Vec<String>,
+    hosts: Vec<CheckpointHost>,
+}
+
+impl Checkpoint {
+    // Function to rebuild the progress and the results found so far for the hosts and ports of
+    // `protocol` of the resumed scan. Hosts are matched by address, as hostnames may resolve
+    // differently now.
+    pub fn restore(
+        &self,
+        hosts: &[Target],
+        ports: &[u16],
+        protocol: Protocol,
+    ) -> (Progress, Vec<PortResult>) {
+        let index: HashMap<String, usize> = hosts
+            .iter()
+            .enumerate()
+            .map(|(i, target)| (target.address(), i))
+            .collect();
+        let wanted: HashSet<u16> = ports.iter().copied().collect();
+        let mut progress = Progress::new(hosts.len(), ports.len());
+        let mut results = vec![];
+        let mut missing = 0;
+        for saved in &self.hosts {
+            let Some(&host) = index.get(&saved.address) else {
+                missing += 1;
+                continue;
+            };
+            if saved.complete {
+                progress.finish(host);
+            }
+            for &port in saved.done.iter().filter(|port| wanted.contains(port)) {
+                progress.record(host, port, protocol);
+            }
+            results.extend(saved.open.iter().map(|open| PortResult {
+                host,
+                port: open.port,
+                protocol: open.protocol,
+                state: open.state,
+                reason: open.reason,
+                attempts: open.attempts,
+                answered: open.answered,
+                second_pass: open.second_pass,
+                findings: Findings::default(),
+            }));
+        }
+        if missing > 0 {
+            eprintln!(
+                "Warning: {} host(s) in the checkpoint are no longer among the targets",
+                missing
+            );
+        }
+        (progress, results)
+    }
+}
+
+// Function to read a checkpoint file written by `save`.
+pub fn load(path: &str) -> Result<Checkpoint, String> {
+    let contents =
+        fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    serde_json::from_str(&contents).map_err(|e| format!("Invalid checkpoint file {}: {}", path, e))
+}
+
+// Function to write the progress of a scan to a checkpoint file.
+// The file is replaced in one step, so a crash while saving leaves the previous checkpoint intact.
+pub fn save(
+    path: &str,
+    args: &[String],
+    hosts: &[Target],
+    progress: &Progress,
+    results: &[PortResult],
+) -> Result<(), String> {
+    let mut saved: Vec<CheckpointHost> = hosts
+        .iter()
+        .map(|target| CheckpointHost {
+            address: target.address(),
+            complete: false,
+            done: vec![],
+            open: vec![],
+        })
+        .collect();
+    for (host, state) in progress.hosts.iter().enumerate() {
+        match state {
+            // A checkpoint only ever holds a scan of one protocol.
+            HostProgress::Partial(done) => {
+                saved[host].done = done.iter().map(|&(port, _)| port).collect()
+            }
+            HostProgress::Complete => saved[host].complete = true,
+        }
+    }
+    for result in results {
+        saved[result.host].open.push(CheckpointPort {
+            port: result.port,
+            protocol: result.protocol,
+            state: result.state,
+            reason: result.reason,
+            attempts: result.attempts,
+            answered: result.answered,
+            second_pass: result.second_pass,
+        });
+    }
+    for host in &mut saved {
+        host.done.sort_unstable();
+    }
+    saved.retain(|host| host.complete || !host.done.is_empty());
+
+    let checkpoint = Checkpoint {
+        args: args.to_vec(),
+        hosts: saved,
+    };
+    let json = serde_json::to_string(&checkpoint).map_err(|e| e.to_string())?;
+    let temp = format!("{}.tmp", path);
+    fs::write(&temp, json + "\n")
+        .and_then(|_| fs::rename(&temp, path))
+        .map_err(|e| format!("Failed to write {}: {}", path, e))
+}
@@ -0,0 +1,47 @@
+use serde::Deserialize; // Import `Deserialize` to read the config file into typed settings.
+use std::collections::HashMap; // Import `HashMap` for named entries.
+use std::env; // Import environment access to locate the default config file.
+use std::fs; // Import filesystem access to read the config file.
+use std::path::PathBuf; // Import `PathBuf` to build the config file location.
+
+// Settings read from the TOML config file.
+//
+// ```toml
+// [groups]
+// internal = "8000-8100,9090,@web"
+// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // Custom port groups, usable as `@name` in port specifications.
+    pub groups: HashMap<String, String>,
+}
+
+// Function to locate the default config file: `$XDG_CONFIG_HOME/port_sniffer/config.toml`,
+// or `~/.config/port_sniffer/config.toml` when XDG_CONFIG_HOME is unset.
+fn default_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("port_sniffer").join("config.toml"))
+}
+
+// Function to load the config file. An explicitly given file must exist,
+// while a missing default file simply means no custom settings.
+pub fn load(path: Option<&str>) -> Result<Config, String> {
+    let (path, required) = match path {
+        Some(path) => (PathBuf::from(path), true),
+        None => match default_path() {
+            Some(path) => (path, false),
+            None => return Ok(Config::default()),
+        },
+    };
+
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(_) if !required && !path.exists() => return Ok(Config::default()),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    toml::from_str(&contents).map_err(|e| format!("Invalid config file {}: {}", path.display(), e))
+}
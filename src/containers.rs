@@ -0,0 +1,179 @@
+use crate::http::{self, Response}; // Import the HTTP client the APIs are asked with.
+use crate::scanner::{self, PortResult, PortState, Protocol, Settings}; // Import the open ports to ask and the scan settings to connect with.
+use crate::targets::Target; // Import the hosts the ports belong to.
+use crate::tls; // Import the check for servers that want client certificates.
+use crate::web; // Import the plain and TLS connects of the web probes.
+use serde::Serialize; // Import `Serialize` to report the APIs in the structured output.
+use serde_json::Value; // Import JSON values to read the answers of the APIs.
+use std::future::Future; // Import `Future` for the requests `probe` is given.
+use std::io; // Import I/O errors for socket failures.
+use std::net::SocketAddr; // Import `SocketAddr` for the ports asked.
+use std::time::Duration; // Import `Duration` for the connect and response timeouts.
+use tokio::time::timeout; // Import `timeout` to give up on ports that do not answer.
+
+// The container platform APIs that can be asked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Api {
+    Docker,
+    Kubernetes,
+    Kubelet,
+}
+
+impl Api {
+    // Function to name the API for the report.
+    fn name(self) -> &'static str {
+        match self {
+            Api::Docker => "Docker API",
+            Api::Kubernetes => "Kubernetes API server",
+            Api::Kubelet => "kubelet API",
+        }
+    }
+}
+
+// What a container platform API answered a client without credentials.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Container {
+    // The API, e.g. `Docker API`.
+    pub api: &'static str,
+    // Set when it was asked over TLS.
+    pub tls: bool,
+    // Its version, e.g. `24.0.7` or `v1.29.2`, when it told.
+    pub version: Option<String>,
+    // Set when it answered a request that lists or controls containers.
+    pub unauthenticated: bool,
+    // The answer the verdict comes from, e.g. `GET /pods: 401`.
+    pub reply: String,
+}
+
+// Function to ask every open TCP port in `results` that may run a container platform API: the
+// Docker daemon on port 2375 and on 2376 over TLS, the Kubernetes API server on 6443 and the
+// kubelet on 10250, both over TLS. Ports --tls found TLS on are asked over TLS. Each request gets
+// `wait` for the connect, the handshake and the response each. Only GET requests are sent.
+pub async fn probe_all(
+    hosts: &[Target],
+    results: &mut [PortResult],
+    settings: &Settings,
+    wait: Duration,
+) {
+    scanner::check_ports(
+        hosts,
+        results,
+        settings.concurrency,
+        |r, host| {
+            if r.protocol != Protocol::Tcp || r.state != PortState::Open {
+                return None;
+            }
+            let (api, tls) = match r.port {
+                2375 => (Api::Docker, r.findings.tls.is_some()),
+                2376 => (Api::Docker, true),
+                6443 => (Api::Kubernetes, true),
+                10250 => (Api::Kubelet, true),
+                _ => return None,
+            };
+            Some((host.hostnames.first().map(String::as_str), api, tls))
+        },
+        |addr, (name, api, tls)| async move {
+            let request = |path| get(addr, name, tls, path, settings, wait);
+            let container = match probe(api, request).await {
+                Ok(answer) => answer,
+                // Servers that want a client certificate keep everyone else out.
+                Err(error) if tls::certificate_required(&error) => {
+                    Some((None, false, "TLS client certificate required".to_string()))
+                }
+                Err(_) => None,
+            };
+            container.map(|(version, unauthenticated, reply)| Container {
+                api: api.name(),
+                tls,
+                version,
+                unauthenticated,
+                reply,
+            })
+        },
+        |r, container| r.findings.container = container,
+    )
+    .await;
+}
+
+// Function to ask the `api` through `get` for its version and for something only an authorized
+// client may see. Gives the version, whether it was shown and the answer that said so, or `None`
+// if the answers do not come from the API.
+async fn probe<F, R>(api: Api, get: F) -> io::Result<Option<(Option<String>, bool, String)>>
+where
+    F: Fn(&'static str) -> R,
+    R: Future<Output = io::Result<Response>>,
+{
+    match api {
+        // The Docker daemon needs no login for anything: whoever reaches it controls the host.
+        Api::Docker => {
+            let response = get("/version").await?;
+            let version = json(&response).and_then(|version| text(&version, "Version"));
+            let reply = format!("GET /version: {}", response.status);
+            Ok(match response.status {
+                200 if version.is_some() => Some((version, true, reply)),
+                401 | 403 => Some((None, false, reply)),
+                _ => None,
+            })
+        }
+        // The version is public on most clusters; the namespaces are not.
+        Api::Kubernetes => {
+            let version = get("/version")
+                .await
+                .ok()
+                .and_then(|response| json(&response))
+                .and_then(|version| text(&version, "gitVersion"));
+            let response = get("/api/v1/namespaces").await?;
+            let kind = json(&response).and_then(|list| text(&list, "kind"));
+            let reply = format!("GET /api/v1/namespaces: {}", response.status);
+            Ok(match (response.status, kind.as_deref()) {
+                (200, Some("NamespaceList")) => Some((version, true, reply)),
+                (401 | 403, _) if version.is_some() || kind.is_some() => {
+                    Some((version, false, reply))
+                }
+                _ => None,
+            })
+        }
+        // A kubelet open to anyone lists its pods and runs commands in them.
+        Api::Kubelet => {
+            let response = get("/pods").await?;
+            let kind = json(&response).and_then(|list| text(&list, "kind"));
+            let reply = format!("GET /pods: {}", response.status);
+            Ok(match (response.status, kind.as_deref()) {
+                (200, Some("PodList")) => Some((None, true, reply)),
+                (401 | 403, _) => Some((None, false, reply)),
+                _ => None,
+            })
+        }
+    }
+}
+
+// Function to connect to `addr`, over TLS if `tls` is set, and GET `path` for the host `name`,
+// or else the address, within `wait` for the connect, the handshake and the response each.
+async fn get(
+    addr: SocketAddr,
+    name: Option<&str>,
+    tls: bool,
+    path: &str,
+    settings: &Settings,
+    wait: Duration,
+) -> io::Result<Response> {
+    let mut stream = web::connect(addr, name, tls, settings, wait).await?;
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: port_sniffer\r\nAccept: application/json\r\nConnection: close\r\n\r\n",
+        path,
+        web::authority(addr, name, tls)
+    );
+    timeout(wait, http::send(&mut stream, request.as_bytes()))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?
+}
+
+// Function to read the body of `response` as JSON.
+fn json(response: &Response) -> Option<Value> {
+    serde_json::from_slice(&response.body).ok()
+}
+
+// Function to read the string field `field` of the JSON object `value`.
+fn text(value: &Value, field: &str) -> Option<String> {
+    value.get(field)?.as_str().map(str::to_string)
+}
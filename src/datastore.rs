@@ -0,0 +1,465 @@
+use crate::scanner::{self, PortResult, PortState, Protocol, Settings}; // Import the open ports to ask and the scan settings to connect with.
+use crate::targets::Target; // Import the hosts the ports belong to.
+use serde::Serialize; // Import `Serialize` to report the stores in the structured output.
+use socket2::Type; // Import socket types for the connects.
+use std::io; // Import I/O errors for socket failures.
+use std::net::SocketAddr; // Import `SocketAddr` for the ports asked.
+use std::time::Duration; // Import `Duration` for the connect and read timeouts.
+use tokio::io::{AsyncReadExt, AsyncWriteExt}; // Import reads and writes on the connected streams.
+use tokio::net::{TcpSocket, TcpStream}; // Import sockets of the source for the connects.
+use tokio::time::{timeout_at, Instant}; // Import deadlines to give up on slow servers.
+
+// Define the longest answer read from a store.
+const MAX_ANSWER: usize = 256 * 1024;
+
+// Define the MongoDB opcodes: OP_REPLY, OP_QUERY and OP_MSG.
+const OP_REPLY: i32 = 1;
+const OP_QUERY: i32 = 2004;
+const OP_MSG: i32 = 2013;
+
+// Define the first wire version without OP_QUERY for commands other than the handshake (6.0).
+const WIRE_VERSION_OP_MSG_ONLY: i32 = 17;
+
+// The data stores that can be asked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Redis,
+    Memcached,
+    Mongodb,
+}
+
+impl Kind {
+    // Function to name the store for the report.
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Redis => "Redis",
+            Kind::Memcached => "Memcached",
+            Kind::Mongodb => "MongoDB",
+        }
+    }
+}
+
+// What a data store answered a client that did not log in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Datastore {
+    // The store, e.g. `Redis`.
+    pub service: &'static str,
+    // Its version, e.g. `7.2.4`, when it told.
+    pub version: Option<String>,
+    // Set when it answered the command without asking for authentication.
+    pub unauthenticated: bool,
+    // The answer the verdict comes from, e.g. `+PONG` or `-NOAUTH Authentication required.`.
+    pub reply: String,
+}
+
+// Function to send a harmless command to every open TCP port in `results` that may run a data
+// store: Redis on port 6379, Memcached on 11211 and MongoDB on 27017 and 27018, and ports
+// --service-version named redis, memcache or mongodb. Each gets `wait` for the connect and the
+// whole exchange together. Nothing is written to the stores.
+pub async fn probe_all(
+    hosts: &[Target],
+    results: &mut [PortResult],
+    settings: &Settings,
+    wait: Duration,
+) {
+    scanner::check_ports(
+        hosts,
+        results,
+        settings.concurrency,
+        |r, _| {
+            if r.protocol != Protocol::Tcp || r.state != PortState::Open {
+                return None;
+            }
+            match (
+                r.port,
+                r.findings.detected.as_ref().map(|s| s.name.as_str()),
+            ) {
+                (_, Some("redis")) => Some(Kind::Redis),
+                (_, Some("memcache")) => Some(Kind::Memcached),
+                (_, Some("mongodb")) => Some(Kind::Mongodb),
+                (_, Some(_)) => None,
+                (6379, None) => Some(Kind::Redis),
+                (11211, None) => Some(Kind::Memcached),
+                (27017 | 27018, None) => Some(Kind::Mongodb),
+                _ => None,
+            }
+        },
+        |addr, kind| async move { probe(addr, kind, settings, wait).await.ok().flatten() },
+        |r, datastore| r.findings.datastore = datastore,
+    )
+    .await;
+}
+
+// Function to connect to `addr` and ask the store of `kind` there, within `wait`. Gives `None`
+// if the port does not answer like one.
+async fn probe(
+    addr: SocketAddr,
+    kind: Kind,
+    settings: &Settings,
+    wait: Duration,
+) -> io::Result<Option<Datastore>> {
+    let deadline = Instant::now() + wait;
+    let socket = TcpSocket::from_std_stream(settings.socket(addr, Type::STREAM).await?.into());
+    let mut stream = timeout_at(deadline, socket.connect(addr))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    let answer = match kind {
+        Kind::Redis => redis(&mut stream, deadline).await?,
+        Kind::Memcached => memcached(&mut stream, deadline).await?,
+        Kind::Mongodb => mongodb(&mut stream, deadline).await?,
+    };
+    Ok(answer.map(|(version, unauthenticated, reply)| Datastore {
+        service: kind.name(),
+        version,
+        unauthenticated,
+        reply,
+    }))
+}
+
+// Function to send PING to a Redis server and, if it answers without a login, ask INFO server
+// for its version.
+async fn redis(
+    stream: &mut TcpStream,
+    deadline: Instant,
+) -> io::Result<Option<(Option<String>, bool, String)>> {
+    stream.write_all(b"PING\r\n").await?;
+    let answer = read(stream, deadline, resp_length).await?;
+    let reply = first_line(&answer);
+    if !reply.starts_with(['+', '-']) {
+        return Ok(None);
+    }
+    // NOAUTH and the DENIED of protected mode both refuse the command.
+    if !reply.starts_with("+PONG") {
+        return Ok(Some((None, false, reply)));
+    }
+    stream.write_all(b"INFO server\r\n").await?;
+    let info = read(stream, deadline, resp_length).await?;
+    let version = String::from_utf8_lossy(&info)
+        .lines()
+        .find_map(|line| line.strip_prefix("redis_version:").map(str::to_string));
+    Ok(Some((version, true, reply)))
+}
+
+// Function to tell how long the RESP reply at the start of `data` is: a simple string, an error
+// or an integer up to its line end, or a bulk string with the length its first line gives.
+fn resp_length(data: &[u8]) -> Option<usize> {
+    let end = data.windows(2).position(|w| w == b"\r\n")? + 2;
+    if data[0] != b'$' {
+        return Some(end);
+    }
+    let length: i64 = std::str::from_utf8(&data[1..end - 2]).ok()?.parse().ok()?;
+    // A nil bulk string has no body.
+    let total = match usize::try_from(length) {
+        Ok(length) => end + length + 2,
+        Err(_) => end,
+    };
+    (data.len() >= total).then_some(total)
+}
+
+// Function to send `stats` to a Memcached server and take its version from the answer.
+async fn memcached(
+    stream: &mut TcpStream,
+    deadline: Instant,
+) -> io::Result<Option<(Option<String>, bool, String)>> {
+    stream.write_all(b"stats\r\n").await?;
+    let answer = read(stream, deadline, stats_length).await?;
+    let answer = String::from_utf8_lossy(&answer);
+    let reply = first_line(answer.as_bytes());
+    if reply.ends_with("ERROR") || reply.contains("ERROR ") {
+        return Ok(Some((None, false, reply)));
+    }
+    if !reply.starts_with("STAT ") {
+        return Ok(None);
+    }
+    let version = answer
+        .lines()
+        .find_map(|line| line.strip_prefix("STAT version "))
+        .map(|version| version.trim().to_string());
+    Ok(Some((version, true, "stats: ok".to_string())))
+}
+
+// Function to tell how long the answer to `stats` at the start of `data` is: statistics up to
+// END, or a single line that is no statistic, e.g. an error.
+fn stats_length(data: &[u8]) -> Option<usize> {
+    if !data.starts_with(b"STAT ") {
+        return data
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .map(|end| end + 2);
+    }
+    data.windows(5)
+        .position(|w| w == b"END\r\n")
+        .filter(|&end| end == 0 || data[end - 1] == b'\n')
+        .map(|end| end + 5)
+}
+
+// Function to run isMaster, buildInfo and listDatabases on a MongoDB server. The first two
+// answer without a login everywhere; the last one needs it where authentication is on.
+async fn mongodb(
+    stream: &mut TcpStream,
+    deadline: Instant,
+) -> io::Result<Option<(Option<String>, bool, String)>> {
+    // The handshake goes over OP_QUERY, which every version takes.
+    stream
+        .write_all(&op_query(1, &document(&[("isMaster", 1)])))
+        .await?;
+    let Some(hello) = reply_document(&read(stream, deadline, message_length).await?) else {
+        return Ok(None);
+    };
+    let op_msg = hello.wire_version.unwrap_or(0) >= WIRE_VERSION_OP_MSG_ONLY;
+    let version = command(stream, deadline, op_msg, 2, "buildInfo")
+        .await?
+        .and_then(|reply| reply.version);
+    let Some(databases) = command(stream, deadline, op_msg, 3, "listDatabases").await? else {
+        return Ok(Some((
+            version,
+            false,
+            "listDatabases: no answer".to_string(),
+        )));
+    };
+    let reply = match &databases.error {
+        Some(error) => format!("listDatabases: {}", error),
+        None => "listDatabases: ok".to_string(),
+    };
+    Ok(Some((version, databases.ok, reply)))
+}
+
+// Function to run the MongoDB command `name` on the admin database, over OP_MSG if `op_msg` is
+// set and else over OP_QUERY.
+async fn command(
+    stream: &mut TcpStream,
+    deadline: Instant,
+    op_msg: bool,
+    id: i32,
+    name: &str,
+) -> io::Result<Option<Reply>> {
+    let message = match op_msg {
+        true => op_msg_command(id, name),
+        false => op_query(id, &document(&[(name, 1)])),
+    };
+    stream.write_all(&message).await?;
+    Ok(reply_document(
+        &read(stream, deadline, message_length).await?,
+    ))
+}
+
+// What a MongoDB command answered.
+#[derive(Debug, Default, PartialEq)]
+struct Reply {
+    ok: bool,
+    version: Option<String>,
+    wire_version: Option<i32>,
+    error: Option<String>,
+}
+
+// Function to build an OP_QUERY of the command `query` on the admin database.
+fn op_query(id: i32, query: &[u8]) -> Vec<u8> {
+    let mut body = 0i32.to_le_bytes().to_vec(); // No flags.
+    body.extend_from_slice(b"admin.$cmd\x00");
+    body.extend_from_slice(&0i32.to_le_bytes()); // Nothing skipped.
+    body.extend_from_slice(&(-1i32).to_le_bytes()); // One document back.
+    body.extend_from_slice(query);
+    message(id, OP_QUERY, &body)
+}
+
+// Function to build an OP_MSG running the command `name` on the admin database.
+fn op_msg_command(id: i32, name: &str) -> Vec<u8> {
+    let mut command = document(&[(name, 1)]);
+    // Insert $db: "admin" before the closing zero of the document.
+    command.pop();
+    command.push(0x02);
+    command.extend_from_slice(b"$db\x00");
+    command.extend_from_slice(&6i32.to_le_bytes());
+    command.extend_from_slice(b"admin\x00\x00");
+    let length = command.len() as i32;
+    command[..4].copy_from_slice(&length.to_le_bytes());
+    let mut body = 0u32.to_le_bytes().to_vec(); // No flags.
+    body.push(0); // The body section.
+    body.extend_from_slice(&command);
+    message(id, OP_MSG, &body)
+}
+
+// Function to frame `body` with the 16-byte header of a MongoDB message.
+fn message(id: i32, opcode: i32, body: &[u8]) -> Vec<u8> {
+    let mut message = ((16 + body.len()) as i32).to_le_bytes().to_vec();
+    message.extend_from_slice(&id.to_le_bytes());
+    message.extend_from_slice(&0i32.to_le_bytes()); // Not a response.
+    message.extend_from_slice(&opcode.to_le_bytes());
+    message.extend_from_slice(body);
+    message
+}
+
+// Function to encode a BSON document of 32-bit integers.
+fn document(fields: &[(&str, i32)]) -> Vec<u8> {
+    let mut elements = vec![];
+    for (name, value) in fields {
+        elements.push(0x10);
+        elements.extend_from_slice(name.as_bytes());
+        elements.push(0);
+        elements.extend_from_slice(&value.to_le_bytes());
+    }
+    let mut document = ((elements.len() + 5) as i32).to_le_bytes().to_vec();
+    document.extend_from_slice(&elements);
+    document.push(0);
+    document
+}
+
+// Function to tell how long the MongoDB message at the start of `data` is, from its header.
+fn message_length(data: &[u8]) -> Option<usize> {
+    let length = i32::from_le_bytes(data.get(..4)?.try_into().ok()?);
+    let length = usize::try_from(length)
+        .ok()
+        .filter(|&length| length >= 16)?;
+    (data.len() >= length).then_some(length)
+}
+
+// Function to read the document of an OP_REPLY or OP_MSG answer.
+fn reply_document(message: &[u8]) -> Option<Reply> {
+    let opcode = i32::from_le_bytes(message.get(12..16)?.try_into().ok()?);
+    let document = match opcode {
+        OP_REPLY => message.get(36..)?,
+        // The flags, then a body section of kind 0.
+        OP_MSG if *message.get(20)? == 0 => message.get(21..)?,
+        _ => return None,
+    };
+    let length = usize::try_from(i32::from_le_bytes(document.get(..4)?.try_into().ok()?)).ok()?;
+    let mut elements = document.get(4..length.checked_sub(1)?)?;
+    let mut reply = Reply::default();
+    while let Some((&kind, rest)) = elements.split_first() {
+        let end = rest.iter().position(|&b| b == 0)?;
+        let name = &rest[..end];
+        let value = &rest[end + 1..];
+        let size = match kind {
+            0x01 | 0x09 | 0x11 | 0x12 => 8,
+            0x02 | 0x0d | 0x0e => 4 + usize::try_from(i32_at(value)?).ok()?,
+            0x03 | 0x04 => usize::try_from(i32_at(value)?).ok()?,
+            0x05 => 5 + usize::try_from(i32_at(value)?).ok()?,
+            0x07 => 12,
+            0x08 => 1,
+            0x0a | 0x06 | 0x7f | 0xff => 0,
+            0x10 => 4,
+            0x13 => 16,
+            // Anything else cannot be skipped, so the fields read so far have to do.
+            _ => break,
+        };
+        let value = value.get(..size)?;
+        match (name, kind) {
+            (b"ok", 0x01) => reply.ok = f64::from_le_bytes(value.try_into().ok()?) == 1.0,
+            (b"ok", 0x10) => reply.ok = i32_at(value)? == 1,
+            (b"maxWireVersion", 0x10) => reply.wire_version = i32_at(value),
+            (b"version", 0x02) => reply.version = string(value),
+            (b"errmsg", 0x02) => reply.error = string(value),
+            _ => {}
+        }
+        elements = &rest[end + 1 + size..];
+    }
+    Some(reply)
+}
+
+// Function to read a little-endian i32 off the start of `data`.
+fn i32_at(data: &[u8]) -> Option<i32> {
+    Some(i32::from_le_bytes(data.get(..4)?.try_into().ok()?))
+}
+
+// Function to read a BSON string: its length with the closing zero, then the text.
+fn string(value: &[u8]) -> Option<String> {
+    let text = value.get(4..value.len().checked_sub(1)?)?;
+    Some(String::from_utf8_lossy(text).into_owned())
+}
+
+// Function to read from `stream` before `deadline` until `complete` says how long the answer
+// is, giving that much.
+async fn read(
+    stream: &mut TcpStream,
+    deadline: Instant,
+    complete: fn(&[u8]) -> Option<usize>,
+) -> io::Result<Vec<u8>> {
+    let mut received = vec![];
+    let mut buffer = [0u8; 4096];
+    loop {
+        if let Some(length) = received.first().and_then(|_| complete(&received)) {
+            received.truncate(length);
+            return Ok(received);
+        }
+        let read = timeout_at(deadline, stream.read(&mut buffer))
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        if read == 0 || received.len() > MAX_ANSWER {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        received.extend_from_slice(&buffer[..read]);
+    }
+}
+
+// Function to give the first line of `data`, printable only.
+fn first_line(data: &[u8]) -> String {
+    let line = data.split(|&b| b == b'\n').next().unwrap_or_default();
+    String::from_utf8_lossy(line)
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_answers() {
+        assert_eq!(resp_length(b"+PONG\r\n+"), Some(7));
+        assert_eq!(resp_length(b"$5\r\nhello\r\n"), Some(11));
+        assert_eq!(resp_length(b"$5\r\nhel"), None);
+        assert_eq!(resp_length(b"$-1\r\n"), Some(5));
+        let stats = b"STAT pid 1\r\nSTAT version 1.6.21\r\nEND\r\n";
+        assert_eq!(stats_length(stats), Some(stats.len()));
+        assert_eq!(stats_length(b"STAT pid 1\r\nSTAT"), None);
+        assert_eq!(stats_length(b"ERROR\r\n"), Some(7));
+    }
+
+    #[test]
+    fn builds_mongodb_commands() {
+        assert_eq!(
+            document(&[("isMaster", 1)]),
+            b"\x13\x00\x00\x00\x10isMaster\x00\x01\x00\x00\x00\x00"
+        );
+        let query = op_query(1, &document(&[("isMaster", 1)]));
+        assert_eq!(message_length(&query), Some(query.len()));
+        assert_eq!(&query[12..16], &OP_QUERY.to_le_bytes());
+        let msg = op_msg_command(2, "buildInfo");
+        let command = &msg[21..];
+        assert_eq!(i32_at(command), Some(command.len() as i32));
+        assert!(command.ends_with(b"$db\x00\x06\x00\x00\x00admin\x00\x00"));
+    }
+
+    #[test]
+    fn reads_mongodb_replies() {
+        let mut fields = vec![0x02];
+        fields.extend_from_slice(b"version\x00\x06\x00\x00\x004.4.6\x00");
+        fields.extend_from_slice(b"\x08isWritablePrimary\x00\x01");
+        fields.extend_from_slice(b"\x10maxWireVersion\x00\x09\x00\x00\x00");
+        fields.extend_from_slice(b"\x01ok\x00");
+        fields.extend_from_slice(&1f64.to_le_bytes());
+        let mut document = ((fields.len() + 5) as i32).to_le_bytes().to_vec();
+        document.extend_from_slice(&fields);
+        document.push(0);
+        let mut body = vec![0; 20]; // Flags, cursor, start and count.
+        body.extend_from_slice(&document);
+        let reply = message(7, OP_REPLY, &body);
+        assert_eq!(
+            reply_document(&reply),
+            Some(Reply {
+                ok: true,
+                version: Some("4.4.6".to_string()),
+                wire_version: Some(9),
+                error: None,
+            })
+        );
+        let mut body = vec![0; 5];
+        body.extend_from_slice(&document);
+        assert_eq!(
+            reply_document(&message(7, OP_MSG, &body)).map(|reply| reply.ok),
+            Some(true)
+        );
+        assert_eq!(reply_document(&reply[..reply.len() - 4]), None);
+    }
+}
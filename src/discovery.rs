@@ -0,0 +1,344 @@
+#[cfg(target_os = "linux")]
+use crate::arp::{self, Link}; // Import ARP requests for hosts on a local network.
+#[cfg(target_os = "linux")]
+use crate::ndp; // Import neighbor discovery for IPv6 hosts on a local network.
+#[cfg(target_os = "linux")]
+use crate::raw; // Import the route lookup to find the hosts on a local network.
+use crate::scanner::{self, Source}; // Import the TCP knocks and the source to send from.
+#[cfg(target_os = "linux")]
+use crate::targets::Mac; // Import the MAC addresses of hosts on a local network.
+use crate::targets::Target; // Import the hosts to discover.
+use futures::stream::{self, FuturesUnordered, StreamExt}; // Import streams to probe hosts concurrently.
+use futures::FutureExt; // Import `boxed` to race the probes of a host.
+#[cfg(target_os = "linux")]
+use rand::Rng; // Import `Rng` to draw the identifiers of echo requests.
+#[cfg(target_os = "linux")]
+use socket2::{Domain, Protocol, Socket, Type}; // Import socket2 to open ICMP sockets.
+#[cfg(target_os = "linux")]
+use std::collections::{HashMap, HashSet}; // Import maps and sets of the hosts found.
+#[cfg(target_os = "linux")]
+use std::io; // Import I/O errors for socket failures.
+#[cfg(target_os = "linux")]
+use std::mem::MaybeUninit; // Import `MaybeUninit` for the receive buffer.
+#[cfg(target_os = "linux")]
+use std::net::{IpAddr, SocketAddr}; // Import address types for the probed hosts.
+use std::time::Duration; // Import `Duration` for reply timeouts.
+#[cfg(target_os = "linux")]
+use tokio::io::unix::AsyncFd; // Import `AsyncFd` to wait for ICMP replies without blocking.
+#[cfg(target_os = "linux")]
+use tokio::time::timeout; // Import `timeout` to give up on silent hosts.
+
+// Define the TCP ports knocked on, as hosts that drop pings mostly still serve the web.
+const KNOCK_PORTS: [u16; 2] = [443, 80];
+
+// Define how many sockets the discovery of one host takes at most: an ICMP socket and a knock
+// on each port.
+const SOCKETS_PER_HOST: usize = 1 + KNOCK_PORTS.len();
+
+// Function to find out which of `hosts` are up, keeping their order. A multicast IPv6 target
+// such as ff02::1%eth0 stands for the hosts that answer a ping sent to it. Hosts on a local
+// network are up if they answer an ARP request or IPv6 neighbor solicitation, which they do
+// even when they drop everything else; their MAC address is kept. Other hosts are up if they
+// answer an ICMP echo request or a TCP connect to one of `KNOCK_PORTS`, be it accepted or
+// refused. Each probe waits at most `wait`, and about `concurrency` sockets are open at a time.
+// ARP, neighbor discovery and pings go through the raw sockets of Linux; elsewhere hosts are
+// only knocked on.
+pub async fn discover(
+    hosts: Vec<Target>,
+    source: &Source,
+    wait: Duration,
+    concurrency: usize,
+) -> Vec<Target> {
+    // Each host, with whether it is up once that is known.
+    #[cfg(target_os = "linux")]
+    let mut hosts = expand_groups(hosts, wait).await;
+    #[cfg(not(target_os = "linux"))]
+    let mut hosts: Vec<(Target, Option<bool>)> =
+        hosts.into_iter().map(|target| (target, None)).collect();
+    #[cfg(target_os = "linux")]
+    ask_neighbors(&mut hosts, source, wait).await;
+
+    let others: Vec<usize> = (0..hosts.len())
+        .filter(|&host| hosts[host].1.is_none())
+        .collect();
+    #[cfg(target_os = "linux")]
+    if !others.is_empty() && icmp_socket(Domain::IPV4).is_err() {
+        eprintln!(
+            "Warning: --ping-first cannot send ICMP echo requests without root, the CAP_NET_RAW \
+             capability or a group in net.ipv4.ping_group_range, so it only knocks on TCP ports"
+        );
+    }
+    #[cfg(not(target_os = "linux"))]
+    if !others.is_empty() {
+        eprintln!(
+            "Warning: --ping-first only sends ICMP echo requests on Linux, so it only knocks on \
+             TCP ports"
+        );
+    }
+    let answers: Vec<bool> = stream::iter(&others)
+        .map(|&host| is_up(&hosts[host].0, source, wait))
+        .buffered((concurrency / SOCKETS_PER_HOST).max(1))
+        .collect()
+        .await;
+    for (host, answer) in others.into_iter().zip(answers) {
+        hosts[host].1 = Some(answer);
+    }
+    hosts
+        .into_iter()
+        .filter_map(|(target, up)| (up == Some(true)).then_some(target))
+        .collect()
+}
+
+// Function to ask the hosts on a local network whether they are up over ARP or neighbor
+// discovery, keeping the MAC address of those that answer. Hosts elsewhere are left unknown.
+#[cfg(target_os = "linux")]
+async fn ask_neighbors(hosts: &mut [(Target, Option<bool>)], source: &Source, wait: Duration) {
+    let links = arp::links().unwrap_or_default();
+    for link in &links {
+        let on_link: Vec<usize> = (0..hosts.len())
+            .filter(|&host| hosts[host].1.is_none())
+            .filter(|&host| {
+                let target = &hosts[host].0;
+                raw::local_addr(source, target.socket_addr(9))
+                    .is_ok_and(|local| link.reaches(local, target.addr))
+            })
+            .collect();
+        if on_link.is_empty() {
+            continue;
+        }
+        let ips: Vec<IpAddr> = on_link.iter().map(|&host| hosts[host].0.addr).collect();
+        match neighbors(link, &ips, wait).await {
+            Ok(found) => {
+                for host in on_link {
+                    let (target, up) = &mut hosts[host];
+                    let answer = found.get(&target.addr);
+                    target.mac = answer.copied().flatten();
+                    *up = Some(answer.is_some());
+                }
+            }
+            Err(e) => eprintln!(
+                "Warning: --ping-first cannot send {} on {} ({}), so it pings the hosts there \
+                 instead",
+                match link.addr {
+                    IpAddr::V4(_) => "ARP requests",
+                    IpAddr::V6(_) => "neighbor solicitations",
+                },
+                link.name,
+                e
+            ),
+        }
+    }
+}
+
+// Function to replace multicast IPv6 targets by the hosts that answer a ping to them, which
+// are up, skipping those already among the targets.
+#[cfg(target_os = "linux")]
+async fn expand_groups(hosts: Vec<Target>, wait: Duration) -> Vec<(Target, Option<bool>)> {
+    let mut seen: HashSet<(IpAddr, u32)> = hosts
+        .iter()
+        .map(|target| (target.addr, target.scope_id))
+        .collect();
+    let mut expanded = vec![];
+    for target in hosts {
+        let IpAddr::V6(group) = target.addr else {
+            expanded.push((target, None));
+            continue;
+        };
+        if !group.is_multicast() {
+            expanded.push((target, None));
+            continue;
+        }
+        match ndp::ping_group(group, target.scope_id, wait).await {
+            Ok(members) => expanded.extend(
+                members
+                    .into_iter()
+                    .filter(|&addr| seen.insert((addr, target.scope_id)))
+                    .map(|addr| {
+                        let member = Target {
+                            addr,
+                            ..target.clone()
+                        };
+                        (member, Some(true))
+                    }),
+            ),
+            Err(e) => eprintln!("Warning: --ping-first cannot ping {} ({})", target, e),
+        }
+    }
+    expanded
+}
+
+// Function to ask the hosts at `ips` on `link` for their MAC address, over ARP or neighbor
+// discovery depending on the family of the network. Gives the hosts that answered.
+#[cfg(target_os = "linux")]
+async fn neighbors(
+    link: &Link,
+    ips: &[IpAddr],
+    wait: Duration,
+) -> io::Result<HashMap<IpAddr, Option<Mac>>> {
+    match link.addr {
+        IpAddr::V4(_) => {
+            let v4: Vec<_> = ips
+                .iter()
+                .filter_map(|ip| match ip {
+                    IpAddr::V4(ip) => Some(*ip),
+                    IpAddr::V6(_) => None,
+                })
+                .collect();
+            let found = arp::sweep(link, &v4, wait).await?;
+            Ok(found
+                .into_iter()
+                .map(|(ip, mac)| (IpAddr::V4(ip), Some(mac)))
+                .collect())
+        }
+        IpAddr::V6(_) => {
+            let v6: Vec<_> = ips
+                .iter()
+                .filter_map(|ip| match ip {
+                    IpAddr::V6(ip) => Some(*ip),
+                    IpAddr::V4(_) => None,
+                })
+                .collect();
+            let found = ndp::solicit(link, &v6, wait).await?;
+            Ok(found
+                .into_iter()
+                .map(|(ip, mac)| (IpAddr::V6(ip), mac))
+                .collect())
+        }
+    }
+}
+
+// Function to probe every address of a host at once, stopping at the first sign of life.
+async fn is_up(target: &Target, source: &Source, wait: Duration) -> bool {
+    let probes = FuturesUnordered::new();
+    for addr in std::iter::once(target.socket_addr(0)).chain(target.fallback_addr(0)) {
+        #[cfg(target_os = "linux")]
+        probes.push(async move { ping(addr, source, wait).await.unwrap_or(false) }.boxed());
+        for port in KNOCK_PORTS {
+            let mut knock = addr;
+            knock.set_port(port);
+            probes.push(scanner::answers(knock, wait, source).boxed());
+        }
+    }
+    probes.any(|up| async move { up }).await
+}
+
+// Function to open a socket to ping from: a raw ICMP socket if allowed, or else one of the
+// unprivileged ICMP sockets Linux offers to the groups in net.ipv4.ping_group_range. Tells
+// which of the two it is, as only raw sockets check the identifier of the replies.
+#[cfg(target_os = "linux")]
+fn icmp_socket(domain: Domain) -> io::Result<(Socket, bool)> {
+    let protocol = match domain {
+        Domain::IPV6 => Protocol::ICMPV6,
+        _ => Protocol::ICMPV4,
+    };
+    match Socket::new(domain, Type::RAW, Some(protocol)) {
+        Ok(socket) => Ok((socket, true)),
+        Err(_) => Ok((Socket::new(domain, Type::DGRAM, Some(protocol))?, false)),
+    }
+}
+
+// Function to send an ICMP (or ICMPv6) echo request to `addr` and wait at most `wait` for its
+// reply.
+#[cfg(target_os = "linux")]
+async fn ping(addr: SocketAddr, source: &Source, wait: Duration) -> io::Result<bool> {
+    let (socket, raw) = icmp_socket(Domain::for_address(addr))?;
+    source.bind_to(&socket, addr.ip())?;
+    socket.set_nonblocking(true)?;
+    // A connected socket only gets the packets of `addr`.
+    socket.connect(&addr.into())?;
+    let socket = AsyncFd::new(socket)?;
+
+    // Unprivileged sockets put in an identifier of their own, and only hand back its replies.
+    let id = rand::thread_rng().gen::<u16>();
+    socket.get_ref().send(&echo_request(addr.ip(), id))?;
+    let reply = async {
+        let mut buffer = [MaybeUninit::<u8>::uninit(); 1500];
+        loop {
+            let mut ready = socket.readable().await?;
+            let Ok(length) = ready.try_io(|socket| socket.get_ref().recv(&mut buffer)) else {
+                continue;
+            };
+            // SAFETY: `recv` initialized the first `length` bytes.
+            let packet =
+                unsafe { std::slice::from_raw_parts(buffer.as_ptr().cast::<u8>(), length?) };
+            if is_echo_reply(packet, addr.ip(), raw.then_some(id)) {
+                return Ok(true);
+            }
+        }
+    };
+    timeout(wait, reply).await.unwrap_or(Ok(false))
+}
+
+// Function to build an echo request to `to` with the given identifier and a sequence number of
+// 1. The kernel fills in the checksum of ICMPv6 messages, as it covers the addresses.
+#[cfg(target_os = "linux")]
+pub fn echo_request(to: IpAddr, id: u16) -> Vec<u8> {
+    let kind = match to {
+        IpAddr::V4(_) => 8,
+        IpAddr::V6(_) => 128,
+    };
+    let mut echo = vec![kind, 0, 0, 0];
+    echo.extend_from_slice(&id.to_be_bytes());
+    echo.extend_from_slice(&[0, 1]);
+    echo.extend_from_slice(b"port-sniffer");
+    if to.is_ipv4() {
+        let sum = raw::checksum(&[&echo]);
+        echo[2..4].copy_from_slice(&sum.to_be_bytes());
+    }
+    echo
+}
+
+// Function to check whether a packet from `from` is an echo reply, with the identifier `id` if
+// given. Raw IPv4 sockets (which check the identifier) deliver the IP header in front of it.
+#[cfg(target_os = "linux")]
+pub fn is_echo_reply(packet: &[u8], from: IpAddr, id: Option<u16>) -> bool {
+    let (icmp, reply) = match (from, id) {
+        (IpAddr::V4(_), Some(_)) => (
+            packet
+                .first()
+                .and_then(|first| packet.get(usize::from(first & 0x0f) * 4..)),
+            0,
+        ),
+        (IpAddr::V4(_), None) => (Some(packet), 0),
+        (IpAddr::V6(_), _) => (Some(packet), 129),
+    };
+    match (icmp, id) {
+        (Some([kind, _, _, _, high, low, ..]), Some(id)) => {
+            *kind == reply && u16::from_be_bytes([*high, *low]) == id
+        }
+        (Some([kind, ..]), None) => *kind == reply,
+        _ => false,
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_echo_replies() {
+        let v4 = IpAddr::from([10, 0, 0, 2]);
+        let mut reply = echo_request(v4, 0x1234);
+        reply[0] = 0;
+        assert!(is_echo_reply(&reply, v4, None));
+        let mut packet = vec![
+            0x45, 0, 0, 0, 0, 0, 0, 0, 64, 1, 0, 0, 10, 0, 0, 2, 10, 0, 0, 1,
+        ];
+        packet.extend_from_slice(&reply);
+        assert!(is_echo_reply(&packet, v4, Some(0x1234)));
+        assert!(!is_echo_reply(&packet, v4, Some(0x4321)));
+        // The request itself, or an unreachable, is no reply.
+        assert!(!is_echo_reply(&echo_request(v4, 0x1234), v4, None));
+        assert!(!is_echo_reply(&[3, 1, 0, 0], v4, None));
+        assert!(!is_echo_reply(&[], v4, None));
+
+        // ICMPv6 sockets never deliver the IP header.
+        let v6 = IpAddr::from([0xfd00, 0, 0, 0, 0, 0, 0, 1]);
+        let mut reply = echo_request(v6, 7);
+        assert!(!is_echo_reply(&reply, v6, Some(7)));
+        reply[0] = 129;
+        assert!(is_echo_reply(&reply, v6, Some(7)));
+        assert!(!is_echo_reply(&reply, v6, Some(8)));
+        assert!(is_echo_reply(&reply, v6, None));
+    }
+}
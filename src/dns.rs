@@ -0,0 +1,482 @@
+use std::io; // Import I/O types for socket errors.
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr}; // Import IP address types for DNS records.
+use std::time::Duration; // Import `Duration` for query timeouts.
+use tokio::io::{AsyncReadExt, AsyncWriteExt}; // Import async read/write helpers for DNS over TCP.
+use tokio::net::{TcpStream, UdpSocket}; // Import sockets to talk to DNS servers.
+use tokio::time::timeout; // Import `timeout` to give up on silent servers.
+
+// Record types used by the scanner.
+pub const TYPE_A: u16 = 1;
+pub const TYPE_NS: u16 = 2;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_SRV: u16 = 33;
+
+// Record classes used by the scanner.
+pub const CLASS_IN: u16 = 1;
+pub const CLASS_CH: u16 = 3;
+
+// How long to wait for a DNS server to answer a single query.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+// The data part of a resource record, for the types the scanner understands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RData {
+    Ip(IpAddr),
+    Name(String),
+    // The strings of a TXT record, put together.
+    Text(String),
+    // The port and host of an SRV record.
+    Service { port: u16, target: String },
+    Other(Vec<u8>),
+}
+
+// A resource record of a response.
+#[derive(Debug, Clone)]
+pub struct Record {
+    // The name the record is about.
+    pub name: String,
+    pub data: RData,
+}
+
+// A parsed DNS response.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub id: u16,
+    pub flags: u16,
+    pub answers: Vec<Record>,
+    // The records of the authority and additional sections, where mDNS responders put the SRV
+    // and address records of what they answer.
+    pub additionals: Vec<Record>,
+}
+
+impl Message {
+    // Function to get the response code (0 = no error, 3 = name does not exist, 5 = refused, ...).
+    pub fn rcode(&self) -> u8 {
+        (self.flags & 0x000f) as u8
+    }
+
+    // Function to check whether the response was truncated and must be retried over TCP.
+    pub fn truncated(&self) -> bool {
+        self.flags & 0x0200 != 0
+    }
+
+    // Function to check whether the server offers to resolve names recursively.
+    pub fn recursion_available(&self) -> bool {
+        self.flags & 0x0080 != 0
+    }
+
+    // Function to name the response code, e.g. `NOERROR` or `REFUSED`.
+    pub fn rcode_name(&self) -> String {
+        match self.rcode() {
+            0 => "NOERROR".to_string(),
+            1 => "FORMERR".to_string(),
+            2 => "SERVFAIL".to_string(),
+            3 => "NXDOMAIN".to_string(),
+            4 => "NOTIMP".to_string(),
+            5 => "REFUSED".to_string(),
+            code => format!("RCODE{}", code),
+        }
+    }
+}
+
+// Function to build a query for `name`, asking recursive servers to resolve it fully when `recurse` is set.
+pub fn build_query(id: u16, name: &str, rtype: u16, class: u16, recurse: bool) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(32 + name.len());
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&(if recurse { 0x0100u16 } else { 0 }).to_be_bytes());
+    packet.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]); // One question, no other records.
+    encode_name(&mut packet, name);
+    packet.extend_from_slice(&rtype.to_be_bytes());
+    packet.extend_from_slice(&class.to_be_bytes());
+    packet
+}
+
+// Function to append a domain name in wire format (length-prefixed labels).
+pub fn encode_name(packet: &mut Vec<u8>, name: &str) {
+    for label in name
+        .trim_end_matches('.')
+        .split('.')
+        .filter(|l| !l.is_empty())
+    {
+        let label = &label.as_bytes()[..label.len().min(63)];
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label);
+    }
+    packet.push(0);
+}
+
+// Function to build the name used for reverse (PTR) lookups of an address,
+// e.g. `4.3.2.1.in-addr.arpa` for 1.2.3.4.
+pub fn reverse_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, d] = v4.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        IpAddr::V6(v6) => {
+            // One label per nibble, least significant first.
+            let mut name = String::with_capacity(72);
+            for byte in v6.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0x0f, byte >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+// Function to read a big-endian u16 at `pos`.
+fn read_u16(buf: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*buf.get(pos)?, *buf.get(pos + 1)?]))
+}
+
+// Function to read a possibly compressed domain name at `pos`, returning it and the position after it.
+pub fn read_name(buf: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = vec![];
+    let mut end = None;
+    // Bound the number of compression jumps so a malicious packet cannot loop forever.
+    for _ in 0..128 {
+        let len = *buf.get(pos)? as usize;
+        match len {
+            0 => {
+                return Some((labels.join("."), end.unwrap_or(pos + 1)));
+            }
+            // A compression pointer to an earlier name.
+            l if l & 0xc0 == 0xc0 => {
+                let target = (read_u16(buf, pos)? & 0x3fff) as usize;
+                end.get_or_insert(pos + 2);
+                pos = target;
+            }
+            l => {
+                let label = buf.get(pos + 1..pos + 1 + l)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + l;
+            }
+        }
+    }
+    None
+}
+
+// Function to parse a DNS response packet.
+pub fn parse_message(buf: &[u8]) -> Option<Message> {
+    let id = read_u16(buf, 0)?;
+    let flags = read_u16(buf, 2)?;
+    let questions = read_u16(buf, 4)?;
+    let answers = read_u16(buf, 6)?;
+    let mut pos = 12;
+
+    // Skip the echoed questions.
+    for _ in 0..questions {
+        let (_, next) = read_name(buf, pos)?;
+        pos = next + 4;
+    }
+
+    let (answers, pos) = read_records(buf, pos, answers)?;
+    // The authority and additional sections only matter to multicast DNS, so malformed ones do
+    // not spoil the answers.
+    let others = read_u16(buf, 8)?.saturating_add(read_u16(buf, 10)?);
+    let additionals = read_records(buf, pos, others)
+        .map(|(records, _)| records)
+        .unwrap_or_default();
+
+    Some(Message {
+        id,
+        flags,
+        answers,
+        additionals,
+    })
+}
+
+// Function to read `count` resource records starting at `pos`, returning them and the position after them.
+fn read_records(buf: &[u8], mut pos: usize, count: u16) -> Option<(Vec<Record>, usize)> {
+    let mut records = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let (name, next) = read_name(buf, pos)?;
+        let rtype = read_u16(buf, next)?;
+        let len = read_u16(buf, next + 8)? as usize;
+        let start = next + 10;
+        let raw = buf.get(start..start + len)?;
+
+        let data = match (rtype, len) {
+            (TYPE_A, 4) => RData::Ip(IpAddr::V4(Ipv4Addr::new(raw[0], raw[1], raw[2], raw[3]))),
+            (TYPE_AAAA, 16) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(raw);
+                RData::Ip(IpAddr::V6(Ipv6Addr::from(octets)))
+            }
+            (TYPE_PTR, _) | (5, _) => RData::Name(read_name(buf, start)?.0), // PTR and CNAME.
+            (TYPE_TXT, _) => RData::Text(text(raw)?),
+            // Priority and weight come before the port.
+            (TYPE_SRV, 7..) => RData::Service {
+                port: read_u16(raw, 4)?,
+                target: read_name(buf, start + 6)?.0,
+            },
+            _ => RData::Other(raw.to_vec()),
+        };
+        records.push(Record { name, data });
+        pos = start + len;
+    }
+    Some((records, pos))
+}
+
+// Function to put the length-prefixed strings of a TXT record together.
+fn text(mut raw: &[u8]) -> Option<String> {
+    let mut text = String::new();
+    while let Some((&len, rest)) = raw.split_first() {
+        text.push_str(&String::from_utf8_lossy(rest.get(..len as usize)?));
+        raw = &rest[len as usize..];
+    }
+    Some(text)
+}
+
+// Function to send a query to a DNS server over UDP, retrying over TCP when the answer is truncated.
+pub async fn query(server: SocketAddr, name: &str, rtype: u16, class: u16) -> io::Result<Message> {
+    let id: u16 = rand::random();
+    let packet = build_query(id, name, rtype, class, true);
+
+    let bind: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(server).await?;
+    socket.send(&packet).await?;
+
+    let mut buf = vec![0u8; 4096];
+    loop {
+        let len = timeout(QUERY_TIMEOUT, socket.recv(&mut buf))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "DNS query timed out"))??;
+        // Ignore stray packets that do not answer this query.
+        match parse_message(&buf[..len]) {
+            Some(message) if message.id == id && message.truncated() => {
+                return query_tcp(server, &packet).await;
+            }
+            Some(message) if message.id == id => return Ok(message),
+            _ => continue,
+        }
+    }
+}
+
+// Function to send an already built query over TCP (two-byte length prefix framing).
+pub async fn query_tcp(server: SocketAddr, packet: &[u8]) -> io::Result<Message> {
+    let exchange = async {
+        let mut stream = TcpStream::connect(server).await?;
+        let mut framed = (packet.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(packet);
+        stream.write_all(&framed).await?;
+
+        let mut len = [0u8; 2];
+        stream.read_exact(&mut len).await?;
+        let mut buf = vec![0u8; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut buf).await?;
+        parse_message(&buf)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed DNS response"))
+    };
+    timeout(QUERY_TIMEOUT, exchange)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "DNS query timed out"))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Function to build a response header with the given flags and record counts.
+    fn header(id: u16, flags: u16, questions: u16, answers: u16) -> Vec<u8> {
+        let mut packet = vec![];
+        for value in [id, flags, questions, answers, 0, 0] {
+            packet.extend_from_slice(&value.to_be_bytes());
+        }
+        packet
+    }
+
+    // Function to append an answer record whose name points back at the question (offset 12).
+    fn answer(packet: &mut Vec<u8>, rtype: u16, data: &[u8]) {
+        packet.extend_from_slice(&[0xc0, 12]);
+        packet.extend_from_slice(&rtype.to_be_bytes());
+        packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+        packet.extend_from_slice(&60u32.to_be_bytes());
+        packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        packet.extend_from_slice(data);
+    }
+
+    // Function to build a response to a question for `example.com` with the given answers.
+    fn response(answers: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut packet = header(7, 0x8180, 1, answers.len() as u16);
+        encode_name(&mut packet, "example.com");
+        packet.extend_from_slice(&TYPE_A.to_be_bytes());
+        packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+        for (rtype, data) in answers {
+            answer(&mut packet, *rtype, data);
+        }
+        packet
+    }
+
+    #[test]
+    fn query_layout() {
+        let packet = build_query(0x1234, "example.com.", TYPE_AAAA, CLASS_IN, true);
+        assert_eq!(&packet[..4], &[0x12, 0x34, 0x01, 0x00]);
+        assert_eq!(&packet[12..25], b"\x07example\x03com\x00");
+        assert_eq!(&packet[25..], &[0, 28, 0, 1]);
+    }
+
+    #[test]
+    fn reverse_names() {
+        assert_eq!(
+            reverse_name("1.2.3.4".parse().unwrap()),
+            "4.3.2.1.in-addr.arpa"
+        );
+        let v6 = reverse_name("2001:db8::1".parse().unwrap());
+        assert!(v6.starts_with("1.0.0.0.0.0.0.0."));
+        assert!(v6.ends_with("8.b.d.0.1.0.0.2.ip6.arpa"));
+    }
+
+    #[test]
+    fn parses_addresses_and_names() {
+        let mut ptr = vec![];
+        encode_name(&mut ptr, "host.example");
+        let v6 = "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets();
+        let packet = response(&[(TYPE_A, &[10, 0, 0, 1]), (TYPE_AAAA, &v6), (TYPE_PTR, &ptr)]);
+
+        let message = parse_message(&packet).unwrap();
+        assert_eq!(message.id, 7);
+        assert_eq!(message.rcode(), 0);
+        assert!(!message.truncated());
+        let data: Vec<RData> = message.answers.into_iter().map(|r| r.data).collect();
+        assert_eq!(
+            data,
+            vec![
+                RData::Ip("10.0.0.1".parse().unwrap()),
+                RData::Ip("2001:db8::1".parse().unwrap()),
+                RData::Name("host.example".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn compressed_names() {
+        let packet = response(&[]);
+        // The question name itself, then a pointer to it.
+        assert_eq!(
+            read_name(&packet, 12),
+            Some(("example.com".to_string(), 25))
+        );
+        let mut with_pointer = packet.clone();
+        with_pointer.extend_from_slice(&[3, b'w', b'w', b'w', 0xc0, 12]);
+        assert_eq!(
+            read_name(&with_pointer, packet.len()),
+            Some(("www.example.com".to_string(), packet.len() + 6))
+        );
+    }
+
+    #[test]
+    fn pointer_loops_are_rejected() {
+        // A pointer to itself, and two pointers to each other.
+        let mut packet = header(1, 0x8180, 0, 0);
+        packet.extend_from_slice(&[0xc0, 12]);
+        assert_eq!(read_name(&packet, 12), None);
+
+        let mut packet = header(1, 0x8180, 0, 0);
+        packet.extend_from_slice(&[1, b'a', 0xc0, 16, 1, b'b', 0xc0, 12]);
+        assert_eq!(read_name(&packet, 12), None);
+    }
+
+    #[test]
+    fn pointers_out_of_bounds_are_rejected() {
+        let mut packet = header(1, 0x8180, 0, 0);
+        packet.extend_from_slice(&[0xc0, 0xff]);
+        assert_eq!(read_name(&packet, 12), None);
+        // A pointer cut off after its first byte.
+        let mut packet = header(1, 0x8180, 0, 0);
+        packet.push(0xc0);
+        assert_eq!(read_name(&packet, 12), None);
+    }
+
+    #[test]
+    fn truncated_packets_are_rejected() {
+        let packet = response(&[(TYPE_A, &[10, 0, 0, 1])]);
+        // Every strict prefix is missing part of the header, question or answer.
+        for len in 0..packet.len() {
+            assert!(parse_message(&packet[..len]).is_none(), "length {}", len);
+        }
+        assert!(parse_message(&packet).is_some());
+    }
+
+    #[test]
+    fn label_running_past_the_end_is_rejected() {
+        let mut packet = header(1, 0x8180, 0, 0);
+        packet.extend_from_slice(&[10, b'a', b'b']);
+        assert_eq!(read_name(&packet, 12), None);
+    }
+
+    #[test]
+    fn odd_record_lengths_are_kept_raw() {
+        // An A record must be 4 bytes; anything else is not an address.
+        let packet = response(&[(TYPE_A, &[10, 0, 0])]);
+        let message = parse_message(&packet).unwrap();
+        assert_eq!(message.answers[0].data, RData::Other(vec![10, 0, 0]));
+    }
+
+    #[test]
+    fn record_length_past_the_end_is_rejected() {
+        let mut packet = response(&[(TYPE_A, &[10, 0, 0, 1])]);
+        // Claim 200 bytes of data where there are only 4.
+        let len_at = packet.len() - 6;
+        packet[len_at..len_at + 2].copy_from_slice(&200u16.to_be_bytes());
+        assert!(parse_message(&packet).is_none());
+    }
+
+    #[test]
+    fn parses_text() {
+        let packet = response(&[(TYPE_TXT, b"\x069.18.2\x014")]);
+        let message = parse_message(&packet).unwrap();
+        assert_eq!(message.answers[0].data, RData::Text("9.18.24".to_string()));
+        let packet = response(&[(TYPE_TXT, b"\x099.18")]);
+        assert!(parse_message(&packet).is_none());
+    }
+
+    #[test]
+    fn flags() {
+        let message = parse_message(&header(1, 0x8383, 0, 0)).unwrap();
+        assert_eq!(message.rcode(), 3);
+        assert_eq!(message.rcode_name(), "NXDOMAIN");
+        assert!(message.truncated());
+        assert!(message.recursion_available());
+        assert!(!parse_message(&header(1, 0x8105, 0, 0))
+            .unwrap()
+            .recursion_available());
+    }
+
+    #[test]
+    fn parses_additional_services() {
+        // An SRV record for port 631 on printer.local, then the same answer counted as an
+        // additional record.
+        let mut srv = vec![0, 0, 0, 0, 0x02, 0x77];
+        encode_name(&mut srv, "printer.local");
+        let mut packet = response(&[(TYPE_SRV, &srv), (TYPE_A, &[192, 168, 1, 30])]);
+        packet[6..8].copy_from_slice(&1u16.to_be_bytes());
+        packet[10..12].copy_from_slice(&1u16.to_be_bytes());
+
+        let message = parse_message(&packet).unwrap();
+        assert_eq!(message.answers[0].name, "example.com");
+        assert_eq!(
+            message.answers[0].data,
+            RData::Service {
+                port: 631,
+                target: "printer.local".to_string(),
+            }
+        );
+        assert_eq!(
+            message.additionals[0].data,
+            RData::Ip("192.168.1.30".parse().unwrap())
+        );
+        // A broken additional section leaves the answers alone.
+        packet[10..12].copy_from_slice(&2u16.to_be_bytes());
+        let message = parse_message(&packet).unwrap();
+        assert_eq!(message.answers.len(), 1);
+        assert!(message.additionals.is_empty());
+    }
+}
@@ -0,0 +1,155 @@
+use crate::scanner::{self, PortResult, PortState, Protocol, Settings}; // Import the open ports to log in to and the scan settings to connect with.
+use crate::targets::Target; // Import the hosts the ports belong to.
+use serde::Serialize; // Import `Serialize` to report the logins in the structured output.
+use socket2::Type; // Import socket types for the connects.
+use std::io; // Import I/O errors for socket failures.
+use std::net::SocketAddr; // Import `SocketAddr` for the ports tried.
+use std::time::Duration; // Import `Duration` for the connect and read timeouts.
+use tokio::io::{AsyncReadExt, AsyncWriteExt}; // Import reads and writes on the connected streams.
+use tokio::net::{TcpSocket, TcpStream}; // Import sockets of the source for the connects.
+use tokio::time::{timeout_at, Instant}; // Import deadlines to give up on slow servers.
+
+// Define how long a reply may get before the server is given up on.
+const MAX_REPLY: usize = 8 * 1024;
+
+// What an anonymous login to an FTP server gave.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Ftp {
+    // Set when the server logged the anonymous user in.
+    pub anonymous: bool,
+    // The last line of its answer to the login, e.g. `230 Login successful.`.
+    pub reply: String,
+}
+
+// Function to try an anonymous login on every open TCP port in `results` that may run FTP:
+// port 21, ports --service-version named ftp and ports whose banner is an FTP greeting. Each
+// gets `wait` for the connect and the whole login together. No file is listed or transferred.
+pub async fn login_all(
+    hosts: &[Target],
+    results: &mut [PortResult],
+    settings: &Settings,
+    wait: Duration,
+) {
+    scanner::check_ports(
+        hosts,
+        results,
+        settings.concurrency,
+        |r, _| {
+            let ftp = r.port == 21
+                || r.findings
+                    .detected
+                    .as_ref()
+                    .is_some_and(|s| s.name == "ftp")
+                || r.findings.banner.as_ref().is_some_and(|b| {
+                    b.starts_with("220") && b.to_ascii_lowercase().contains("ftp")
+                });
+            (r.protocol == Protocol::Tcp && r.state == PortState::Open && ftp).then_some(())
+        },
+        |addr, ()| async move { login(addr, settings, wait).await.ok().flatten() },
+        |r, ftp| r.findings.ftp = ftp,
+    )
+    .await;
+}
+
+// Function to connect to `addr`, wait for the greeting and log in as `anonymous`, within `wait`.
+// Gives `None` if the port does not greet like an FTP server.
+async fn login(addr: SocketAddr, settings: &Settings, wait: Duration) -> io::Result<Option<Ftp>> {
+    let deadline = Instant::now() + wait;
+    let socket = TcpSocket::from_std_stream(settings.socket(addr, Type::STREAM).await?.into());
+    let mut stream = timeout_at(deadline, socket.connect(addr))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    let mut received = vec![];
+    let (code, _) = answer(&mut stream, &mut received, deadline).await?;
+    if code != 220 {
+        return Ok(None);
+    }
+    stream.write_all(b"USER anonymous\r\n").await?;
+    let (mut code, mut lines) = answer(&mut stream, &mut received, deadline).await?;
+    // 331 asks for a password; 230 already logged the user in.
+    if code == 331 {
+        stream.write_all(b"PASS anonymous@\r\n").await?;
+        (code, lines) = answer(&mut stream, &mut received, deadline).await?;
+    }
+    // Leave politely; the answer does not matter.
+    let _ = stream.write_all(b"QUIT\r\n").await;
+    Ok(Some(Ftp {
+        anonymous: code == 230,
+        reply: lines.pop().unwrap_or_default(),
+    }))
+}
+
+// Function to read the next reply of the server, taking it off `received`. Gives its code and
+// lines. SMTP servers answer the same way.
+pub async fn answer(
+    stream: &mut TcpStream,
+    received: &mut Vec<u8>,
+    deadline: Instant,
+) -> io::Result<(u16, Vec<String>)> {
+    let mut buffer = [0u8; 4096];
+    loop {
+        if let Some((code, lines, used)) = reply(received) {
+            received.drain(..used);
+            return Ok((code, lines));
+        }
+        let read = timeout_at(deadline, stream.read(&mut buffer))
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        if read == 0 || received.len() > MAX_REPLY {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        received.extend_from_slice(&buffer[..read]);
+    }
+}
+
+// Function to read a complete reply off the start of `received` (RFC 959): a line starting with
+// a 3-digit code and a space, or a first line with the code and a `-` and further lines up to
+// one starting with the same code and a space. Gives the code, the lines, printable only, and
+// how many bytes it took, or `None` while it is incomplete or no FTP reply.
+fn reply(received: &[u8]) -> Option<(u16, Vec<String>, usize)> {
+    let code = std::str::from_utf8(received.get(..3)?).ok()?;
+    let number: u16 = code.parse().ok()?;
+    let multiline = *received.get(3)? == b'-';
+    let mut lines = vec![];
+    let mut start = 0;
+    while let Some(end) = received[start..].iter().position(|&b| b == b'\n') {
+        let line = &received[start..start + end];
+        let text: String = String::from_utf8_lossy(line)
+            .chars()
+            .filter(|c| !c.is_control())
+            .collect();
+        lines.push(text.trim().to_string());
+        if !multiline || (line.starts_with(code.as_bytes()) && line.get(3) == Some(&b' ')) {
+            return Some((number, lines, start + end + 1));
+        }
+        start += end + 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_replies() {
+        assert_eq!(
+            reply(b"220 (vsFTPd 3.0.5)\r\n331"),
+            Some((220, vec!["220 (vsFTPd 3.0.5)".to_string()], 20))
+        );
+        let welcome = b"220-Welcome\r\n 220 users online\r\n220 ProFTPD Server ready.\r\n";
+        let (code, lines, used) = reply(welcome).unwrap();
+        assert_eq!((code, used), (220, welcome.len()));
+        assert_eq!(
+            lines,
+            [
+                "220-Welcome",
+                "220 users online",
+                "220 ProFTPD Server ready."
+            ]
+        );
+        assert_eq!(reply(b"220-Welcome\r\n"), None);
+        assert_eq!(reply(b"230 Login"), None);
+        assert_eq!(reply(b"SSH-2.0-OpenSSH_9.6\r\n"), None);
+    }
+}
@@ -0,0 +1,431 @@
+use crate::hello::{self, Reply, ServerHello}; // Import hand-built handshakes for the versions rustls does not speak.
+use crate::md5; // Import MD5 to hash JA3S fingerprints.
+use crate::scanner::{self, PortResult, PortState, Protocol, RateLimiter, Source}; // Import the open ports to probe, the pace of the probes and the source to connect from.
+use crate::targets::Target; // Import the hosts the ports belong to.
+use crate::tls; // Import the TLS handshakes that take any certificate.
+use crate::x509; // Import certificate parsing.
+use serde::Serialize; // Import `Serialize` to report handshakes in the structured output.
+use socket2::Type; // Import socket types for the connects.
+use std::fs; // Import file writing to export the certificates.
+use std::io; // Import I/O errors for socket failures.
+use std::net::SocketAddr; // Import `SocketAddr` for the ports probed.
+use std::sync::OnceLock; // Import `OnceLock` to list the named cipher suites once.
+use std::time::Duration; // Import `Duration` for the connect and handshake timeouts.
+use tokio::net::{TcpSocket, TcpStream}; // Import `TcpSocket` to connect the sockets of the source.
+use tokio::time::timeout; // Import `timeout` to give up on ports that do not speak TLS.
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName}; // Import certificates and the name they are checked for.
+use tokio_rustls::rustls::{CipherSuite, ProtocolVersion}; // Import TLS versions and cipher suites to name the ones agreed.
+
+// The versions tried with --tls-versions, oldest first.
+const VERSIONS: [u16; 4] = [
+    hello::TLS_1_0,
+    hello::TLS_1_1,
+    hello::TLS_1_2,
+    hello::TLS_1_3,
+];
+
+// What a TLS handshake with a port showed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Tls {
+    // The name sent in the handshake (SNI), which the certificate is checked for. Handshakes
+    // with addresses send none, and the certificate is checked for the address.
+    pub sni: Option<String>,
+    // The protocol version and cipher suite agreed, e.g. `TLS 1.3` and `TLS13_AES_256_GCM_SHA384`.
+    pub version: String,
+    pub cipher: String,
+    // The application protocol the server picked from the ones offered (ALPN), e.g. `h2`.
+    pub alpn: Option<String>,
+    // The certificate the server presented, if it could be read.
+    pub certificate: Option<x509::Summary>,
+    // Why the certificate is not trusted by the Mozilla roots, unless it is.
+    pub untrusted: Option<String>,
+    // The days left until the certificate expires, negative once it has, when that is within
+    // the window of --cert-warn-days.
+    pub expiring: Option<i64>,
+    // The versions from TLS 1.0 to 1.3 the server accepts, oldest first, with --tls-versions.
+    pub versions: Vec<String>,
+    // The cipher suites the server accepts with each of those versions, in the order it prefers
+    // them, with --tls-ciphers.
+    pub ciphers: Vec<Suite>,
+    // How the server answers a fixed ClientHello, to tell TLS stacks apart.
+    pub ja3s: Option<Ja3s>,
+    // The certificates the server presented, its own first, in DER.
+    #[serde(skip)]
+    pub chain: Vec<Vec<u8>>,
+}
+
+// A JA3S fingerprint: the version, cipher suite and extensions of a ServerHello, e.g.
+// `771,4866,43-51`, and their MD5 hash, as fingerprint databases list them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Ja3s {
+    pub hash: String,
+    pub string: String,
+}
+
+// A cipher suite a server accepts with a version.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Suite {
+    pub version: String,
+    pub name: String,
+    // Why the suite is weak, e.g. `RC4` or `export-grade`; empty if it is not.
+    pub weak: Vec<String>,
+}
+
+impl Suite {
+    // Function to describe the suite `code` accepted with `version`.
+    fn new(version: u16, code: u16) -> Suite {
+        let name = suite_name(code);
+        let has = |part: &str| name.split('_').any(|p| p == part);
+        let flags = [
+            ("export-grade", name.contains("EXPORT")),
+            ("no encryption", has("NULL")),
+            ("no authentication", has("anon")),
+            ("RC4", has("RC4")),
+            ("RC2", has("RC2")),
+            ("3DES", has("3DES")),
+            ("DES", has("DES") || has("DES40")),
+            ("MD5", name.ends_with("_MD5")),
+            ("CBC with TLS 1.0", version == hello::TLS_1_0 && has("CBC")),
+        ];
+        let weak = flags
+            .iter()
+            .filter(|(_, set)| *set)
+            .map(|(flag, _)| flag.to_string())
+            .collect();
+        Suite {
+            version: hello::version_name(version),
+            name,
+            weak,
+        }
+    }
+}
+
+impl Tls {
+    // Function to get the accepted versions that RFC 8996 deprecated, TLS 1.0 and 1.1.
+    pub fn deprecated(&self) -> Vec<&str> {
+        self.versions
+            .iter()
+            .map(String::as_str)
+            .filter(|v| ["TLS 1.0", "TLS 1.1"].contains(v))
+            .collect()
+    }
+}
+
+// How the handshakes with the open ports are run.
+pub struct Options<'a> {
+    // The server name to send instead of the first hostname of each host.
+    pub sni: Option<&'a str>,
+    // Whether to find out which versions each port accepts, one handshake per version, and which
+    // cipher suites with each, one handshake per suite, which needs the versions.
+    pub versions: bool,
+    pub ciphers: bool,
+    // How long each connect and handshake may take.
+    pub wait: Duration,
+    // How many ports are probed at the same time, and how fast the connects go out.
+    pub concurrency: usize,
+    pub rate: Option<&'a RateLimiter>,
+}
+
+// Function to run a TLS handshake with every open TCP port in `results` and keep what it showed
+// on the result. The handshakes send the server name of the options, or else the first hostname
+// of the host, if it has one. Ports that do not speak TLS stay without one.
+pub async fn probe_all(
+    hosts: &[Target],
+    results: &mut [PortResult],
+    source: &Source,
+    options: &Options<'_>,
+) {
+    scanner::check_ports(
+        hosts,
+        results,
+        options.concurrency,
+        |r, host| {
+            let name = options.sni.or(host.hostnames.first().map(String::as_str));
+            (r.protocol == Protocol::Tcp && r.state == PortState::Open).then_some(name)
+        },
+        |addr, name| probe(addr, name, source, options),
+        |r, tls| r.findings.tls = tls,
+    )
+    .await;
+}
+
+// Function to find out what a port shows of its TLS, fingerprinted with JA3S.
+async fn probe(
+    addr: SocketAddr,
+    sni: Option<&str>,
+    source: &Source,
+    options: &Options<'_>,
+) -> Option<Tls> {
+    let tls = describe(addr, sni, source, options).await?;
+    let ja3s = fingerprint(addr, tls.sni.as_deref(), source, options).await;
+    Some(Tls { ja3s, ..tls })
+}
+
+// Function to find out what a port shows of its TLS, running the version and cipher suite
+// handshakes as well if asked to. Servers that only speak versions rustls does not are
+// described from the ServerHello of the newest version they took.
+async fn describe(
+    addr: SocketAddr,
+    sni: Option<&str>,
+    source: &Source,
+    options: &Options<'_>,
+) -> Option<Tls> {
+    let tls = handshake(addr, sni, source, options).await.ok();
+    if !options.versions {
+        return tls;
+    }
+    let (sni, name) = server_name(addr, sni);
+    let mut versions = vec![];
+    let mut ciphers = vec![];
+    let mut newest = None;
+    for version in VERSIONS {
+        let suites = suites(version, options.ciphers);
+        let Some(hello) = offer(addr, &[version], &suites, sni, source, options).await else {
+            continue;
+        };
+        versions.push(hello::version_name(version));
+        if options.ciphers {
+            // Offering what is left after each pick gives the suites in the server's order.
+            let mut picked = hello.cipher;
+            let mut left = suites;
+            while left.contains(&picked) {
+                ciphers.push(Suite::new(version, picked));
+                left.retain(|&suite| suite != picked);
+                if left.is_empty() {
+                    break;
+                }
+                match offer(addr, &[version], &left, sni, source, options).await {
+                    Some(hello) => picked = hello.cipher,
+                    None => break,
+                }
+            }
+        }
+        newest = Some(hello);
+    }
+    match (tls, newest) {
+        (Some(tls), _) => Some(Tls {
+            versions,
+            ciphers,
+            ..tls
+        }),
+        (None, Some(hello)) => {
+            let chain: Vec<CertificateDer> = hello
+                .certificates
+                .iter()
+                .map(|der| CertificateDer::from(der.as_slice()))
+                .collect();
+            let certificate = chain.first().and_then(|leaf| x509::parse(leaf).ok());
+            Some(Tls {
+                sni: sni.map(str::to_string),
+                version: hello::version_name(hello.version),
+                cipher: suite_name(hello.cipher),
+                alpn: None,
+                untrusted: trust(&chain, &name, certificate.as_ref()),
+                certificate,
+                expiring: None,
+                versions,
+                ciphers,
+                ja3s: None,
+                chain: hello.certificates,
+            })
+        }
+        (None, None) => None,
+    }
+}
+
+// Function to list the cipher suites offered with `version`: the usual ones, or with `all` every
+// suite with a name, down to the export-grade ones, to find out all that the server takes.
+fn suites(version: u16, all: bool) -> Vec<u16> {
+    static NAMED: OnceLock<Vec<u16>> = OnceLock::new();
+    match (version, all) {
+        (hello::TLS_1_3, _) => hello::TLS13_SUITES.to_vec(),
+        (_, false) => hello::LEGACY_SUITES.to_vec(),
+        (_, true) => NAMED
+            .get_or_init(|| {
+                (1..=u16::MAX)
+                    .filter(|&code| {
+                        CipherSuite::from(code).as_str().is_some_and(|name| {
+                            !name.starts_with("TLS13_")
+                                && !name.ends_with("_SCSV")
+                                && !name.ends_with("_draft")
+                        })
+                    })
+                    .collect()
+            })
+            .clone(),
+    }
+}
+
+// Function to offer `versions` and `suites` to `addr` in a hand-built ClientHello, giving the
+// ServerHello if the server took them.
+async fn offer(
+    addr: SocketAddr,
+    versions: &[u16],
+    suites: &[u16],
+    sni: Option<&str>,
+    source: &Source,
+    options: &Options<'_>,
+) -> Option<ServerHello> {
+    let mut stream = connect(addr, source, options).await.ok()?;
+    let client_hello = hello::client_hello(versions, suites, sni);
+    match hello::exchange(&mut stream, &client_hello, options.wait).await {
+        // Servers that answer with an older version do not speak the ones offered.
+        Ok(Reply::Hello(hello)) if versions.contains(&hello.version) => Some(hello),
+        _ => None,
+    }
+}
+
+// Function to take the JA3S fingerprint of `addr`. Every server gets the same ClientHello,
+// offering TLS 1.0 to 1.3 and the usual suites, so that servers running the same TLS stack with
+// the same settings answer it the same way.
+async fn fingerprint(
+    addr: SocketAddr,
+    sni: Option<&str>,
+    source: &Source,
+    options: &Options<'_>,
+) -> Option<Ja3s> {
+    let versions: Vec<u16> = VERSIONS.iter().rev().copied().collect();
+    let suites = [hello::TLS13_SUITES, hello::LEGACY_SUITES].concat();
+    let hello = offer(addr, &versions, &suites, sni, source, options).await?;
+    let string = hello::ja3s(&hello);
+    Some(Ja3s {
+        hash: md5::hex_digest(string.as_bytes()),
+        string,
+    })
+}
+
+// Function to connect to `addr` from the source within the wait, once the rate allows it.
+async fn connect(
+    addr: SocketAddr,
+    source: &Source,
+    options: &Options<'_>,
+) -> io::Result<TcpStream> {
+    if let Some(rate) = options.rate {
+        rate.wait().await;
+    }
+    let socket = TcpSocket::from_std_stream(source.socket(addr, Type::STREAM, None)?.into());
+    timeout(options.wait, socket.connect(addr))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?
+}
+
+// Function to pick the server name of the handshakes with `addr`: `sni`, unless it is none or
+// no valid server name, in which case none is sent and certificates are checked for the address.
+fn server_name(addr: SocketAddr, sni: Option<&str>) -> (Option<&str>, ServerName<'static>) {
+    match sni.and_then(|sni| Some((sni, tls::server_name(sni).ok()?))) {
+        Some((sni, name)) => (Some(sni), name),
+        None => (None, ServerName::from(addr.ip())),
+    }
+}
+
+// Function to say why a certificate chain is not trusted for `name`, unless it is. A self-signed
+// certificate cannot lead to a root, so that is all there is to say.
+fn trust(
+    chain: &[CertificateDer<'_>],
+    name: &ServerName<'_>,
+    certificate: Option<&x509::Summary>,
+) -> Option<String> {
+    tls::verify(chain, name)
+        .err()
+        .map(|reason| match certificate.is_some_and(|c| c.self_signed) {
+            true => "self-signed".to_string(),
+            false => reason,
+        })
+}
+
+// Function to name a cipher suite, e.g. `TLS_RSA_WITH_AES_128_CBC_SHA`, or give its code.
+fn suite_name(code: u16) -> String {
+    match CipherSuite::from(code).as_str() {
+        Some(name) => name.to_string(),
+        None => format!("{:#06x}", code),
+    }
+}
+
+// Function to write the certificate chain of each handshake in `results` to `dir` as a PEM file
+// named after the address and port, e.g. `192.0.2.7_443.pem`, returning how many were written.
+pub fn export(hosts: &[Target], results: &[PortResult], dir: &str) -> Result<usize, String> {
+    fs::create_dir_all(dir).map_err(|e| format!("Cannot create {}: {}", dir, e))?;
+    let mut written = 0;
+    for r in results {
+        let Some(tls) = r.findings.tls.as_ref().filter(|tls| !tls.chain.is_empty()) else {
+            continue;
+        };
+        let ip = r.answered.unwrap_or(hosts[r.host].socket_addr(r.port).ip());
+        let path = format!("{}/{}_{}.pem", dir.trim_end_matches('/'), ip, r.port);
+        let pem: String = tls.chain.iter().map(|der| x509::pem(der)).collect();
+        fs::write(&path, pem).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+        written += 1;
+    }
+    Ok(written)
+}
+
+// Function to flag the certificates in `results` that expire within `days` days from now, or
+// already have, returning how many there are.
+pub fn flag_expiring(results: &mut [PortResult], days: u32) -> usize {
+    let now = x509::Time::now();
+    let mut flagged = 0;
+    for tls in results.iter_mut().filter_map(|r| r.findings.tls.as_mut()) {
+        let Some(certificate) = &tls.certificate else {
+            continue;
+        };
+        let left = (certificate.not_after.0 - now.0).div_euclid(86_400);
+        if left < i64::from(days) {
+            tls.expiring = Some(left);
+            flagged += 1;
+        }
+    }
+    flagged
+}
+
+// Function to connect to `addr` and run a TLS handshake for `sni`, within the wait each. The
+// handshake offers the common application protocols, and is run again without them if the
+// server speaks none of them.
+async fn handshake(
+    addr: SocketAddr,
+    sni: Option<&str>,
+    source: &Source,
+    options: &Options<'_>,
+) -> io::Result<Tls> {
+    let (sni, name) = server_name(addr, sni);
+    let establish = |alpn| {
+        let name = name.clone();
+        async move {
+            let stream = connect(addr, source, options).await?;
+            timeout(options.wait, tls::connect_any(stream, name, alpn))
+                .await
+                .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?
+        }
+    };
+    let stream = match establish(true).await {
+        Err(e) if tls::no_common_protocol(&e) => establish(false).await?,
+        result => result?,
+    };
+    let (_, connection) = stream.get_ref();
+    let version = match connection.protocol_version() {
+        Some(ProtocolVersion::TLSv1_3) => "TLS 1.3",
+        Some(ProtocolVersion::TLSv1_2) => "TLS 1.2",
+        _ => "TLS",
+    };
+    let cipher = connection
+        .negotiated_cipher_suite()
+        .and_then(|suite| suite.suite().as_str())
+        .unwrap_or("unknown");
+    let alpn = connection
+        .alpn_protocol()
+        .map(|protocol| String::from_utf8_lossy(protocol).into_owned());
+    let chain = connection.peer_certificates().unwrap_or_default();
+    let certificate = chain.first().and_then(|leaf| x509::parse(leaf).ok());
+    Ok(Tls {
+        sni: sni.map(str::to_string),
+        version: version.to_string(),
+        cipher: cipher.to_string(),
+        alpn,
+        untrusted: trust(chain, &name, certificate.as_ref()),
+        certificate,
+        expiring: None,
+        versions: vec![],
+        ciphers: vec![],
+        ja3s: None,
+        chain: chain.iter().map(|der| der.to_vec()).collect(),
+    })
+}
@@ -0,0 +1,385 @@
+use std::io; // Import I/O errors for socket failures.
+use std::time::Duration; // Import `Duration` for the answer timeouts.
+use tokio::io::{AsyncReadExt, AsyncWriteExt}; // Import async read/write helpers for the handshakes.
+use tokio::net::TcpStream; // Import `TcpStream` for the connections to the servers.
+use tokio::time::timeout; // Import `timeout` to give up on servers that say nothing.
+
+// The protocol versions a ClientHello can offer, as sent on the wire.
+pub const TLS_1_0: u16 = 0x0301;
+pub const TLS_1_1: u16 = 0x0302;
+pub const TLS_1_2: u16 = 0x0303;
+pub const TLS_1_3: u16 = 0x0304;
+
+// Record types.
+const CHANGE_CIPHER_SPEC: u8 = 20;
+const HANDSHAKE: u8 = 22;
+const ALERT: u8 = 21;
+const APPLICATION_DATA: u8 = 23;
+// Handshake message types.
+const CLIENT_HELLO: u8 = 1;
+const SERVER_HELLO: u8 = 2;
+const CERTIFICATE: u8 = 11;
+const SERVER_HELLO_DONE: u8 = 14;
+// Extension types.
+const SERVER_NAME: u16 = 0x0000;
+const SUPPORTED_GROUPS: u16 = 0x000a;
+const EC_POINT_FORMATS: u16 = 0x000b;
+const SIGNATURE_ALGORITHMS: u16 = 0x000d;
+const SUPPORTED_VERSIONS: u16 = 0x002b;
+const KEY_SHARE: u16 = 0x0033;
+const RENEGOTIATION_INFO: u16 = 0xff01;
+// The X25519 group, the only one a key share is sent for.
+const X25519: u16 = 0x001d;
+
+// Define how much of the server's first flight is read at most.
+const MAX_FLIGHT: usize = 128 * 1024;
+
+// The cipher suites of TLS 1.3.
+pub const TLS13_SUITES: &[u16] = &[0x1301, 0x1302, 0x1303, 0x1304, 0x1305];
+
+// The cipher suites offered to TLS 1.0 to 1.2, the usual ones of each era from ECDHE with
+// AES-GCM down to RSA with 3DES, so that any server still in use finds one it takes.
+pub const LEGACY_SUITES: &[u16] = &[
+    0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, 0xcca8, 0xc009, 0xc013, 0xc00a, 0xc014, 0xc023, 0xc027,
+    0xc024, 0xc028, 0x009e, 0x009f, 0x0033, 0x0039, 0x0067, 0x006b, 0x009c, 0x009d, 0x002f, 0x0035,
+    0x003c, 0x003d, 0xc012, 0x0016, 0x000a, 0xc011, 0xc007, 0x0005, 0x0004,
+];
+
+// The groups and signature algorithms offered, most servers insist on seeing them.
+const GROUPS: &[u16] = &[X25519, 0x0017, 0x0018, 0x0019];
+const SIGNATURE_SCHEMES: &[u16] = &[
+    0x0403, 0x0503, 0x0603, 0x0804, 0x0805, 0x0806, 0x0401, 0x0501, 0x0601, 0x0203, 0x0201,
+];
+
+// What a server answered a ClientHello with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    Hello(ServerHello),
+    // An alert, by its description, e.g. 40 for a handshake failure or 70 for a version it does
+    // not speak.
+    Alert(u8),
+}
+
+// The ServerHello of a server, with the certificates that come right after it before TLS 1.3.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerHello {
+    // The version agreed, from the supported versions extension if there is one, and the one in
+    // the message itself, which stays at TLS 1.2 from TLS 1.3 on.
+    pub version: u16,
+    pub legacy_version: u16,
+    pub cipher: u16,
+    // The extensions in the order they were sent, by type with their data.
+    pub extensions: Vec<(u16, Vec<u8>)>,
+    // The certificate chain in DER, its own first; empty from TLS 1.3 on, where it is encrypted.
+    pub certificates: Vec<Vec<u8>>,
+}
+
+// Function to get the name of a protocol version, e.g. `TLS 1.2`.
+pub fn version_name(version: u16) -> String {
+    match version {
+        0x0300 => "SSL 3.0".to_string(),
+        TLS_1_0 => "TLS 1.0".to_string(),
+        TLS_1_1 => "TLS 1.1".to_string(),
+        TLS_1_2 => "TLS 1.2".to_string(),
+        TLS_1_3 => "TLS 1.3".to_string(),
+        other => format!("TLS {:#06x}", other),
+    }
+}
+
+// Function to build a ClientHello record offering `versions`, newest first, and `suites`, for
+// the server name `sni` if there is one.
+pub fn client_hello(versions: &[u16], suites: &[u16], sni: Option<&str>) -> Vec<u8> {
+    let version = versions.iter().copied().max().unwrap_or(TLS_1_2);
+    let mut extensions = vec![];
+    if let Some(name) = sni {
+        let name = name.as_bytes();
+        let mut list = vec![0];
+        list.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        list.extend_from_slice(name);
+        extension(&mut extensions, SERVER_NAME, &prefixed(&list));
+    }
+    extension(&mut extensions, SUPPORTED_GROUPS, &prefixed(&words(GROUPS)));
+    extension(&mut extensions, EC_POINT_FORMATS, &[1, 0]);
+    if version >= TLS_1_2 {
+        extension(
+            &mut extensions,
+            SIGNATURE_ALGORITHMS,
+            &prefixed(&words(SIGNATURE_SCHEMES)),
+        );
+    }
+    if version >= TLS_1_3 {
+        let mut list = vec![2 * versions.len() as u8];
+        list.extend_from_slice(&words(versions));
+        extension(&mut extensions, SUPPORTED_VERSIONS, &list);
+        // Any 32 bytes make an X25519 public key, and the handshake ends before it matters.
+        let mut share = X25519.to_be_bytes().to_vec();
+        share.extend_from_slice(&32u16.to_be_bytes());
+        share.extend_from_slice(&rand::random::<[u8; 32]>());
+        extension(&mut extensions, KEY_SHARE, &prefixed(&share));
+    }
+    extension(&mut extensions, RENEGOTIATION_INFO, &[0]);
+
+    let mut body = version.min(TLS_1_2).to_be_bytes().to_vec();
+    body.extend_from_slice(&rand::random::<[u8; 32]>());
+    body.push(32);
+    body.extend_from_slice(&rand::random::<[u8; 32]>());
+    body.extend_from_slice(&prefixed(&words(suites)));
+    body.extend_from_slice(&[1, 0]); // No compression.
+    body.extend_from_slice(&prefixed(&extensions));
+
+    let mut record = vec![HANDSHAKE, 0x03, 0x01];
+    record.extend_from_slice(&(body.len() as u16 + 4).to_be_bytes());
+    record.push(CLIENT_HELLO);
+    record.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+    record.extend_from_slice(&body);
+    record
+}
+
+// Function to append an extension of `kind` with `data`.
+fn extension(extensions: &mut Vec<u8>, kind: u16, data: &[u8]) {
+    extensions.extend_from_slice(&kind.to_be_bytes());
+    extensions.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    extensions.extend_from_slice(data);
+}
+
+// Function to put the two-byte length of `data` in front of it.
+fn prefixed(data: &[u8]) -> Vec<u8> {
+    let mut out = (data.len() as u16).to_be_bytes().to_vec();
+    out.extend_from_slice(data);
+    out
+}
+
+// Function to write a list of 16-bit values.
+fn words(values: &[u16]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_be_bytes()).collect()
+}
+
+// Function to write the JA3S string of a ServerHello: its version, cipher suite and extension
+// types in decimal, e.g. `771,4866,43-51`.
+pub fn ja3s(hello: &ServerHello) -> String {
+    let extensions: Vec<String> = hello
+        .extensions
+        .iter()
+        .map(|(kind, _)| kind.to_string())
+        .collect();
+    format!(
+        "{},{},{}",
+        hello.legacy_version,
+        hello.cipher,
+        extensions.join("-")
+    )
+}
+
+// Function to send `hello` on `stream` and read what the server answers, within `wait`.
+pub async fn exchange(stream: &mut TcpStream, hello: &[u8], wait: Duration) -> io::Result<Reply> {
+    let exchange = async {
+        stream.write_all(hello).await?;
+        let mut flight = vec![];
+        let mut chunk = [0u8; 16 * 1024];
+        loop {
+            if let Some(reply) = parse_flight(&flight)? {
+                return Ok(reply);
+            }
+            let n = stream.read(&mut chunk).await?;
+            if n == 0 || flight.len() + n > MAX_FLIGHT {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+            }
+            flight.extend_from_slice(&chunk[..n]);
+        }
+    };
+    timeout(wait, exchange)
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?
+}
+
+// Function to read the records a server sent so far, giving its reply once the ServerHello and
+// the certificates after it are all there, or `None` while more is needed.
+pub fn parse_flight(flight: &[u8]) -> io::Result<Option<Reply>> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+    // Handshake messages may be split over records, so their bytes are joined first.
+    let mut handshake = vec![];
+    let mut rest = flight;
+    let mut complete = false;
+    while rest.len() >= 5 {
+        if !(CHANGE_CIPHER_SPEC..=APPLICATION_DATA).contains(&rest[0]) || rest[1] != 3 {
+            return Err(invalid("not a TLS record"));
+        }
+        let length = usize::from(u16::from_be_bytes([rest[3], rest[4]]));
+        let Some(fragment) = rest.get(5..5 + length) else {
+            break;
+        };
+        match rest[0] {
+            HANDSHAKE => handshake.extend_from_slice(fragment),
+            ALERT if fragment.len() >= 2 && handshake.is_empty() => {
+                return Ok(Some(Reply::Alert(fragment[1])));
+            }
+            // Encrypted records mean the plaintext part of the handshake is over.
+            APPLICATION_DATA => complete = true,
+            _ => {}
+        }
+        rest = &rest[5 + length..];
+    }
+
+    let mut hello: Option<ServerHello> = None;
+    let mut messages = handshake.as_slice();
+    while messages.len() >= 4 {
+        let length = usize::from(messages[1]) << 16 | usize::from(messages[2]) << 8;
+        let length = length | usize::from(messages[3]);
+        let Some(body) = messages.get(4..4 + length) else {
+            break;
+        };
+        match (messages[0], &mut hello) {
+            (SERVER_HELLO, None) => {
+                let parsed = server_hello(body).ok_or_else(|| invalid("invalid ServerHello"))?;
+                // From TLS 1.3 on, nothing after the ServerHello is readable.
+                complete |= parsed.version >= TLS_1_3;
+                hello = Some(parsed);
+            }
+            (CERTIFICATE, Some(hello)) => {
+                hello.certificates =
+                    certificates(body).ok_or_else(|| invalid("invalid Certificate"))?;
+                complete = true;
+            }
+            (SERVER_HELLO_DONE, Some(_)) => complete = true,
+            (_, None) => return Err(invalid("no ServerHello")),
+            _ => {}
+        }
+        messages = &messages[4 + length..];
+    }
+    match (hello, complete) {
+        (Some(hello), true) => Ok(Some(Reply::Hello(hello))),
+        _ => Ok(None),
+    }
+}
+
+// Function to read the body of a ServerHello.
+fn server_hello(body: &[u8]) -> Option<ServerHello> {
+    let legacy = u16::from_be_bytes([*body.first()?, *body.get(1)?]);
+    let session = usize::from(*body.get(34)?);
+    let at = 35 + session;
+    let cipher = u16::from_be_bytes([*body.get(at)?, *body.get(at + 1)?]);
+    // The extensions after the compression method are optional before TLS 1.2.
+    let mut extensions = vec![];
+    if let Some(length) = body.get(at + 3..at + 5) {
+        let length = usize::from(u16::from_be_bytes([length[0], length[1]]));
+        let mut data = body.get(at + 5..at + 5 + length)?;
+        while data.len() >= 4 {
+            let kind = u16::from_be_bytes([data[0], data[1]]);
+            let size = usize::from(u16::from_be_bytes([data[2], data[3]]));
+            extensions.push((kind, data.get(4..4 + size)?.to_vec()));
+            data = &data[4 + size..];
+        }
+    }
+    let version = extensions
+        .iter()
+        .find(|(kind, data)| *kind == SUPPORTED_VERSIONS && data.len() == 2)
+        .map_or(legacy, |(_, data)| u16::from_be_bytes([data[0], data[1]]));
+    Some(ServerHello {
+        version,
+        legacy_version: legacy,
+        cipher,
+        extensions,
+        certificates: vec![],
+    })
+}
+
+// Function to read the chain of a Certificate message.
+fn certificates(body: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut list = body.get(3..)?;
+    let mut chain = vec![];
+    while list.len() >= 3 {
+        let length = usize::from(list[0]) << 16 | usize::from(list[1]) << 8 | usize::from(list[2]);
+        chain.push(list.get(3..3 + length)?.to_vec());
+        list = &list[3 + length..];
+    }
+    Some(chain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Function to wrap handshake messages in one record.
+    fn record(kind: u8, data: &[u8]) -> Vec<u8> {
+        let mut out = vec![kind, 0x03, 0x03];
+        out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        out.extend_from_slice(data);
+        out
+    }
+
+    // Function to build a handshake message.
+    fn message(kind: u8, body: &[u8]) -> Vec<u8> {
+        let mut out = vec![kind];
+        out.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        out.extend_from_slice(body);
+        out
+    }
+
+    // Function to build the body of a ServerHello picking `cipher`, with `extensions`.
+    fn hello_body(version: u16, cipher: u16, extensions: &[u8]) -> Vec<u8> {
+        let mut body = version.to_be_bytes().to_vec();
+        body.extend_from_slice(&[7; 32]);
+        body.push(0);
+        body.extend_from_slice(&cipher.to_be_bytes());
+        body.push(0);
+        body.extend_from_slice(&prefixed(extensions));
+        body
+    }
+
+    #[test]
+    fn builds_client_hellos() {
+        let hello = client_hello(&[TLS_1_0], &[0x002f], Some("example.com"));
+        assert_eq!(&hello[..3], &[HANDSHAKE, 0x03, 0x01]);
+        assert_eq!(
+            usize::from(u16::from_be_bytes([hello[3], hello[4]])),
+            hello.len() - 5
+        );
+        assert_eq!(hello[5], CLIENT_HELLO);
+        assert_eq!(&hello[9..11], &[0x03, 0x01]);
+        // After the random and the session ID come the suites.
+        assert_eq!(&hello[76..80], &[0, 2, 0x00, 0x2f]);
+        assert!(hello.windows(11).any(|w| w == b"example.com"));
+
+        let hello = client_hello(&[TLS_1_3, TLS_1_2], TLS13_SUITES, None);
+        assert_eq!(&hello[9..11], &[0x03, 0x03]);
+        assert!(hello
+            .windows(9)
+            .any(|w| w == [0x00, 0x2b, 0, 5, 4, 0x03, 0x04, 0x03, 0x03]));
+    }
+
+    #[test]
+    fn reads_server_flights() {
+        // A TLS 1.2 ServerHello, split over two records, then a certificate.
+        let hello = message(
+            SERVER_HELLO,
+            &hello_body(TLS_1_2, 0xc02f, &[0xff, 0x01, 0, 1, 0]),
+        );
+        let chain = message(CERTIFICATE, &[0, 0, 5, 0, 0, 2, 0x30, 0x00]);
+        let mut flight = record(HANDSHAKE, &hello[..10]);
+        flight.extend(record(HANDSHAKE, &[&hello[10..], &chain[..]].concat()));
+        assert_eq!(parse_flight(&flight[..flight.len() - 3]).unwrap(), None);
+        let Some(Reply::Hello(parsed)) = parse_flight(&flight).unwrap() else {
+            panic!("no ServerHello");
+        };
+        assert_eq!((parsed.version, parsed.cipher), (TLS_1_2, 0xc02f));
+        assert_eq!(parsed.extensions, [(RENEGOTIATION_INFO, vec![0])]);
+        assert_eq!(parsed.certificates, [vec![0x30, 0x00]]);
+
+        // TLS 1.3 says so in an extension, and is done after the ServerHello.
+        let hello = message(
+            SERVER_HELLO,
+            &hello_body(TLS_1_2, 0x1302, &[0x00, 0x2b, 0, 2, 0x03, 0x04]),
+        );
+        let Some(Reply::Hello(parsed)) = parse_flight(&record(HANDSHAKE, &hello)).unwrap() else {
+            panic!("no ServerHello");
+        };
+        assert_eq!((parsed.version, parsed.cipher), (TLS_1_3, 0x1302));
+        assert_eq!(ja3s(&parsed), "771,4866,43");
+
+        assert_eq!(
+            parse_flight(&record(ALERT, &[2, 70])).unwrap(),
+            Some(Reply::Alert(70))
+        );
+        assert!(parse_flight(b"HTTP/1.1 400 Bad Request\r\n").is_err());
+    }
+}
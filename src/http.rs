@@ -0,0 +1,322 @@
+use std::io; // Import I/O types for stream errors.
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt}; // Import async stream traits.
+
+// Upper bound on how much of a response is kept in memory.
+const MAX_RESPONSE: usize = 1 << 20;
+
+// The parts of a URL the scanner needs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub https: bool,
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+// Function to split an `http://` or `https://` URL into its parts.
+pub fn parse_url(url: &str) -> Result<Url, String> {
+    let (https, rest) = if let Some(rest) = url.strip_prefix("https://") {
+        (true, rest)
+    } else if let Some(rest) = url.strip_prefix("http://") {
+        (false, rest)
+    } else {
+        return Err(format!("{} is not an http:// or https:// URL", url));
+    };
+
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let default_port = if https { 443 } else { 80 };
+
+    // Bracketed IPv6 literals carry colons of their own.
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => (
+            host,
+            port.parse()
+                .map_err(|_| format!("{} has an invalid port", url))?,
+        ),
+        _ => (authority, default_port),
+    };
+    if host.is_empty() {
+        return Err(format!("{} has no host", url));
+    }
+
+    Ok(Url {
+        https,
+        host: host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string(),
+        port,
+        path: path.to_string(),
+    })
+}
+
+impl Url {
+    // Function to format the `Host` header value: IPv6 literals in brackets, non-default ports appended.
+    pub fn authority(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        match (self.https, self.port) {
+            (true, 443) | (false, 80) => host,
+            (_, port) => format!("{}:{}", host, port),
+        }
+    }
+}
+
+// An HTTP response.
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    // Function to get the first header with the given name (case-insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+// Function to send a raw HTTP/1.1 request and read the response.
+// Requests should carry `Connection: close` so the body ends when the server closes the stream.
+pub async fn send<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    request: &[u8],
+) -> io::Result<Response> {
+    stream.write_all(request).await?;
+    stream.flush().await?;
+
+    // Read until the connection closes or the declared body length has arrived.
+    let mut raw = Vec::new();
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = match stream.read(&mut buf).await {
+            Ok(n) => n,
+            // Many TLS servers skip close_notify; treat that like a normal end of stream.
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && !raw.is_empty() => 0,
+            Err(e) => return Err(e),
+        };
+        if n == 0 {
+            break;
+        }
+        raw.extend_from_slice(&buf[..n]);
+        if raw.len() > MAX_RESPONSE || response_complete(&raw) {
+            break;
+        }
+    }
+
+    parse_response(&raw)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response"))
+}
+
+// Function to send a raw request and read only the head of the response, for requests such as
+// upgrades whose connection stays open after it.
+pub async fn send_head<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    request: &[u8],
+) -> io::Result<Response> {
+    stream.write_all(request).await?;
+    stream.flush().await?;
+    let mut raw = Vec::new();
+    let mut buf = [0u8; 8192];
+    while header_end(&raw).is_none() {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || raw.len() > MAX_RESPONSE {
+            break;
+        }
+        raw.extend_from_slice(&buf[..n]);
+    }
+    let end = header_end(&raw).unwrap_or(raw.len());
+    parse_response(&raw[..end])
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response"))
+}
+
+// Function to find the end of the header block.
+fn header_end(raw: &[u8]) -> Option<usize> {
+    raw.windows(4).position(|w| w == b"\r\n\r\n").map(|i| i + 4)
+}
+
+// Function to check whether a response with a Content-Length has been fully received.
+fn response_complete(raw: &[u8]) -> bool {
+    let Some(end) = header_end(raw) else {
+        return false;
+    };
+    let head = String::from_utf8_lossy(&raw[..end]);
+    head.lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .is_some_and(|len| raw.len() >= end + len)
+}
+
+// Function to parse a raw response into status, headers and (de-chunked) body.
+pub fn parse_response(raw: &[u8]) -> Option<Response> {
+    let end = header_end(raw)?;
+    let head = String::from_utf8_lossy(&raw[..end]);
+    let mut lines = head.split("\r\n");
+
+    // Status line, e.g. `HTTP/1.1 200 OK`.
+    let status_line = lines.next()?;
+    if !status_line.starts_with("HTTP/") {
+        return None;
+    }
+    let status = status_line.split_whitespace().nth(1)?.parse().ok()?;
+
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+
+    let mut response = Response {
+        status,
+        headers,
+        body: raw[end..].to_vec(),
+    };
+    if response
+        .header("transfer-encoding")
+        .is_some_and(|v| v.eq_ignore_ascii_case("chunked"))
+    {
+        response.body = dechunk(&response.body)?;
+    }
+    if let Some(len) = response
+        .header("content-length")
+        .and_then(|v| v.parse::<usize>().ok())
+    {
+        response.body.truncate(len);
+    }
+    Some(response)
+}
+
+// Function to join the chunks of a chunked transfer-encoded body.
+// Returns `None` for malformed chunk sizes and for bodies cut off before the last chunk.
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(body.len());
+    loop {
+        let line_end = body.windows(2).position(|w| w == b"\r\n")?;
+        let size_line = std::str::from_utf8(&body[..line_end]).ok()?;
+        // The size may be followed by `;extension`s, which are ignored.
+        let size_hex = size_line.split(';').next().unwrap_or("").trim();
+        if !size_hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let size = usize::from_str_radix(size_hex, 16).ok()?;
+        if size == 0 {
+            return Some(out);
+        }
+        // `get` keeps absurd sizes from overflowing or reading past the end.
+        let data = &body[line_end + 2..];
+        out.extend_from_slice(data.get(..size)?);
+        body = data.get(size..)?.strip_prefix(b"\r\n")?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls() {
+        let url = parse_url("https://dns.example/dns-query").unwrap();
+        assert_eq!(
+            url,
+            Url {
+                https: true,
+                host: "dns.example".to_string(),
+                port: 443,
+                path: "/dns-query".to_string(),
+            }
+        );
+        let url = parse_url("http://[2001:db8::1]:8080").unwrap();
+        assert_eq!(
+            (url.host.as_str(), url.port, url.path.as_str()),
+            ("2001:db8::1", 8080, "/")
+        );
+        let url = parse_url("https://[2001:db8::1]/q").unwrap();
+        assert_eq!((url.host.as_str(), url.port), ("2001:db8::1", 443));
+        assert_eq!(url.authority(), "[2001:db8::1]");
+        assert_eq!(
+            parse_url("http://[::1]:8080/").unwrap().authority(),
+            "[::1]:8080"
+        );
+        assert_eq!(
+            parse_url("https://dns.example:8443/").unwrap().authority(),
+            "dns.example:8443"
+        );
+        assert!(parse_url("ftp://example").is_err());
+        assert!(parse_url("https:///path").is_err());
+        assert!(parse_url("https://example:http/").is_err());
+    }
+
+    #[test]
+    fn plain_response() {
+        let raw =
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\nhello, extra";
+        let response = parse_response(raw).unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("content-type"), Some("text/plain"));
+        assert_eq!(response.body, b"hello");
+        assert!(response_complete(raw));
+        assert!(!response_complete(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhel"
+        ));
+    }
+
+    #[test]
+    fn malformed_heads_are_rejected() {
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n").is_none());
+        assert!(parse_response(b"SSH-2.0-OpenSSH\r\n\r\n").is_none());
+        assert!(parse_response(b"HTTP/1.1 abc\r\n\r\n").is_none());
+        assert!(parse_response(b"HTTP/1.1\r\n\r\n").is_none());
+        assert!(parse_response(b"").is_none());
+    }
+
+    #[test]
+    fn chunked_response() {
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    4\r\nWiki\r\n6;name=value\r\npedia \r\nE\r\nin \r\n\r\nchunks.\r\n0\r\n\r\n";
+        let response = parse_response(raw).unwrap();
+        assert_eq!(response.body, b"Wikipedia in \r\n\r\nchunks.");
+    }
+
+    #[test]
+    fn chunks() {
+        assert_eq!(dechunk(b"3\r\nabc\r\n0\r\n\r\n"), Some(b"abc".to_vec()));
+        assert_eq!(dechunk(b"0\r\n\r\n"), Some(vec![]));
+    }
+
+    #[test]
+    fn malformed_chunk_sizes_are_rejected() {
+        for body in [
+            &b"zz\r\nabc\r\n0\r\n\r\n"[..],
+            b"+3\r\nabc\r\n0\r\n\r\n",
+            b"-3\r\nabc\r\n0\r\n\r\n",
+            b"\r\nabc\r\n0\r\n\r\n",
+            // Too large for a usize, and large enough to overflow an offset.
+            b"fffffffffffffffffffff\r\nabc\r\n",
+            b"ffffffffffffffff\r\nabc\r\n",
+        ] {
+            assert_eq!(dechunk(body), None, "{:?}", String::from_utf8_lossy(body));
+        }
+    }
+
+    #[test]
+    fn truncated_chunks_are_rejected() {
+        // Data cut short, the CRLF after the data missing, no size line, and no last chunk.
+        assert_eq!(dechunk(b"a\r\nabc"), None);
+        assert_eq!(dechunk(b"3\r\nabcdef\r\n0\r\n\r\n"), None);
+        assert_eq!(dechunk(b"3\r\nabc"), None);
+        assert_eq!(dechunk(b"3\r\nabc\r\n"), None);
+        assert_eq!(dechunk(b""), None);
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nab";
+        assert!(parse_response(raw).is_none());
+    }
+}
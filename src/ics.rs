@@ -0,0 +1,505 @@
+use crate::scanner::{self, PortResult, PortState, Protocol, Settings}; // Import the open ports to ask and the scan settings to connect with.
+use crate::targets::Target; // Import the hosts the ports belong to.
+use serde::Serialize; // Import `Serialize` to report the devices in the structured output.
+use socket2::Type; // Import socket types for the connects.
+use std::io; // Import I/O errors for socket failures.
+use std::net::SocketAddr; // Import `SocketAddr` for the ports asked.
+use std::time::Duration; // Import `Duration` for the timeouts and the pauses of --safe.
+use tokio::io::{AsyncReadExt, AsyncWriteExt}; // Import reads and writes on the connected streams.
+use tokio::net::{TcpSocket, TcpStream}; // Import sockets of the source for the connects.
+use tokio::time::{sleep, timeout_at, Instant}; // Import deadlines and pauses.
+
+// Define the pause --safe leaves before each request to a device.
+const SAFE_GAP: Duration = Duration::from_secs(1);
+
+// Define the Modbus unit a device addressed directly over TCP answers for.
+const MODBUS_UNIT: u8 = 0xff;
+
+// Define the DNP3 outstations asked for their link status: the self address every outstation
+// that supports it answers for, then the usual defaults. --safe asks the self address alone.
+const DNP3_OUTSTATIONS: [u16; 3] = [0xfffc, 1, 10];
+
+// Define the DNP3 address the requests come from, the usual one of a master.
+const DNP3_MASTER: u16 = 1;
+
+// The industrial protocols that can be asked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Modbus,
+    S7,
+    Dnp3,
+}
+
+impl Kind {
+    // Function to name the protocol for the report.
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Modbus => "Modbus/TCP",
+            Kind::S7 => "Siemens S7",
+            Kind::Dnp3 => "DNP3",
+        }
+    }
+}
+
+// One piece of identification a device gave, e.g. `vendor` and `Schneider Electric`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Field {
+    pub name: &'static str,
+    pub value: String,
+}
+
+// What an industrial device answered the read-only identification requests.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Ics {
+    // The protocol it speaks, e.g. `Modbus/TCP`.
+    pub protocol: &'static str,
+    // What it told about itself, in the order it came.
+    pub identification: Vec<Field>,
+}
+
+// Function to identify every open TCP port in `results` that may run an industrial protocol:
+// Modbus/TCP on port 502, Siemens S7 on 102 and DNP3 on 20000, and ports --service-version
+// named one of them. Only requests that read are sent: Read Device Identification to Modbus
+// devices, the module and component identification lists to S7 CPUs and link status requests
+// to DNP3 outstations. With `safe`, for devices that fall over when rushed, one device is asked
+// at a time, every request waits `SAFE_GAP` first and fewer are sent: S7 CPUs only get asked
+// for the module list and DNP3 outstations only at the self address. Each request gets `wait`.
+pub async fn probe_all(
+    hosts: &[Target],
+    results: &mut [PortResult],
+    safe: bool,
+    settings: &Settings,
+    wait: Duration,
+) {
+    let parallel = if safe { 1 } else { settings.concurrency };
+    scanner::check_ports(
+        hosts,
+        results,
+        parallel,
+        |r, _| {
+            if r.protocol != Protocol::Tcp || r.state != PortState::Open {
+                return None;
+            }
+            match (
+                r.port,
+                r.findings.detected.as_ref().map(|s| s.name.as_str()),
+            ) {
+                (_, Some("mbap" | "modbus")) => Some(Kind::Modbus),
+                (_, Some("iso-tsap" | "s7comm")) => Some(Kind::S7),
+                (_, Some("dnp" | "dnp3")) => Some(Kind::Dnp3),
+                (_, Some(_)) => None,
+                (502, None) => Some(Kind::Modbus),
+                (102, None) => Some(Kind::S7),
+                (20000, None) => Some(Kind::Dnp3),
+                _ => None,
+            }
+        },
+        |addr, kind| async move { probe(addr, kind, safe, settings, wait).await.ok().flatten() },
+        |r, ics| r.findings.ics = ics,
+    )
+    .await;
+}
+
+// A connection to a device, pausing before each request if --safe asked for it.
+struct Device {
+    stream: TcpStream,
+    safe: bool,
+    wait: Duration,
+}
+
+impl Device {
+    // Function to send `request` and read the answer, framed by `length`, within `wait`.
+    async fn exchange(
+        &mut self,
+        request: &[u8],
+        length: fn(&[u8]) -> Option<usize>,
+    ) -> io::Result<Vec<u8>> {
+        if self.safe {
+            sleep(SAFE_GAP).await;
+        }
+        let deadline = Instant::now() + self.wait;
+        self.stream.write_all(request).await?;
+        let mut received = vec![];
+        let mut buffer = [0u8; 1024];
+        loop {
+            if let Some(length) = length(&received) {
+                received.truncate(length);
+                return Ok(received);
+            }
+            let read = timeout_at(deadline, self.stream.read(&mut buffer))
+                .await
+                .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+            if read == 0 || received.len() > 64 * 1024 {
+                return Err(io::ErrorKind::InvalidData.into());
+            }
+            received.extend_from_slice(&buffer[..read]);
+        }
+    }
+}
+
+// Function to connect to `addr` and ask the device there for its identification. Gives `None`
+// if it does not answer like a device of `kind`.
+async fn probe(
+    addr: SocketAddr,
+    kind: Kind,
+    safe: bool,
+    settings: &Settings,
+    wait: Duration,
+) -> io::Result<Option<Ics>> {
+    let socket = TcpSocket::from_std_stream(settings.socket(addr, Type::STREAM).await?.into());
+    let stream = timeout_at(Instant::now() + wait, socket.connect(addr))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    let mut device = Device { stream, safe, wait };
+    let identification = match kind {
+        Kind::Modbus => {
+            let answer = device.exchange(&modbus_request(1), mbap_length).await?;
+            modbus_identification(&answer)
+        }
+        Kind::S7 => s7(&mut device).await?,
+        Kind::Dnp3 => dnp3(&mut device).await?,
+    };
+    Ok(identification.map(|identification| Ics {
+        protocol: kind.name(),
+        identification,
+    }))
+}
+
+// Function to build a Modbus/TCP Read Device Identification request (function 43, MEI 14) for
+// the basic objects: vendor name, product code and revision.
+fn modbus_request(transaction: u16) -> Vec<u8> {
+    let mut request = transaction.to_be_bytes().to_vec();
+    request.extend_from_slice(&[0, 0, 0, 5, MODBUS_UNIT]); // Protocol 0, 5 bytes follow.
+    request.extend_from_slice(&[0x2b, 0x0e, 0x01, 0x00]);
+    request
+}
+
+// Function to tell how long the Modbus/TCP answer at the start of `data` is, from its MBAP
+// header.
+fn mbap_length(data: &[u8]) -> Option<usize> {
+    let length = 6 + usize::from(u16::from_be_bytes(data.get(4..6)?.try_into().ok()?));
+    (data.len() >= length).then_some(length)
+}
+
+// Function to read the objects of a Read Device Identification answer. An exception answer
+// still shows a Modbus device, one without identification.
+fn modbus_identification(answer: &[u8]) -> Option<Vec<Field>> {
+    if answer.get(2..4)? != [0, 0] {
+        return None;
+    }
+    match *answer.get(7)? {
+        0x2b if answer.get(8) == Some(&0x0e) => {}
+        0xab => {
+            let code = answer.get(8)?;
+            return Some(vec![Field {
+                name: "exception",
+                value: format!("code {}", code),
+            }]);
+        }
+        _ => return None,
+    }
+    let count = usize::from(*answer.get(13)?);
+    let mut objects = answer.get(14..)?;
+    let mut fields = vec![];
+    for _ in 0..count {
+        let (&[id, length], rest) = objects.split_first_chunk::<2>()?;
+        let value = rest.get(..usize::from(length))?;
+        let name = match id {
+            0 => "vendor",
+            1 => "product code",
+            2 => "revision",
+            3 => "vendor URL",
+            4 => "product name",
+            5 => "model name",
+            _ => "object",
+        };
+        fields.push(Field {
+            name,
+            value: printable(value),
+        });
+        objects = &rest[usize::from(length)..];
+    }
+    Some(fields)
+}
+
+// Function to open an S7 connection to the CPU in rack 0, slot 2 and read the module and, unless
+// `safe` keeps it to one list, the component identification.
+async fn s7(device: &mut Device) -> io::Result<Option<Vec<Field>>> {
+    let confirm = device.exchange(&COTP_CONNECT, tpkt_length).await?;
+    // A COTP connection confirm shows an ISO-TSAP service, not yet an S7 CPU.
+    if confirm.get(5) != Some(&0xd0) {
+        return Ok(None);
+    }
+    let setup = device.exchange(&S7_SETUP, tpkt_length).await?;
+    if setup.get(7) != Some(&0x32) {
+        return Ok(None);
+    }
+    let mut fields = vec![];
+    let module = device.exchange(&szl_request(0x0011), tpkt_length).await?;
+    for (index, item) in szl_items(&module, 0x0011).unwrap_or_default() {
+        let name = match index {
+            1 => "module",
+            6 => "hardware",
+            7 => "firmware",
+            _ => continue,
+        };
+        let value = match index {
+            // The firmware keeps its version in the last bytes, e.g. 0x56 0x02 0x06 0x05.
+            7 if item.len() >= 26 => format!("V{}.{}.{}", item[23], item[24], item[25]),
+            _ => printable(&item[..item.len().min(20)]),
+        };
+        fields.push(Field { name, value });
+    }
+    if !device.safe {
+        let component = device.exchange(&szl_request(0x001c), tpkt_length).await?;
+        for (index, item) in szl_items(&component, 0x001c).unwrap_or_default() {
+            let name = match index {
+                1 => "system name",
+                2 => "module name",
+                3 => "plant identification",
+                4 => "copyright",
+                5 => "serial number",
+                7 => "module type",
+                _ => continue,
+            };
+            fields.push(Field {
+                name,
+                value: printable(item),
+            });
+        }
+    }
+    Ok(Some(fields))
+}
+
+// Define the COTP connection request for the CPU in rack 0, slot 2 (TSAP 0x0102).
+const COTP_CONNECT: [u8; 22] = [
+    3, 0, 0, 22, 17, 0xe0, 0, 0, 0, 1, 0, 0xc0, 1, 10, 0xc1, 2, 1, 0, 0xc2, 2, 1, 2,
+];
+
+// Define the S7 setup communication job, asking for one job at a time and 480-byte PDUs.
+const S7_SETUP: [u8; 25] = [
+    3, 0, 0, 25, 2, 0xf0, 0x80, 0x32, 1, 0, 0, 0, 0, 0, 8, 0, 0, 0xf0, 0, 0, 1, 0, 1, 1, 0xe0,
+];
+
+// Function to build an S7 userdata request that reads the system status list `id`.
+fn szl_request(id: u16) -> Vec<u8> {
+    let mut request = vec![3, 0, 0, 33, 2, 0xf0, 0x80];
+    // Userdata, 8 bytes of parameters and 8 of data.
+    request.extend_from_slice(&[0x32, 7, 0, 0, 0, 0, 0, 8, 0, 8]);
+    // CPU functions, read SZL.
+    request.extend_from_slice(&[0, 1, 0x12, 4, 0x11, 0x44, 1, 0]);
+    request.extend_from_slice(&[0xff, 9, 0, 4]);
+    request.extend_from_slice(&id.to_be_bytes());
+    request.extend_from_slice(&1u16.to_be_bytes()); // Index 1.
+    request
+}
+
+// Function to tell how long the TPKT packet at the start of `data` is.
+fn tpkt_length(data: &[u8]) -> Option<usize> {
+    if *data.first()? != 3 {
+        return Some(data.len());
+    }
+    let length = usize::from(u16::from_be_bytes(data.get(2..4)?.try_into().ok()?));
+    (data.len() >= length).then_some(length)
+}
+
+// Function to read the items of the system status list `id` out of an S7 userdata answer, each
+// with its index and what follows it.
+fn szl_items(answer: &[u8], id: u16) -> Option<Vec<(u16, &[u8])>> {
+    // TPKT, COTP data and the S7 userdata header.
+    if answer.get(7)? != &0x32 || answer.get(8)? != &7 {
+        return None;
+    }
+    let parameters = usize::from(u16::from_be_bytes(answer.get(13..15)?.try_into().ok()?));
+    let data = answer.get(17 + parameters..)?;
+    // A return code of 0xff means success.
+    if *data.first()? != 0xff || u16::from_be_bytes(data.get(4..6)?.try_into().ok()?) != id {
+        return None;
+    }
+    let size = usize::from(u16::from_be_bytes(data.get(8..10)?.try_into().ok()?));
+    let count = usize::from(u16::from_be_bytes(data.get(10..12)?.try_into().ok()?));
+    if size < 2 {
+        return None;
+    }
+    let items = data
+        .get(12..)?
+        .chunks_exact(size)
+        .take(count)
+        .map(|item| (u16::from_be_bytes([item[0], item[1]]), &item[2..]))
+        .collect();
+    Some(items)
+}
+
+// Function to ask DNP3 outstations for their link status and report the first that answers,
+// with the address it answered from and the master it answered.
+async fn dnp3(device: &mut Device) -> io::Result<Option<Vec<Field>>> {
+    let outstations = if device.safe {
+        &DNP3_OUTSTATIONS[..1]
+    } else {
+        &DNP3_OUTSTATIONS[..]
+    };
+    for &outstation in outstations {
+        let answer = match device
+            .exchange(&link_status_request(outstation, DNP3_MASTER), dnp3_length)
+            .await
+        {
+            Ok(answer) => answer,
+            Err(error) if error.kind() == io::ErrorKind::TimedOut => continue,
+            Err(error) => return Err(error),
+        };
+        let Some((source, destination)) = link_status(&answer) else {
+            return Ok(None);
+        };
+        return Ok(Some(vec![
+            Field {
+                name: "outstation address",
+                value: source.to_string(),
+            },
+            Field {
+                name: "master address",
+                value: destination.to_string(),
+            },
+        ]));
+    }
+    Ok(None)
+}
+
+// Function to build a DNP3 link layer REQUEST_LINK_STATES frame from `source` to `destination`.
+fn link_status_request(destination: u16, source: u16) -> Vec<u8> {
+    // Start bytes, length, and control: from the master, primary, function 9.
+    let mut frame = vec![0x05, 0x64, 5, 0xc9];
+    frame.extend_from_slice(&destination.to_le_bytes());
+    frame.extend_from_slice(&source.to_le_bytes());
+    let crc = dnp3_crc(&frame);
+    frame.extend_from_slice(&crc.to_le_bytes());
+    frame
+}
+
+// Function to tell how long the DNP3 frame at the start of `data` is: a header block of 10
+// bytes and, for user data, blocks of up to 16 bytes, each with a CRC.
+fn dnp3_length(data: &[u8]) -> Option<usize> {
+    if data.get(..2)? != [0x05, 0x64] {
+        return Some(data.len());
+    }
+    let user = usize::from(*data.get(2)?).saturating_sub(5);
+    let length = 10 + user + 2 * user.div_ceil(16);
+    (data.len() >= length).then_some(length)
+}
+
+// Function to read a LINK_STATUS answer, giving its source and destination addresses.
+fn link_status(frame: &[u8]) -> Option<(u16, u16)> {
+    if frame.get(..2)? != [0x05, 0x64] || frame.len() < 10 {
+        return None;
+    }
+    if dnp3_crc(&frame[..8]).to_le_bytes() != frame[8..10] || frame[3] & 0x0f != 0x0b {
+        return None;
+    }
+    let destination = u16::from_le_bytes([frame[4], frame[5]]);
+    let source = u16::from_le_bytes([frame[6], frame[7]]);
+    Some((source, destination))
+}
+
+// Function to compute the DNP3 CRC of `data`: CRC-16 with the reflected polynomial 0xa6bc,
+// complemented.
+fn dnp3_crc(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= u16::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xa6bc
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+// Function to turn the bytes of an identification string into text, up to the first zero and
+// without control characters or padding.
+fn printable(value: &[u8]) -> String {
+    let end = value.iter().position(|&b| b == 0).unwrap_or(value.len());
+    String::from_utf8_lossy(&value[..end])
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_modbus_identification() {
+        assert_eq!(
+            modbus_request(1),
+            b"\x00\x01\x00\x00\x00\x05\xff\x2b\x0e\x01\x00"
+        );
+        let answer = b"\x00\x01\x00\x00\x00\x2b\xff\x2b\x0e\x01\x01\x00\x00\x03\
+                       \x00\x12Schneider Electric\x01\x07BMX P34\x02\x04v3.1";
+        assert_eq!(mbap_length(answer), Some(answer.len()));
+        let fields = modbus_identification(answer).unwrap();
+        let values: Vec<(&str, &str)> = fields
+            .iter()
+            .map(|field| (field.name, field.value.as_str()))
+            .collect();
+        assert_eq!(
+            values,
+            [
+                ("vendor", "Schneider Electric"),
+                ("product code", "BMX P34"),
+                ("revision", "v3.1")
+            ]
+        );
+        assert_eq!(
+            modbus_identification(b"\x00\x01\x00\x00\x00\x03\xff\xab\x01").unwrap()[0].value,
+            "code 1"
+        );
+        assert_eq!(modbus_identification(&answer[..20]), None);
+    }
+
+    #[test]
+    fn reads_system_status_lists() {
+        let request = szl_request(0x0011);
+        assert_eq!(request.len(), 33);
+        assert_eq!(tpkt_length(&request), Some(33));
+
+        let mut items = vec![];
+        for (index, text, version) in [
+            (1u16, "6ES7 315-2EH14-0AB0 ", [0, 0, 0, 0]),
+            (7, "                    ", [0x56, 3, 2, 6]),
+        ] {
+            items.extend_from_slice(&index.to_be_bytes());
+            items.extend_from_slice(text.as_bytes());
+            items.extend_from_slice(&[0, 0xc0]);
+            items.extend_from_slice(&version);
+        }
+        let mut answer = vec![3, 0, 0, 0, 2, 0xf0, 0x80, 0x32, 7, 0, 0, 0, 0, 0, 12, 0, 0];
+        answer.extend_from_slice(&[0; 12]); // Parameters of the answer.
+        answer.extend_from_slice(&[0xff, 9, 0, 0, 0, 0x11, 0, 1, 0, 28, 0, 2]);
+        answer.extend_from_slice(&items);
+        let items = szl_items(&answer, 0x0011).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].0, 1);
+        assert_eq!(printable(&items[0].1[..20]), "6ES7 315-2EH14-0AB0");
+        assert_eq!(&items[1].1[23..26], [3, 2, 6]);
+        assert_eq!(szl_items(&answer, 0x001c), None);
+    }
+
+    #[test]
+    fn reads_dnp3_link_status() {
+        // The CRC of a link status request from master 1 to outstation 10.
+        let request = link_status_request(10, 1);
+        assert_eq!(request, b"\x05\x64\x05\xc9\x0a\x00\x01\x00\xfe\xda");
+        let mut answer = vec![0x05, 0x64, 5, 0x0b, 1, 0, 10, 0];
+        let crc = dnp3_crc(&answer);
+        answer.extend_from_slice(&crc.to_le_bytes());
+        assert_eq!(dnp3_length(&answer), Some(10));
+        assert_eq!(link_status(&answer), Some((10, 1)));
+        answer[9] ^= 1;
+        assert_eq!(link_status(&answer), None);
+    }
+}
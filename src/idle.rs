@@ -0,0 +1,275 @@
+use crate::raw; // Import the packet builders and checksums shared with the other raw scans.
+use crate::scanner::{PortState, Source}; // Import the states of probed ports and the source to send from.
+use rand::Rng; // Import `Rng` to draw sequence numbers, IP identifications and ports.
+use socket2::{Domain, Protocol, Socket, Type}; // Import socket2 to open raw sockets.
+use std::io; // Import I/O errors for socket failures.
+use std::mem::MaybeUninit; // Import `MaybeUninit` for the receive buffer.
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4}; // Import address types for the zombie and the probed ports.
+use std::sync::{Arc, Mutex}; // Import shared state between the probes and the receiving thread.
+use std::time::Duration; // Import `Duration` for reply timeouts.
+use tokio::sync::oneshot; // Import oneshot channels to hand each IP ID to its probe.
+use tokio::time::{sleep, timeout}; // Import `sleep` and `timeout` to pace the samples of the zombie.
+
+// Define the port of the zombie that is sampled when --idle-zombie names none.
+const ZOMBIE_PORT: u16 = 80;
+
+// Define how many IP IDs of the zombie are sampled before the scan, to check it is fit for one.
+const SAMPLES: usize = 4;
+
+// Define the largest step between two IP IDs sampled before the scan that still counts as idle.
+const MAX_STEP: u16 = 5;
+
+// Define how long the target gets to answer the zombie before its IP ID is sampled again.
+const SETTLE: Duration = Duration::from_millis(250);
+
+// Define how often a port is measured again when the zombie sent other packets in between.
+const MEASUREMENTS: usize = 3;
+
+// The protocol number of TCP.
+const TCP: u8 = libc::IPPROTO_TCP as u8;
+
+// The sample of the zombie waiting for its RST: the acknowledgment number of the SYN/ACK.
+type Pending = Mutex<Option<(u32, oneshot::Sender<u16>)>>;
+
+// A third-party host (the zombie) whose IP IDs reveal the ports of the targets: it answers the
+// SYN/ACK of an open port to the SYNs sent in its name with a RST, using up one IP ID, while
+// the RST of a closed port (or silence) costs it none.
+#[derive(Debug)]
+pub struct Zombie {
+    // A raw socket of protocol IPPROTO_RAW, to send packets with any source address.
+    send: Socket,
+    addr: SocketAddrV4,
+    // The local address towards the zombie, and the port it is sampled from.
+    local: Ipv4Addr,
+    port: u16,
+    // The source the SYNs in the zombie's name take their port and TTL from, if it fixes them.
+    source: Source,
+    pending: Arc<Pending>,
+}
+
+// Function to read the --idle-zombie argument: an IPv4 address, optionally with a port.
+pub fn parse(spec: &str) -> Result<SocketAddrV4, String> {
+    match (spec.parse::<SocketAddrV4>(), spec.parse::<Ipv4Addr>()) {
+        (Ok(addr), _) => Ok(addr),
+        (_, Ok(ip)) => Ok(SocketAddrV4::new(ip, ZOMBIE_PORT)),
+        _ => Err(format!(
+            "{} is not a valid zombie (must be an IPv4 address, optionally with a :port)",
+            spec
+        )),
+    }
+}
+
+impl Zombie {
+    // Function to open the raw sockets, which fails without root or the CAP_NET_RAW capability.
+    pub fn open(source: &Source, addr: SocketAddrV4) -> Result<Zombie, String> {
+        let denied = |e| raw::denied("--idle-zombie", e);
+        let send = Socket::new(
+            Domain::IPV4,
+            Type::RAW,
+            Some(Protocol::from(libc::IPPROTO_RAW)),
+        )
+        .map_err(denied)?;
+        source
+            .bind_to(&send, IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+            .map_err(denied)?;
+        let replies = Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::TCP)).map_err(denied)?;
+        let local = raw::local_v4(source, *addr.ip())
+            .map_err(|e| format!("--idle-zombie has no route to {}: {}", addr.ip(), e))?;
+        let port = rand::thread_rng().gen_range(32768..61000);
+
+        let pending: Arc<Pending> = Arc::default();
+        let waiting = Arc::clone(&pending);
+        std::thread::Builder::new()
+            .name("zombie replies".to_string())
+            .spawn(move || receive(&replies, addr, port, &waiting))
+            .map_err(|e| format!("--idle-zombie cannot start its receiver: {}", e))?;
+        Ok(Zombie {
+            send,
+            addr,
+            local,
+            port,
+            source: source.clone(),
+            pending,
+        })
+    }
+
+    // Function to check that the zombie answers and counts its IP IDs up one by one, which hosts
+    // with random or per-destination IP IDs (like current Linux) do not.
+    pub async fn check(&self, wait: Duration) -> Result<(), String> {
+        let mut ids = Vec::with_capacity(SAMPLES);
+        for _ in 0..SAMPLES {
+            match self.ip_id(wait).await {
+                Ok(Some(id)) => ids.push(id),
+                Ok(None) => {
+                    return Err(format!(
+                        "Zombie {} did not answer a SYN/ACK with a RST, pick a host that is up \
+                         and does not filter port {}",
+                        self.addr.ip(),
+                        self.addr.port()
+                    ))
+                }
+                Err(e) => return Err(format!("Cannot probe zombie {}: {}", self.addr, e)),
+            }
+        }
+        let steady = ids
+            .windows(2)
+            .all(|pair| (1..=MAX_STEP).contains(&pair[1].wrapping_sub(pair[0])));
+        if !steady {
+            return Err(format!(
+                "Zombie {} is not idle or does not count its IP IDs up (it sent {:?}), pick an idle \
+                 host with a global IP ID counter",
+                self.addr.ip(),
+                ids
+            ));
+        }
+        Ok(())
+    }
+
+    // Function to find out whether `target` is open: sample the IP ID of the zombie, send a SYN
+    // to the target in the zombie's name, and sample it again. One step up means the zombie only
+    // answered the second sample (closed or filtered); two steps that it also reset the SYN/ACK
+    // of an open port. Gives `None` if the zombie stopped answering or never stayed idle.
+    pub async fn probe(
+        &self,
+        target: SocketAddrV4,
+        wait: Duration,
+    ) -> io::Result<Option<PortState>> {
+        for _ in 0..MEASUREMENTS {
+            let Some(before) = self.ip_id(wait).await? else {
+                return Ok(None);
+            };
+            let (seq, id, from) = {
+                let mut rng = rand::thread_rng();
+                (
+                    rng.gen::<u32>(),
+                    rng.gen::<u16>(),
+                    self.source
+                        .port()
+                        .unwrap_or_else(|| rng.gen_range(32768..61000)),
+                )
+            };
+            let mut syn = raw::segment(from, target.port(), seq, 0, raw::SYN);
+            raw::seal(&mut syn, 16, TCP, *self.addr.ip(), *target.ip());
+            let mut packet = raw::ipv4(*self.addr.ip(), *target.ip(), TCP, id, &syn);
+            self.source.limit_packet(&mut packet);
+            self.send
+                .send_to(&packet, &SocketAddr::from((*target.ip(), 0)).into())?;
+            sleep(SETTLE.min(wait)).await;
+            let Some(after) = self.ip_id(wait).await? else {
+                return Ok(None);
+            };
+            match after.wrapping_sub(before) {
+                1 => return Ok(Some(PortState::ClosedFiltered)),
+                2 => return Ok(Some(PortState::Open)),
+                // The zombie sent other packets in between, so this measurement tells nothing.
+                _ => continue,
+            }
+        }
+        Ok(None)
+    }
+
+    // Function to send the zombie a SYN/ACK it did not expect and read the IP ID of its RST.
+    // Gives `None` if nothing came back in time.
+    async fn ip_id(&self, wait: Duration) -> io::Result<Option<u16>> {
+        let (seq, ack, id) = rand::thread_rng().gen::<(u32, u32, u16)>();
+        let mut segment = raw::segment(self.port, self.addr.port(), seq, ack, raw::SYN | raw::ACK);
+        raw::seal(&mut segment, 16, TCP, self.local, *self.addr.ip());
+        let packet = raw::ipv4(self.local, *self.addr.ip(), TCP, id, &segment);
+
+        let (answer, reply) = oneshot::channel();
+        *self.pending.lock().unwrap() = Some((ack, answer));
+        self.send
+            .send_to(&packet, &SocketAddr::from((*self.addr.ip(), 0)).into())?;
+        Ok(timeout(wait, reply).await.ok().and_then(Result::ok))
+    }
+}
+
+// Function run by the receiving thread: hands the IP ID of every RST the zombie sends to `port`
+// to the sample waiting for it.
+fn receive(socket: &Socket, zombie: SocketAddrV4, port: u16, pending: &Pending) {
+    let mut buffer = [MaybeUninit::<u8>::uninit(); 65536];
+    loop {
+        let Ok(length) = socket.recv(&mut buffer) else {
+            continue;
+        };
+        // SAFETY: `recv` initialized the first `length` bytes.
+        let packet = unsafe { std::slice::from_raw_parts(buffer.as_ptr().cast::<u8>(), length) };
+        if let Some((seq, id)) = reset(packet, zombie, port) {
+            let mut pending = pending.lock().unwrap();
+            // A RST to a SYN/ACK takes its acknowledgment number as the sequence number.
+            if pending.as_ref().is_some_and(|(ack, _)| *ack == seq) {
+                if let Some((_, answer)) = pending.take() {
+                    let _ = answer.send(id);
+                }
+            }
+        }
+    }
+}
+
+// Function to read a RST the zombie sent to `port`: its sequence number and the IP ID it went
+// out with.
+fn reset(packet: &[u8], zombie: SocketAddrV4, port: u16) -> Option<(u32, u16)> {
+    let header = usize::from(packet.first()? & 0x0f) * 4;
+    let from: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+    let id = u16::from_be_bytes(packet.get(4..6)?.try_into().ok()?);
+    let segment = packet.get(header..)?;
+    let half = |at: usize| {
+        Some(u16::from_be_bytes(
+            segment.get(at..at + 2)?.try_into().ok()?,
+        ))
+    };
+    if Ipv4Addr::from(from) != *zombie.ip()
+        || half(0)? != zombie.port()
+        || half(2)? != port
+        || segment.get(13)? & raw::RST == 0
+    {
+        return None;
+    }
+    Some((u32::from_be_bytes(segment.get(4..8)?.try_into().ok()?), id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_zombies() {
+        assert_eq!(
+            parse("10.0.0.7"),
+            Ok(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 7), 80))
+        );
+        assert_eq!(
+            parse("10.0.0.7:443"),
+            Ok(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 7), 443))
+        );
+        assert!(parse("zombie.example").is_err());
+        assert!(parse("::1").is_err());
+    }
+
+    #[test]
+    fn reads_resets() {
+        let zombie = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 80);
+        let mut rst = raw::segment(80, 40000, 0x1234_5678, 0, raw::RST);
+        let packet = raw::ipv4(
+            Ipv4Addr::new(10, 0, 0, 2),
+            Ipv4Addr::new(10, 0, 0, 1),
+            TCP,
+            0x0a0b,
+            &rst,
+        );
+        assert_eq!(reset(&packet, zombie, 40000), Some((0x1234_5678, 0x0a0b)));
+        // Not our port, not the zombie, or not a RST.
+        assert_eq!(reset(&packet, zombie, 40001), None);
+        let other = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 3), 80);
+        assert_eq!(reset(&packet, other, 40000), None);
+        rst[13] = raw::ACK;
+        let packet = raw::ipv4(
+            Ipv4Addr::new(10, 0, 0, 2),
+            Ipv4Addr::new(10, 0, 0, 1),
+            TCP,
+            1,
+            &rst,
+        );
+        assert_eq!(reset(&packet, zombie, 40000), None);
+    }
+}
@@ -0,0 +1,66 @@
+#[cfg(unix)]
+use std::io::Read; // Import `Read` to take key presses from stdin.
+use tokio::sync::mpsc; // Import channels to hand key presses to the async side.
+
+// Restores the terminal settings saved by `listen` when dropped.
+#[cfg(unix)]
+pub struct Terminal {
+    saved: libc::termios,
+}
+
+#[cfg(unix)]
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        // SAFETY: `saved` holds the settings read from stdin by tcgetattr.
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved) };
+    }
+}
+
+// Function to read single key presses from the terminal while a scan runs.
+// Switches stdin to unbuffered, silent input (Ctrl-C keeps working) and returns the keys
+// together with a guard restoring the terminal, or `None` if stdin is not a terminal.
+#[cfg(unix)]
+pub fn listen() -> Option<(mpsc::UnboundedReceiver<char>, Terminal)> {
+    // SAFETY: isatty only inspects the descriptor.
+    if unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 {
+        return None;
+    }
+    // SAFETY: an all-zero termios is valid storage for tcgetattr to fill in.
+    let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+    // SAFETY: `saved` is valid storage for the settings of stdin.
+    if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } != 0 {
+        return None;
+    }
+    let mut raw = saved;
+    raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+    raw.c_cc[libc::VMIN] = 1;
+    raw.c_cc[libc::VTIME] = 0;
+    // SAFETY: `raw` is a copy of valid settings with only the input mode changed.
+    if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+        return None;
+    }
+
+    // Reading stdin blocks, so it gets a thread of its own; it ends with the process.
+    let (tx, rx) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        let mut byte = [0u8; 1];
+        while std::io::stdin().read_exact(&mut byte).is_ok() {
+            if tx.send(byte[0] as char).is_err() {
+                break;
+            }
+        }
+    });
+    Some((rx, Terminal { saved }))
+}
+
+// Stands in for the terminal guard on systems whose key presses are not read, which never
+// needs one.
+#[cfg(not(unix))]
+pub enum Terminal {}
+
+// Function to read single key presses, which needs the terminal settings of Unix systems, so
+// other systems give `None`.
+#[cfg(not(unix))]
+pub fn listen() -> Option<(mpsc::UnboundedReceiver<char>, Terminal)> {
+    None
+}
@@ -0,0 +1,36 @@
+// Function to raise the soft limit on open files to the hard limit where permitted,
+// returning the limit that is in effect afterwards (or `None` if it could not be read).
+#[cfg(unix)]
+pub fn raise_open_files() -> Option<libc::rlim_t> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `limit` is a valid rlimit for getrlimit to fill in.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return None;
+    }
+    if limit.rlim_cur == libc::RLIM_INFINITY {
+        return Some(libc::rlim_t::MAX);
+    }
+
+    if limit.rlim_max > limit.rlim_cur {
+        let raised = libc::rlimit {
+            rlim_cur: limit.rlim_max,
+            rlim_max: limit.rlim_max,
+        };
+        // SAFETY: `raised` is a valid rlimit. Failure (e.g. an unlimited hard limit the kernel
+        // will not grant) simply leaves the old soft limit in place.
+        if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &raised) } == 0 {
+            limit.rlim_cur = limit.rlim_max;
+        }
+    }
+    Some(limit.rlim_cur)
+}
+
+// Function to raise the limit on open files, which only Unix systems have, so other systems
+// give `None`.
+#[cfg(not(unix))]
+pub fn raise_open_files() -> Option<u64> {
+    None
+}
@@ -1,24 +1,573 @@
+mod advertised; // mDNS, SSDP and UPnP discovery of the hosts on a local network.
+#[cfg(target_os = "linux")]
+mod arp; // ARP requests on local networks.
+mod banner; // Banners read off open ports.
+mod checkpoint; // Saving and resuming scan progress.
+mod config; // Config file loading.
+mod containers; // Unauthenticated access checks of Docker and Kubernetes APIs.
+mod datastore; // Unauthenticated access checks of Redis, Memcached and MongoDB.
+mod discovery; // Host discovery before the port scan.
+mod dns; // Minimal DNS wire-format client.
+mod ftp; // Anonymous FTP login checks.
+mod handshakes; // TLS handshakes with open ports.
+mod hello; // Hand-built TLS ClientHello and ServerHello messages.
+mod http; // Minimal HTTP/1.1 client.
+mod ics; // Read-only identification of Modbus, S7 and DNP3 devices.
+#[cfg(target_os = "linux")]
+mod idle; // Idle scans through a zombie host.
+mod keys; // Interactive key presses during a scan.
+mod limits; // Process resource limits.
+mod md5; // MD5 digests of JA3S fingerprints.
+mod mqtt; // Anonymous access checks of MQTT brokers.
+mod nameserver; // Version and open-resolver checks of DNS servers.
+#[cfg(target_os = "linux")]
+mod ndp; // IPv6 neighbor discovery on local networks.
+mod ntp; // NTP version and amplification checks.
+#[cfg(target_os = "linux")]
+mod pcap; // Capture of the scan traffic to pcap files.
+mod ports; // Port specification parsing.
+mod privileges; // Dropping root once the raw sockets are open.
+#[cfg(target_os = "linux")]
+mod protocols; // IP protocol scans.
+#[cfg(target_os = "linux")]
+mod raw; // Raw TCP sockets for half-open probes.
+mod rdp; // RDP security and NLA checks.
+mod regex; // Regular expressions for the probe database.
+mod report; // Printing of scan results.
+mod resolver; // Hostname resolution backends.
+mod scanner; // Port probes.
+mod services; // Embedded port to service and IP protocol name tables.
+mod smb; // SMB dialect, signing and host name checks.
+mod smtp; // SMTP capability and open-relay checks.
+mod snmp; // SNMP community checks.
+mod ssh; // SSH key exchange audits of open ports.
+mod targets; // Target expansion (IPs, CIDR blocks, hostnames).
+mod tls; // TLS connections.
+#[cfg(target_os = "linux")]
+mod traceroute; // TCP traceroutes to scanned hosts.
+mod udp; // Payloads for UDP probes.
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring; // io_uring connect backend.
+mod versions; // Service and version detection with a probe database.
+mod web; // HTTP probes of open web ports.
+mod x509; // X.509 certificate parsing.
+
 use bpaf::Bpaf; // Import the `bpaf` crate for command-line argument parsing.
+use futures::StreamExt; // Import `StreamExt` to consume scan results as they arrive.
+use rand::rngs::StdRng; // Import a seedable generator for reproducible probe orders.
+use rand::seq::SliceRandom; // Import `SliceRandom` to shuffle the probe order.
+use rand::SeedableRng; // Import `SeedableRng` to build the generator from --seed.
+use std::collections::HashSet; // Import `HashSet` for the ports the second pass heard from.
 use std::io::{self, Write}; // Import I/O operations for writing to stdout.
-use std::net::{IpAddr, Ipv4Addr}; // Import IP address types for network operations.
-use std::sync::mpsc::{channel, Sender}; // Import multi-producer, single-consumer channels for inter-thread communication.
-use tokio::net::TcpStream; // Import the asynchronous `TcpStream` for networking.
-use tokio::task; // Import `tokio::task` to spawn asynchronous tasks.
+use std::pin::pin; // Import `pin!` to poll the result stream in place.
+use std::time::{Duration, Instant}; // Import `Duration` for connection timeouts and `Instant` for elapsed time.
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind}; // Import SIGTERM handling to stop scans cleanly.
+use tokio::sync::mpsc; // Import async channels to hand results from the probes to the output.
 
 // Define the maximum port number (65535).
 const MAX: u16 = 65535;
 
-// Define a fallback IP address for cases where none is provided by the user.
-const IPFALLBACK: IpAddr = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+// Define how many file descriptors to keep free for stdio, DNS lookups and the like.
+const RESERVED_FDS: usize = 32;
+
+// Define how many results may wait for the output before probing pauses.
+const RESULT_BUFFER: usize = 1024;
+
+// Define a fallback address for cases where none is provided by the user.
+const IPFALLBACK: &str = "127.0.0.1";
 
 // Structure to hold command-line arguments.
 #[derive(Debug, Clone, Bpaf)] // Derive debugging and cloning traits for this structure and enable `bpaf` processing.
 #[bpaf(options)] // Mark this struct as being used for `bpaf` options parsing.
 pub struct Arguments {
-    // Address argument with short and long flags (-a, --address). Falls back to `IPFALLBACK` if not provided.
-    #[bpaf(long, short, argument("Address"), fallback(IPFALLBACK))]
-    /// The address that you want to sniff. Must be a valid IPv4 address. Falls back to 127.0.0.1.
-    pub address: IpAddr,
+    // Config file argument (--config).
+    #[bpaf(long, argument("FILE"))]
+    /// Read settings such as custom port groups from this TOML file instead of ~/.config/port_sniffer/config.toml.
+    pub config: Option<String>,
+
+    // Custom service names argument (--services-file).
+    #[bpaf(long, argument("FILE"))]
+    /// Read extra service names from this file, one `name port[/protocol]` per line as in /etc/services, e.g. `billing 8081`, with the protocol defaulting to tcp. Its names win over the built-in ones in the output (see --show-services) and can be used in port lists such as -p billing.
+    pub services_file: Option<String>,
+
+    // Address list argument with short and long flags (-a, --address). Falls back to `IPFALLBACK` if no targets are given.
+    #[bpaf(long, short, argument("Address"))]
+    /// The addresses that you want to sniff, separated by commas. Each one is an IPv4 or IPv6 address (with an optional %zone), a CIDR block, an octet range such as 10.0.0.1-50 or a hostname, optionally followed by tags such as 10.0.0.5#prod#web. Falls back to 127.0.0.1.
+    pub address: Option<String>,
+
+    // Target file argument with short and long flags (-i, --input-list).
+    #[bpaf(long, short, argument("FILE"))]
+    /// Read targets from a file, one per line (IP addresses, CIDR blocks or hostnames such as a subdomain list). Hostnames sharing an address are scanned once. A .csv file holds target,tag1;tag2 rows. Use - for stdin.
+    pub input_list: Option<String>,
+
+    // Target file format argument (--input-format).
+    #[bpaf(long, argument("FORMAT"))]
+    /// How to read the --input-list file and stdin: list or csv. By default only files ending in .csv are read as CSV.
+    pub input_format: Option<targets::InputFormat>,
+
+    // Stdin targets argument (--stdin). Same as `-a -`.
+    #[bpaf(long)]
+    /// Read targets from stdin, one per line.
+    pub stdin: bool,
+
+    // Exclusion list argument (--exclude).
+    #[bpaf(long, argument("TARGETS"))]
+    /// Addresses to skip, separated by commas. Each one is an IP address, a CIDR block or a hostname.
+    pub exclude: Option<String>,
+
+    // Exclusion file argument (--exclude-file).
+    #[bpaf(long, argument("FILE"))]
+    /// Read addresses to skip from a file, one per line.
+    pub exclude_file: Option<String>,
+
+    // DNS server argument (--dns-server).
+    #[bpaf(long, argument("ADDRESS"))]
+    /// Resolve hostnames through this DNS server (IP or IP:port) instead of the system resolver.
+    pub dns_server: Option<String>,
+
+    // DNS-over-HTTPS argument (--doh).
+    #[bpaf(long, argument("URL"))]
+    /// Resolve hostnames through this DNS-over-HTTPS endpoint, e.g. https://cloudflare-dns.com/dns-query.
+    pub doh: Option<String>,
+
+    // Reverse DNS argument (--resolve).
+    #[bpaf(long)]
+    /// Look up the DNS name (PTR record) of each host with results and show it next to the IP.
+    pub resolve: bool,
+
+    // Source address argument (--source-ip).
+    #[bpaf(long, argument("ADDRESS"))]
+    /// Send the probes from this local address instead of the one the routing table picks. Give an IPv4 and an IPv6 address, separated by a comma, to scan both families.
+    pub source_ip: Option<String>,
+
+    // Egress interface argument (--interface).
+    #[bpaf(long, argument("NAME"))]
+    /// Send the probes out of this network interface, e.g. eth1, whatever the routing table says. May need CAP_NET_RAW.
+    pub interface: Option<String>,
+
+    // TTL argument (--ttl).
+    #[bpaf(long, argument("N"))]
+    /// Send the port probes with this TTL (IPv6 hop limit) instead of the system's default, so they travel through at most N-1 routers. Scanning with the host's distance (see --traceroute) and with one less tells whether the probes reach the host at all, e.g. to find out if a firewall on the way or the host itself filters them. Host discovery and --traceroute keep their own TTLs.
+    pub ttl: Option<u8>,
+
+    // Source port argument (--source-port).
+    #[bpaf(long, argument("PORT"))]
+    /// Send the probes from this local port, e.g. 53 or 20, which some firewalls let through as DNS or FTP data replies. Applies to connect, UDP, SYN and the other raw-socket scans. Ports below 1024 need root or the CAP_NET_BIND_SERVICE capability.
+    pub source_port: Option<u16>,
+
+    // Happy Eyeballs argument (--happy-eyeballs).
+    #[bpaf(long)]
+    /// For hostnames with both IPv6 and IPv4 addresses, probe each port once, racing the two families as in RFC 8305 (IPv6 first, IPv4 250 ms later), and report which family answered.
+    pub happy_eyeballs: bool,
+
+    // List scan argument (--list-scan), like nmap's -sL.
+    #[bpaf(long)]
+    /// Only print the targets that would be scanned (after expansion, exclusions and DNS resolution), without probing them.
+    pub list_scan: bool,
+
+    // Port list argument with short and long flags (-p, --ports). Takes precedence over --start/--end.
+    #[bpaf(long, short, argument("PORTS"))]
+    /// The ports to sniff, e.g. 22,https,8000-8100 or @web, or - for all of them. T: and U: mark the TCP and UDP ports of a combined scan, as in 22,T:80,U:53,161, where the ports before the first one count for both. Overrides --start and --end.
+    pub ports: Option<String>,
+
+    // UDP scan argument (--udp).
+    #[bpaf(long)]
+    /// Scan UDP ports instead of TCP. Well-known services (DNS, NTP, SNMP, NetBIOS, SSDP, QUIC on 443, ...) get a request they answer; a reply means open and an ICMP port unreachable closed. Silent ports are reported as filtered, since they may also be open. Hosts send few ICMP errors per second, so use --rate for accurate closed ports.
+    pub udp: bool,
+
+    // TCP port list argument (--tcp-ports).
+    #[bpaf(long, argument("PORTS"))]
+    /// The TCP ports to sniff, using the same syntax as --ports. Together with --udp-ports, both are scanned in one run, the TCP ports first, and each result is labeled with its protocol.
+    pub tcp_ports: Option<String>,
+
+    // UDP port list argument (--udp-ports).
+    #[bpaf(long, argument("PORTS"))]
+    /// The UDP ports to sniff, using the same syntax as --ports, e.g. 53,123,161. On its own it works like --udp with --ports. With a raw-socket scan such as --syn, only the TCP ports get raw probes.
+    pub udp_ports: Option<String>,
+
+    // SYN scan argument (--syn), like nmap's -sS.
+    #[bpaf(long)]
+    /// Send only the SYN of each connection from a raw socket and read the SYN/ACK or RST that comes back, without completing the handshake. Much faster on large port ranges and leaves nothing in the targets' accept queues. Needs root or the CAP_NET_RAW capability, without which it falls back to a connect scan with a warning.
+    pub syn: bool,
+
+    // FIN scan argument (--fin), like nmap's -sF.
+    #[bpaf(long)]
+    /// Send a lone FIN from a raw socket. Closed ports answer with a RST, open ones stay silent and are reported as open|filtered, as a filter dropping the probe looks the same. Gets through packet filters that only block SYNs, but Windows and some other stacks reset every port. Needs root or the CAP_NET_RAW capability.
+    pub fin: bool,
+
+    // NULL scan argument (--null), like nmap's -sN.
+    #[bpaf(long)]
+    /// Like --fin, with no TCP flags set at all.
+    pub null: bool,
+
+    // Xmas scan argument (--xmas), like nmap's -sX.
+    #[bpaf(long)]
+    /// Like --fin, with the FIN, PSH and URG flags set.
+    pub xmas: bool,
+
+    // ACK scan argument (--ack), like nmap's -sA.
+    #[bpaf(long)]
+    /// Send a lone ACK from a raw socket to map firewall rules rather than services. Every port answers it with a RST, open or closed, so ports that do are reported as unfiltered and silent ones as filtered. Needs root or the CAP_NET_RAW capability.
+    pub ack: bool,
+
+    // Window scan argument (--window), like nmap's -sW.
+    #[bpaf(long)]
+    /// Like --ack, but reads the window of the RST that comes back: some stacks (older BSDs, some embedded devices) answer open ports with a window above zero and closed ones with zero. Most stacks send zero either way, so every port looks closed. Needs root or the CAP_NET_RAW capability.
+    pub window: bool,
+
+    // Maimon scan argument (--maimon), like nmap's -sM.
+    #[bpaf(long)]
+    /// Like --fin, with the FIN and ACK flags set. Most stacks reset every port, but BSD-derived ones drop the probe on open ports, which are then reported as open|filtered.
+    pub maimon: bool,
+
+    // SCTP INIT scan argument (--sctp), like nmap's -sY.
+    #[bpaf(long)]
+    /// Scan SCTP ports instead of TCP, sending an INIT from a raw socket: an INIT-ACK means open, an ABORT closed and silence filtered. Without root or the CAP_NET_RAW capability, uses the system's SCTP stack to set up full associations where it has one.
+    pub sctp: bool,
+
+    // IP protocol scan argument (--ip-protocols), like nmap's -sO.
+    #[bpaf(long)]
+    /// Find the IP protocols (ICMP, IGMP, GRE, ESP, ...) an IPv4 host speaks instead of its ports. --ports then takes protocol numbers or names, e.g. 1,6,gre,50-51, and defaults to all 256. A protocol is open if the host answers in it, closed if it reports the protocol unreachable, and open|filtered if it stays silent. Needs root or the CAP_NET_RAW capability.
+    pub ip_protocols: bool,
+
+    // Idle scan argument (--idle-zombie), like nmap's -sI.
+    #[bpaf(long, argument("HOST[:PORT]"))]
+    /// Scan without sending a single packet from your own address: SYNs go out in the name of this IPv4 host (the zombie, sampled on port 80 unless given), and the IP IDs of its RSTs to your probes show whether the target answered it, one port at a time. Open ports are reported as open, the others as closed|filtered. The zombie has to be idle and count its IP IDs up globally, which is checked first. Needs root or the CAP_NET_RAW capability.
+    pub idle_zombie: Option<String>,
+
+    // Decoy argument with short and long flags (-D, --decoys), like nmap's.
+    #[bpaf(short('D'), long, argument("LIST"))]
+    /// Send every probe of a raw-socket scan (--syn, --fin, --null, --xmas, --ack, --window, --maimon, --sctp) from these addresses as well, e.g. 10.0.0.7,ME,10.0.0.9, so the logs of the targets show several scanners. ME is the real probe, put in at a random place if left out; decoys of the other family than a target sit its probes out. The decoys should be up, or they give away the real scanner, and the routers on the way may drop forged packets. Needs root or the CAP_NET_RAW capability.
+    pub decoys: Option<String>,
+
+    // Host discovery argument (--ping-first).
+    #[bpaf(long)]
+    /// Find out which hosts are up before scanning their ports, and skip the others: a host is up if it answers an ICMP echo request or a TCP connect to port 443 or 80, even with a refusal. Hosts on a local network are asked over ARP or IPv6 neighbor discovery instead, which they answer even behind a firewall, and their MAC address is reported. A multicast target such as ff02::1%eth0 (all IPv6 nodes on eth0) stands for every host that answers a ping to it. Saves hours on sparse networks, but misses remote hosts that drop all of these. Pinging needs root, the CAP_NET_RAW capability or a group in net.ipv4.ping_group_range; without it only the TCP ports are tried.
+    pub ping_first: bool,
+
+    // Ping scan argument (--ping-scan), like nmap's -sn.
+    #[bpaf(long)]
+    /// Only find out which hosts are up, the way --ping-first does, and list them with the MAC address of those on a local network, without scanning a single port.
+    pub ping_scan: bool,
+
+    // Local discovery argument (--local-discovery).
+    #[bpaf(long)]
+    /// Before the scan, ask the local IPv4 network over mDNS and SSDP what it offers and listen to its announcements for 3 seconds, fetching the UPnP descriptions the devices point to. The hosts found join the targets, under their mDNS host names, and the TCP ports they advertise services on, e.g. 631 for a printer's `_ipp._tcp`, join the ports of a TCP scan. Each host is reported with what it advertised. Without any target given, only the hosts found are scanned.
+    pub local_discovery: bool,
+
+    // Banner grabbing argument (--banners).
+    #[bpaf(long)]
+    /// After the scan, connect to each open TCP port once more and show the first line the service sends, e.g. `22 is open — SSH-2.0-OpenSSH_9.6`. Waits at most --timeout for the connect and for the banner; services that wait for the client to speak first, such as HTTP, show none.
+    pub banners: bool,
+
+    // Version detection argument (--service-version), like nmap's -sV.
+    #[bpaf(long("service-version"), long("sV"))]
+    /// After the scan, send probes from a built-in database to each open TCP and UDP port and match the responses to name the service and its version, e.g. `22 is open (ssh OpenSSH 9.6p1 Ubuntu-3ubuntu13)`. Each probe waits at most --timeout for the connect and for the response, so silent ports take a few of them. Also spelled --sV.
+    pub service_version: bool,
+
+    // Custom probes argument (--probe-rules).
+    #[bpaf(long, argument("FILE"))]
+    /// With --service-version, also send the probes of this TOML file, before the built-in ones, to name services no database knows, such as in-house protocols. It takes the form of the built-in database (data/probes.toml): each `[[probes]]` has a `name`, a `protocol` (tcp or udp), the `ports` it goes to, the bytes to `send` (a string with \r \n \0 \xNN escapes) or their `hex`, and `tls = true` to speak over TLS; its `[[probes.matches]]` rules give a regular expression `pattern` over the response and the `service`, `product`, `version` and `info` its groups ($1 to $9) fill in.
+    pub probe_rules: Option<String>,
+
+    // TLS handshake argument (--tls).
+    #[bpaf(long)]
+    /// After the scan, run a TLS handshake with each open TCP port and show the protocol version and cipher suite agreed, the application protocol picked from common ones such as h2 and http/1.1 (ALPN), the JA3S fingerprint of the server's answer to a fixed ClientHello, and the subject, issuer and validity of the certificate presented, with why the Mozilla roots do not trust it (self-signed, expired, issued for another name, ...). Any certificate is taken for this; each port waits at most --timeout for the connect and for the handshake.
+    pub tls: bool,
+
+    // TLS server name argument (--sni).
+    #[bpaf(long, argument("NAME"))]
+    /// The server name to send in the --tls handshakes (SNI) and check the certificates for, e.g. internal.example.com, as proxies in front of several sites only present the right certificate for the right name. Defaults to the hostname a target was given as; addresses get no name.
+    pub sni: Option<String>,
+
+    // Certificate expiry argument (--cert-warn-days).
+    #[bpaf(long, argument("DAYS"))]
+    /// Flag the --tls certificates that expire within this many days, or already have, with a WARNING line below the port and a count at the end, e.g. --cert-warn-days 30. The scan then exits with code 2, so scheduled expiry sweeps can tell.
+    pub cert_warn_days: Option<u32>,
+
+    // TLS version argument (--tls-versions).
+    #[bpaf(long)]
+    /// With --tls, also find out which of TLS 1.0, 1.1, 1.2 and 1.3 each port accepts, with one handshake per version that offers only it, and flag TLS 1.0 and 1.1 as deprecated (RFC 8996). Ports that only speak the old versions are shown from the answer to the newest one they take. The handshakes follow --concurrency and --rate like the scan.
+    pub tls_versions: bool,
+
+    // Cipher suite argument (--tls-ciphers).
+    #[bpaf(long)]
+    /// With --tls, also list the cipher suites each port accepts with each of TLS 1.0 to 1.3, in the order the server prefers them, and flag the weak ones: RC4, 3DES and DES, export-grade, unencrypted and anonymous suites, MD5, and CBC with TLS 1.0. Offering all suites but the ones already picked takes one handshake per suite; they follow --concurrency and --rate like the scan. Implies --tls-versions, and the JSON lists every suite with its version and weaknesses.
+    pub tls_ciphers: bool,
+
+    // Certificate export argument (--cert-dir).
+    #[bpaf(long, argument("DIR"))]
+    /// Write the certificate chain each --tls handshake presented to a PEM file in this directory, named after the address and port such as 192.0.2.7_443.pem, the certificate of the server first. The directory is created if needed.
+    pub cert_dir: Option<String>,
+
+    // HTTP probe argument (--http).
+    #[bpaf(long)]
+    /// After the scan, request / from each open TCP port that may be a web server and show the status code, where a redirect points, the page title and the Server header, e.g. `HTTP 302 → /login (Apache-Coyote/1.1)`. Ports that sent a --banners line or that --service-version named as another service are left out; the others get a plain request, then one over TLS if the answer was no HTTP (or right away if --tls found TLS). Each request waits at most --timeout for the connect, the handshake and the response.
+    pub http: bool,
+
+    // Security header argument (--http-headers).
+    #[bpaf(long)]
+    /// With --http, also check the security headers of each answer and list what is missing: Strict-Transport-Security (over TLS, with a max-age of at least 180 days), Content-Security-Policy, X-Frame-Options (or a frame-ancestors policy), X-Content-Type-Options: nosniff and Referrer-Policy, and the Secure (over TLS), HttpOnly and SameSite flags of each cookie set.
+    pub http_headers: bool,
+
+    // WebSocket argument (--websocket).
+    #[bpaf(long)]
+    /// With --http, also ask each web port for a WebSocket upgrade on / and the --websocket-paths, over the connection the page came over, and list the paths that answered 101 Switching Protocols with the right Sec-WebSocket-Accept key.
+    pub websocket: bool,
+
+    // WebSocket path argument (--websocket-paths).
+    #[bpaf(long, argument("LIST"))]
+    /// With --websocket, also try these comma-separated paths besides /, e.g. /ws,/socket.io/?EIO=4&transport=websocket.
+    pub websocket_paths: Option<String>,
+
+    // SSH audit argument (--ssh).
+    #[bpaf(long)]
+    /// After the scan, read the identification string and key exchange offer of each open TCP port that may run SSH (port 22, ports whose --banners line is an SSH identification and ports --service-version named ssh), and list its key exchange methods, host key types, ciphers, MACs and compressions. Flags the deprecated ones: SHA-1 and 1024-bit key exchanges, DSA keys and SHA-1 RSA signatures, CBC, RC4 and 64-bit block ciphers, MD5, SHA-1 and truncated MACs, and protocol version 1. No authentication is tried.
+    pub ssh: bool,
+
+    // Anonymous FTP argument (--ftp-anon).
+    #[bpaf(long)]
+    /// After the scan, try to log in as anonymous on each open TCP port that may run FTP (port 21, ports --service-version named ftp and ports whose --banners line is an FTP greeting) and report whether the server let the user in, with its answer. It logs out right after; nothing is listed or transferred.
+    pub ftp_anon: bool,
+
+    // SMTP argument (--smtp).
+    #[bpaf(long)]
+    /// After the scan, greet each open TCP port that may run SMTP (ports 25, 587 and 2525, ports --service-version named smtp and ports whose --banners line is an SMTP greeting) with EHLO, list the extensions it offers and warn when STARTTLS is not among them. Then test whether it is an open relay: it is asked to take mail from relay-test@example.com to relay-test@example.org, which is never sent (no DATA; RSET and QUIT follow). The JSON report holds the verdict as open_relay: true, false, or null when the answer said neither.
+    pub smtp: bool,
+
+    // DNS argument (--dns).
+    #[bpaf(long)]
+    /// After the scan, query each open port 53, over TCP or UDP like the port, and each port --service-version named domain, for the TXT record version.bind of class CHAOS and for the address of example.com. A server that resolves it, an outside name, is an open resolver anyone can use, e.g. for reflection attacks. The JSON report holds the verdict as open_resolver: true, false, or null when the server did not answer.
+    pub dns: bool,
+
+    // SNMP argument (--snmp).
+    #[bpaf(long)]
+    /// After a UDP scan that covered port 161, ask it for sysDescr and sysName with each of the --snmp-communities (public by default) and report the communities it answered, with what they read. Filtered ports are asked too, since agents ignore communities they do not know. All requests go out at once; answers are awaited for --timeout.
+    pub snmp: bool,
+
+    // SNMP community argument (--snmp-communities).
+    #[bpaf(long, argument("LIST"))]
+    /// With --snmp, the comma-separated communities to try instead of public, e.g. public,private,community.
+    pub snmp_communities: Option<String>,
+
+    // NTP argument (--ntp).
+    #[bpaf(long)]
+    /// After a UDP scan that found port 123 open, send it a client request, a mode 6 read of the system variables and a mode 7 monlist at once, and report the NTP version and stratum it answered with, the daemon and system its variables name, and how many bytes it sent back to each query: servers that answer mode 6 or monlist reply with many times the bytes of the request to anyone, including victims whose address a request is forged with. Answers are awaited for --timeout.
+    pub ntp: bool,
+
+    // SMB argument (--smb).
+    #[bpaf(long)]
+    /// After the scan, negotiate with each open TCP port that may run SMB (ports 139 and 445 and ports --service-version named microsoft-ds or netbios-ssn) and report the newest SMB dialect it agrees on, whether it still speaks SMB1 and whether it requires message signing. An NTLM login is started but never finished: its challenge names the server (NetBIOS and DNS computer and domain names) and its Windows version. Port 139 gets a NetBIOS session request first.
+    pub smb: bool,
+
+    // RDP argument (--rdp).
+    #[bpaf(long)]
+    /// After the scan, send X.224 connection requests to each open TCP port that may run RDP (port 3389 and ports --service-version named ms-wbt-server): one offering every security protocol, to report the one the server picks, and one offering TLS alone, which servers enforcing Network Level Authentication refuse. Warns when NLA is not required, as then anyone reaches the login screen before authenticating. No login is tried.
+    pub rdp: bool,
+
+    // MQTT argument (--mqtt).
+    #[bpaf(long)]
+    /// After the scan, send an MQTT CONNECT without user name or password to each open TCP port that may run an MQTT broker (port 1883, port 8883 over TLS, and ports --service-version named mqtt) and report whether the broker accepted it. Accepted clients disconnect right away; nothing is subscribed to or published.
+    pub mqtt: bool,
+
+    // Data store argument (--datastores).
+    #[bpaf(long)]
+    /// After the scan, send a harmless command to each open TCP port that may run Redis (6379), Memcached (11211) or MongoDB (27017 and 27018), or that --service-version named one of them: PING and INFO server to Redis, stats to Memcached, and isMaster, buildInfo and listDatabases to MongoDB. Reports whether the store answered without authentication and the version it gave. Nothing is written to the stores.
+    pub datastores: bool,
+
+    // Container API argument (--containers).
+    #[bpaf(long)]
+    /// After the scan, ask each open TCP port that may run a container platform API: GET /version of the Docker daemon on 2375 and, over TLS, 2376, GET /version and /api/v1/namespaces of the Kubernetes API server on 6443 and GET /pods of the kubelet on 10250, both over TLS. Reports whether the API answered without credentials, which hands the containers and often the host to anyone, and the version it gave.
+    pub containers: bool,
+
+    // Industrial protocol argument (--ics).
+    #[bpaf(long)]
+    /// After the scan, identify each open TCP port that may run an industrial protocol (Modbus/TCP on 502, Siemens S7 on 102, DNP3 on 20000, and ports --service-version named one of them) with requests that only read: Read Device Identification for the vendor, product code and revision of Modbus devices, the module and component identification lists of S7 CPUs, and link status requests to DNP3 outstations. Nothing is written and no device state changes.
+    pub ics: bool,
+
+    // Safe argument (--safe).
+    #[bpaf(long)]
+    /// With --ics, go easy on fragile devices: one device at a time, a second before each request, and fewer requests (only the module list of S7 CPUs and only the self address of DNP3 outstations).
+    pub safe: bool,
+
+    // Traceroute argument (--traceroute).
+    #[bpaf(long)]
+    /// After the scan, trace the route to the first open TCP port of each host: connects go out with every TTL from 1 to 30 at once, and the routers that drop them on the way tell their address with an ICMP time exceeded error. Shows where the path ends, at the host or at a filter that rejects or drops the probes on the way, to tell a host firewall from a network ACL. Needs root or the CAP_NET_RAW capability.
+    pub traceroute: bool,
+
+    // Fragmentation argument (-f, --fragment), like nmap's -f.
+    #[bpaf(short('f'), long)]
+    /// Split each probe of a raw-socket scan (--syn, --fin, --null, --xmas, --ack, --window, --maimon or --sctp) into IP fragments of 8 bytes, so its TCP or SCTP header is spread over several of them, which slips past packet filters that only look at whole headers. Many firewalls, and Linux hosts over IPv6, reassemble or drop such fragments, in which case the results are only as good as a scan without.
+    pub fragment: bool,
+
+    // Fragment size argument (--mtu).
+    #[bpaf(long, argument("BYTES"))]
+    /// Like -f, but with fragments of this many bytes, which must be a multiple of 8.
+    pub mtu: Option<usize>,
+
+    // Privilege drop argument (--drop-privs).
+    #[bpaf(long, argument("USER"))]
+    /// When run as root, switch to this user (and its group) for good once the raw sockets are open and the hosts are discovered, so the hours of a long scan run unprivileged. The checkpoint and --json files are then written as that user. Cannot be combined with --traceroute, which opens raw sockets after the scan, or a --source-port below 1024.
+    pub drop_privs: Option<String>,
+
+    // Packet capture argument (--pcap).
+    #[bpaf(long, argument("FILE"))]
+    /// Record every packet sent to or received from the targets during the scan (host discovery, probes, replies, retries and traceroutes) to this pcap file, to open in Wireshark or tcpdump. Packets of all interfaces are recorded, or only of --interface if given. Needs root or the CAP_NET_RAW capability.
+    pub pcap: Option<String>,
+
+    // Top ports argument (--top-ports).
+    #[bpaf(long, argument("N"))]
+    /// Sniff only the N most commonly open ports instead of a range. The embedded frequency table ranks a few hundred ports; a larger N scans all of them.
+    pub top_ports: Option<usize>,
+
+    // Port exclusion argument (--exclude-ports).
+    #[bpaf(long, argument("PORTS"))]
+    /// Ports to skip, using the same syntax as --ports.
+    pub exclude_ports: Option<String>,
+
+    // Structured output argument (--json).
+    #[bpaf(long, argument("FILE"))]
+    /// Also write the results as JSON to this file, including the tags attached to each target, the --banners read, the --service-version found, the --tls handshakes with the names their certificates are valid for and the --http answers. Lists the open ports, and with --verbose the closed and filtered ones as well.
+    pub json: Option<String>,
+
+    // Checkpoint argument (--checkpoint).
+    #[bpaf(long, argument("FILE"))]
+    /// Save the progress of the scan to this file every few seconds and when it stops, so that --resume can continue it after a crash or Ctrl-C.
+    pub checkpoint: Option<String>,
+
+    // Resume argument (--resume).
+    #[bpaf(long, argument("FILE"))]
+    /// Continue the scan saved in this --checkpoint file with the options it was started with, skipping the probes that were already done.
+    pub resume: Option<String>,
+
+    // Service name display argument (--show-services).
+    #[bpaf(long)]
+    /// Show the well-known service name next to each open port, e.g. `443 is open (https)`, from the IANA table built into the binary. The --json output always has them.
+    pub show_services: bool,
+
+    // Verbose output argument with short and long flags (-v, --verbose).
+    #[bpaf(short('v'), long)]
+    /// Also report the closed and filtered ports, here and in the JSON report. A closed port refused the connection, so the host is up with nothing listening there; a filtered one did not answer or the network turned the probe away, as a firewall in the way does.
+    pub verbose: bool,
+
+    // Reason display argument (--reason).
+    #[bpaf(long)]
+    /// Show why each reported port got its state: the reply that came back (syn-ack, conn-refused, reset, udp-response, port-unreach, host-unreach, admin-prohibited, ...), or no-response and how often it was retried. The JSON report always has it.
+    pub reason: bool,
+
+    // Port order argument (--randomize-ports).
+    #[bpaf(long)]
+    /// Probe the ports in random order instead of ascending, which is harder to spot for intrusion detection. Results are still reported in ascending order.
+    pub randomize_ports: bool,
+
+    // Host order argument (--randomize-hosts).
+    #[bpaf(long)]
+    /// Probe the hosts in random order instead of the order given. Results are still reported in that order.
+    pub randomize_hosts: bool,
+
+    // Random seed argument (--seed).
+    #[bpaf(long, argument("SEED"))]
+    /// Seed for --randomize-hosts and --randomize-ports, so two runs probe in the same order. A random one is picked and printed if not given.
+    pub seed: Option<u64>,
+
+    // Timing template argument (-T0 to -T5), like nmap's.
+    #[bpaf(
+        short('T'),
+        argument("0-5"),
+        guard(template_guard, "Must be between 0 and 5")
+    )]
+    /// Timing template bundling --timeout, --concurrency, --retries and a delay between probes: 0 paranoid, 1 sneaky, 2 polite, 3 normal (the default), 4 aggressive (LANs), 5 insane. Options given explicitly override the template.
+    pub template: Option<u8>,
+
+    // Connection timeout argument (--timeout), in milliseconds.
+    #[bpaf(long, argument("MS"), guard(timeout_guard, "Must be greater than 0"))]
+    /// The longest time to wait for each connection, in milliseconds, before treating the port as filtered. Timeouts shrink to match each host's measured round-trip time, but never below 100 ms. Defaults to 3000.
+    pub timeout: Option<u64>,
+
+    // Per-port timeout argument (--timeout-override).
+    #[bpaf(long, argument("PORT=MS"))]
+    /// Give specific ports a timeout of their own, in milliseconds, e.g. 25=5000,8443=3000 for tarpits or slow web UIs. Ports can also be ranges, service names or @groups. These timeouts do not adapt to the round-trip time.
+    pub timeout_override: Option<String>,
+
+    // Retry argument (--retries).
+    #[bpaf(long, argument("N"))]
+    /// Probe a port up to N more times when it does not answer, waiting longer before each retry. Ports that refuse the connection are not retried. Defaults to 0.
+    pub retries: Option<u32>,
+
+    // Concurrency argument (--concurrency).
+    #[bpaf(
+        long,
+        argument("N"),
+        guard(concurrency_guard, "Must be greater than 0")
+    )]
+    /// How many connections to have in flight at the same time. Defaults to 500, or less if the open file limit is lower.
+    pub concurrency: Option<usize>,
+
+    // Second pass argument (--verify-filtered).
+    #[bpaf(long)]
+    /// Once the scan is done, probe the filtered ports once more with twice the --timeout, when the network is quieter, and report the ones that answer this time.
+    pub verify_filtered: bool,
+
+    // Host parallelism argument (--host-parallelism).
+    #[bpaf(
+        long,
+        argument("N"),
+        guard(host_parallelism_guard, "Must be greater than 0")
+    )]
+    /// Scan up to N hosts at the same time, taking turns between their ports. Defaults to 1, one host after the other.
+    pub host_parallelism: Option<usize>,
+
+    // Per-host concurrency argument (--per-host-concurrency).
+    #[bpaf(
+        long,
+        argument("N"),
+        guard(per_host_concurrency_guard, "Must be greater than 0")
+    )]
+    /// Have at most N connections in flight to any single host, so fragile devices are not flooded. By default only --concurrency limits them.
+    pub per_host_concurrency: Option<usize>,
+
+    // Host budget argument (--host-timeout), in seconds.
+    #[bpaf(
+        long,
+        argument("SECS"),
+        guard(host_timeout_guard, "Must be greater than 0")
+    )]
+    /// Give up on a host once it has been probed for this many seconds, reporting it as timed out.
+    pub host_timeout: Option<f64>,
+
+    // Scan deadline argument (--max-duration), in seconds.
+    #[bpaf(
+        long,
+        argument("SECS"),
+        guard(max_duration_guard, "Must be greater than 0")
+    )]
+    /// Stop the whole scan after this many seconds and report what was found so far, marked as truncated.
+    pub max_duration: Option<f64>,
+
+    // Rate limit argument (--rate), in probes per second.
+    #[bpaf(
+        long,
+        argument("PER_SECOND"),
+        guard(rate_guard, "Must be at least 0.01")
+    )]
+    /// Start at most this many probes per second, across all hosts. Fractions such as 0.5 are allowed.
+    pub rate: Option<f64>,
+
+    // Jitter argument (--jitter), in milliseconds.
+    #[bpaf(long, argument("MS"), guard(jitter_guard, "Must be greater than 0"))]
+    /// Wait a random time between 0 and this many milliseconds between the start of two probes, so the traffic is not perfectly regular. Adds to --rate and the timing template's delay.
+    pub jitter: Option<u64>,
+
+    // Burst size argument (--batch-size).
+    #[bpaf(long, argument("N"), guard(batch_size_guard, "Must be greater than 0"))]
+    /// Send probes in bursts of N, with a quiet period of --batch-delay in between, for network gear that drops probes under sustained load but copes with short bursts.
+    pub batch_size: Option<usize>,
+
+    // Burst spacing argument (--batch-delay), in milliseconds.
+    #[bpaf(long, argument("MS"))]
+    /// How long to stay quiet between two bursts of --batch-size probes, in milliseconds.
+    pub batch_delay: Option<u64>,
 
     // Start port argument with short and long flags (-s, --start). Must be greater than 0.
     #[bpaf(
@@ -47,60 +596,1224 @@ fn start_port_guard(input: &u16) -> bool {
 }
 
 // Guard function to ensure the end port is less than or equal to MAX (65535).
+// Always true for a `u16`, but kept so the limit stays documented in `--help`.
+#[allow(clippy::absurd_extreme_comparisons)]
 fn end_port_guard(input: &u16) -> bool {
     *input <= MAX
 }
 
-// Function to scan a specific port.
-async fn scan(tx: Sender<u16>, start_port: u16, addr: IpAddr) {
-    // Attempt to connect to the given IP address and port.
-    match TcpStream::connect(format!("{}:{}", addr, start_port)).await {
-        // If the connection is successful:
-        Ok(_) => {
-            print!("."); // Print a dot to indicate progress.
-            io::stdout().flush().unwrap(); // Flush stdout to ensure the dot appears immediately.
-            tx.send(start_port).unwrap(); // Send the port number to the channel.
+// Guard function to ensure the timing template exists.
+fn template_guard(input: &Option<u8>) -> bool {
+    input.is_none_or(|level| level <= 5)
+}
+
+// Guard function to ensure the connection timeout is not zero.
+fn timeout_guard(input: &Option<u64>) -> bool {
+    *input != Some(0)
+}
+
+// Guard function to ensure at least one connection may be in flight.
+fn concurrency_guard(input: &Option<usize>) -> bool {
+    *input != Some(0)
+}
+
+// Guard function to ensure at least one host is scanned at a time.
+fn host_parallelism_guard(input: &Option<usize>) -> bool {
+    *input != Some(0)
+}
+
+// Guard function to ensure at least one connection per host may be in flight.
+fn per_host_concurrency_guard(input: &Option<usize>) -> bool {
+    *input != Some(0)
+}
+
+// Guard function to ensure the host budget is a positive number of seconds.
+fn host_timeout_guard(input: &Option<f64>) -> bool {
+    input.is_none_or(|secs| secs.is_finite() && secs > 0.0 && secs < 1e9)
+}
+
+// Guard function to ensure the scan deadline is a positive number of seconds.
+fn max_duration_guard(input: &Option<f64>) -> bool {
+    input.is_none_or(|secs| secs.is_finite() && secs > 0.0 && secs < 1e9)
+}
+
+// Guard function to ensure the probe rate is a number no lower than one probe every 100 seconds.
+fn rate_guard(input: &Option<f64>) -> bool {
+    input.is_none_or(|rate| rate.is_finite() && rate >= 0.01)
+}
+
+// Guard function to ensure the jitter is not zero.
+fn jitter_guard(input: &Option<u64>) -> bool {
+    *input != Some(0)
+}
+
+// Guard function to ensure a burst holds at least one probe.
+fn batch_size_guard(input: &Option<usize>) -> bool {
+    *input != Some(0)
+}
+
+// Function to unwrap a setup result, printing the error and exiting if it failed.
+fn or_exit<T>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    })
+}
+
+// Function to stop the --pcap capture, if any, and tell where it went.
+#[cfg(target_os = "linux")]
+fn save_capture(capture: Option<pcap::Capture>) {
+    if let Some(capture) = capture {
+        match capture.stop() {
+            Ok((path, count)) => eprintln!("Captured {} packet(s) to {}", count, path),
+            Err(e) => eprintln!("Warning: {}", e),
         }
-        // If the connection fails (port is closed):
-        Err(_) => {}
+    }
+}
+
+// Function to wait until the user interrupts the scan with Ctrl-C (SIGINT) or it is sent SIGTERM.
+async fn interrupted() {
+    #[cfg(unix)]
+    let terminated = async {
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    // Other systems have no SIGTERM.
+    #[cfg(not(unix))]
+    let terminated = std::future::pending::<()>();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminated => {}
     }
 }
 
 // Entry point of the program.
 #[tokio::main] // Use the `tokio` runtime for asynchronous execution.
 async fn main() {
-    // Parse the command-line arguments.
-    let opts = arguments().run();
+    // Parse the command-line arguments. `--resume` takes the arguments from the checkpoint instead,
+    // which keep saving to the same --checkpoint file.
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let mut opts = arguments().run();
+    let resumed = opts.resume.clone().map(|path| {
+        if args
+            .iter()
+            .any(|arg| arg != "--resume" && *arg != path && !arg.starts_with("--resume="))
+        {
+            or_exit(Err("--resume cannot be combined with other options, the scan continues with the ones it was started with".to_string()))
+        }
+        let checkpoint = or_exit(checkpoint::load(&path));
+        let words: Vec<&str> = checkpoint.args.iter().map(String::as_str).collect();
+        opts = or_exit(
+            arguments()
+                .run_inner(bpaf::Args::from(words.as_slice()))
+                .map_err(|e| match e {
+                    bpaf::ParseFailure::Stdout(e) | bpaf::ParseFailure::Stderr(e) => {
+                        format!("Invalid arguments in checkpoint file {}: {}", path, e)
+                    }
+                }),
+        );
+        args = checkpoint.args.clone();
+        checkpoint
+    });
 
-    // Initialize a channel for inter-task communication.
-    let (tx, rx) = channel();
+    // `T:` and `U:` in --ports split it into a TCP and a UDP list, the way --tcp-ports and
+    // --udp-ports give them.
+    if let Some((tcp, udp)) = opts.ports.as_deref().and_then(ports::split_protocols) {
+        if opts.tcp_ports.is_some() || opts.udp_ports.is_some() {
+            or_exit(Err(
+                "--ports with T: or U: cannot be combined with --tcp-ports or --udp-ports"
+                    .to_string(),
+            ))
+        }
+        opts.ports = None;
+        opts.tcp_ports = tcp;
+        opts.udp_ports = udp;
+    }
 
-    // Iterate over the range of ports specified by the user.
-    for i in opts.start_port..opts.end_port {
-        let tx = tx.clone(); // Clone the transmitter for each task.
+    // Per-protocol port lists stand for --ports and --udp; given both, the UDP ports are scanned
+    // after the TCP ones.
+    let mut udp_spec = None;
+    if opts.tcp_ports.is_some() || opts.udp_ports.is_some() {
+        if opts.ports.is_some() || opts.top_ports.is_some() || opts.udp {
+            or_exit(Err(
+                "--tcp-ports and --udp-ports cannot be combined with --ports, --top-ports or --udp"
+                    .to_string(),
+            ))
+        }
+        if opts.sctp || opts.ip_protocols || opts.idle_zombie.is_some() {
+            or_exit(Err(
+                "--tcp-ports and --udp-ports cannot be combined with --sctp, --ip-protocols or --idle-zombie"
+                    .to_string(),
+            ))
+        }
+        match (opts.tcp_ports.take(), opts.udp_ports.take()) {
+            (Some(tcp), Some(udp)) => {
+                if opts.checkpoint.is_some() {
+                    or_exit(Err(
+                        "--checkpoint only saves scans of one protocol, so it cannot be combined with both --tcp-ports and --udp-ports"
+                            .to_string(),
+                    ))
+                }
+                opts.ports = Some(tcp);
+                udp_spec = Some(udp);
+            }
+            (tcp, None) => opts.ports = tcp,
+            (None, udp) => {
+                opts.ports = udp;
+                opts.udp = true;
+            }
+        }
+    }
 
-        // Spawn an asynchronous task to scan the current port.
-        task::spawn(async move { scan(tx, i, opts.address).await });
+    // The server name is only sent in the TLS handshakes, and has to be a valid one.
+    if let Some(name) = &opts.sni {
+        if !opts.tls {
+            or_exit(Err(
+                "--sni only applies to the handshakes of --tls".to_string()
+            ))
+        }
+        or_exit(tls::server_name(name));
+    }
+    if opts.cert_warn_days.is_some() && !opts.tls {
+        or_exit(Err(
+            "--cert-warn-days checks the certificates of --tls, so it needs --tls".to_string(),
+        ))
+    }
+    if opts.tls_versions && !opts.tls {
+        or_exit(Err(
+            "--tls-versions adds handshakes to --tls, so it needs --tls".to_string(),
+        ))
+    }
+    if opts.tls_ciphers && !opts.tls {
+        or_exit(Err(
+            "--tls-ciphers adds handshakes to --tls, so it needs --tls".to_string(),
+        ))
+    }
+    if opts.http_headers && !opts.http {
+        or_exit(Err(
+            "--http-headers checks the answers of --http, so it needs --http".to_string(),
+        ))
+    }
+    if opts.websocket && !opts.http {
+        or_exit(Err(
+            "--websocket upgrades the connections of --http, so it needs --http".to_string(),
+        ))
+    }
+    if opts.websocket_paths.is_some() && !opts.websocket {
+        or_exit(Err(
+            "--websocket-paths adds paths to --websocket, so it needs --websocket".to_string(),
+        ))
     }
+    let websocket_paths = opts
+        .websocket
+        .then(|| or_exit(web::paths(opts.websocket_paths.as_deref())));
+    if opts.snmp && !opts.udp && udp_spec.is_none() {
+        or_exit(Err(
+            "--snmp asks UDP port 161, so it needs --udp or --udp-ports".to_string(),
+        ))
+    }
+    if opts.snmp_communities.is_some() && !opts.snmp {
+        or_exit(Err(
+            "--snmp-communities sets the communities of --snmp, so it needs --snmp".to_string(),
+        ))
+    }
+    if opts.ntp && !opts.udp && udp_spec.is_none() {
+        or_exit(Err(
+            "--ntp asks UDP port 123, so it needs --udp or --udp-ports".to_string(),
+        ))
+    }
+    if opts.probe_rules.is_some() && !opts.service_version {
+        or_exit(Err(
+            "--probe-rules adds probes to --service-version, so it needs --service-version"
+                .to_string(),
+        ))
+    }
+    if let Some(path) = &opts.probe_rules {
+        or_exit(versions::load(path));
+    }
+    if opts.safe && !opts.ics {
+        or_exit(Err(
+            "--safe slows down the probes of --ics, so it needs --ics".to_string(),
+        ))
+    }
+    let snmp_communities = opts
+        .snmp
+        .then(|| or_exit(snmp::communities(opts.snmp_communities.as_deref())));
+    if opts.cert_dir.is_some() && !opts.tls {
+        or_exit(Err(
+            "--cert-dir writes the certificates of --tls, so it needs --tls".to_string(),
+        ))
+    }
+
+    // Load the config file with user-defined settings.
+    let config = or_exit(config::load(opts.config.as_deref()));
+    // Load the user's service names before the port lists, which may use them.
+    if let Some(path) = &opts.services_file {
+        or_exit(services::load(path));
+    }
+    let groups = ports::Groups::new(config.groups);
+
+    // Pick the resolver used for hostnames in targets and exclusions.
+    let resolver = match (&opts.dns_server, &opts.doh) {
+        (Some(_), Some(_)) => or_exit(Err("--dns-server and --doh cannot be combined".to_string())),
+        (Some(server), None) => or_exit(resolver::Resolver::server(server)),
+        (None, Some(url)) => or_exit(resolver::Resolver::doh(url).await),
+        (None, None) => resolver::Resolver::System,
+    };
+
+    // Gather the target specifications from the address list, the input file and stdin.
+    // A `-` in the address list stands for the targets piped in on stdin.
+    let mut specs = opts
+        .address
+        .as_deref()
+        .map(targets::split_list)
+        .unwrap_or_default();
+    let from_stdin = opts.stdin || specs.iter().any(|s| s == "-");
+    specs.retain(|s| s != "-");
+    if let Some(path) = &opts.input_list {
+        specs.extend(or_exit(targets::read_list(path, opts.input_format)));
+    }
+    if from_stdin && opts.input_list.as_deref() != Some("-") {
+        specs.extend(or_exit(targets::read_list("-", opts.input_format)));
+    }
+
+    // A resumed scan has to find the same targets again, which a pipe cannot provide.
+    if opts.checkpoint.is_some() && (from_stdin || opts.input_list.as_deref() == Some("-")) {
+        or_exit(Err(
+            "--checkpoint cannot be used with targets read from stdin".to_string(),
+        ))
+    }
+
+    // Fall back to the loopback address when no target was given at all, unless local discovery
+    // is to find them.
+    if opts.address.is_none() && opts.input_list.is_none() && !from_stdin && !opts.local_discovery {
+        specs.push(IPFALLBACK.to_string());
+    }
+
+    // Expand the specifications into the individual hosts to scan, bailing out if one is invalid.
+    // Local discovery alone may give all of them.
+    let mut hosts = match specs.is_empty() && opts.local_discovery {
+        true => vec![],
+        false => or_exit(targets::expand_all(&specs, &resolver).await),
+    };
+
+    // Gather the exclusions the same way and drop matching hosts before any probe is sent.
+    let mut excluded = opts
+        .exclude
+        .as_deref()
+        .map(targets::split_list)
+        .unwrap_or_default();
+    if let Some(path) = &opts.exclude_file {
+        excluded.extend(or_exit(targets::read_list(path, None)));
+    }
+    let networks = or_exit(targets::exclusions(&excluded, &resolver).await);
+    targets::exclude(&mut hosts, &networks);
+    if hosts.is_empty() && !opts.local_discovery {
+        eprintln!("Every target was excluded, nothing to scan");
+        std::process::exit(1);
+    }
+
+    // Probe dual-stack hostnames once per port, racing both families.
+    if opts.happy_eyeballs {
+        targets::pair_families(&mut hosts);
+    }
+
+    // Start from the timing template and let explicit options override its values.
+    let template = scanner::template(opts.template.unwrap_or(3));
+    let timeout = opts
+        .timeout
+        .map(Duration::from_millis)
+        .unwrap_or(template.timeout);
+
+    // Keep the sockets within the file-descriptor limit, raised as far as allowed.
+    let fd_budget = limits::raise_open_files().map(|n| {
+        let n = usize::try_from(n).unwrap_or(usize::MAX);
+        n.saturating_sub(RESERVED_FDS).max(1)
+    });
+    let concurrency = match (opts.concurrency, fd_budget) {
+        // The IP IDs of the zombie only tell about one probe at a time.
+        (Some(n), _) if n > 1 && opts.idle_zombie.is_some() => {
+            eprintln!("Warning: --idle-zombie probes one port at a time, ignoring --concurrency");
+            1
+        }
+        _ if opts.idle_zombie.is_some() => 1,
+        (Some(n), Some(budget)) if n > budget => {
+            eprintln!(
+                "Warning: --concurrency {} exceeds the open file limit, expect it to be lowered",
+                n
+            );
+            n
+        }
+        (Some(n), _) => n,
+        (None, Some(budget)) => template.concurrency.min(budget),
+        (None, None) => template.concurrency,
+    };
+
+    // `--rate` (or the template's delay) paces the start of each probe, over all hosts together,
+    // and `--jitter` adds a random delay between two probes on top.
+    let rate = match (opts.rate, template.delay, opts.jitter) {
+        (Some(per_second), _, _) => Some(scanner::RateLimiter::new(per_second)),
+        (None, Some(delay), _) => Some(scanner::RateLimiter::every(delay)),
+        (None, None, Some(_)) => Some(scanner::RateLimiter::every(Duration::ZERO)),
+        (None, None, None) => None,
+    }
+    .map(|rate| match opts.jitter {
+        Some(ms) => rate.jittered(Duration::from_millis(ms)),
+        None => rate,
+    });
+
+    // Bind the probes to the requested source, which needs an address of each scanned family.
+    let source = or_exit(scanner::Source::new(
+        opts.source_ip.as_deref(),
+        opts.interface.clone(),
+        opts.source_port,
+        opts.ttl,
+    ));
+
+    // Add the hosts the local network advertises, outside the exclusions.
+    if opts.local_discovery {
+        if opts.idle_zombie.is_some() {
+            or_exit(Err(
+                "--local-discovery sends queries from your own address, which --idle-zombie is meant to avoid"
+                    .to_string(),
+            ))
+        }
+        let found = advertised::discover(&source, concurrency, rate.as_ref()).await;
+        advertised::merge(&mut hosts, found, &networks);
+        if hosts.is_empty() {
+            eprintln!("Local discovery found no hosts, nothing to scan");
+            std::process::exit(1);
+        }
+    }
+
+    // Protocol and idle scans craft IPv4 headers, so they cannot reach IPv6 hosts.
+    let ipv4_only = match (opts.ip_protocols, &opts.idle_zombie) {
+        (true, _) => Some("--ip-protocols"),
+        (false, Some(_)) => Some("--idle-zombie"),
+        (false, None) => None,
+    };
+    for target in &hosts {
+        if let Some(option) = ipv4_only.filter(|_| !target.addr.is_ipv4()) {
+            or_exit(Err(format!(
+                "{} only scans IPv4 hosts, not {}",
+                option, target
+            )))
+        }
+        for addr in std::iter::once(target.addr).chain(target.fallback) {
+            if !source.covers(addr) {
+                let family = if addr.is_ipv4() { "IPv4" } else { "IPv6" };
+                or_exit(Err(format!(
+                    "--source-ip has no {} address to probe {} from",
+                    family, target
+                )))
+            }
+        }
+    }
+
+    // Open the raw sockets of a raw-socket scan up front, so missing privileges stop it right away.
+    let raw_scans: Vec<scanner::RawScan> = [
+        (opts.syn, scanner::RawScan::Syn),
+        (opts.fin, scanner::RawScan::Fin),
+        (opts.null, scanner::RawScan::Null),
+        (opts.xmas, scanner::RawScan::Xmas),
+        (opts.ack, scanner::RawScan::Ack),
+        (opts.window, scanner::RawScan::Window),
+        (opts.maimon, scanner::RawScan::Maimon),
+        (opts.sctp, scanner::RawScan::Sctp),
+    ]
+    .into_iter()
+    .filter_map(|(chosen, scan)| chosen.then_some(scan))
+    .collect();
+
+    // Raw and packet sockets are those of Linux; other systems only run the scans that go
+    // through their own sockets.
+    #[cfg(not(target_os = "linux"))]
+    {
+        let raw_only = [
+            (opts.ip_protocols, "--ip-protocols"),
+            (opts.idle_zombie.is_some(), "--idle-zombie"),
+            (opts.decoys.is_some(), "--decoys"),
+            (opts.fragment, "-f"),
+            (opts.mtu.is_some(), "--mtu"),
+            (opts.traceroute, "--traceroute"),
+            (opts.pcap.is_some(), "--pcap"),
+        ];
+        // SCTP ports can still be probed through the system's SCTP stack, if it has one.
+        let system_sctp = opts.sctp && scanner::sctp_available();
+        if system_sctp {
+            eprintln!("Warning: --sctp sends raw packets only on Linux, setting up full associations through the system's SCTP stack instead");
+        }
+        let mut options = raw_scans
+            .iter()
+            .filter(|&&scan| !(system_sctp && scan == scanner::RawScan::Sctp))
+            .map(|scan| scan.option())
+            .chain(
+                raw_only
+                    .into_iter()
+                    .filter_map(|(chosen, option)| chosen.then_some(option)),
+            );
+        if let Some(option) = options.next() {
+            or_exit(Err(format!(
+                "{} needs the raw sockets of Linux, which this system does not offer",
+                option
+            )))
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let engine = scanner::Engine::Sockets;
+    #[cfg(target_os = "linux")]
+    let engine = match raw_scans[..] {
+        [scan] if opts.ip_protocols => or_exit(Err(format!(
+            "{} and --ip-protocols cannot be combined",
+            scan.option()
+        ))),
+        [] if opts.ip_protocols && opts.udp => or_exit(Err(
+            "--udp and --ip-protocols cannot be combined".to_string(),
+        )),
+        [] if opts.ip_protocols && !opts.list_scan => {
+            scanner::Engine::Protocols(or_exit(protocols::Prober::open(&source)))
+        }
+        [] => scanner::Engine::Sockets,
+        [scan] if opts.udp => or_exit(Err(format!(
+            "{} and --udp cannot be combined",
+            scan.option()
+        ))),
+        [_] if opts.list_scan => scanner::Engine::Sockets,
+        // Without the privileges for raw sockets, a SYN scan becomes the connect scan that finds
+        // the same open ports, unless an option only the raw probes have asks for it.
+        [scanner::RawScan::Syn]
+            if !raw::capable()
+                && opts.decoys.is_none()
+                && !opts.fragment
+                && opts.mtu.is_none() =>
+        {
+            eprintln!("Warning: --syn needs root or the CAP_NET_RAW capability to send raw packets, which this process lacks, so it runs a connect scan instead: the same ports show up as open, with full connections the targets may log");
+            scanner::Engine::Sockets
+        }
+        [scan] => match raw::Raw::open(&source, scan.option(), scan.transport()) {
+            Ok(raw) => scanner::Engine::Raw(raw, scan),
+            // Without raw sockets, SCTP ports can still be probed through the system's SCTP stack.
+            Err(_) if scan == scanner::RawScan::Sctp && scanner::sctp_available() => {
+                eprintln!("Warning: --sctp cannot open a raw socket, setting up full associations through the system's SCTP stack instead");
+                scanner::Engine::Sockets
+            }
+            Err(e) => or_exit(Err(e)),
+        },
+        _ => or_exit(Err(
+            "Only one of --syn, --fin, --null, --xmas, --ack, --window, --maimon and --sctp can be given"
+                .to_string(),
+        )),
+    };
 
-    // Create a vector to store open ports.
-    let mut out = vec![];
+    // Decoys send the raw probes again from other addresses, which no other scan has.
+    #[cfg(target_os = "linux")]
+    let engine = match (&opts.decoys, engine) {
+        (Some(spec), scanner::Engine::Raw(raw, scan)) if !opts.list_scan => {
+            let decoys = or_exit(raw::decoys(spec));
+            scanner::Engine::Raw(or_exit(raw.with_decoys(decoys)), scan)
+        }
+        (Some(_), _) if !opts.list_scan => or_exit(Err(
+            "--decoys only works with the raw-socket scans: --syn, --fin, --null, --xmas, --ack, --window, --maimon or --sctp"
+                .to_string(),
+        )),
+        (_, engine) => engine,
+    };
 
-    // Drop the original transmitter to signal completion to the receiver.
-    drop(tx);
+    // Fragments split the raw probes as well, which no other scan crafts.
+    #[cfg(target_os = "linux")]
+    let option = if opts.mtu.is_some() { "--mtu" } else { "-f" };
+    #[cfg(target_os = "linux")]
+    let fragment = match opts.mtu {
+        Some(size) if size == 0 || size % 8 != 0 => or_exit(Err(format!(
+            "--mtu {} is not a positive multiple of 8",
+            size
+        ))),
+        size => size.or(opts.fragment.then_some(8)),
+    };
+    #[cfg(target_os = "linux")]
+    let engine = match (fragment, engine) {
+        (Some(size), scanner::Engine::Raw(raw, scan)) if !opts.list_scan => {
+            // Connection tracking reassembles the fragments that leave this host, to refragment
+            // them only where the path needs it.
+            if raw::defragmenting() {
+                eprintln!("Warning: this host tracks connections, which reassembles the fragments of {} before they leave it", option);
+            }
+            scanner::Engine::Raw(or_exit(raw.with_fragments(size, option)), scan)
+        }
+        (Some(_), _) if !opts.list_scan => or_exit(Err(format!(
+            "{} only works with the raw-socket scans: --syn, --fin, --null, --xmas, --ack, --window, --maimon or --sctp",
+            option
+        ))),
+        (_, engine) => engine,
+    };
 
-    // Collect all open ports from the receiver and add them to the vector.
-    for p in rx {
-        out.push(p);
+    // In list-scan mode only show the final target list, without sending a single probe.
+    if opts.list_scan {
+        if opts.resolve {
+            let all: Vec<usize> = (0..hosts.len()).collect();
+            targets::reverse_lookup(&mut hosts, &all, &resolver).await;
+        }
+        report::print_target_list(&hosts);
+        return;
     }
 
-    println!(""); // Print a newline for output formatting.
+    // Expand the port specification, falling back to the --start/--end range (inclusive).
+    // Protocol scans take protocol numbers instead, all 256 of them by default.
+    let mut port_list = match (&opts.ports, opts.top_ports) {
+        (Some(_), Some(_)) => {
+            or_exit(Err("--ports and --top-ports cannot be combined".to_string()))
+        }
+        (Some(spec), None) if opts.ip_protocols => or_exit(ports::parse_protocols(spec)),
+        (None, Some(_)) if opts.ip_protocols => or_exit(Err(
+            "--top-ports cannot be combined with --ip-protocols".to_string(),
+        )),
+        (None, None) if opts.ip_protocols => (0..=255).collect(),
+        (Some(spec), None) => or_exit(ports::parse_spec(spec, &groups)),
+        (None, Some(n)) => {
+            let top = or_exit(ports::top_ports(n));
+            if top.len() < n {
+                eprintln!(
+                    "Warning: the frequency table ranks only {} ports, scanning all of them for --top-ports {}",
+                    top.len(),
+                    n
+                );
+            }
+            top
+        }
+        (None, None) if opts.start_port > opts.end_port => or_exit(Err(format!(
+            "The start port ({}) is greater than the end port ({})",
+            opts.start_port, opts.end_port
+        ))),
+        (None, None) => (opts.start_port..=opts.end_port).collect(),
+    };
+
+    // The UDP ports of a combined scan, probed after `port_list`.
+    let mut udp_list = match &udp_spec {
+        Some(spec) => or_exit(ports::parse_spec(spec, &groups)),
+        None => vec![],
+    };
+    let protocol = match (opts.udp, opts.sctp, opts.ip_protocols) {
+        (true, _, _) => scanner::Protocol::Udp,
+        (false, true, _) => scanner::Protocol::Sctp,
+        (false, false, true) => scanner::Protocol::Ip,
+        (false, false, false) => scanner::Protocol::Tcp,
+    };
+
+    // Add the TCP ports the hosts advertised services on after those asked for.
+    if opts.local_discovery && protocol == scanner::Protocol::Tcp {
+        for port in advertised::tcp_ports(&hosts) {
+            if !port_list.contains(&port) {
+                port_list.push(port);
+            }
+        }
+    }
+
+    // Remove excluded ports once the specification has been expanded.
+    let mut excluded_ports = 0;
+    if let Some(spec) = &opts.exclude_ports {
+        let skip = match opts.ip_protocols {
+            true => or_exit(ports::parse_protocols(spec)),
+            false => or_exit(ports::parse_spec(spec, &groups)),
+        };
+        let before = port_list.len() + udp_list.len();
+        port_list.retain(|p| skip.binary_search(p).is_err());
+        udp_list.retain(|p| skip.binary_search(p).is_err());
+        excluded_ports = before - port_list.len() - udp_list.len();
+        if port_list.is_empty() && udp_list.is_empty() {
+            eprintln!("Every port was excluded, nothing to scan");
+            std::process::exit(1);
+        }
+    }
+
+    // Banners, versions, handshakes, pages, key exchanges, logins, queries and negotiations come
+    // from full connects from your own address, which idle scans avoid.
+    if (opts.banners
+        || opts.service_version
+        || opts.tls
+        || opts.http
+        || opts.ssh
+        || opts.ftp_anon
+        || opts.smtp
+        || opts.dns
+        || opts.snmp
+        || opts.smb
+        || opts.rdp
+        || opts.mqtt
+        || opts.datastores
+        || opts.containers
+        || opts.ics
+        || opts.ntp)
+        && opts.idle_zombie.is_some()
+    {
+        or_exit(Err(
+            "--banners, --service-version, --tls, --http, --ssh, --ftp-anon, --smtp, --dns, --snmp, --smb, --rdp, --mqtt, --datastores, --containers, --ics and --ntp connect from your own address, which --idle-zombie is meant to avoid"
+                .to_string(),
+        ))
+    }
+
+    // An idle scan sends its SYNs in the name of the zombie, which has to prove fit for it first.
+    #[cfg(target_os = "linux")]
+    let engine = match (&opts.idle_zombie, engine) {
+        (None, engine) => engine,
+        (Some(spec), scanner::Engine::Sockets) if !opts.udp && !opts.sctp && !opts.ip_protocols => {
+            let zombie = or_exit(idle::Zombie::open(&source, or_exit(idle::parse(spec))));
+            or_exit(zombie.check(timeout).await);
+            scanner::Engine::Idle(zombie)
+        }
+        (Some(_), _) => or_exit(Err(
+            "--idle-zombie cannot be combined with other scan types".to_string(),
+        )),
+    };
+
+    // Traceroutes follow TCP connects from your own address, which other scans may be avoiding.
+    if opts.traceroute {
+        if opts.idle_zombie.is_some() {
+            or_exit(Err("--traceroute sends probes from your own address, which --idle-zombie is meant to avoid".to_string()))
+        }
+        if opts.udp || opts.sctp || opts.ip_protocols {
+            or_exit(Err(
+                "--traceroute follows open TCP ports, so it cannot be combined with --udp, --sctp or --ip-protocols"
+                    .to_string(),
+            ))
+        }
+        if opts.source_port.is_some() {
+            or_exit(Err(
+                "--traceroute tells its probes apart by their local ports, so it cannot be combined with --source-port"
+                    .to_string(),
+            ))
+        }
+        #[cfg(target_os = "linux")]
+        or_exit(traceroute::check(&source));
+    }
+
+    // Root is given up before the scan, so nothing after it may need it.
+    if opts.drop_privs.is_some() {
+        if opts.traceroute {
+            or_exit(Err(
+                "--traceroute opens raw sockets after the scan, so it cannot be combined with --drop-privs"
+                    .to_string(),
+            ))
+        }
+        if opts.source_port.is_some_and(|port| port < 1024) {
+            or_exit(Err(
+                "--source-port below 1024 needs root for every probe, so it cannot be combined with --drop-privs"
+                    .to_string(),
+            ))
+        }
+    }
+    if opts.ip_protocols && opts.source_port.is_some() {
+        or_exit(Err(
+            "--ip-protocols probes IP protocols, not ports, so it cannot be combined with --source-port"
+                .to_string(),
+        ))
+    }
+
+    // Record the traffic to and from the targets from here on, if asked to.
+    #[cfg(target_os = "linux")]
+    let capture = opts.pcap.as_deref().map(|path| {
+        or_exit(pcap::Capture::start(
+            path,
+            &hosts,
+            opts.interface.as_deref(),
+        ))
+    });
+
+    // Only scan the hosts that show a sign of life, if asked to, or only list them.
+    if opts.ping_first || opts.ping_scan {
+        let option = if opts.ping_scan {
+            "--ping-scan"
+        } else {
+            "--ping-first"
+        };
+        if opts.idle_zombie.is_some() {
+            or_exit(Err(format!(
+                "{} sends probes from your own address, which --idle-zombie is meant to avoid",
+                option
+            )))
+        }
+        let total = hosts.len();
+        hosts = discovery::discover(hosts, &source, timeout, concurrency).await;
+        if opts.ping_scan {
+            if opts.resolve {
+                let all: Vec<usize> = (0..hosts.len()).collect();
+                targets::reverse_lookup(&mut hosts, &all, &resolver).await;
+            }
+            report::print_up_hosts(&hosts, total);
+            if let Some(path) = &opts.json {
+                let stats = report::Stats {
+                    ports_scanned: 0,
+                    hosts_scanned: total,
+                    ports_excluded: 0,
+                    probes_total: 0,
+                    probes_completed: 0,
+                    truncated: false,
+                    interrupted: false,
+                    verify_total: 0,
+                    verify_completed: 0,
+                    verify_flipped: 0,
+                };
+                or_exit(report::write_json(path, &hosts, &[], &stats));
+            }
+            #[cfg(target_os = "linux")]
+            save_capture(capture);
+            return;
+        }
+        eprintln!(
+            "Host discovery: {} host(s) up, {} target(s) given",
+            hosts.len(),
+            total
+        );
+        if hosts.is_empty() {
+            eprintln!("No host is up, nothing to scan");
+            #[cfg(target_os = "linux")]
+            save_capture(capture);
+            std::process::exit(1);
+        }
+    }
+
+    // Shuffle the probe order if asked for; results are sorted again before reporting.
+    // One seeded generator drives both shuffles, so a run can be repeated exactly.
+    let mut host_order: Vec<usize> = (0..hosts.len()).collect();
+    if opts.randomize_hosts || opts.randomize_ports {
+        let seed = opts.seed.unwrap_or_else(|| {
+            let seed = rand::random();
+            eprintln!("Randomizing with --seed {}", seed);
+            seed
+        });
+        let mut rng = StdRng::seed_from_u64(seed);
+        if opts.randomize_hosts {
+            host_order.shuffle(&mut rng);
+        }
+        if opts.randomize_ports {
+            port_list.shuffle(&mut rng);
+            udp_list.shuffle(&mut rng);
+        }
+    }
+
+    // Timeouts start at --timeout and adapt to the round-trip time of each host.
+    let host_timeout = opts.host_timeout.map(Duration::from_secs_f64);
+    let overrides = opts
+        .timeout_override
+        .as_deref()
+        .map(|spec| or_exit(ports::parse_timeouts(spec, &groups)))
+        .unwrap_or_default();
+    let timing = scanner::Timing::new(
+        hosts.len(),
+        scanner::MIN_TIMEOUT,
+        timeout,
+        host_timeout,
+        overrides.clone(),
+    );
+    // A resumed scan starts with the probes and results of the checkpoint.
+    let (done, resumed_out) = match &resumed {
+        Some(checkpoint) => checkpoint.restore(&hosts, &port_list, protocol),
+        None => (
+            checkpoint::Progress::new(hosts.len(), port_list.len() + udp_list.len()),
+            vec![],
+        ),
+    };
+    let settings = scanner::Settings {
+        retries: opts.retries.unwrap_or(template.retries),
+        concurrency,
+        rate,
+        source,
+        engine,
+        batches: match (opts.batch_size, opts.batch_delay) {
+            (Some(size), Some(ms)) => Some(scanner::Batches::new(size, Duration::from_millis(ms))),
+            (None, None) => None,
+            _ => or_exit(Err(
+                "--batch-size and --batch-delay have to be given together".to_string(),
+            )),
+        },
+        pause: scanner::Pause::default(),
+        done,
+        host_parallelism: opts.host_parallelism.unwrap_or(1),
+        per_host: opts.per_host_concurrency,
+    };
+
+    // Everything that needs root is set up by now, so give it up if asked to.
+    if let Some(user) = &opts.drop_privs {
+        or_exit(privileges::drop_to(user));
+    }
+
+    // Save the starting point right away, so a checkpoint file that cannot be written
+    // is noticed before the scan instead of after it.
+    if let Some(path) = &opts.checkpoint {
+        or_exit(checkpoint::save(
+            path,
+            &args,
+            &hosts,
+            &settings.done,
+            &resumed_out,
+        ));
+    }
+
+    // Probe every host and port while a consumer handles the results as they come in,
+    // so progress is shown live and slow output never holds up the probes.
+    let (tx, mut rx) = mpsc::channel(RESULT_BUFFER);
+    let max_duration = opts.max_duration.map(Duration::from_secs_f64);
+    let (probed_hosts, probed_ports, timing, settings) = (&hosts, &port_list, &timing, &settings);
+    let probed_udp = &udp_list;
+    let probing = async move {
+        let run = async {
+            let mut results = pin!(scanner::scan(
+                probed_hosts,
+                &host_order,
+                probed_ports,
+                protocol,
+                timing,
+                settings
+            )
+            .chain(scanner::scan(
+                probed_hosts,
+                &host_order,
+                probed_udp,
+                scanner::Protocol::Udp,
+                timing,
+                settings
+            )));
+            while let Some(result) = results.next().await {
+                // The receiver only goes away once every result has been read.
+                let _ = tx.send(result).await;
+            }
+        };
+        // `--max-duration` and Ctrl-C stop the whole scan, dropping the outstanding probes
+        // but keeping what was found so far. Returns whether it was truncated or interrupted.
+        let deadline = async {
+            match max_duration {
+                Some(limit) => tokio::time::sleep(limit).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = run => (false, false),
+            _ = deadline => (true, false),
+            _ = interrupted() => (false, true),
+        }
+    };
+    // Keys pressed in the terminal pause (`p`), resume (`r`) or report on (`s`) the scan,
+    // unless stdin is taken up by the target list.
+    let probes_total = hosts.len() * (port_list.len() + udp_list.len());
+    let listening = if from_stdin || opts.input_list.as_deref() == Some("-") {
+        None
+    } else {
+        keys::listen()
+    };
+    let (mut keys, terminal) = listening.unzip();
+    let started = Instant::now();
+    // Agents ignore the communities they do not know, so --snmp asks filtered ports 161 too.
+    let snmp_candidate = |r: &scanner::PortResult| {
+        opts.snmp && r.protocol == scanner::Protocol::Udp && r.port == 161
+    };
+    let consuming = async {
+        let mut out = resumed_out;
+        let mut filtered = vec![];
+        let mut progress = settings.done.clone();
+        let mut saving = tokio::time::interval_at(
+            tokio::time::Instant::now() + checkpoint::INTERVAL,
+            checkpoint::INTERVAL,
+        );
+        loop {
+            let key = async {
+                match keys.as_mut() {
+                    Some(keys) => keys.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                result = rx.recv() => {
+                    let Some(result) = result else { break };
+                    progress.record(result.host, result.port, result.protocol);
+                    if result.state == scanner::PortState::Filtered && opts.verify_filtered {
+                        filtered.push((result.host, result.port, result.protocol));
+                    }
+                    if result.state.reported() {
+                        print!("."); // Print a dot to indicate progress.
+                        io::stdout().flush().unwrap(); // Flush stdout to ensure the dot appears immediately.
+                        out.push(result);
+                    } else if opts.verbose || snmp_candidate(&result) {
+                        out.push(result);
+                    }
+                }
+                Some(key) = key => match key {
+                    'p' if !settings.pause.is_paused() => {
+                        settings.pause.set(true);
+                        eprintln!("\nPaused, press r to resume");
+                    }
+                    'r' if settings.pause.is_paused() => {
+                        settings.pause.set(false);
+                        eprintln!("\nResumed");
+                    }
+                    's' => eprintln!(
+                        "\nStatus: {} of {} probes done ({:.1}%), {} open port(s) found, {:.1}s elapsed{}",
+                        progress.completed(),
+                        probes_total,
+                        report::percent(progress.completed(), probes_total),
+                        out.iter().filter(|r| r.state.reported()).count(),
+                        started.elapsed().as_secs_f64(),
+                        if settings.pause.is_paused() { ", paused" } else { "" }
+                    ),
+                    _ => {}
+                },
+                Some(path) = async { saving.tick().await; opts.checkpoint.as_deref() } => {
+                    if let Err(e) = checkpoint::save(path, &args, probed_hosts, &progress, &out) {
+                        eprintln!("\nWarning: {}", e);
+                    }
+                }
+            }
+        }
+        (out, filtered, progress)
+    };
+    let ((truncated, mut interrupted), (mut out, filtered, progress)) =
+        tokio::join!(probing, consuming);
+    #[cfg_attr(not(unix), allow(clippy::drop_non_drop))]
+    drop(terminal); // Give the terminal back its usual settings before reporting.
+    let completed = progress.completed();
+
+    // Not a single answer to fragmented probes means the path most likely drops fragments.
+    #[cfg(target_os = "linux")]
+    if let (Some(_), scanner::Engine::Raw(raw, _)) = (fragment, &settings.engine) {
+        if completed > 0 && raw.answered() == 0 {
+            eprintln!("Warning: no fragmented probe was answered, so the path to the targets may drop or reassemble fragments; scan again without {} to compare", option);
+        }
+    }
+
+    // Save where the scan stopped, so an interrupted or truncated one can be resumed.
+    if let Some(path) = &opts.checkpoint {
+        if let Err(e) = checkpoint::save(path, &args, &hosts, &progress, &out) {
+            eprintln!("Warning: {}", e);
+        }
+    }
+
+    // Probe the filtered ports of a finished scan once more, with twice the timeout and without
+    // adapting it, in case they were only dropped because the network was busy.
+    let mut verified = 0;
+    let mut flipped = 0;
+    // The ports that answered the second pass, whose filtered results of the first one are stale.
+    let mut answered = HashSet::new();
+    if !filtered.is_empty() && !truncated && !interrupted {
+        let wait = timeout * 2;
+        print!(
+            "\nVerifying {} filtered port(s) with a {} ms timeout",
+            filtered.len(),
+            wait.as_millis()
+        );
+        io::stdout().flush().unwrap();
+        let timing = scanner::Timing::new(hosts.len(), wait, wait, None, overrides);
+        let run = async {
+            let mut results = pin!(scanner::verify(&hosts, &filtered, &timing, settings));
+            while let Some(result) = results.next().await {
+                verified += 1;
+                if result.state == scanner::PortState::Filtered {
+                    continue;
+                }
+                flipped += 1;
+                if result.state.reported() {
+                    print!(".");
+                    io::stdout().flush().unwrap();
+                }
+                if result.state.reported() || opts.verbose {
+                    answered.insert((result.host, result.port, result.protocol));
+                    out.push(result);
+                }
+            }
+        };
+        // Ctrl-C ends the second pass early, keeping the main results.
+        tokio::select! {
+            _ = run => {}
+            _ = crate::interrupted() => interrupted = true,
+        }
+    }
+
+    println!(); // Print a newline for output formatting.
+
+    // Remember which hosts ran out of time so the report can say their results are incomplete.
+    for (i, target) in hosts.iter_mut().enumerate() {
+        target.timed_out = timing.timed_out(i);
+    }
+
+    // Drop what the first pass said about the ports that answered the second one.
+    out.retain(|r| {
+        r.state != scanner::PortState::Filtered || !answered.contains(&(r.host, r.port, r.protocol))
+    });
 
     // Sort the vector of open ports in ascending order.
-    out.sort();
+    out.sort_by_key(|r| (r.host, r.port, r.protocol != scanner::Protocol::Tcp));
 
-    // Print each open port.
-    for v in out {
-        println!("{} is open", v); // Display the open port.
+    // The lookups and checks of the open ports stop at Ctrl-C or SIGTERM too, keeping what the
+    // ones before found.
+    let checks = async {
+        // Look up the names of the hosts that are about to be reported.
+        if opts.resolve {
+            let mut reported: Vec<usize> = out.iter().map(|r| r.host).collect();
+            reported.dedup();
+            targets::reverse_lookup(&mut hosts, &reported, &resolver).await;
+        }
+
+        // Read what the services on the open ports say first, unless the scan was cut short.
+        if opts.banners && !truncated && !interrupted {
+            banner::grab_all(&hosts, &mut out, settings, timeout).await;
+        }
+
+        // Identify the services on the open ports and their versions, unless the scan was cut short.
+        if opts.service_version && !truncated && !interrupted {
+            versions::detect_all(&hosts, &mut out, settings, timeout).await;
+        }
+
+        // Look at the TLS certificates of the open ports, unless the scan was cut short.
+        if opts.tls && !truncated && !interrupted {
+            let options = handshakes::Options {
+                sni: opts.sni.as_deref(),
+                versions: opts.tls_versions || opts.tls_ciphers,
+                ciphers: opts.tls_ciphers,
+                wait: timeout,
+                concurrency: settings.concurrency,
+                rate: settings.rate.as_ref(),
+            };
+            handshakes::probe_all(&hosts, &mut out, &settings.source, &options).await;
+        }
+
+        // Request the pages of the open web ports, unless the scan was cut short.
+        if opts.http && !truncated && !interrupted {
+            web::probe_all(
+                &hosts,
+                &mut out,
+                opts.http_headers,
+                websocket_paths.as_deref(),
+                settings,
+                timeout,
+            )
+            .await;
+        }
+
+        // Audit the key exchanges of the open SSH ports, unless the scan was cut short.
+        if opts.ssh && !truncated && !interrupted {
+            ssh::audit_all(&hosts, &mut out, settings, timeout).await;
+        }
+
+        // Try anonymous logins on the open FTP ports, unless the scan was cut short.
+        if opts.ftp_anon && !truncated && !interrupted {
+            ftp::login_all(&hosts, &mut out, settings, timeout).await;
+        }
+
+        // Probe the open SMTP ports, unless the scan was cut short.
+        if opts.smtp && !truncated && !interrupted {
+            smtp::probe_all(&hosts, &mut out, settings, timeout).await;
+        }
+
+        // Query the open DNS ports, unless the scan was cut short.
+        if opts.dns && !truncated && !interrupted {
+            nameserver::probe_all(&hosts, &mut out, settings, timeout).await;
+        }
+
+        // Try the SNMP communities on the UDP ports 161, unless the scan was cut short.
+        if let Some(communities) = snmp_communities
+            .as_deref()
+            .filter(|_| !truncated && !interrupted)
+        {
+            snmp::probe_all(&hosts, &mut out, communities, settings, timeout).await;
+        }
+
+        // Negotiate with the open SMB ports, unless the scan was cut short.
+        if opts.smb && !truncated && !interrupted {
+            smb::negotiate_all(&hosts, &mut out, settings, timeout).await;
+        }
+
+        // Probe the open RDP ports, unless the scan was cut short.
+        if opts.rdp && !truncated && !interrupted {
+            rdp::probe_all(&hosts, &mut out, settings, timeout).await;
+        }
+
+        // Try anonymous connects on the open MQTT ports, unless the scan was cut short.
+        if opts.mqtt && !truncated && !interrupted {
+            mqtt::connect_all(&hosts, &mut out, settings, timeout).await;
+        }
+
+        // Ask the open data store ports, unless the scan was cut short.
+        if opts.datastores && !truncated && !interrupted {
+            datastore::probe_all(&hosts, &mut out, settings, timeout).await;
+        }
+
+        // Ask the open container platform API ports, unless the scan was cut short.
+        if opts.containers && !truncated && !interrupted {
+            containers::probe_all(&hosts, &mut out, settings, timeout).await;
+        }
+
+        // Identify the open industrial protocol ports, unless the scan was cut short.
+        if opts.ics && !truncated && !interrupted {
+            ics::probe_all(&hosts, &mut out, opts.safe, settings, timeout).await;
+        }
+
+        // Check the open NTP ports for amplification, unless the scan was cut short.
+        if opts.ntp && !truncated && !interrupted {
+            ntp::probe_all(&hosts, &mut out, settings, timeout).await;
+        }
+
+        // Trace the route to the hosts with an open port, unless the scan was cut short.
+        #[cfg(target_os = "linux")]
+        if opts.traceroute && !truncated && !interrupted {
+            traceroute::trace_all(&mut hosts, &out, settings, timeout).await;
+        }
+    };
+    tokio::select! {
+        _ = checks => {}
+        _ = crate::interrupted() => interrupted = true,
+    }
+    // The filtered ports kept for --snmp only show if an agent answered on them.
+    if opts.snmp && !opts.verbose {
+        out.retain(|r| r.state.reported());
+    }
+    if let Some(dir) = &opts.cert_dir {
+        let written = or_exit(handshakes::export(&hosts, &out, dir));
+        eprintln!("Wrote {} certificate chain(s) to {}", written, dir);
+    }
+    let expiring = opts
+        .cert_warn_days
+        .map_or(0, |days| handshakes::flag_expiring(&mut out, days));
+    #[cfg(target_os = "linux")]
+    save_capture(capture);
+
+    // Print the open ports grouped per host, then how dual-stack hostnames differ per family.
+    report::print_hosts(&hosts, &out, opts.show_services, opts.reason);
+    report::print_dual_stack(&hosts, &out);
+
+    // Print the scan statistics.
+    let stats = report::Stats {
+        ports_scanned: port_list.len() + udp_list.len(),
+        hosts_scanned: hosts.len(),
+        ports_excluded: excluded_ports,
+        probes_total,
+        probes_completed: completed,
+        truncated,
+        interrupted,
+        verify_total: filtered.len(),
+        verify_completed: verified,
+        verify_flipped: flipped,
+    };
+    report::print_stats(&stats);
+
+    // Write the structured report if asked for.
+    if let Some(path) = &opts.json {
+        or_exit(report::write_json(path, &hosts, &out, &stats));
+    }
+
+    // Expiring certificates are the point of the sweep, so say so last and in the exit code.
+    if let Some(days) = opts.cert_warn_days.filter(|_| expiring > 0) {
+        eprintln!(
+            "Warning: {} certificate(s) expire within {} day(s) or already have",
+            expiring, days
+        );
+    }
+    // An interrupted scan exits with the code of one stopped by Ctrl-C, having reported what it
+    // found.
+    if interrupted {
+        std::process::exit(130);
+    }
+    if expiring > 0 {
+        std::process::exit(2);
     }
 }
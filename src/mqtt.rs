@@ -0,0 +1,141 @@
+use crate::scanner::{self, PortResult, PortState, Protocol, Settings}; // Import the open ports to connect to and the scan settings to connect with.
+use crate::targets::Target; // Import the hosts the ports belong to.
+use crate::web; // Import the plain and TLS connects of the web probes.
+use serde::Serialize; // Import `Serialize` to report the brokers in the structured output.
+use std::io; // Import I/O errors for socket failures.
+use std::net::SocketAddr; // Import `SocketAddr` for the ports tried.
+use std::time::Duration; // Import `Duration` for the connect and read timeouts.
+use tokio::io::{AsyncReadExt, AsyncWriteExt}; // Import reads and writes on the connections.
+use tokio::time::timeout; // Import `timeout` to give up on silent brokers.
+
+// What an MQTT broker answered a CONNECT without credentials.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Mqtt {
+    // Set when the CONNECT went over TLS.
+    pub tls: bool,
+    // Set when the broker accepted the client.
+    pub anonymous: bool,
+    // What its CONNACK said, e.g. `accepted` or `not authorized`.
+    pub reply: String,
+}
+
+// Function to connect to every open TCP port in `results` that may run MQTT without credentials:
+// ports 1883 and 8883 and ports --service-version named mqtt. Port 8883 and ports --tls found TLS
+// on are tried over TLS. Each gets `wait` for the connect, the handshake and the answer each. The client
+// disconnects right after; nothing is subscribed to or published.
+pub async fn connect_all(
+    hosts: &[Target],
+    results: &mut [PortResult],
+    settings: &Settings,
+    wait: Duration,
+) {
+    scanner::check_ports(
+        hosts,
+        results,
+        settings.concurrency,
+        |r, host| {
+            let mqtt = matches!(r.port, 1883 | 8883)
+                || r.findings
+                    .detected
+                    .as_ref()
+                    .is_some_and(|s| s.name == "mqtt");
+            let name = host.hostnames.first().map(String::as_str);
+            (r.protocol == Protocol::Tcp && r.state == PortState::Open && mqtt)
+                .then_some((name, r.port == 8883 || r.findings.tls.is_some()))
+        },
+        |addr, (name, tls)| async move {
+            connect(addr, name, tls, settings, wait)
+                .await
+                .ok()
+                .flatten()
+        },
+        |r, mqtt| r.findings.mqtt = mqtt,
+    )
+    .await;
+}
+
+// Function to send a CONNECT without credentials to `addr`, over TLS if `tls` is set, and read
+// the CONNACK. Gives `None` if the port does not answer like an MQTT broker.
+async fn connect(
+    addr: SocketAddr,
+    name: Option<&str>,
+    tls: bool,
+    settings: &Settings,
+    wait: Duration,
+) -> io::Result<Option<Mqtt>> {
+    let mut stream = web::connect(addr, name, tls, settings, wait).await?;
+    let client = format!("portsniffer-{:08x}", rand::random::<u32>());
+    stream.write_all(&connect_packet(&client)).await?;
+    let mut answer = [0u8; 4];
+    timeout(wait, stream.read_exact(&mut answer))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    let Some(code) = connack(&answer) else {
+        return Ok(None);
+    };
+    // Leave politely; the answer does not matter.
+    if code == 0 {
+        let _ = stream.write_all(&[0xe0, 0]).await;
+    }
+    Ok(Some(Mqtt {
+        tls,
+        anonymous: code == 0,
+        reply: reply_name(code),
+    }))
+}
+
+// Function to build an MQTT 3.1.1 CONNECT for the client ID `client`, with a clean session, no
+// will and no user name or password.
+fn connect_packet(client: &str) -> Vec<u8> {
+    let mut body = b"\x00\x04MQTT\x04\x02".to_vec(); // Protocol name, level 4, clean session.
+    body.extend_from_slice(&60u16.to_be_bytes()); // Keep alive in seconds.
+    body.extend_from_slice(&(client.len() as u16).to_be_bytes());
+    body.extend_from_slice(client.as_bytes());
+    // A remaining length under 128 takes one byte.
+    let mut packet = vec![0x10, body.len() as u8];
+    packet.extend_from_slice(&body);
+    packet
+}
+
+// Function to read the return code of a CONNACK.
+fn connack(packet: &[u8]) -> Option<u8> {
+    match packet {
+        [0x20, 2, flags, code] if flags & 0xfe == 0 => Some(*code),
+        _ => None,
+    }
+}
+
+// Function to name a CONNACK return code.
+fn reply_name(code: u8) -> String {
+    match code {
+        0 => "accepted".to_string(),
+        1 => "unacceptable protocol version".to_string(),
+        2 => "client identifier rejected".to_string(),
+        3 => "server unavailable".to_string(),
+        4 => "bad user name or password".to_string(),
+        5 => "not authorized".to_string(),
+        other => format!("return code {}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_connects() {
+        assert_eq!(
+            connect_packet("portsniffer-0000abcd"),
+            b"\x10\x20\x00\x04MQTT\x04\x02\x00\x3c\x00\x14portsniffer-0000abcd"
+        );
+    }
+
+    #[test]
+    fn reads_connacks() {
+        assert_eq!(connack(b"\x20\x02\x00\x00"), Some(0));
+        assert_eq!(connack(b"\x20\x02\x00\x05"), Some(5));
+        assert_eq!(connack(b"\x20\x03\x00\x00"), None);
+        assert_eq!(connack(b"SSH-"), None);
+        assert_eq!(reply_name(5), "not authorized");
+    }
+}
@@ -0,0 +1,157 @@
+use crate::dns::{self, Message, RData}; // Import the DNS wire format of the queries.
+use crate::scanner::{self, PortResult, PortState, Protocol, Settings}; // Import the open ports to query and the scan settings to query with.
+use crate::targets::Target; // Import the hosts the ports belong to.
+use serde::Serialize; // Import `Serialize` to report the servers in the structured output.
+use socket2::Type; // Import socket types for the queries.
+use std::io; // Import I/O errors for socket failures.
+use std::net::SocketAddr; // Import `SocketAddr` for the ports queried.
+use std::time::Duration; // Import `Duration` for the query timeouts.
+use tokio::io::{AsyncReadExt, AsyncWriteExt}; // Import reads and writes for DNS over TCP.
+use tokio::net::{TcpSocket, UdpSocket}; // Import sockets of the source for the queries.
+use tokio::time::timeout; // Import `timeout` to give up on silent servers.
+
+// Define the outside name the recursion test asks for; any resolver can find it.
+const RECURSION_NAME: &str = "example.com";
+
+// What a DNS server told about itself and whether it resolves names for anyone.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Nameserver {
+    // The TXT record version.bind of class CHAOS, e.g. `9.18.24`, unless it keeps it to itself.
+    pub version: Option<String>,
+    // `true` when it resolved the outside name for us, `false` when it would not and `None`
+    // when it did not answer the question.
+    pub open_resolver: Option<bool>,
+    // The response code of the recursion test, e.g. `NOERROR` or `REFUSED`.
+    pub recursion: Option<String>,
+}
+
+// Function to query every open port 53 in `results`, over TCP or UDP like the port, and ports
+// --service-version named domain, for version.bind and an outside name. Each query gets
+// `wait`.
+pub async fn probe_all(
+    hosts: &[Target],
+    results: &mut [PortResult],
+    settings: &Settings,
+    wait: Duration,
+) {
+    scanner::check_ports(
+        hosts,
+        results,
+        settings.concurrency,
+        |r, _| {
+            let dns = r.port == 53
+                || r.findings
+                    .detected
+                    .as_ref()
+                    .is_some_and(|s| s.name == "domain");
+            let transport = matches!(r.protocol, Protocol::Tcp | Protocol::Udp);
+            (transport && r.state == PortState::Open && dns).then_some(r.protocol)
+        },
+        |addr, protocol| probe(addr, protocol, settings, wait),
+        |r, nameserver| r.findings.dns = nameserver,
+    )
+    .await;
+}
+
+// Function to ask the server at `addr` for its version and to resolve the outside name. Gives
+// `None` if it answered neither.
+async fn probe(
+    addr: SocketAddr,
+    protocol: Protocol,
+    settings: &Settings,
+    wait: Duration,
+) -> Option<Nameserver> {
+    let version = dns::build_query(
+        rand::random(),
+        "version.bind",
+        dns::TYPE_TXT,
+        dns::CLASS_CH,
+        false,
+    );
+    let version = exchange(addr, protocol, &version, settings, wait)
+        .await
+        .ok();
+    let recursion = dns::build_query(
+        rand::random(),
+        RECURSION_NAME,
+        dns::TYPE_A,
+        dns::CLASS_IN,
+        true,
+    );
+    let recursion = exchange(addr, protocol, &recursion, settings, wait)
+        .await
+        .ok();
+    if version.is_none() && recursion.is_none() {
+        return None;
+    }
+    Some(Nameserver {
+        version: version.as_ref().and_then(version_text),
+        open_resolver: recursion.as_ref().map(resolves),
+        recursion: recursion.as_ref().map(Message::rcode_name),
+    })
+}
+
+// Function to send the query `packet` to `addr` over `protocol` and wait for the answer to it,
+// within `wait`.
+async fn exchange(
+    addr: SocketAddr,
+    protocol: Protocol,
+    packet: &[u8],
+    settings: &Settings,
+    wait: Duration,
+) -> io::Result<Message> {
+    let id = u16::from_be_bytes([packet[0], packet[1]]);
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed DNS response");
+    let exchange = async {
+        if protocol == Protocol::Udp {
+            let socket = UdpSocket::from_std(settings.socket(addr, Type::DGRAM).await?.into())?;
+            socket.connect(addr).await?;
+            socket.send(packet).await?;
+            let mut buffer = vec![0u8; 4096];
+            loop {
+                let read = socket.recv(&mut buffer).await?;
+                // Ignore stray packets that do not answer this query.
+                match dns::parse_message(&buffer[..read]) {
+                    Some(message) if message.id == id => return Ok(message),
+                    _ => continue,
+                }
+            }
+        }
+        let socket = TcpSocket::from_std_stream(settings.socket(addr, Type::STREAM).await?.into());
+        let mut stream = socket.connect(addr).await?;
+        let mut framed = (packet.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(packet);
+        stream.write_all(&framed).await?;
+        let mut length = [0u8; 2];
+        stream.read_exact(&mut length).await?;
+        let mut buffer = vec![0u8; usize::from(u16::from_be_bytes(length))];
+        stream.read_exact(&mut buffer).await?;
+        dns::parse_message(&buffer).ok_or_else(malformed)
+    };
+    timeout(wait, exchange)
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?
+}
+
+// Function to take the version out of the answer to version.bind, if the server gave one.
+fn version_text(message: &Message) -> Option<String> {
+    message
+        .answers
+        .iter()
+        .find_map(|record| match &record.data {
+            RData::Text(text) => Some(text.chars().filter(|c| !c.is_control()).collect()),
+            _ => None,
+        })
+        .filter(|text: &String| !text.trim().is_empty())
+}
+
+// Function to tell whether the answer to the recursion test resolved the outside name: no
+// error, recursion available and an address in the answer.
+fn resolves(message: &Message) -> bool {
+    message.rcode() == 0
+        && message.recursion_available()
+        && message
+            .answers
+            .iter()
+            .any(|record| matches!(record.data, RData::Ip(_)))
+}
@@ -0,0 +1,216 @@
+use crate::arp::Link; // Import the local networks.
+use crate::discovery; // Import the echo requests of pings.
+use crate::targets::Mac; // Import the MAC addresses found on the local networks.
+use rand::Rng; // Import `Rng` to draw the identifiers of echo requests.
+use socket2::{Domain, Protocol, Socket, Type}; // Import socket2 to open ICMPv6 sockets.
+use std::collections::{HashMap, HashSet}; // Import maps and sets of the hosts asked for.
+use std::io; // Import I/O errors for socket failures.
+use std::mem::MaybeUninit; // Import `MaybeUninit` for the receive buffer.
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6}; // Import IPv6 address types.
+use std::time::Duration; // Import `Duration` for reply timeouts.
+use tokio::io::unix::AsyncFd; // Import `AsyncFd` to wait for replies without blocking.
+use tokio::time::{timeout_at, Instant}; // Import `timeout_at` to stop listening for replies.
+
+// ICMPv6 message types (RFC 4861).
+const NEIGHBOR_SOLICITATION: u8 = 135;
+const NEIGHBOR_ADVERTISEMENT: u8 = 136;
+
+// Neighbor discovery options carrying a link-layer address.
+const SOURCE_LINK_ADDRESS: u8 = 1;
+const TARGET_LINK_ADDRESS: u8 = 2;
+
+// Define how often hosts that did not answer are asked again.
+const ROUNDS: usize = 2;
+
+// Function to open a raw ICMPv6 socket that sends on interface `index` with a hop limit of 255,
+// which receivers of neighbor discovery messages insist on (RFC 4861). The kernel fills in the
+// checksums of ICMPv6 sockets. Needs root or the CAP_NET_RAW capability.
+fn socket(index: u32) -> io::Result<AsyncFd<Socket>> {
+    let socket = Socket::new(Domain::IPV6, Type::RAW, Some(Protocol::ICMPV6))?;
+    socket.set_nonblocking(true)?;
+    socket.set_multicast_if_v6(index)?;
+    socket.set_multicast_hops_v6(255)?;
+    socket.set_unicast_hops_v6(255)?;
+    // This host does not need to answer its own multicasts.
+    socket.set_multicast_loop_v6(false)?;
+    AsyncFd::new(socket)
+}
+
+// Function to send a packet on a non-blocking socket, waiting for room if its buffer is full.
+async fn send(socket: &AsyncFd<Socket>, packet: &[u8], to: SocketAddrV6) -> io::Result<()> {
+    loop {
+        let mut ready = socket.writable().await?;
+        if let Ok(sent) = ready.try_io(|socket| socket.get_ref().send_to(packet, &to.into())) {
+            return sent.map(drop);
+        }
+    }
+}
+
+// Function to read ICMPv6 messages off `socket` until `deadline`, handing each one and its
+// sender to `message`, which returns true once it has heard enough.
+async fn listen(
+    socket: &AsyncFd<Socket>,
+    deadline: Instant,
+    mut message: impl FnMut(&[u8], Ipv6Addr) -> bool,
+) -> io::Result<()> {
+    let receive = async {
+        let mut buffer = [MaybeUninit::<u8>::uninit(); 1500];
+        loop {
+            let mut ready = socket.readable().await?;
+            let Ok(received) = ready.try_io(|socket| socket.get_ref().recv_from(&mut buffer))
+            else {
+                continue;
+            };
+            let (length, from) = received?;
+            let Some(SocketAddr::V6(from)) = from.as_socket() else {
+                continue;
+            };
+            // SAFETY: `recv_from` initialized the first `length` bytes.
+            let packet =
+                unsafe { std::slice::from_raw_parts(buffer.as_ptr().cast::<u8>(), length) };
+            if message(packet, *from.ip()) {
+                return Ok(());
+            }
+        }
+    };
+    timeout_at(deadline, receive).await.unwrap_or(Ok(()))
+}
+
+// Function to ask every host in `hosts` on the IPv6 network `link` for its MAC address with a
+// neighbor solicitation, giving each round of them `wait` to be answered. Hosts that answer
+// without their link-layer address are up, but their MAC address is unknown.
+pub async fn solicit(
+    link: &Link,
+    hosts: &[Ipv6Addr],
+    wait: Duration,
+) -> io::Result<HashMap<Ipv6Addr, Option<Mac>>> {
+    let socket = socket(link.index)?;
+    let wanted: HashSet<Ipv6Addr> = hosts.iter().copied().collect();
+    let mut found = HashMap::new();
+    for _ in 0..ROUNDS {
+        for &host in hosts.iter().filter(|host| !found.contains_key(*host)) {
+            let group = SocketAddrV6::new(solicited_node(host), 0, 0, link.index);
+            send(&socket, &solicitation(host, link.mac), group).await?;
+        }
+        listen(&socket, Instant::now() + wait, |packet, _| {
+            if let Some((host, mac)) =
+                advertisement(packet).filter(|(host, _)| wanted.contains(host))
+            {
+                found.insert(host, mac);
+            }
+            found.len() == wanted.len()
+        })
+        .await?;
+        if found.len() == wanted.len() {
+            break;
+        }
+    }
+    Ok(found)
+}
+
+// Function to ping the multicast `group` on interface `index` and collect the hosts that answer
+// within `wait`. Pinging the all-nodes group ff02::1 finds the IPv6 hosts on a link without
+// knowing their addresses, which are too many to sweep.
+pub async fn ping_group(group: Ipv6Addr, index: u32, wait: Duration) -> io::Result<Vec<IpAddr>> {
+    let socket = socket(index)?;
+    let id = rand::thread_rng().gen::<u16>();
+    let request = discovery::echo_request(IpAddr::V6(group), id);
+    send(&socket, &request, SocketAddrV6::new(group, 0, 0, index)).await?;
+    let mut hosts = vec![];
+    listen(&socket, Instant::now() + wait, |packet, from| {
+        let from = IpAddr::V6(from);
+        if discovery::is_echo_reply(packet, from, Some(id)) && !hosts.contains(&from) {
+            hosts.push(from);
+        }
+        false
+    })
+    .await?;
+    Ok(hosts)
+}
+
+// Function to get the solicited-node multicast group of `host`, which only the hosts sharing the
+// last 24 bits of its address listen to (RFC 4291).
+fn solicited_node(host: Ipv6Addr) -> Ipv6Addr {
+    let [.., a, b, c] = host.octets();
+    Ipv6Addr::from([
+        0xff02,
+        0,
+        0,
+        0,
+        0,
+        1,
+        0xff00 | u16::from(a),
+        u16::from_be_bytes([b, c]),
+    ])
+}
+
+// Function to build a neighbor solicitation for `host`, with `mac` as the link-layer address to
+// answer to.
+fn solicitation(host: Ipv6Addr, mac: Mac) -> Vec<u8> {
+    let mut packet = vec![NEIGHBOR_SOLICITATION, 0, 0, 0, 0, 0, 0, 0];
+    packet.extend_from_slice(&host.octets());
+    packet.extend_from_slice(&[SOURCE_LINK_ADDRESS, 1]);
+    packet.extend_from_slice(&mac.0);
+    packet
+}
+
+// Function to read a neighbor advertisement: the address it is for, and the MAC address behind
+// it if the advertisement carries one.
+fn advertisement(packet: &[u8]) -> Option<(Ipv6Addr, Option<Mac>)> {
+    if *packet.first()? != NEIGHBOR_ADVERTISEMENT {
+        return None;
+    }
+    let target: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+    let mut options = packet.get(24..)?;
+    let mut mac = None;
+    // Options come in units of 8 bytes, their length counted in those units.
+    while let [kind, units, ..] = *options {
+        let length = usize::from(units) * 8;
+        if length == 0 || options.len() < length {
+            break;
+        }
+        if kind == TARGET_LINK_ADDRESS && length == 8 {
+            mac = Some(Mac(options[2..8].try_into().ok()?));
+        }
+        options = &options[length..];
+    }
+    Some((Ipv6Addr::from(target), mac))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_solicitations() {
+        let host: Ipv6Addr = "fd00::12:3456".parse().unwrap();
+        assert_eq!(
+            solicited_node(host),
+            "ff02::1:ff12:3456".parse::<Ipv6Addr>().unwrap()
+        );
+        let packet = solicitation(host, Mac([2, 0, 0, 0, 0, 1]));
+        assert_eq!(packet.len(), 32);
+        assert_eq!(&packet[8..24], &host.octets());
+        assert_eq!(&packet[24..], &[1, 1, 2, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn reads_advertisements() {
+        let host: Ipv6Addr = "fd00::1".parse().unwrap();
+        let mut packet = vec![NEIGHBOR_ADVERTISEMENT, 0, 0, 0, 0x60, 0, 0, 0];
+        packet.extend_from_slice(&host.octets());
+        assert_eq!(advertisement(&packet), Some((host, None)));
+        packet.extend_from_slice(&[TARGET_LINK_ADDRESS, 1, 2, 0xfc, 0, 0, 0, 5]);
+        assert_eq!(
+            advertisement(&packet),
+            Some((host, Some(Mac([2, 0xfc, 0, 0, 0, 5]))))
+        );
+        // A solicitation is no advertisement, and a short one is skipped.
+        assert_eq!(advertisement(&solicitation(host, Mac([0; 6]))), None);
+        assert_eq!(advertisement(&packet[..20]), None);
+        // An option of length zero ends the options.
+        packet.truncate(24);
+        packet.extend_from_slice(&[TARGET_LINK_ADDRESS, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(advertisement(&packet), Some((host, None)));
+    }
+}
@@ -0,0 +1,267 @@
+use crate::scanner::{self, PortResult, PortState, Protocol, Settings}; // Import the UDP ports to probe and the scan settings to probe with.
+use crate::targets::Target; // Import the hosts the ports belong to.
+use serde::Serialize; // Import `Serialize` to report the servers in the structured output.
+use socket2::Type; // Import socket types for the probes.
+use std::io; // Import I/O errors for socket failures.
+use std::net::SocketAddr; // Import `SocketAddr` for the ports probed.
+use std::time::Duration; // Import `Duration` for the response timeouts.
+use tokio::net::UdpSocket; // Import sockets of the source for the probes.
+use tokio::time::{timeout_at, Instant}; // Import deadlines to stop waiting for answers.
+
+// Define the modes of NTP packets (RFC 5905 and the ntpd sources).
+const MODE_CLIENT: u8 = 3;
+const MODE_SERVER: u8 = 4;
+const MODE_CONTROL: u8 = 6;
+const MODE_PRIVATE: u8 = 7;
+
+// Define the mode 6 opcode that reads the system variables, and the mode 7 request that lists
+// the last clients of ntpd (MON_GETLIST_1 of the XNTPD implementation).
+const READ_VARIABLES: u8 = 2;
+const MONLIST: u8 = 42;
+const IMPL_XNTPD: u8 = 3;
+
+// Define the sequence number of the mode 6 request.
+const SEQUENCE: u16 = 0x5053;
+
+// What an NTP server answered a client request and the queries amplification attacks use.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Ntp {
+    // The NTP version and stratum of its answer to the client request, if it gave one.
+    pub version: Option<u8>,
+    pub stratum: Option<u8>,
+    // The daemon and system it named in its system variables, e.g. `ntpd 4.2.8p15@1.3728-o` and
+    // `Linux/5.15.0`.
+    pub daemon: Option<String>,
+    pub system: Option<String>,
+    // How many bytes it answered the mode 6 read of its variables and the mode 7 monlist with,
+    // which it would send as well to a victim whose address a request is forged with.
+    pub control_bytes: Option<usize>,
+    pub monlist_bytes: Option<usize>,
+}
+
+// The answers collected so far, one request at a time.
+#[derive(Debug, Default)]
+struct Answers {
+    server: Option<(u8, u8)>,
+    // The offset and data of each fragment of the variables, and whether the last one came.
+    control: Vec<(usize, Vec<u8>)>,
+    control_bytes: usize,
+    control_done: bool,
+    monlist_bytes: usize,
+    monlist_done: bool,
+}
+
+impl Answers {
+    // Function to check whether every request got its full answer.
+    fn complete(&self) -> bool {
+        self.server.is_some() && self.control_done && self.monlist_done
+    }
+}
+
+// Function to probe every open UDP port 123 in `results` with a client request, a mode 6 read
+// of the system variables and a mode 7 monlist, sent at once, and collect the answers for
+// `wait`.
+pub async fn probe_all(
+    hosts: &[Target],
+    results: &mut [PortResult],
+    settings: &Settings,
+    wait: Duration,
+) {
+    scanner::check_ports(
+        hosts,
+        results,
+        settings.concurrency,
+        |r, _| {
+            (r.protocol == Protocol::Udp && r.port == 123 && r.state == PortState::Open)
+                .then_some(())
+        },
+        |addr, ()| async move { probe(addr, settings, wait).await.ok().flatten() },
+        |r, ntp| r.findings.ntp = ntp,
+    )
+    .await;
+}
+
+// Function to send the three requests to `addr` and read the answers until all came or `wait`
+// is over. Gives `None` if none was answered.
+async fn probe(addr: SocketAddr, settings: &Settings, wait: Duration) -> io::Result<Option<Ntp>> {
+    let socket = UdpSocket::from_std(settings.socket(addr, Type::DGRAM).await?.into())?;
+    socket.connect(addr).await?;
+    for request in [client_request(), control_request(), monlist_request()] {
+        socket.send(&request).await?;
+    }
+    let deadline = Instant::now() + wait;
+    let mut answers = Answers::default();
+    let mut buffer = vec![0u8; 2048];
+    while !answers.complete() {
+        let Ok(read) = timeout_at(deadline, socket.recv(&mut buffer)).await else {
+            break;
+        };
+        read_answer(&buffer[..read?], &mut answers);
+    }
+
+    let variables = joined(answers.control);
+    let ntp = Ntp {
+        version: answers.server.map(|(version, _)| version),
+        stratum: answers.server.map(|(_, stratum)| stratum),
+        daemon: variable(&variables, "version"),
+        system: variable(&variables, "system"),
+        control_bytes: (answers.control_bytes > 0).then_some(answers.control_bytes),
+        monlist_bytes: (answers.monlist_bytes > 0).then_some(answers.monlist_bytes),
+    };
+    let answered =
+        ntp.version.is_some() || ntp.control_bytes.is_some() || ntp.monlist_bytes.is_some();
+    Ok(answered.then_some(ntp))
+}
+
+// Function to put the fragments of the variables together in the order of their offsets.
+fn joined(mut fragments: Vec<(usize, Vec<u8>)>) -> String {
+    fragments.sort_by_key(|(offset, _)| *offset);
+    let data: Vec<u8> = fragments.into_iter().flat_map(|(_, data)| data).collect();
+    String::from_utf8_lossy(&data).into_owned()
+}
+
+// Function to build an NTP version 4 client request.
+fn client_request() -> Vec<u8> {
+    let mut packet = vec![0u8; 48];
+    packet[0] = 4 << 3 | MODE_CLIENT;
+    packet
+}
+
+// Function to build a mode 6 request that reads the system variables (association 0).
+fn control_request() -> Vec<u8> {
+    let mut packet = vec![2 << 3 | MODE_CONTROL, READ_VARIABLES];
+    packet.extend_from_slice(&SEQUENCE.to_be_bytes());
+    packet.extend_from_slice(&[0; 8]); // Status, association, offset and count.
+    packet
+}
+
+// Function to build a mode 7 monlist request, padded to the 48 bytes ntpdc sends.
+fn monlist_request() -> Vec<u8> {
+    let mut packet = vec![2 << 3 | MODE_PRIVATE, 0, IMPL_XNTPD, MONLIST];
+    packet.resize(48, 0);
+    packet
+}
+
+// Function to add an answer of the server to `answers`, whichever request it belongs to.
+// Anything else is ignored.
+fn read_answer(packet: &[u8], answers: &mut Answers) {
+    let Some(&first) = packet.first() else {
+        return;
+    };
+    match first & 0x07 {
+        MODE_SERVER if packet.len() >= 48 => {
+            answers.server = Some((first >> 3 & 0x07, packet[1]));
+        }
+        // A response to the read, with the More bit set on all but the last fragment.
+        MODE_CONTROL if packet.len() >= 12 && packet[1] & 0x9f == 0x80 | READ_VARIABLES => {
+            if packet[2..4] != SEQUENCE.to_be_bytes() {
+                return;
+            }
+            let offset = usize::from(u16::from_be_bytes([packet[8], packet[9]]));
+            let count = usize::from(u16::from_be_bytes([packet[10], packet[11]]));
+            let data = &packet[12..packet.len().min(12 + count)];
+            answers.control.push((offset, data.to_vec()));
+            answers.control_bytes += packet.len();
+            answers.control_done |= packet[1] & 0x20 == 0;
+        }
+        // A response to the monlist without an error, with the More bit set on all but the
+        // last packet.
+        MODE_PRIVATE if packet.len() >= 8 && first & 0x80 != 0 && packet[3] == MONLIST => {
+            if packet[4] >> 4 != 0 {
+                answers.monlist_done = true;
+                return;
+            }
+            answers.monlist_bytes += packet.len();
+            answers.monlist_done |= first & 0x40 == 0;
+        }
+        _ => {}
+    }
+}
+
+// Function to read the variable `name` from the `name=value` list of a mode 6 read, without
+// the quotes of a string value.
+fn variable(variables: &str, name: &str) -> Option<String> {
+    let mut rest = variables;
+    while !rest.is_empty() {
+        let (key, after) = rest.split_once('=')?;
+        // A quoted value may hold commas of its own.
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let (value, next) = quoted.split_once('"').unwrap_or((quoted, ""));
+                (value, next.split_once(',').map_or("", |(_, next)| next))
+            }
+            None => after.split_once(',').unwrap_or((after, "")),
+        };
+        if key.trim() == name {
+            let value: String = value.chars().filter(|c| !c.is_control()).collect();
+            return (!value.trim().is_empty()).then(|| value.trim().to_string());
+        }
+        rest = next;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_requests() {
+        assert_eq!(client_request()[..2], [0x23, 0]);
+        assert_eq!(client_request().len(), 48);
+        assert_eq!(
+            control_request(),
+            b"\x16\x02\x50\x53\x00\x00\x00\x00\x00\x00\x00\x00"
+        );
+        assert_eq!(monlist_request()[..8], *b"\x17\x00\x03\x2a\x00\x00\x00\x00");
+        assert_eq!(monlist_request().len(), 48);
+    }
+
+    #[test]
+    fn reads_answers() {
+        let mut answers = Answers::default();
+        let mut server = vec![0u8; 48];
+        server[..2].copy_from_slice(&[0x24, 2]);
+        read_answer(&server, &mut answers);
+        assert_eq!(answers.server, Some((4, 2)));
+
+        // Two fragments of the variables, the second one first.
+        let second = b"\x16\x82\x50\x53\x06\x18\x00\x00\x00\x0c\x00\x13system=\"Linux/5.15\"";
+        let first = b"\x16\xa2\x50\x53\x06\x18\x00\x00\x00\x00\x00\x0cversion=\"a\",";
+        read_answer(second, &mut answers);
+        read_answer(first, &mut answers);
+        assert!(answers.control_done);
+        assert_eq!(answers.control_bytes, second.len() + first.len());
+        assert_eq!(
+            joined(std::mem::take(&mut answers.control)),
+            "version=\"a\",system=\"Linux/5.15\""
+        );
+
+        // A monlist answer with more to come, and one refused with an error.
+        let mut monlist = b"\xd7\x00\x03\x2a\x00\x06\x00\x48".to_vec();
+        monlist.resize(440, 0);
+        read_answer(&monlist, &mut answers);
+        assert_eq!(answers.monlist_bytes, 440);
+        assert!(!answers.monlist_done);
+        let mut refused = Answers::default();
+        read_answer(b"\x97\x00\x03\x2a\x40\x00\x00\x00", &mut refused);
+        assert_eq!(refused.monlist_bytes, 0);
+        assert!(refused.monlist_done);
+        assert!(!refused.complete());
+    }
+
+    #[test]
+    fn reads_variables() {
+        let variables = "version=\"ntpd 4.2.8p15@1.3728-o (1)\", processor=\"x86_64\", system=\"Linux/5.15.0, generic\", leap=00, stratum=2";
+        assert_eq!(
+            variable(variables, "version").as_deref(),
+            Some("ntpd 4.2.8p15@1.3728-o (1)")
+        );
+        assert_eq!(
+            variable(variables, "system").as_deref(),
+            Some("Linux/5.15.0, generic")
+        );
+        assert_eq!(variable(variables, "stratum").as_deref(), Some("2"));
+        assert_eq!(variable(variables, "refid"), None);
+    }
+}
@@ -0,0 +1,309 @@
+use crate::targets::Target; // Import the targets whose traffic is recorded.
+use socket2::{Domain, Protocol, SockAddr, Socket, Type}; // Import socket2 to open a packet socket.
+use std::collections::HashSet; // Import `HashSet` of the addresses whose packets are kept.
+use std::fs::File; // Import `File` to write the capture to.
+use std::io::{self, BufWriter, Write}; // Import buffered writing of the capture file.
+use std::mem::MaybeUninit; // Import `MaybeUninit` for the receive buffer.
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr}; // Import IP address types of the captured packets.
+use std::sync::atomic::{AtomicBool, Ordering}; // Import the flag that stops the capture.
+use std::sync::Arc; // Import `Arc` to share the flag with the capturing thread.
+use std::thread::JoinHandle; // Import `JoinHandle` to wait for the capturing thread.
+use std::time::{Duration, SystemTime, UNIX_EPOCH}; // Import time stamps of the captured packets.
+
+// Ethernet types of the packets a packet socket hands over (and all of them, to listen to).
+const ETH_P_ALL: u16 = 0x0003;
+const ETH_P_IP: u16 = 0x0800;
+const ETH_P_IPV6: u16 = 0x86dd;
+
+// The link type of captures holding bare IPv4 and IPv6 packets, without link-layer headers,
+// which keeps packets of every kind of interface in one file.
+const LINKTYPE_RAW: u32 = 101;
+
+// Define the longest packet recorded in full.
+const SNAPLEN: usize = 65535;
+
+// Define how often the capturing thread checks whether to stop.
+const POLL: Duration = Duration::from_millis(100);
+
+// A capture of the packets to and from the targets, written to a pcap file by its own thread
+// until stopped.
+pub struct Capture {
+    path: String,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<io::Result<usize>>,
+}
+
+impl Capture {
+    // Function to start recording the packets to and from the addresses of `hosts` that cross
+    // `interface`, or any interface, into the pcap file at `path`. Needs root or the CAP_NET_RAW
+    // capability.
+    pub fn start(path: &str, hosts: &[Target], interface: Option<&str>) -> Result<Capture, String> {
+        let socket = Socket::new(
+            Domain::PACKET,
+            Type::DGRAM,
+            Some(Protocol::from(i32::from(ETH_P_ALL.to_be()))),
+        )
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::PermissionDenied => {
+                "--pcap captures raw packets, which needs root or the CAP_NET_RAW capability"
+                    .to_string()
+            }
+            _ => format!("--pcap cannot open a packet socket: {}", e),
+        })?;
+        socket
+            .set_read_timeout(Some(POLL))
+            .map_err(|e| format!("--pcap cannot set up its socket: {}", e))?;
+        if let Some(interface) = interface {
+            let index = interface_index(interface)
+                .ok_or_else(|| format!("--pcap cannot find interface {}", interface))?;
+            socket
+                .bind(&link_addr(index))
+                .map_err(|e| format!("--pcap cannot listen on {}: {}", interface, e))?;
+        }
+        // Packets on the loopback interface are seen leaving and arriving, so only one is kept.
+        let loopback = interface_index("lo");
+
+        let file = File::create(path).map_err(|e| format!("Cannot create {}: {}", path, e))?;
+        let mut writer = BufWriter::new(file);
+        writer
+            .write_all(&header())
+            .map_err(|e| format!("Cannot write to {}: {}", path, e))?;
+
+        let addrs: HashSet<IpAddr> = hosts.iter().map(|target| target.addr).collect();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        let thread = std::thread::Builder::new()
+            .name("pcap".to_string())
+            .spawn(move || record(&socket, &mut writer, &addrs, loopback, &stopped))
+            .map_err(|e| format!("--pcap cannot start its capture: {}", e))?;
+        Ok(Capture {
+            path: path.to_string(),
+            stop,
+            thread,
+        })
+    }
+
+    // Function to stop the capture and finish its file, giving the path and how many packets
+    // it holds.
+    pub fn stop(self) -> Result<(String, usize), String> {
+        self.stop.store(true, Ordering::Relaxed);
+        match self.thread.join() {
+            Ok(Ok(count)) => Ok((self.path, count)),
+            Ok(Err(e)) => Err(format!("Cannot write to {}: {}", self.path, e)),
+            Err(_) => Err(format!("The capture to {} failed", self.path)),
+        }
+    }
+}
+
+// Function run by the capturing thread: writes every IP packet to or from `addrs` read off
+// `socket` until `stop` is set, and gives how many it wrote.
+fn record(
+    socket: &Socket,
+    writer: &mut BufWriter<File>,
+    addrs: &HashSet<IpAddr>,
+    loopback: Option<u32>,
+    stop: &AtomicBool,
+) -> io::Result<usize> {
+    let mut buffer = [MaybeUninit::<u8>::uninit(); SNAPLEN];
+    let mut count = 0;
+    while !stop.load(Ordering::Relaxed) {
+        let (length, from) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted
+                ) =>
+            {
+                continue
+            }
+            Err(e) => return Err(e),
+        };
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        // SAFETY: a packet socket fills in a `sockaddr_ll`, which fits the address storage.
+        let ll = unsafe { &*from.as_ptr().cast::<libc::sockaddr_ll>() };
+        let protocol = u16::from_be(ll.sll_protocol);
+        if protocol != ETH_P_IP && protocol != ETH_P_IPV6 {
+            continue;
+        }
+        if loopback == Some(ll.sll_ifindex as u32) && ll.sll_pkttype == libc::PACKET_OUTGOING {
+            continue;
+        }
+        // SAFETY: `recv_from` initialized the first `length` bytes.
+        let packet = unsafe { std::slice::from_raw_parts(buffer.as_ptr().cast::<u8>(), length) };
+        if !concerns(packet, addrs) {
+            continue;
+        }
+        writer.write_all(&entry(time, packet))?;
+        count += 1;
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+// Function to find the index of the network interface `name`.
+fn interface_index(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    // SAFETY: `name` is a valid C string.
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    (index != 0).then_some(index)
+}
+
+// Function to build the address that binds a packet socket to interface `index`.
+fn link_addr(index: u32) -> SockAddr {
+    // SAFETY: an all-zero `sockaddr_ll` is valid, and the storage is large enough to hold one.
+    unsafe {
+        let mut storage: libc::sockaddr_storage = std::mem::zeroed();
+        let ll = &mut *(&mut storage as *mut libc::sockaddr_storage).cast::<libc::sockaddr_ll>();
+        ll.sll_family = libc::AF_PACKET as u16;
+        ll.sll_protocol = ETH_P_ALL.to_be();
+        ll.sll_ifindex = index as i32;
+        SockAddr::new(
+            storage,
+            std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+        )
+    }
+}
+
+// Function to check whether a packet goes to or comes from one of `addrs`, or is an ICMP error
+// about such a packet, as routers on the way send in reply to probes.
+fn concerns(packet: &[u8], addrs: &HashSet<IpAddr>) -> bool {
+    let Some((source, destination)) = addresses(packet) else {
+        return false;
+    };
+    if addrs.contains(&source) || addrs.contains(&destination) {
+        return true;
+    }
+    // The packet in error follows the ICMP header (RFC 792, RFC 4443): destination
+    // unreachable and time exceeded (3 and 11) for IPv4, any type below 128 for IPv6.
+    let quoted = match packet[0] >> 4 {
+        4 if packet.get(9) == Some(&1) => {
+            let header = usize::from(packet[0] & 0x0f) * 4;
+            packet
+                .get(header)
+                .filter(|kind| matches!(kind, 3 | 11))
+                .and_then(|_| packet.get(header + 8..))
+        }
+        6 if packet.get(6) == Some(&58) => packet
+            .get(40)
+            .filter(|kind| **kind < 128)
+            .and_then(|_| packet.get(48..)),
+        _ => None,
+    };
+    quoted
+        .and_then(addresses)
+        .is_some_and(|(_, destination)| addrs.contains(&destination))
+}
+
+// Function to read the source and destination addresses of an IPv4 or IPv6 packet.
+fn addresses(packet: &[u8]) -> Option<(IpAddr, IpAddr)> {
+    match packet.first()? >> 4 {
+        4 => {
+            let source: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            let destination: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            Some((
+                Ipv4Addr::from(source).into(),
+                Ipv4Addr::from(destination).into(),
+            ))
+        }
+        6 => {
+            let source: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let destination: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            Some((
+                Ipv6Addr::from(source).into(),
+                Ipv6Addr::from(destination).into(),
+            ))
+        }
+        _ => None,
+    }
+}
+
+// Function to build the global header of a pcap file (version 2.4, microsecond time stamps),
+// in this host's byte order, which readers tell from the magic number.
+fn header() -> Vec<u8> {
+    let mut header = Vec::with_capacity(24);
+    header.extend_from_slice(&0xa1b2_c3d4u32.to_ne_bytes());
+    header.extend_from_slice(&2u16.to_ne_bytes());
+    header.extend_from_slice(&4u16.to_ne_bytes());
+    // The time zone offset and accuracy of the time stamps, always zero.
+    header.extend_from_slice(&[0; 8]);
+    header.extend_from_slice(&(SNAPLEN as u32).to_ne_bytes());
+    header.extend_from_slice(&LINKTYPE_RAW.to_ne_bytes());
+    header
+}
+
+// Function to build the record of a packet captured at `time` since the epoch.
+fn entry(time: Duration, packet: &[u8]) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(16 + packet.len());
+    entry.extend_from_slice(&(time.as_secs() as u32).to_ne_bytes());
+    entry.extend_from_slice(&time.subsec_micros().to_ne_bytes());
+    // The length recorded and the length on the wire, which packets up to SNAPLEN share.
+    entry.extend_from_slice(&(packet.len() as u32).to_ne_bytes());
+    entry.extend_from_slice(&(packet.len() as u32).to_ne_bytes());
+    entry.extend_from_slice(packet);
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_pcap_records() {
+        let header = header();
+        assert_eq!(header.len(), 24);
+        assert_eq!(&header[..4], &0xa1b2_c3d4u32.to_ne_bytes());
+        assert_eq!(&header[20..], &101u32.to_ne_bytes());
+        let packet = crate::raw::ipv4(
+            Ipv4Addr::new(10, 0, 0, 1),
+            Ipv4Addr::new(10, 0, 0, 2),
+            6,
+            1,
+            &[0; 20],
+        );
+        let entry = entry(Duration::from_micros(1_500_002), &packet);
+        assert_eq!(&entry[..4], &1u32.to_ne_bytes());
+        assert_eq!(&entry[4..8], &500_002u32.to_ne_bytes());
+        assert_eq!(&entry[8..12], &40u32.to_ne_bytes());
+        assert_eq!(&entry[16..], &packet[..]);
+    }
+
+    #[test]
+    fn reads_packet_addresses() {
+        let packet = crate::raw::ipv6(
+            "fd00::1".parse().unwrap(),
+            "fd00::2".parse().unwrap(),
+            6,
+            &[0; 20],
+        );
+        assert_eq!(
+            addresses(&packet),
+            Some(("fd00::1".parse().unwrap(), "fd00::2".parse().unwrap()))
+        );
+        assert_eq!(addresses(&packet[..30]), None);
+        assert_eq!(addresses(&[0x12, 0, 0]), None);
+
+        // A router's time exceeded error about a probe of 10.0.0.2 concerns that target.
+        let (us, router, target) = (
+            Ipv4Addr::new(10, 0, 0, 1),
+            Ipv4Addr::new(10, 0, 1, 1),
+            Ipv4Addr::new(10, 0, 0, 2),
+        );
+        let probe = crate::raw::ipv4(us, target, 6, 1, &[0; 20]);
+        let mut error = vec![11, 0, 0, 0, 0, 0, 0, 0];
+        error.extend_from_slice(&probe[..28]);
+        let error = crate::raw::ipv4(router, us, 1, 2, &error);
+        let addrs = HashSet::from([IpAddr::V4(target)]);
+        assert!(concerns(&probe, &addrs));
+        assert!(concerns(&error, &addrs));
+        assert!(concerns(&error, &HashSet::from([IpAddr::V4(router)])));
+        assert!(!concerns(
+            &error,
+            &HashSet::from([IpAddr::from([10, 9, 9, 9])])
+        ));
+    }
+}
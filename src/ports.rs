@@ -0,0 +1,324 @@
+use crate::services; // Import the service and protocol tables to accept names such as `ssh` and `gre`.
+use std::collections::{BTreeSet, HashMap}; // Import `BTreeSet` to keep ports sorted and unique, `HashMap` for groups.
+use std::time::Duration; // Import `Duration` for per-port timeouts.
+
+// Frequency-ordered table of the most commonly open TCP ports, embedded at build time.
+const TOP_PORTS: &str = include_str!("../data/top-ports.txt");
+
+// Built-in port groups usable as `@name` in port specifications.
+const BUILTIN_GROUPS: &[(&str, &str)] = &[
+    (
+        "web",
+        "80,81,443,591,2082,2083,3000,5000,8000,8008,8080,8081,8443,8888,9000,9443",
+    ),
+    (
+        "db",
+        "1433,1521,3306,5432,5984,6379,7000,7001,9042,9200,9300,11211,27017,27018,50000",
+    ),
+    ("mail", "25,110,143,465,587,993,995,2525"),
+    (
+        "remote-admin",
+        "22,23,512,513,514,2222,3389,5800,5900,5901,5985,5986,10000",
+    ),
+];
+
+// Nesting depth after which a group referring to other groups is considered a cycle.
+const MAX_GROUP_DEPTH: usize = 8;
+
+// Named port groups: the built-in ones plus user-defined ones from the config file.
+#[derive(Debug, Clone, Default)]
+pub struct Groups {
+    custom: HashMap<String, String>,
+}
+
+impl Groups {
+    // Function to build the group table; custom groups override built-ins with the same name.
+    pub fn new(custom: HashMap<String, String>) -> Groups {
+        Groups { custom }
+    }
+
+    // Function to look up the port specification a group name stands for.
+    fn get(&self, name: &str) -> Option<&str> {
+        self.custom.get(name).map(String::as_str).or_else(|| {
+            BUILTIN_GROUPS
+                .iter()
+                .find(|(group, _)| *group == name)
+                .map(|(_, spec)| *spec)
+        })
+    }
+}
+
+// Function to expand a port specification such as `22,https,8000-8100,@web` into a sorted, deduplicated list.
+pub fn parse_spec(spec: &str, groups: &Groups) -> Result<Vec<u16>, String> {
+    let mut ports = BTreeSet::new();
+    expand_into(spec, groups, 0, &mut ports)?;
+
+    if ports.is_empty() {
+        return Err(format!("Port specification {:?} contains no ports", spec));
+    }
+    Ok(ports.into_iter().collect())
+}
+
+// Function to split a port specification with `T:` and `U:` prefixes, such as `22,T:80,U:53`,
+// into its TCP and UDP parts. A prefix holds for the items after it up to the next one, and the
+// items before the first prefix belong to both. Gives `None` for a specification without any.
+pub fn split_protocols(spec: &str) -> Option<(Option<String>, Option<String>)> {
+    let items: Vec<&str> = spec.split(',').map(str::trim).collect();
+    if !items
+        .iter()
+        .any(|item| item.starts_with("T:") || item.starts_with("U:"))
+    {
+        return None;
+    }
+    let (mut tcp, mut udp): (Option<Vec<&str>>, Option<Vec<&str>>) = (None, None);
+    let (mut to_tcp, mut to_udp) = (true, true);
+    for item in items {
+        let item = match (item.strip_prefix("T:"), item.strip_prefix("U:")) {
+            (Some(rest), _) => {
+                (to_tcp, to_udp) = (true, false);
+                tcp.get_or_insert_with(Vec::new);
+                rest
+            }
+            (_, Some(rest)) => {
+                (to_tcp, to_udp) = (false, true);
+                udp.get_or_insert_with(Vec::new);
+                rest
+            }
+            _ => item,
+        };
+        if item.is_empty() {
+            continue;
+        }
+        for (wanted, list) in [(to_tcp, &mut tcp), (to_udp, &mut udp)] {
+            if wanted {
+                list.get_or_insert_with(Vec::new).push(item);
+            }
+        }
+    }
+    Some((
+        tcp.map(|list| list.join(",")),
+        udp.map(|list| list.join(",")),
+    ))
+}
+
+// Function to add every port of a specification to `ports`, expanding groups recursively.
+fn expand_into(
+    spec: &str,
+    groups: &Groups,
+    depth: usize,
+    ports: &mut BTreeSet<u16>,
+) -> Result<(), String> {
+    for item in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        // A named group such as @web.
+        if let Some(name) = item.strip_prefix('@') {
+            if depth >= MAX_GROUP_DEPTH {
+                return Err(format!("Port group @{} is nested too deeply", name));
+            }
+            let group = groups
+                .get(name)
+                .ok_or_else(|| format!("Unknown port group @{}", name))?;
+            expand_into(group, groups, depth + 1, ports)?;
+            continue;
+        }
+
+        // A service name such as `ssh`. Checked before ranges since names like `http-alt` contain dashes.
+        if item.parse::<u16>().is_err() {
+            if let Some(port) = services::port_by_name(item, "tcp") {
+                ports.insert(port);
+                continue;
+            }
+        }
+
+        // `-` alone stands for every port.
+        if item == "-" {
+            ports.extend(1..=u16::MAX);
+            continue;
+        }
+
+        match item.split_once('-') {
+            // A range of ports, inclusive on both ends.
+            Some((start, end)) => {
+                let start = parse_port(start)?;
+                let end = parse_port(end)?;
+                if start > end {
+                    return Err(format!("Port range {} is reversed", item));
+                }
+                ports.extend(start..=end);
+            }
+            // A single port.
+            None => {
+                ports.insert(parse_port(item)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Function to parse per-port timeouts such as `25=5000,smtp=5000,8000-8100=3000`, in milliseconds.
+// Each entry takes one item of a port specification; later entries win for ports listed twice.
+pub fn parse_timeouts(spec: &str, groups: &Groups) -> Result<HashMap<u16, Duration>, String> {
+    let mut timeouts = HashMap::new();
+    for item in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let (ports, ms) = item
+            .split_once('=')
+            .ok_or_else(|| format!("Timeout override {:?} must look like PORT=MS", item))?;
+        let ms = match ms.trim().parse::<u64>() {
+            Ok(ms) if ms > 0 => ms,
+            _ => {
+                return Err(format!(
+                    "Timeout override {:?} needs a timeout of at least 1 ms",
+                    item
+                ))
+            }
+        };
+        for port in parse_spec(ports, groups)? {
+            timeouts.insert(port, Duration::from_millis(ms));
+        }
+    }
+    Ok(timeouts)
+}
+
+// Function to parse a single port number, rejecting port 0.
+fn parse_port(input: &str) -> Result<u16, String> {
+    match input.trim().parse::<u16>() {
+        Ok(port) if port > 0 => Ok(port),
+        _ => Err(format!(
+            "{} is not a valid port (must be 1-65535 or a service name)",
+            input.trim()
+        )),
+    }
+}
+
+// Function to expand a list of IP protocols such as `1,6,gre,50-51` for --ip-protocols into a
+// sorted, deduplicated list of protocol numbers.
+pub fn parse_protocols(spec: &str) -> Result<Vec<u16>, String> {
+    let number = |item: &str| -> Result<u16, String> {
+        let item = item.trim();
+        match item.parse::<u8>() {
+            Ok(number) => Ok(u16::from(number)),
+            Err(_) => services::protocol_by_name(item)
+                .map(u16::from)
+                .ok_or_else(|| {
+                    format!(
+                        "{} is not a valid IP protocol (must be 0-255 or a protocol name)",
+                        item
+                    )
+                }),
+        }
+    };
+    let mut numbers = BTreeSet::new();
+    for item in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        // Names are checked before ranges since names like `ipv6-icmp` contain dashes.
+        if let Some(known) = services::protocol_by_name(item) {
+            numbers.insert(u16::from(known));
+            continue;
+        }
+        match item.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (number(start)?, number(end)?);
+                if start > end {
+                    return Err(format!("Protocol range {} is reversed", item));
+                }
+                numbers.extend(start..=end);
+            }
+            _ => {
+                numbers.insert(number(item)?);
+            }
+        }
+    }
+    if numbers.is_empty() {
+        return Err(format!("Protocol list {:?} contains no protocols", spec));
+    }
+    Ok(numbers.into_iter().collect())
+}
+
+// Function to pick the `n` most commonly open ports from the embedded frequency table, or all
+// of them if it ranks fewer.
+pub fn top_ports(n: usize) -> Result<Vec<u16>, String> {
+    let table: Vec<u16> = TOP_PORTS
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.parse().ok())
+        .collect();
+
+    if n == 0 {
+        return Err("--top-ports must be at least 1".to_string());
+    }
+
+    // Scan the chosen ports in ascending order, like any other port list.
+    let mut ports = table[..n.min(table.len())].to_vec();
+    ports.sort_unstable();
+    Ok(ports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Function to expand `spec` with the built-in groups only.
+    fn spec(spec: &str) -> Result<Vec<u16>, String> {
+        parse_spec(spec, &Groups::default())
+    }
+
+    #[test]
+    fn parses_specs() {
+        assert_eq!(spec("22,80,443"), Ok(vec![22, 80, 443]));
+        assert_eq!(spec("8000-8003"), Ok(vec![8000, 8001, 8002, 8003]));
+        assert_eq!(spec("443, 22 ,80-81,"), Ok(vec![22, 80, 81, 443]));
+        // Duplicates and overlapping ranges come out once, in order.
+        assert_eq!(spec("80,22,80,21-23"), Ok(vec![21, 22, 23, 80]));
+        assert_eq!(spec("ssh,https"), Ok(vec![22, 443]));
+        assert_eq!(spec("5-5"), Ok(vec![5]));
+        assert_eq!(spec("-").map(|ports| ports.len()), Ok(65535));
+        assert_eq!(spec("65535-65535"), Ok(vec![65535]));
+        assert!(spec("@mail").unwrap().contains(&25));
+    }
+
+    #[test]
+    fn rejects_bad_specs() {
+        for bad in [
+            "0", "0-10", "5-1", "1-", "-5", "65536", "1-65536", "x", "T:22", "", ",", "@nope",
+        ] {
+            assert!(spec(bad).is_err(), "{}", bad);
+        }
+        let mut custom = HashMap::new();
+        custom.insert("loop".to_string(), "@loop".to_string());
+        assert!(parse_spec("@loop", &Groups::new(custom)).is_err());
+    }
+
+    #[test]
+    fn splits_protocols() {
+        assert_eq!(split_protocols("22,80"), None);
+        assert_eq!(
+            split_protocols("T:22,80,U:53,161"),
+            Some((Some("22,80".to_string()), Some("53,161".to_string())))
+        );
+        assert_eq!(
+            split_protocols("443,U:53"),
+            Some((Some("443".to_string()), Some("443,53".to_string())))
+        );
+        assert_eq!(
+            split_protocols("U:53-55"),
+            Some((None, Some("53-55".to_string())))
+        );
+        // A prefix with nothing after it still asks for its protocol, which then has no ports.
+        assert_eq!(
+            split_protocols("T:,U:53").map(|(tcp, _)| tcp),
+            Some(Some(String::new()))
+        );
+    }
+
+    #[test]
+    fn picks_top_ports() {
+        let top = top_ports(100).unwrap();
+        assert_eq!(top.len(), 100);
+        assert!(top.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(top.contains(&80) && top.contains(&443));
+        // Asking for more than the table ranks gives all of it.
+        let all = top_ports(usize::MAX).unwrap();
+        assert_eq!(top_ports(1000).unwrap(), all);
+        assert!(all.len() < 1000);
+        assert!(top_ports(0).is_err());
+    }
+}
@@ -0,0 +1,97 @@
+#[cfg(unix)]
+use std::ffi::CString; // Import C strings for the user database.
+#[cfg(unix)]
+use std::io; // Import OS errors of failed calls.
+
+// Define the buffer size for the user database entry, which getpwnam_r may ask to enlarge.
+#[cfg(unix)]
+const ENTRY_BUFFER: usize = 1024;
+
+// Function to switch the whole process (every thread) to `user`, given by name or numeric ID,
+// and its primary group, for good: the saved IDs change as well, so it cannot become root
+// again. Sockets already open keep working.
+#[cfg(unix)]
+pub fn drop_to(user: &str) -> Result<(), String> {
+    // SAFETY: geteuid cannot fail.
+    if unsafe { libc::geteuid() } != 0 {
+        return Err("--drop-privs only has privileges to drop when run as root".to_string());
+    }
+    let (uid, gid) = lookup(user)?;
+    let failed = |call: &str| {
+        format!(
+            "--drop-privs cannot switch to {}: {} failed: {}",
+            user,
+            call,
+            io::Error::last_os_error()
+        )
+    };
+    // The supplementary groups go first, then the group, and the user last, as only root may
+    // change the others. glibc applies each call to every thread of the process.
+    // SAFETY: `gid` is a valid array of one group ID for setgroups to read.
+    if unsafe { libc::setgroups(1, &gid) } != 0 {
+        return Err(failed("setgroups"));
+    }
+    // SAFETY: setgid and setuid only take plain IDs.
+    if unsafe { libc::setgid(gid) } != 0 {
+        return Err(failed("setgid"));
+    }
+    // SAFETY: as above.
+    if unsafe { libc::setuid(uid) } != 0 {
+        return Err(failed("setuid"));
+    }
+    // SAFETY: as above. Getting root back has to fail now.
+    if uid != 0 && unsafe { libc::setuid(0) } == 0 {
+        return Err(format!("--drop-privs could not give up root for {}", user));
+    }
+    Ok(())
+}
+
+// Function to find the user and primary group IDs of `user` in the user database, or of the
+// numeric user ID it gives.
+// Function to switch to `user`, which only Unix systems have users and groups to switch to.
+#[cfg(not(unix))]
+pub fn drop_to(_user: &str) -> Result<(), String> {
+    Err("--drop-privs needs a Unix system".to_string())
+}
+
+#[cfg(unix)]
+fn lookup(user: &str) -> Result<(libc::uid_t, libc::gid_t), String> {
+    let name = CString::new(user).map_err(|_| format!("{} is not a valid user name", user))?;
+    let mut buffer = vec![0 as libc::c_char; ENTRY_BUFFER];
+    loop {
+        // SAFETY: an all-zero passwd is valid for getpwnam_r to fill in.
+        let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut found: *mut libc::passwd = std::ptr::null_mut();
+        // SAFETY: every pointer is valid, and `buffer` is as long as given.
+        let result = unsafe {
+            match user.parse::<libc::uid_t>() {
+                Ok(uid) => libc::getpwuid_r(
+                    uid,
+                    &mut entry,
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                    &mut found,
+                ),
+                Err(_) => libc::getpwnam_r(
+                    name.as_ptr(),
+                    &mut entry,
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                    &mut found,
+                ),
+            }
+        };
+        match result {
+            libc::ERANGE => buffer.resize(buffer.len() * 2, 0),
+            0 if !found.is_null() => return Ok((entry.pw_uid, entry.pw_gid)),
+            0 => return Err(format!("--drop-privs cannot find user {}", user)),
+            code => {
+                return Err(format!(
+                    "--drop-privs cannot look up user {}: {}",
+                    user,
+                    io::Error::from_raw_os_error(code)
+                ))
+            }
+        }
+    }
+}
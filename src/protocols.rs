@@ -0,0 +1,260 @@
+use crate::raw; // Import the packet builders and checksums shared with the other raw scans.
+use crate::scanner::{PortState, Reason, Source}; // Import the states of probed protocols, why they were given, and the source to send from.
+use rand::Rng; // Import `Rng` to draw IP identifications and the local port.
+use socket2::{Domain, Protocol, Socket, Type}; // Import socket2 to open raw sockets.
+use std::collections::HashMap; // Import `HashMap` to match replies to the probes waiting for them.
+use std::io; // Import I/O errors for socket failures.
+use std::mem::MaybeUninit; // Import `MaybeUninit` for the receive buffers.
+use std::net::{IpAddr, Ipv4Addr, SocketAddr}; // Import address types for the probed hosts.
+use std::sync::{Arc, Mutex}; // Import shared state between the probes and the receiving threads.
+use std::time::Duration; // Import `Duration` for reply timeouts.
+use tokio::sync::oneshot; // Import oneshot channels to hand each answer to its probe.
+use tokio::time::timeout; // Import `timeout` to give up on silent protocols.
+
+// The protocols whose own answers (rather than ICMP errors) are watched for: a RST, a UDP
+// reply or an SCTP ABORT sent back to the local port of the probes.
+const ANSWERING: &[u8] = &[6, 17, 132];
+
+// Define the port the TCP, UDP and SCTP probes are sent to.
+const PROBED_PORT: u16 = 80;
+
+// A probe waiting for its answer: the IP identification it was sent with.
+#[derive(Debug)]
+struct Waiting {
+    id: u16,
+    answer: oneshot::Sender<(PortState, Reason)>,
+}
+
+// What a packet tells about a probed protocol: the host and protocol, the IP identification of
+// the probe if the packet quotes it, and the state with its reason.
+type Answer = ((Ipv4Addr, u8), Option<u16>, (PortState, Reason));
+
+// The probes waiting for an answer, by host and protocol.
+type Pending = Mutex<HashMap<(Ipv4Addr, u8), Waiting>>;
+
+// Raw sockets to send IPv4 packets of any protocol from, and to read the answers on: ICMP
+// errors, echo replies, and the packets of the protocols in `ANSWERING`.
+#[derive(Debug)]
+pub struct Prober {
+    send: Socket,
+    // The local port of TCP, UDP and SCTP probes, also the identifier of ICMP echo requests.
+    port: u16,
+    pending: Arc<Pending>,
+    source: Source,
+}
+
+impl Prober {
+    // Function to open the raw sockets, which fails without root or the CAP_NET_RAW capability.
+    pub fn open(source: &Source) -> Result<Prober, String> {
+        let denied = |e| raw::denied("--ip-protocols", e);
+        // A raw socket of protocol IPPROTO_RAW sends packets with the IP header included.
+        let send = Socket::new(
+            Domain::IPV4,
+            Type::RAW,
+            Some(Protocol::from(libc::IPPROTO_RAW)),
+        )
+        .map_err(denied)?;
+        source
+            .bind_to(&send, IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+            .map_err(denied)?;
+        let port = rand::thread_rng().gen_range(32768..61000);
+
+        let pending: Arc<Pending> = Arc::default();
+        for protocol in std::iter::once(libc::IPPROTO_ICMP as u8).chain(ANSWERING.iter().copied()) {
+            let socket = Socket::new(
+                Domain::IPV4,
+                Type::RAW,
+                Some(Protocol::from(i32::from(protocol))),
+            )
+            .map_err(denied)?;
+            let pending = Arc::clone(&pending);
+            std::thread::Builder::new()
+                .name("protocol replies".to_string())
+                .spawn(move || receive(&socket, protocol, port, &pending))
+                .map_err(|e| format!("--ip-protocols cannot start its receiver: {}", e))?;
+        }
+        Ok(Prober {
+            send,
+            port,
+            pending,
+            source: source.clone(),
+        })
+    }
+
+    // Function to send a packet of `protocol` to `host` and wait at most `wait` for what it
+    // tells about the protocol, and what told it. Gives `None` if nothing came back in time.
+    pub async fn probe(
+        &self,
+        host: Ipv4Addr,
+        protocol: u8,
+        wait: Duration,
+    ) -> io::Result<Option<(PortState, Reason)>> {
+        let local = raw::local_v4(&self.source, host)?;
+        let id = rand::thread_rng().gen_range(1..=u16::MAX);
+        let mut packet = packet(local, host, protocol, id, self.port);
+        self.source.limit_packet(&mut packet);
+
+        let (answer, state) = oneshot::channel();
+        self.pending
+            .lock()
+            .unwrap()
+            .insert((host, protocol), Waiting { id, answer });
+        let _waiting = Forget {
+            pending: &self.pending,
+            key: (host, protocol),
+            id,
+        };
+        self.send
+            .send_to(&packet, &SocketAddr::from((host, 0)).into())?;
+        Ok(timeout(wait, state).await.ok().and_then(Result::ok))
+    }
+}
+
+// Removes a probe from the waiting ones once it stops waiting, answered or not.
+struct Forget<'a> {
+    pending: &'a Pending,
+    key: (Ipv4Addr, u8),
+    id: u16,
+}
+
+impl Drop for Forget<'_> {
+    fn drop(&mut self) {
+        let mut pending = self.pending.lock().unwrap();
+        // A later probe of the same protocol may have taken the slot already.
+        if pending
+            .get(&self.key)
+            .is_some_and(|waiting| waiting.id == self.id)
+        {
+            pending.remove(&self.key);
+        }
+    }
+}
+
+// Function to build an IPv4 packet of `protocol` from `local` to `remote`. Protocols hosts
+// only answer when the packet makes sense get a valid header: an ICMP echo request, an IGMP
+// membership query, a TCP ACK, an empty UDP datagram and an SCTP INIT. Other protocols go
+// out without a payload.
+fn packet(local: Ipv4Addr, remote: Ipv4Addr, protocol: u8, id: u16, port: u16) -> Vec<u8> {
+    let payload = match protocol {
+        1 => {
+            let mut echo = vec![8, 0, 0, 0];
+            echo.extend_from_slice(&port.to_be_bytes());
+            echo.extend_from_slice(&id.to_be_bytes());
+            let sum = raw::checksum(&[&echo]);
+            echo[2..4].copy_from_slice(&sum.to_be_bytes());
+            echo
+        }
+        2 => {
+            let mut query = vec![0x11, 0, 0, 0, 0, 0, 0, 0];
+            let sum = raw::checksum(&[&query]);
+            query[2..4].copy_from_slice(&sum.to_be_bytes());
+            query
+        }
+        6 => {
+            let (seq, ack) = rand::thread_rng().gen::<(u32, u32)>();
+            let mut segment = raw::segment(port, PROBED_PORT, seq, ack, raw::ACK);
+            raw::seal(&mut segment, 16, protocol, local, remote);
+            segment
+        }
+        17 => {
+            let mut datagram = vec![];
+            datagram.extend_from_slice(&port.to_be_bytes());
+            datagram.extend_from_slice(&PROBED_PORT.to_be_bytes());
+            datagram.extend_from_slice(&[0, 8, 0, 0]);
+            raw::seal(&mut datagram, 6, protocol, local, remote);
+            datagram
+        }
+        132 => {
+            let (tag, tsn) = rand::thread_rng().gen::<(u32, u32)>();
+            raw::init(port, PROBED_PORT, tag.max(1), tsn)
+        }
+        _ => vec![],
+    };
+
+    raw::ipv4(local, remote, protocol, id, &payload)
+}
+
+// Function run by a receiving thread: hands what every packet of `protocol` says about a
+// probed protocol to the probe waiting for it.
+fn receive(socket: &Socket, protocol: u8, port: u16, pending: &Pending) {
+    let mut buffer = [MaybeUninit::<u8>::uninit(); 65536];
+    loop {
+        let Ok(length) = socket.recv(&mut buffer) else {
+            continue;
+        };
+        // SAFETY: `recv` initialized the first `length` bytes.
+        let packet = unsafe { std::slice::from_raw_parts(buffer.as_ptr().cast::<u8>(), length) };
+        let answer = match protocol {
+            1 => icmp_answer(packet, port),
+            _ => transport_answer(packet, port),
+        };
+        if let Some((key, id, state)) = answer {
+            let mut pending = pending.lock().unwrap();
+            let answers = pending
+                .get(&key)
+                .is_some_and(|waiting| id.is_none_or(|id| id == waiting.id));
+            if answers {
+                if let Some(waiting) = pending.remove(&key) {
+                    let _ = waiting.answer.send(state);
+                }
+            }
+        }
+    }
+}
+
+// Function to read the header of an IPv4 packet: the source, destination, protocol,
+// identification and the rest of the packet.
+fn ip_header(packet: &[u8]) -> Option<(Ipv4Addr, Ipv4Addr, u8, u16, &[u8])> {
+    let length = usize::from(packet.first()? & 0x0f) * 4;
+    let source: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+    let destination: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+    let id = u16::from_be_bytes(packet.get(4..6)?.try_into().ok()?);
+    Some((
+        source.into(),
+        destination.into(),
+        *packet.get(9)?,
+        id,
+        packet.get(length..)?,
+    ))
+}
+
+// Function to read what an ICMP message says about a probed protocol: the host and protocol,
+// the IP identification of the probe if the message quotes it, and the state. An echo reply
+// means ICMP is open; an unreachable protocol closed; an unreachable port open, as a transport
+// that rejects a port is running; other unreachables filtered.
+fn icmp_answer(packet: &[u8], port: u16) -> Option<Answer> {
+    let (source, _, _, _, icmp) = ip_header(packet)?;
+    match (*icmp.first()?, *icmp.get(1)?) {
+        (0, _) if icmp.get(4..6)? == port.to_be_bytes() => {
+            Some(((source, 1), None, (PortState::Open, Reason::ProtoResponse)))
+        }
+        (3, code) => {
+            let (_, destination, protocol, id, _) = ip_header(icmp.get(8..)?)?;
+            let answer = match code {
+                2 => (PortState::Closed, Reason::ProtoUnreach),
+                3 => (PortState::Open, Reason::PortUnreach),
+                0 => (PortState::Filtered, Reason::NetUnreach),
+                1 => (PortState::Filtered, Reason::HostUnreach),
+                // Network, host or communication administratively prohibited.
+                9 | 10 | 13 => (PortState::Filtered, Reason::AdminProhibited),
+                _ => (PortState::Filtered, Reason::UnexpectedReply),
+            };
+            Some(((destination, protocol), Some(id), answer))
+        }
+        _ => None,
+    }
+}
+
+// Function to read a TCP, UDP or SCTP packet sent back to the local port of the probes, which
+// shows the host speaks the protocol.
+fn transport_answer(packet: &[u8], port: u16) -> Option<Answer> {
+    let (source, _, protocol, _, transport) = ip_header(packet)?;
+    if transport.get(2..4)? != port.to_be_bytes() {
+        return None;
+    }
+    Some((
+        (source, protocol),
+        None,
+        (PortState::Open, Reason::ProtoResponse),
+    ))
+}
@@ -0,0 +1,922 @@
+use crate::scanner::Source; // Import the source addresses, interface and port to send from.
+use rand::Rng; // Import `Rng` to draw sequence numbers and verification tags.
+use socket2::{Domain, Protocol, Socket, Type}; // Import socket2 to open raw sockets.
+use std::collections::HashMap; // Import `HashMap` to match replies to the probes waiting for them.
+use std::io; // Import I/O errors for socket failures.
+use std::mem::MaybeUninit; // Import `MaybeUninit` for the receive buffer.
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket}; // Import socket address types for the probed ports.
+use std::sync::atomic::{AtomicUsize, Ordering}; // Import the count of answered probes.
+use std::sync::{Arc, Mutex}; // Import shared state between the probes and the receiving threads.
+use std::time::Duration; // Import `Duration` for reply timeouts.
+use tokio::sync::oneshot; // Import oneshot channels to hand each reply to its probe.
+use tokio::time::timeout; // Import `timeout` to give up on silent ports.
+
+// TCP header flags.
+pub const FIN: u8 = 0x01;
+pub const SYN: u8 = 0x02;
+pub const RST: u8 = 0x04;
+pub const PSH: u8 = 0x08;
+pub const ACK: u8 = 0x10;
+pub const URG: u8 = 0x20;
+
+// SCTP chunk types.
+pub const INIT: u8 = 1;
+pub const INIT_ACK: u8 = 2;
+pub const ABORT: u8 = 6;
+
+// The number of the capability to open raw sockets (linux/capability.h).
+const CAP_NET_RAW: u32 = 13;
+
+// Define the size of the receive buffer of the raw sockets, so bursts of replies are not dropped.
+const RECV_BUFFER: usize = 4 << 20;
+
+// The transport protocol of the raw sockets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Tcp,
+    Sctp,
+}
+
+// What came back for a probe.
+#[derive(Debug, Clone, Copy)]
+pub enum Reply {
+    // A TCP segment, with its flags and window.
+    Segment { flags: u8, window: u16 },
+    // An SCTP packet, with the type of its first chunk.
+    Chunk(u8),
+}
+
+// A packet read off a raw socket, with the numbers that tie it to its probe.
+struct Received {
+    from: SocketAddr,
+    reply: Reply,
+    // The sequence and acknowledgment numbers of a segment, or the verification tag of an SCTP
+    // packet and whether the sender reflected the tag of the probe (the T flag of an ABORT).
+    first: u32,
+    second: u32,
+}
+
+// A probe waiting for its reply: the sequence and acknowledgment numbers it was sent with,
+// or for SCTP the initiate tag and zero.
+#[derive(Debug)]
+struct Waiting {
+    seq: u32,
+    ack: u32,
+    reply: oneshot::Sender<Reply>,
+}
+
+impl Waiting {
+    // Function to check whether a packet answers the probe, rather than an older one of the port.
+    // A segment acknowledges the probe's sequence number (plus one for a SYN or FIN), or,
+    // answering an ACK, takes its acknowledgment number as the sequence number. An SCTP reply
+    // carries the initiate tag of the INIT, unless it reflects the probe's tag of zero.
+    fn answered_by(&self, received: &Received) -> bool {
+        match received.reply {
+            Reply::Segment { flags, .. } => {
+                received.second.wrapping_sub(self.seq) <= 1
+                    || (flags & ACK == 0 && received.first == self.ack)
+            }
+            Reply::Chunk(_) => {
+                received.first == self.seq || (received.second == 1 && received.first == 0)
+            }
+        }
+    }
+}
+
+// The probes waiting for a reply, by the address they were sent to.
+type Pending = Mutex<HashMap<SocketAddr, Waiting>>;
+
+// Raw TCP or SCTP sockets to send hand-crafted packets from and read the replies on, one per
+// family. Every probe leaves from the same local port, on which a thread per family watches
+// for replies.
+#[derive(Debug)]
+pub struct Raw {
+    v4: Arc<Socket>,
+    v6: Option<Arc<Socket>>,
+    port: u16,
+    // Keeps the local TCP port bound, so no connect of this host picks it while the scan runs.
+    _reserved: Option<Socket>,
+    pending: Arc<Pending>,
+    // The local IPv4 address towards each host, for the checksum of the segments.
+    routes: Mutex<HashMap<Ipv4Addr, Ipv4Addr>>,
+    source: Source,
+    // The decoys each probe is sent from as well, in order, `None` standing for the real probe,
+    // and the sockets of protocol IPPROTO_RAW they go out on, which take the IP header as given.
+    decoys: Vec<Option<IpAddr>>,
+    spoof_v4: Option<Socket>,
+    spoof_v6: Option<Socket>,
+    // The size of the fragments the probes are split into, if they are (-f, --mtu).
+    fragment: Option<usize>,
+    // Probes that got an answer, to tell whether fragments get through at all.
+    answers: AtomicUsize,
+}
+
+impl Raw {
+    // Function to open the raw sockets, which fails without root or the CAP_NET_RAW capability.
+    // `option` is the flag that asked for raw probing, named in the error messages.
+    pub fn open(source: &Source, option: &str, transport: Transport) -> Result<Raw, String> {
+        let denied = |e| denied(option, e);
+        let v4 = raw_socket(Domain::IPV4, source, transport).map_err(denied)?;
+        // A host without IPv6 can still scan over IPv4.
+        let v6 = match raw_socket(Domain::IPV6, source, transport) {
+            Err(e) if e.raw_os_error() == Some(libc::EAFNOSUPPORT) => None,
+            result => Some(result.map_err(denied)?),
+        };
+
+        let reserve = |port| {
+            let socket = Socket::new(Domain::IPV4, Type::STREAM, None)?;
+            socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)).into())?;
+            Ok::<_, io::Error>(socket)
+        };
+        let (reserved, port) = match (transport, source.port()) {
+            // A port the kernel knows a listener on is not given up for the scan, whose replies
+            // reach the raw sockets all the same.
+            (Transport::Tcp, Some(port)) => (reserve(port).ok(), port),
+            (Transport::Tcp, None) => {
+                let reserved = reserve(0)
+                    .map_err(|e| format!("{} cannot reserve a local port: {}", option, e))?;
+                let port = reserved
+                    .local_addr()
+                    .ok()
+                    .and_then(|addr| addr.as_socket())
+                    .map(|addr| addr.port())
+                    .ok_or_else(|| format!("{} cannot reserve a local port", option))?;
+                (Some(reserved), port)
+            }
+            // The kernel often has no SCTP stack to reserve a port with, nor one to clash with.
+            (Transport::Sctp, port) => (
+                None,
+                port.unwrap_or_else(|| rand::thread_rng().gen_range(32768..61000)),
+            ),
+        };
+
+        let pending: Arc<Pending> = Arc::default();
+        let v4 = Arc::new(v4);
+        let v6 = v6.map(Arc::new);
+        for socket in std::iter::once(&v4).chain(&v6) {
+            let (socket, pending) = (Arc::clone(socket), Arc::clone(&pending));
+            std::thread::Builder::new()
+                .name("raw replies".to_string())
+                .spawn(move || receive(&socket, transport, port, &pending))
+                .map_err(|e| format!("{} cannot start its receiver: {}", option, e))?;
+        }
+        Ok(Raw {
+            v4,
+            v6,
+            port,
+            _reserved: reserved,
+            pending,
+            routes: Mutex::default(),
+            source: source.clone(),
+            decoys: vec![],
+            spoof_v4: None,
+            spoof_v6: None,
+            fragment: None,
+            answers: AtomicUsize::new(0),
+        })
+    }
+
+    // Function to send every probe from the `decoys` as well (see `decoys`), opening the sockets
+    // that forge their source addresses.
+    pub fn with_decoys(mut self, decoys: Vec<Option<IpAddr>>) -> Result<Raw, String> {
+        let denied = |e| denied("--decoys", e);
+        if decoys.iter().flatten().any(IpAddr::is_ipv4) && self.spoof_v4.is_none() {
+            self.spoof_v4 = Some(spoof_socket(Domain::IPV4, &self.source).map_err(denied)?);
+        }
+        if decoys.iter().flatten().any(IpAddr::is_ipv6) && self.spoof_v6.is_none() {
+            self.spoof_v6 = Some(spoof_socket(Domain::IPV6, &self.source).map_err(denied)?);
+        }
+        self.decoys = decoys;
+        Ok(self)
+    }
+
+    // Function to split every probe into fragments carrying `size` bytes of it each (see
+    // `fragments`), which go out on the sockets of protocol IPPROTO_RAW with their own IP headers.
+    pub fn with_fragments(mut self, size: usize, option: &str) -> Result<Raw, String> {
+        let denied = |e| denied(option, e);
+        if self.spoof_v4.is_none() {
+            self.spoof_v4 = Some(spoof_socket(Domain::IPV4, &self.source).map_err(denied)?);
+        }
+        if self.spoof_v6.is_none() && self.v6.is_some() {
+            self.spoof_v6 = Some(spoof_socket(Domain::IPV6, &self.source).map_err(denied)?);
+        }
+        self.fragment = Some(size);
+        Ok(self)
+    }
+
+    // Function to get how many probes got an answer so far.
+    pub fn answered(&self) -> usize {
+        self.answers.load(Ordering::Relaxed)
+    }
+
+    // Function to send a TCP segment with the given flags to `addr` and wait at most `wait` for
+    // the host to answer it. Gives `None` if nothing came back in time.
+    pub async fn probe(
+        &self,
+        addr: SocketAddr,
+        flags: u8,
+        wait: Duration,
+    ) -> io::Result<Option<Reply>> {
+        let (seq, ack) = rand::thread_rng().gen::<(u32, u32)>();
+        let mut segment = segment(self.port, addr.port(), seq, ack, flags);
+        // The kernel fills in the checksum of IPv6 segments (see `raw_socket`).
+        if let IpAddr::V4(remote) = addr.ip() {
+            let local = self.route(remote)?;
+            seal(&mut segment, 16, libc::IPPROTO_TCP as u8, local, remote);
+        }
+        self.exchange(addr, &segment, Some(flags), seq, ack, wait)
+            .await
+    }
+
+    // Function to send an SCTP INIT to `addr` and wait at most `wait` for the host to answer it.
+    // Gives `None` if nothing came back in time.
+    pub async fn init(&self, addr: SocketAddr, wait: Duration) -> io::Result<Option<Reply>> {
+        // The initiate tag must not be zero.
+        let (tag, tsn) = rand::thread_rng().gen::<(u32, u32)>();
+        let tag = tag.max(1);
+        let packet = init(self.port, addr.port(), tag, tsn);
+        self.exchange(addr, &packet, None, tag, 0, wait).await
+    }
+
+    // Function to send a packet built for `addr` and wait for the reply that answers it. The
+    // decoys send the same kind of probe (a segment with `flags`, or an SCTP INIT) around it.
+    async fn exchange(
+        &self,
+        addr: SocketAddr,
+        packet: &[u8],
+        flags: Option<u8>,
+        seq: u32,
+        ack: u32,
+        wait: Duration,
+    ) -> io::Result<Option<Reply>> {
+        let socket = match addr {
+            SocketAddr::V4(_) => Some(&self.v4),
+            SocketAddr::V6(_) => self.v6.as_ref(),
+        };
+        let socket = socket.ok_or_else(|| io::Error::from(io::ErrorKind::Unsupported))?;
+
+        let (reply, answer) = oneshot::channel();
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(addr, Waiting { seq, ack, reply });
+        let _waiting = Forget {
+            pending: &self.pending,
+            addr,
+            seq,
+        };
+        // Raw sockets take the protocol in place of a port.
+        let to = SocketAddr::new(addr.ip(), 0).into();
+        // Fragments need an IP header of their own, so the real probe gets one like the decoys.
+        let send = |socket: &Socket| match self.fragment {
+            Some(_) => self.send_whole(&self.whole(addr, packet, flags)?, &to),
+            None => socket.send_to(packet, &to).map(drop),
+        };
+        if self.decoys.is_empty() {
+            send(socket)?;
+        }
+        for decoy in &self.decoys {
+            match decoy {
+                None => send(socket)?,
+                // Decoys of the other family cannot take part.
+                Some(decoy) if decoy.is_ipv4() != addr.is_ipv4() => {}
+                Some(decoy) => self.send_whole(&self.forge(*decoy, addr, flags), &to)?,
+            }
+        }
+        let reply = timeout(wait, answer).await.ok().and_then(Result::ok);
+        if reply.is_some() {
+            self.answers.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(reply)
+    }
+
+    // Function to send a packet built with its IP header over the socket of protocol IPPROTO_RAW
+    // of its family, in fragments if the probes are split.
+    fn send_whole(&self, packet: &[u8], to: &socket2::SockAddr) -> io::Result<()> {
+        let mut packet = packet.to_vec();
+        self.source.limit_packet(&mut packet);
+        let spoof = match packet.first().map(|byte| byte >> 4) {
+            Some(4) => self.spoof_v4.as_ref(),
+            _ => self.spoof_v6.as_ref(),
+        };
+        let Some(spoof) = spoof else {
+            return Ok(());
+        };
+        match self.fragment {
+            Some(size) => {
+                let id = rand::thread_rng().gen::<u32>();
+                for fragment in fragments(&packet, size, id) {
+                    spoof.send_to(&fragment, to)?;
+                }
+            }
+            None => {
+                spoof.send_to(&packet, to)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Function to wrap the probe `packet` of `addr` in an IP header from this host: a segment
+    // with `flags`, or an SCTP INIT.
+    fn whole(&self, addr: SocketAddr, packet: &[u8], flags: Option<u8>) -> io::Result<Vec<u8>> {
+        let protocol = match flags {
+            Some(_) => libc::IPPROTO_TCP as u8,
+            None => libc::IPPROTO_SCTP as u8,
+        };
+        Ok(match addr.ip() {
+            IpAddr::V4(remote) => {
+                let id = rand::thread_rng().gen::<u16>().max(1);
+                ipv4(self.route(remote)?, remote, protocol, id, packet)
+            }
+            IpAddr::V6(remote) => {
+                let IpAddr::V6(local) = local_addr(&self.source, addr)? else {
+                    return Err(io::ErrorKind::AddrNotAvailable.into());
+                };
+                // Only the raw TCP socket has the kernel fill in the checksum of IPv6 segments.
+                let mut payload = packet.to_vec();
+                if flags.is_some() {
+                    seal_v6(&mut payload, 16, protocol, local, remote);
+                }
+                ipv6(local, remote, protocol, &payload)
+            }
+        })
+    }
+
+    // Function to build the probe of `addr` a decoy sends, IP header included: a segment with
+    // `flags`, or an SCTP INIT.
+    fn forge(&self, decoy: IpAddr, addr: SocketAddr, flags: Option<u8>) -> Vec<u8> {
+        let (seq, tag, id) = rand::thread_rng().gen::<(u32, u32, u16)>();
+        // The kernel would give each fragment an identification of its own in place of zero.
+        let id = id.max(1);
+        let (protocol, mut payload) = match flags {
+            Some(flags) => (
+                libc::IPPROTO_TCP as u8,
+                segment(self.port, addr.port(), seq, 0, flags),
+            ),
+            None => (
+                libc::IPPROTO_SCTP as u8,
+                init(self.port, addr.port(), tag.max(1), seq),
+            ),
+        };
+        match (decoy, addr.ip()) {
+            (IpAddr::V4(decoy), IpAddr::V4(remote)) => {
+                if flags.is_some() {
+                    seal(&mut payload, 16, protocol, decoy, remote);
+                }
+                ipv4(decoy, remote, protocol, id, &payload)
+            }
+            (IpAddr::V6(decoy), IpAddr::V6(remote)) => {
+                if flags.is_some() {
+                    seal_v6(&mut payload, 16, protocol, decoy, remote);
+                }
+                ipv6(decoy, remote, protocol, &payload)
+            }
+            // `exchange` leaves out the decoys of the other family.
+            _ => vec![],
+        }
+    }
+
+    // Function to find the local IPv4 address the kernel sends to `remote` from.
+    fn route(&self, remote: Ipv4Addr) -> io::Result<Ipv4Addr> {
+        if let Some(&local) = self.routes.lock().unwrap().get(&remote) {
+            return Ok(local);
+        }
+        let local = local_v4(&self.source, remote)?;
+        self.routes.lock().unwrap().insert(remote, local);
+        Ok(local)
+    }
+}
+
+// Function to describe a failure to open a raw socket for `option`, pointing out the missing
+// privileges when that is the cause.
+pub fn denied(option: &str, e: io::Error) -> String {
+    match e.kind() {
+        // Root without the capability, as in containers that drop it, has nothing to gain from sudo.
+        // SAFETY: geteuid cannot fail.
+        io::ErrorKind::PermissionDenied if unsafe { libc::geteuid() } == 0 => format!(
+            "{} sends raw packets, which needs the CAP_NET_RAW capability, and this process runs \
+             as root without it (e.g. in a container started without --cap-add=NET_RAW)",
+            option
+        ),
+        io::ErrorKind::PermissionDenied => format!(
+            "{} sends raw packets, which needs root or the CAP_NET_RAW capability \
+             (run it with sudo, or grant it with `setcap cap_net_raw+ep` on the binary)",
+            option
+        ),
+        _ => format!("{} cannot open a raw socket: {}", option, e),
+    }
+}
+
+// Function to check whether this process may open raw sockets: whether CAP_NET_RAW is among
+// its effective capabilities, which root has unless they were dropped. Without the process
+// status to read, being root has to do.
+pub fn capable() -> bool {
+    let effective = std::fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            let line = status.lines().find(|line| line.starts_with("CapEff:"))?;
+            u64::from_str_radix(line["CapEff:".len()..].trim(), 16).ok()
+        });
+    match effective {
+        Some(capabilities) => capabilities & (1 << CAP_NET_RAW) != 0,
+        // SAFETY: geteuid cannot fail.
+        None => unsafe { libc::geteuid() == 0 },
+    }
+}
+
+// Function to check whether the kernel tracks connections (netfilter's conntrack), which makes
+// it reassemble the fragments this host sends, and then split them only as its MTU requires.
+pub fn defragmenting() -> bool {
+    std::fs::read_to_string("/proc/sys/net/netfilter/nf_conntrack_count")
+        .is_ok_and(|count| count.trim() != "0")
+}
+
+// Function to find the local IPv4 address the kernel sends to `remote` from.
+pub fn local_v4(source: &Source, remote: Ipv4Addr) -> io::Result<Ipv4Addr> {
+    match local_addr(source, SocketAddr::from((remote, 9)))? {
+        IpAddr::V4(local) => Ok(local),
+        IpAddr::V6(_) => Err(io::ErrorKind::AddrNotAvailable.into()),
+    }
+}
+
+// Function to find the local address the kernel sends to `remote` from, of either family.
+pub fn local_addr(source: &Source, remote: SocketAddr) -> io::Result<IpAddr> {
+    // Connecting a UDP socket only looks up the route, it sends nothing.
+    let socket = Socket::new(Domain::for_address(remote), Type::DGRAM, None)?;
+    source.bind_to(&socket, remote.ip())?;
+    socket.connect(&remote.into())?;
+    Ok(UdpSocket::from(socket).local_addr()?.ip())
+}
+
+// Removes a probe from the waiting ones once it stops waiting, answered or not.
+struct Forget<'a> {
+    pending: &'a Pending,
+    addr: SocketAddr,
+    seq: u32,
+}
+
+impl Drop for Forget<'_> {
+    fn drop(&mut self) {
+        let mut pending = self.pending.lock().unwrap();
+        // A later probe of the same port may have taken the slot already.
+        if pending
+            .get(&self.addr)
+            .is_some_and(|waiting| waiting.seq == self.seq)
+        {
+            pending.remove(&self.addr);
+        }
+    }
+}
+
+// Function to open a raw socket of protocol IPPROTO_RAW of a family, bound to the source of the
+// scan, which sends packets with the IP header they are given.
+fn spoof_socket(domain: Domain, source: &Source) -> io::Result<Socket> {
+    let socket = Socket::new(domain, Type::RAW, Some(Protocol::from(libc::IPPROTO_RAW)))?;
+    let unspecified = match domain {
+        Domain::IPV6 => IpAddr::from([0u16; 8]),
+        _ => IpAddr::from([0u8; 4]),
+    };
+    source.bind_to(&socket, unspecified)?;
+    Ok(socket)
+}
+
+// Function to open a raw socket of a family, bound to the source of the scan.
+fn raw_socket(domain: Domain, source: &Source, transport: Transport) -> io::Result<Socket> {
+    let protocol = match transport {
+        Transport::Tcp => Protocol::TCP,
+        Transport::Sctp => Protocol::from(libc::IPPROTO_SCTP),
+    };
+    let socket = Socket::new(domain, Type::RAW, Some(protocol))?;
+    let _ = socket.set_recv_buffer_size(RECV_BUFFER);
+    let unspecified = match domain {
+        Domain::IPV6 => IpAddr::from([0u16; 8]),
+        _ => IpAddr::from([0u8; 4]),
+    };
+    source.bind_to(&socket, unspecified)?;
+    source.limit(&socket, unspecified)?;
+    if domain == Domain::IPV6 && transport == Transport::Tcp {
+        // Have the kernel compute the checksum at offset 16 of the segment, which covers the
+        // source address the kernel picks. SCTP checksums leave the addresses out.
+        let offset: libc::c_int = 16;
+        // SAFETY: `offset` is a readable c_int, as IPV6_CHECKSUM expects.
+        let result = unsafe {
+            libc::setsockopt(
+                std::os::fd::AsRawFd::as_raw_fd(&socket),
+                libc::IPPROTO_IPV6,
+                libc::IPV6_CHECKSUM,
+                (&offset as *const libc::c_int).cast(),
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(socket)
+}
+
+// Function to build a TCP segment (a 20-byte header plus an MSS option, as real stacks send),
+// with the checksum left at zero.
+pub fn segment(from: u16, to: u16, seq: u32, ack: u32, flags: u8) -> Vec<u8> {
+    let mut segment = Vec::with_capacity(24);
+    segment.extend_from_slice(&from.to_be_bytes());
+    segment.extend_from_slice(&to.to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    // The acknowledgment number only counts with the ACK flag, but is set either way.
+    segment.extend_from_slice(&ack.to_be_bytes());
+    // A header of 6 words, and the flags.
+    segment.extend_from_slice(&[6 << 4, flags]);
+    // The window, checksum and urgent pointer.
+    segment.extend_from_slice(&[0x04, 0x00, 0, 0, 0, 0]);
+    // A maximum segment size of 1460.
+    segment.extend_from_slice(&[2, 4, 0x05, 0xb4]);
+    segment
+}
+
+// Function to build an SCTP packet holding one INIT chunk, checksum included.
+pub fn init(from: u16, to: u16, tag: u32, tsn: u32) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(32);
+    packet.extend_from_slice(&from.to_be_bytes());
+    packet.extend_from_slice(&to.to_be_bytes());
+    // An INIT goes out with a verification tag of zero, and the checksum is filled in below.
+    packet.extend_from_slice(&[0; 8]);
+    // The chunk type, flags and length.
+    packet.extend_from_slice(&[INIT, 0, 0, 20]);
+    packet.extend_from_slice(&tag.to_be_bytes());
+    // A receiver window of 64 KiB, 10 outbound and 65535 inbound streams.
+    packet.extend_from_slice(&[0, 1, 0, 0, 0, 10, 0xff, 0xff]);
+    packet.extend_from_slice(&tsn.to_be_bytes());
+    let sum = crc32c(&packet);
+    packet[8..12].copy_from_slice(&sum.to_le_bytes());
+    packet
+}
+
+// Function to build an IPv4 packet of `protocol` from `local` to `remote` around `payload`, for
+// raw sockets of protocol IPPROTO_RAW, which take the IP header from the caller.
+pub fn ipv4(local: Ipv4Addr, remote: Ipv4Addr, protocol: u8, id: u16, payload: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(20 + payload.len());
+    // Version 4 and a header of 5 words, no type of service, and the total length.
+    packet.extend_from_slice(&[0x45, 0]);
+    packet.extend_from_slice(&(20 + payload.len() as u16).to_be_bytes());
+    packet.extend_from_slice(&id.to_be_bytes());
+    // No fragmentation flags, a TTL of 64, the protocol and a checksum the kernel fills in.
+    packet.extend_from_slice(&[0, 0, 64, protocol, 0, 0]);
+    packet.extend_from_slice(&local.octets());
+    packet.extend_from_slice(&remote.octets());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+// Function to build an IPv6 packet of `protocol` from `local` to `remote` around `payload`, for
+// raw sockets of protocol IPPROTO_RAW.
+pub fn ipv6(local: Ipv6Addr, remote: Ipv6Addr, protocol: u8, payload: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(40 + payload.len());
+    // Version 6, no traffic class or flow label.
+    packet.extend_from_slice(&[0x60, 0, 0, 0]);
+    packet.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    // The protocol and a hop limit of 64.
+    packet.extend_from_slice(&[protocol, 64]);
+    packet.extend_from_slice(&local.octets());
+    packet.extend_from_slice(&remote.octets());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+// Function to split an IPv4 or IPv6 packet built by `ipv4` or `ipv6` into fragments carrying
+// `size` bytes of its payload each (a multiple of 8, the unit of fragment offsets), so the
+// transport header is spread over several of them. IPv4 fragments keep the identification of
+// the packet; IPv6 ones get a fragment header with `id` (RFC 8200).
+pub fn fragments(packet: &[u8], size: usize, id: u32) -> Vec<Vec<u8>> {
+    let ipv6 = packet.first().is_some_and(|byte| byte >> 4 == 6);
+    let header = match ipv6 {
+        true => 40,
+        false => usize::from(packet.first().map_or(5, |byte| byte & 0x0f)) * 4,
+    };
+    let (header, payload) = packet.split_at(header.min(packet.len()));
+    let count = payload.chunks(size).count();
+    let mut fragments = Vec::with_capacity(count);
+    for (index, piece) in payload.chunks(size).enumerate() {
+        // The offset in units of 8 bytes, and whether more fragments follow.
+        let offset = (index * size / 8) as u16;
+        let more = u16::from(index + 1 < count);
+        let mut fragment = header.to_vec();
+        if ipv6 {
+            let next = fragment[6];
+            fragment[4..6].copy_from_slice(&(8 + piece.len() as u16).to_be_bytes());
+            fragment[6] = libc::IPPROTO_FRAGMENT as u8;
+            fragment.extend_from_slice(&[next, 0]);
+            fragment.extend_from_slice(&(offset << 3 | more).to_be_bytes());
+            fragment.extend_from_slice(&id.to_be_bytes());
+        } else {
+            fragment[2..4].copy_from_slice(&((header.len() + piece.len()) as u16).to_be_bytes());
+            fragment[6..8].copy_from_slice(&(more << 13 | offset).to_be_bytes());
+        }
+        fragment.extend_from_slice(piece);
+        fragments.push(fragment);
+    }
+    fragments
+}
+
+// Function to compute the CRC32c checksum of SCTP packets (RFC 9260, appendix A).
+fn crc32c(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0x82f6_3b78,
+                _ => crc >> 1,
+            };
+        }
+    }
+    !crc
+}
+
+// Function to fill in the checksum at offset `at` of a TCP or UDP header sent over IPv4,
+// which covers a pseudo-header with both addresses and the protocol.
+pub fn seal(packet: &mut [u8], at: usize, protocol: u8, local: Ipv4Addr, remote: Ipv4Addr) {
+    let sum = checksum(&[
+        &local.octets(),
+        &remote.octets(),
+        &[0, protocol],
+        &(packet.len() as u16).to_be_bytes(),
+        packet,
+    ]);
+    packet[at..at + 2].copy_from_slice(&sum.to_be_bytes());
+}
+
+// Function to fill in the checksum at offset `at` of a TCP or UDP header sent over IPv6, whose
+// pseudo-header holds both addresses, the length and the protocol (RFC 8200).
+pub fn seal_v6(packet: &mut [u8], at: usize, protocol: u8, local: Ipv6Addr, remote: Ipv6Addr) {
+    let sum = checksum(&[
+        &local.octets(),
+        &remote.octets(),
+        &(packet.len() as u32).to_be_bytes(),
+        &[0, 0, 0, protocol],
+        packet,
+    ]);
+    packet[at..at + 2].copy_from_slice(&sum.to_be_bytes());
+}
+
+// Function to read the --decoys list: addresses to send every probe from as well, and `ME` for
+// the real probe among them (`None`), which goes in at a random place if not given.
+pub fn decoys(spec: &str) -> Result<Vec<Option<IpAddr>>, String> {
+    let mut decoys = vec![];
+    for decoy in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        match decoy.parse::<IpAddr>() {
+            Ok(ip) => decoys.push(Some(ip)),
+            Err(_) if decoy.eq_ignore_ascii_case("me") && !decoys.contains(&None) => {
+                decoys.push(None)
+            }
+            Err(_) if decoy.eq_ignore_ascii_case("me") => {
+                return Err("--decoys can only name ME once".to_string())
+            }
+            Err(_) => {
+                return Err(format!(
+                    "{} is not a valid decoy (must be an IP address or ME)",
+                    decoy
+                ))
+            }
+        }
+    }
+    if !decoys.iter().any(Option::is_some) {
+        return Err("--decoys needs at least one decoy address".to_string());
+    }
+    if !decoys.contains(&None) {
+        let at = rand::thread_rng().gen_range(0..=decoys.len());
+        decoys.insert(at, None);
+    }
+    Ok(decoys)
+}
+
+// Function to compute the Internet checksum of some bytes, given in pieces.
+pub fn checksum(pieces: &[&[u8]]) -> u16 {
+    let bytes: Vec<u8> = pieces.concat();
+    let mut sum: u32 = bytes
+        .chunks(2)
+        .map(|pair| u32::from(u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)])))
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+// Function run by the receiving thread of a raw socket: hands every packet sent to `port` to
+// the probe waiting for it.
+fn receive(socket: &Socket, transport: Transport, port: u16, pending: &Pending) {
+    let mut buffer = [MaybeUninit::<u8>::uninit(); 65536];
+    loop {
+        let Ok((length, from)) = socket.recv_from(&mut buffer) else {
+            continue;
+        };
+        // SAFETY: `recv_from` initialized the first `length` bytes.
+        let packet = unsafe { std::slice::from_raw_parts(buffer.as_ptr().cast::<u8>(), length) };
+        let Some(from) = from.as_socket() else {
+            continue;
+        };
+        if let Some(received) = parse(packet, from.ip(), transport, port) {
+            let mut pending = pending.lock().unwrap();
+            let answers = pending
+                .get(&received.from)
+                .is_some_and(|waiting| waiting.answered_by(&received));
+            if answers {
+                if let Some(waiting) = pending.remove(&received.from) {
+                    let _ = waiting.reply.send(received.reply);
+                }
+            }
+        }
+    }
+}
+
+// Function to read a TCP segment or SCTP packet sent to `port` by `from`.
+// IPv4 raw sockets deliver the IP header as well, IPv6 ones only the transport header.
+fn parse(packet: &[u8], from: IpAddr, transport: Transport, port: u16) -> Option<Received> {
+    let (ip, segment) = match from {
+        IpAddr::V4(_) => {
+            let header = usize::from(packet.first()? & 0x0f) * 4;
+            let source: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            (IpAddr::from(source), packet.get(header..)?)
+        }
+        ip => (ip, packet),
+    };
+    let word = |at: usize| {
+        Some(u32::from_be_bytes(
+            segment.get(at..at + 4)?.try_into().ok()?,
+        ))
+    };
+    let half = |at: usize| {
+        Some(u16::from_be_bytes(
+            segment.get(at..at + 2)?.try_into().ok()?,
+        ))
+    };
+    if half(2)? != port {
+        return None;
+    }
+    let from = SocketAddr::new(ip, half(0)?);
+    match transport {
+        Transport::Tcp => Some(Received {
+            from,
+            reply: Reply::Segment {
+                flags: *segment.get(13)?,
+                window: half(14)?,
+            },
+            first: word(4)?,
+            second: word(8)?,
+        }),
+        Transport::Sctp => Some(Received {
+            from,
+            reply: Reply::Chunk(*segment.get(12)?),
+            first: word(4)?,
+            second: u32::from(segment.get(13)? & 1),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Function to wrap a transport header in a minimal IPv4 header from 10.0.0.2.
+    fn ipv4(payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![
+            0x45, 0, 0, 0, 0, 0, 0, 0, 64, 6, 0, 0, 10, 0, 0, 2, 10, 0, 0, 1,
+        ];
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    // Function to build a waiting probe with the given numbers.
+    fn waiting(seq: u32, ack: u32) -> Waiting {
+        Waiting {
+            seq,
+            ack,
+            reply: oneshot::channel().0,
+        }
+    }
+
+    #[test]
+    fn checksums() {
+        // The check value of CRC32c.
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+        // A segment with its checksum filled in sums up to zero.
+        let mut segment = segment(40000, 80, 1, 0, SYN);
+        let header: [&[u8]; 4] = [&[10, 0, 0, 1], &[10, 0, 0, 2], &[0, 6], &[0, 24]];
+        let sum = checksum(&[&header.concat(), &segment]);
+        segment[16..18].copy_from_slice(&sum.to_be_bytes());
+        assert_eq!(checksum(&[&header.concat(), &segment]), 0);
+    }
+
+    #[test]
+    fn builds_ipv6_packets() {
+        let (local, remote) = (Ipv6Addr::LOCALHOST, "fd00::2".parse().unwrap());
+        let mut segment = segment(40000, 80, 1, 0, SYN);
+        seal_v6(&mut segment, 16, 6, local, remote);
+        let packet = ipv6(local, remote, 6, &segment);
+        assert_eq!(&packet[..8], &[0x60, 0, 0, 0, 0, 24, 6, 64]);
+        assert_eq!(&packet[24..40], &remote.octets());
+        // A sealed segment sums up to zero with its pseudo-header.
+        let pseudo: [&[u8]; 4] = [
+            &local.octets(),
+            &remote.octets(),
+            &[0, 0, 0, 24],
+            &[0, 0, 0, 6],
+        ];
+        assert_eq!(checksum(&[&pseudo.concat(), &packet[40..]]), 0);
+    }
+
+    #[test]
+    fn parses_decoys() {
+        assert_eq!(
+            decoys("10.0.0.7, ME ,fd00::9").unwrap(),
+            vec![
+                Some(IpAddr::from([10, 0, 0, 7])),
+                None,
+                Some("fd00::9".parse().unwrap())
+            ]
+        );
+        // Without ME, the real probe goes in somewhere.
+        let parsed = decoys("10.0.0.7,10.0.0.8").unwrap();
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(parsed.iter().filter(|decoy| decoy.is_none()).count(), 1);
+        assert!(decoys("ME").is_err());
+        assert!(decoys("10.0.0.7,me,ME").is_err());
+        assert!(decoys("decoy.example").is_err());
+    }
+
+    #[test]
+    fn splits_fragments() {
+        let (local, remote) = (Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2));
+        let segment = segment(40000, 80, 7, 0, SYN);
+        let packet = super::ipv4(local, remote, 6, 0x1234, &segment);
+        let pieces = fragments(&packet, 8, 0);
+        assert_eq!(pieces.len(), 3);
+        for (index, piece) in pieces.iter().enumerate() {
+            assert_eq!(piece.len(), 28);
+            assert_eq!(&piece[2..4], &28u16.to_be_bytes());
+            assert_eq!(&piece[4..6], &[0x12, 0x34]);
+            assert_eq!(&piece[20..], &segment[index * 8..index * 8 + 8]);
+        }
+        // More fragments follow the first two, at offsets of 8 bytes each.
+        assert_eq!(&pieces[0][6..8], &[0x20, 0]);
+        assert_eq!(&pieces[1][6..8], &[0x20, 1]);
+        assert_eq!(&pieces[2][6..8], &[0, 2]);
+
+        let (local, remote): (Ipv6Addr, Ipv6Addr) =
+            ("fd00::1".parse().unwrap(), "fd00::2".parse().unwrap());
+        let packet = ipv6(local, remote, 6, &segment);
+        let pieces = fragments(&packet, 16, 0xabcd);
+        assert_eq!(pieces.len(), 2);
+        // A fragment header goes in front of each piece, naming TCP as the next header.
+        assert_eq!(&pieces[0][4..8], &[0, 24, 44, 64]);
+        assert_eq!(&pieces[0][40..48], &[6, 0, 0, 1, 0, 0, 0xab, 0xcd]);
+        assert_eq!(&pieces[1][4..8], &[0, 16, 44, 64]);
+        assert_eq!(&pieces[1][40..48], &[6, 0, 0, 16, 0, 0, 0xab, 0xcd]);
+        assert_eq!(&pieces[1][48..], &segment[16..]);
+    }
+
+    #[test]
+    fn parses_tcp_replies() {
+        // A SYN/ACK from port 80 to 40000 acknowledging sequence number 7.
+        let mut reply = segment(80, 40000, 99, 8, SYN | ACK);
+        reply[14..16].copy_from_slice(&512u16.to_be_bytes());
+        let from = IpAddr::from([10, 0, 0, 2]);
+        let received = parse(&ipv4(&reply), from, Transport::Tcp, 40000).unwrap();
+        assert_eq!(received.from, "10.0.0.2:80".parse().unwrap());
+        assert!(matches!(
+            received.reply,
+            Reply::Segment {
+                flags: 0x12,
+                window: 512
+            }
+        ));
+        assert!(waiting(7, 0).answered_by(&received));
+        assert!(!waiting(100, 0).answered_by(&received));
+        // Segments to other ports are not ours.
+        assert!(parse(&ipv4(&reply), from, Transport::Tcp, 40001).is_none());
+        // A RST answering an ACK takes its acknowledgment number as the sequence number.
+        let reset = segment(80, 40000, 1234, 0, RST);
+        let received = parse(&ipv4(&reset), from, Transport::Tcp, 40000).unwrap();
+        assert!(waiting(7, 1234).answered_by(&received));
+    }
+
+    #[test]
+    fn parses_sctp_replies() {
+        let probe = init(40000, 3868, 0xabcd, 1);
+        assert_eq!(&probe[12..16], &[INIT, 0, 0, 20]);
+        // An INIT-ACK carries the initiate tag of the probe as its verification tag.
+        let mut reply = init(3868, 40000, 5, 1);
+        reply[4..8].copy_from_slice(&0xabcdu32.to_be_bytes());
+        reply[12] = INIT_ACK;
+        let from = IpAddr::from([0xfd00, 0, 0, 0, 0, 0, 0, 2]);
+        let received = parse(&reply, from, Transport::Sctp, 40000).unwrap();
+        assert!(matches!(received.reply, Reply::Chunk(INIT_ACK)));
+        assert!(waiting(0xabcd, 0).answered_by(&received));
+        assert!(!waiting(0xabce, 0).answered_by(&received));
+        // An ABORT with the T flag reflects the tag of zero of the INIT.
+        let mut abort = init(3868, 40000, 5, 1);
+        abort[12..14].copy_from_slice(&[ABORT, 1]);
+        let received = parse(&abort, from, Transport::Sctp, 40000).unwrap();
+        assert!(waiting(0xabcd, 0).answered_by(&received));
+    }
+}
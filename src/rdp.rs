@@ -0,0 +1,204 @@
+use crate::scanner::{self, PortResult, PortState, Protocol, Settings}; // Import the open ports to probe and the scan settings to connect with.
+use crate::targets::Target; // Import the hosts the ports belong to.
+use serde::Serialize; // Import `Serialize` to report the servers in the structured output.
+use socket2::Type; // Import socket types for the connects.
+use std::io; // Import I/O errors for socket failures.
+use std::net::SocketAddr; // Import `SocketAddr` for the ports probed.
+use std::time::Duration; // Import `Duration` for the connect and read timeouts.
+use tokio::io::{AsyncReadExt, AsyncWriteExt}; // Import reads and writes on the connected streams.
+use tokio::net::TcpSocket; // Import sockets of the source for the connects.
+use tokio::time::{timeout_at, Instant}; // Import deadlines to give up on slow servers.
+
+// Define the security protocols of an RDP negotiation request (MS-RDPBCGR 2.2.1.1.1).
+const PROTOCOL_SSL: u32 = 0x01;
+const PROTOCOL_HYBRID: u32 = 0x02;
+const PROTOCOL_HYBRID_EX: u32 = 0x08;
+
+// Define the failure code of servers that only take clients doing NLA first.
+const HYBRID_REQUIRED_BY_SERVER: u32 = 0x05;
+
+// What an RDP server agreed to in the X.224 connection.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Rdp {
+    // The security it picked when offered all of them, e.g. `CredSSP`, or the failure it gave.
+    pub security: String,
+    // Set when it refused a client that would only do TLS, so logins need Network Level
+    // Authentication before any session is set up.
+    pub nla_required: bool,
+}
+
+// What a server answered a connection request.
+#[derive(Debug, PartialEq, Eq)]
+enum Negotiation {
+    // The protocol it selected; servers that predate the negotiation select standard security.
+    Selected(u32),
+    // The failure code it gave.
+    Failed(u32),
+}
+
+// Function to probe every open TCP port in `results` that may run RDP: port 3389 and ports
+// --service-version named ms-wbt-server. Each of the two connection requests gets `wait`.
+pub async fn probe_all(
+    hosts: &[Target],
+    results: &mut [PortResult],
+    settings: &Settings,
+    wait: Duration,
+) {
+    scanner::check_ports(
+        hosts,
+        results,
+        settings.concurrency,
+        |r, _| {
+            let rdp = r.port == 3389
+                || r.findings
+                    .detected
+                    .as_ref()
+                    .is_some_and(|s| s.name == "ms-wbt-server");
+            (r.protocol == Protocol::Tcp && r.state == PortState::Open && rdp).then_some(())
+        },
+        |addr, ()| async move { probe(addr, settings, wait).await.ok() },
+        |r, rdp| r.findings.rdp = rdp,
+    )
+    .await;
+}
+
+// Function to ask the server at `addr` twice for a connection: once offering every security
+// protocol, to see which it picks, and once offering TLS alone, which servers that enforce NLA
+// refuse. No login is tried.
+async fn probe(addr: SocketAddr, settings: &Settings, wait: Duration) -> io::Result<Rdp> {
+    let offered = PROTOCOL_SSL | PROTOCOL_HYBRID | PROTOCOL_HYBRID_EX;
+    let security = match negotiate(addr, offered, settings, wait).await? {
+        Negotiation::Selected(protocol) => protocol_name(protocol).to_string(),
+        Negotiation::Failed(code) => failure_name(code),
+    };
+    let nla_required = matches!(
+        negotiate(addr, PROTOCOL_SSL, settings, wait).await?,
+        Negotiation::Failed(HYBRID_REQUIRED_BY_SERVER)
+    );
+    Ok(Rdp {
+        security,
+        nla_required,
+    })
+}
+
+// Function to send a connection request offering the `protocols` to `addr` and read the answer,
+// within `wait`.
+async fn negotiate(
+    addr: SocketAddr,
+    protocols: u32,
+    settings: &Settings,
+    wait: Duration,
+) -> io::Result<Negotiation> {
+    let deadline = Instant::now() + wait;
+    let timed_out = |_| io::Error::from(io::ErrorKind::TimedOut);
+    let socket = TcpSocket::from_std_stream(settings.socket(addr, Type::STREAM).await?.into());
+    let mut stream = timeout_at(deadline, socket.connect(addr))
+        .await
+        .map_err(timed_out)??;
+    stream.write_all(&request(protocols)).await?;
+    // A TPKT header gives the length of the whole packet.
+    let mut header = [0u8; 4];
+    timeout_at(deadline, stream.read_exact(&mut header))
+        .await
+        .map_err(timed_out)??;
+    let length = usize::from(u16::from_be_bytes([header[2], header[3]]));
+    if header[0] != 3 || length < 4 {
+        return Err(io::ErrorKind::InvalidData.into());
+    }
+    let mut packet = header.to_vec();
+    packet.resize(length, 0);
+    timeout_at(deadline, stream.read_exact(&mut packet[4..]))
+        .await
+        .map_err(timed_out)??;
+    confirm(&packet).ok_or_else(|| io::ErrorKind::InvalidData.into())
+}
+
+// Function to build an X.224 connection request in a TPKT, with an RDP negotiation request
+// offering the `protocols`.
+fn request(protocols: u32) -> Vec<u8> {
+    let mut packet = vec![3, 0, 0, 19]; // TPKT version 3 and the length.
+    packet.extend_from_slice(&[14, 0xe0, 0, 0, 0, 0, 0]); // Connection request, class 0.
+    packet.extend_from_slice(&[1, 0, 8, 0]); // Negotiation request of 8 bytes.
+    packet.extend_from_slice(&protocols.to_le_bytes());
+    packet
+}
+
+// Function to read the X.224 connection confirm in a TPKT and its RDP negotiation response or
+// failure, if any.
+fn confirm(packet: &[u8]) -> Option<Negotiation> {
+    if packet.get(..2)? != [3, 0] || *packet.get(5)? != 0xd0 {
+        return None;
+    }
+    let Some(negotiation) = packet.get(11..19) else {
+        return Some(Negotiation::Selected(0));
+    };
+    let value = u32::from_le_bytes(negotiation[4..].try_into().ok()?);
+    match negotiation[0] {
+        2 => Some(Negotiation::Selected(value)),
+        3 => Some(Negotiation::Failed(value)),
+        _ => None,
+    }
+}
+
+// Function to name a selected security protocol.
+fn protocol_name(protocol: u32) -> &'static str {
+    match protocol {
+        0 => "standard RDP security",
+        PROTOCOL_SSL => "TLS",
+        PROTOCOL_HYBRID => "CredSSP",
+        PROTOCOL_HYBRID_EX => "CredSSP with early user authorization",
+        _ => "unknown",
+    }
+}
+
+// Function to describe a negotiation failure code.
+fn failure_name(code: u32) -> String {
+    match code {
+        0x01 => "refused: TLS required".to_string(),
+        0x02 => "refused: TLS not allowed, standard RDP security only".to_string(),
+        0x03 => "refused: no certificate on the server".to_string(),
+        0x04 => "refused: inconsistent flags".to_string(),
+        HYBRID_REQUIRED_BY_SERVER => "refused: CredSSP required".to_string(),
+        0x06 => "refused: TLS with user authentication required".to_string(),
+        other => format!("refused: code {:#x}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_requests() {
+        assert_eq!(
+            request(PROTOCOL_SSL | PROTOCOL_HYBRID),
+            b"\x03\x00\x00\x13\x0e\xe0\x00\x00\x00\x00\x00\x01\x00\x08\x00\x03\x00\x00\x00"
+        );
+    }
+
+    #[test]
+    fn reads_confirms() {
+        let selected =
+            b"\x03\x00\x00\x13\x0e\xd0\x00\x00\x12\x34\x00\x02\x1f\x08\x00\x02\x00\x00\x00";
+        assert_eq!(
+            confirm(selected),
+            Some(Negotiation::Selected(PROTOCOL_HYBRID))
+        );
+        let failed =
+            b"\x03\x00\x00\x13\x0e\xd0\x00\x00\x12\x34\x00\x03\x00\x08\x00\x05\x00\x00\x00";
+        assert_eq!(
+            confirm(failed),
+            Some(Negotiation::Failed(HYBRID_REQUIRED_BY_SERVER))
+        );
+        // Servers older than the negotiation confirm without one.
+        assert_eq!(
+            confirm(b"\x03\x00\x00\x0b\x06\xd0\x00\x00\x12\x34\x00"),
+            Some(Negotiation::Selected(0))
+        );
+        assert_eq!(
+            confirm(b"\x03\x00\x00\x0b\x06\xe0\x00\x00\x00\x00\x00"),
+            None
+        );
+        assert_eq!(confirm(b"SSH-2.0-OpenSSH_9.6\r\n"), None);
+    }
+}
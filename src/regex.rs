@@ -0,0 +1,589 @@
+use std::cell::Cell; // Import `Cell` to count the matching steps through shared references.
+
+// Define how many steps a search may take before it gives up, so a pattern that backtracks
+// badly cannot stall the scan on a large response.
+const STEPS: usize = 1 << 20;
+
+// Define how deep the matching of one pattern may nest, each part matched waiting on the parts
+// after it. A group repeated over a long response would otherwise overflow the stack; reaching
+// the limit ends the search without a match, as running out of steps does.
+const DEPTH: usize = 500;
+
+// The positions of the capture groups found by a search, group 0 being the whole match.
+type Spans = Vec<Option<(usize, usize)>>;
+
+// A compiled regular expression over bytes, as used by the match rules of the probe database.
+// It knows literals and the escapes \d \w \s \D \W \S \xNN \r \n \t \0 (and escaped punctuation),
+// `.`, classes such as [\w.-] or [^\r\n], capturing and (?:...) groups, alternation, the
+// quantifiers * + ? {n} {n,} {n,m} and their lazy forms with a trailing ?, the anchors ^ and $ for
+// the start and end of the response, and the flags (?i) and (?s) at the very start.
+#[derive(Debug)]
+pub struct Regex {
+    node: Node,
+    groups: usize,
+    // Set when the pattern starts with ^, so only the start of the response is tried.
+    anchored: bool,
+}
+
+// One part of a compiled pattern.
+#[derive(Debug)]
+enum Node {
+    // One byte out of a set: a literal, a class or `.`.
+    Byte(Box<[bool; 256]>),
+    Start,
+    End,
+    // A group, with its capture index unless it is (?:...).
+    Group(Box<Node>, Option<usize>),
+    Sequence(Vec<Node>),
+    Either(Vec<Node>),
+    Repeat(Repeat),
+}
+
+// A quantified part of a pattern.
+#[derive(Debug)]
+struct Repeat {
+    node: Box<Node>,
+    min: usize,
+    max: usize,
+    greedy: bool,
+}
+
+// One item of a class: a byte, which may start a range, or a set such as \d.
+enum Item {
+    Byte(u8),
+    Set(Box<[bool; 256]>),
+}
+
+impl Regex {
+    // Function to compile `pattern`, saying where it went wrong if it is not valid.
+    pub fn new(pattern: &str) -> Result<Regex, String> {
+        let mut parser = Parser {
+            pattern: pattern.as_bytes(),
+            at: 0,
+            groups: 0,
+            insensitive: false,
+            dot_all: false,
+        };
+        parser.flags();
+        let node = parser
+            .either()
+            .and_then(|node| match parser.peek() {
+                None => Ok(node),
+                Some(_) => Err("unmatched )".to_string()),
+            })
+            .map_err(|e| format!("invalid pattern {}: {} at byte {}", pattern, e, parser.at))?;
+        let anchored =
+            matches!(&node, Node::Sequence(items) if matches!(items.first(), Some(Node::Start)));
+        Ok(Regex {
+            node,
+            groups: parser.groups,
+            anchored,
+        })
+    }
+
+    // Function to find the first match in `text` and the capture groups of it, group 0 being
+    // the whole match. Groups that took no part in the match are `None`.
+    pub fn captures<'t>(&self, text: &'t [u8]) -> Option<Vec<Option<&'t [u8]>>> {
+        let matcher = Matcher {
+            text,
+            steps: Cell::new(STEPS),
+            depth: Cell::new(0),
+        };
+        let last = if self.anchored { 0 } else { text.len() };
+        for start in 0..=last {
+            let mut spans = vec![None; self.groups + 1];
+            let mut end = None;
+            let found = matcher.run(&self.node, start, &mut spans, &mut |at, _| {
+                end = Some(at);
+                true
+            });
+            if found {
+                spans[0] = end.map(|end| (start, end));
+                return Some(
+                    spans
+                        .into_iter()
+                        .map(|span| span.map(|(from, to)| &text[from..to]))
+                        .collect(),
+                );
+            }
+            if matcher.steps.get() == 0 {
+                return None;
+            }
+        }
+        None
+    }
+}
+
+// State of compiling a pattern.
+struct Parser<'a> {
+    pattern: &'a [u8],
+    at: usize,
+    groups: usize,
+    // Flags from a leading (?i) or (?s): case-insensitive, and `.` matching newlines too.
+    insensitive: bool,
+    dot_all: bool,
+}
+
+impl Parser<'_> {
+    // Function to look at the next byte of the pattern.
+    fn peek(&self) -> Option<u8> {
+        self.pattern.get(self.at).copied()
+    }
+
+    // Function to take the next byte of the pattern.
+    fn next(&mut self) -> Result<u8, String> {
+        let byte = self.peek().ok_or("unexpected end")?;
+        self.at += 1;
+        Ok(byte)
+    }
+
+    // Function to take `byte` if it comes next.
+    fn eat(&mut self, byte: u8) -> bool {
+        let next = self.peek() == Some(byte);
+        self.at += usize::from(next);
+        next
+    }
+
+    // Function to read the flags at the start of the pattern, such as (?i) or (?is).
+    fn flags(&mut self) {
+        while let Some(flags) = self.pattern[self.at..].strip_prefix(b"(?") {
+            let end = flags
+                .iter()
+                .position(|&b| b != b'i' && b != b's')
+                .unwrap_or(0);
+            if end == 0 || flags.get(end) != Some(&b')') {
+                return;
+            }
+            self.insensitive |= flags[..end].contains(&b'i');
+            self.dot_all |= flags[..end].contains(&b's');
+            self.at += end + 3;
+        }
+    }
+
+    // Function to parse alternatives separated by |.
+    fn either(&mut self) -> Result<Node, String> {
+        let mut alternatives = vec![self.sequence()?];
+        while self.eat(b'|') {
+            alternatives.push(self.sequence()?);
+        }
+        Ok(match alternatives.len() {
+            1 => alternatives.remove(0),
+            _ => Node::Either(alternatives),
+        })
+    }
+
+    // Function to parse the parts of one alternative, up to the next | or ).
+    fn sequence(&mut self) -> Result<Node, String> {
+        let mut items = vec![];
+        while let Some(byte) = self.peek() {
+            if byte == b'|' || byte == b')' {
+                break;
+            }
+            let atom = self.atom()?;
+            items.push(self.quantified(atom)?);
+        }
+        Ok(Node::Sequence(items))
+    }
+
+    // Function to parse one part of a pattern without its quantifier.
+    fn atom(&mut self) -> Result<Node, String> {
+        Ok(match self.next()? {
+            b'(' => {
+                let index = match self.pattern[self.at..].starts_with(b"?:") {
+                    true => {
+                        self.at += 2;
+                        None
+                    }
+                    false => {
+                        self.groups += 1;
+                        Some(self.groups)
+                    }
+                };
+                let inner = self.either()?;
+                if !self.eat(b')') {
+                    return Err("unclosed (".to_string());
+                }
+                Node::Group(Box::new(inner), index)
+            }
+            b'[' => Node::Byte(Box::new(self.class()?)),
+            b'.' => {
+                let mut set = [true; 256];
+                set[usize::from(b'\n')] = self.dot_all;
+                Node::Byte(Box::new(set))
+            }
+            b'^' => Node::Start,
+            b'$' => Node::End,
+            b'*' | b'+' | b'?' => return Err("nothing to repeat".to_string()),
+            b'\\' => Node::Byte(Box::new(match self.escape()? {
+                Item::Byte(byte) => self.fold(single(byte)),
+                Item::Set(set) => *set,
+            })),
+            byte => Node::Byte(Box::new(self.fold(single(byte)))),
+        })
+    }
+
+    // Function to parse the escape after a backslash.
+    fn escape(&mut self) -> Result<Item, String> {
+        let byte = self.next()?;
+        let set = |test: fn(&u8) -> bool, negated: bool| {
+            let mut set = [negated; 256];
+            for b in (0..=255u8).filter(test) {
+                set[usize::from(b)] = !negated;
+            }
+            Item::Set(Box::new(set))
+        };
+        Ok(match byte {
+            b'd' | b'D' => set(u8::is_ascii_digit, byte == b'D'),
+            b'w' | b'W' => set(|b| b.is_ascii_alphanumeric() || *b == b'_', byte == b'W'),
+            b's' | b'S' => set(|b| b.is_ascii_whitespace() || *b == 0x0b, byte == b'S'),
+            b'n' => Item::Byte(b'\n'),
+            b'r' => Item::Byte(b'\r'),
+            b't' => Item::Byte(b'\t'),
+            b'0' => Item::Byte(0),
+            b'x' => {
+                let digits = self
+                    .pattern
+                    .get(self.at..self.at + 2)
+                    .ok_or("short \\x escape")?;
+                let value = std::str::from_utf8(digits)
+                    .ok()
+                    .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                    .ok_or("invalid \\x escape")?;
+                self.at += 2;
+                Item::Byte(value)
+            }
+            byte if byte.is_ascii_alphanumeric() => {
+                return Err(format!("unsupported escape \\{}", char::from(byte)))
+            }
+            byte => Item::Byte(byte),
+        })
+    }
+
+    // Function to parse a class after its [, such as [\w.-] or [^\r\n].
+    fn class(&mut self) -> Result<[bool; 256], String> {
+        let negated = self.eat(b'^');
+        let mut set = [false; 256];
+        let mut first = true;
+        loop {
+            let item = match self.next()? {
+                b']' if !first => break,
+                b'\\' => self.escape()?,
+                byte => Item::Byte(byte),
+            };
+            first = false;
+            match item {
+                Item::Set(other) => (0..256).for_each(|b| set[b] |= other[b]),
+                // A range such as a-z, unless the - is the last byte of the class.
+                Item::Byte(from)
+                    if self.peek() == Some(b'-')
+                        && self.pattern.get(self.at + 1) != Some(&b']') =>
+                {
+                    self.at += 1;
+                    let to = match self.next()? {
+                        b'\\' => match self.escape()? {
+                            Item::Byte(to) => to,
+                            Item::Set(_) => return Err("invalid class range".to_string()),
+                        },
+                        to => to,
+                    };
+                    if to < from {
+                        return Err("invalid class range".to_string());
+                    }
+                    (from..=to).for_each(|b| set[usize::from(b)] = true);
+                }
+                Item::Byte(byte) => set[usize::from(byte)] = true,
+            }
+        }
+        let mut set = self.fold(set);
+        if negated {
+            set.iter_mut().for_each(|b| *b = !*b);
+        }
+        Ok(set)
+    }
+
+    // Function to parse the quantifier after `atom`, if any.
+    fn quantified(&mut self, atom: Node) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some(b'{') => match self.bounds() {
+                Some(bounds) => bounds,
+                None => return Ok(atom),
+            },
+            Some(byte @ (b'*' | b'+' | b'?')) => {
+                self.at += 1;
+                match byte {
+                    b'*' => (0, usize::MAX),
+                    b'+' => (1, usize::MAX),
+                    _ => (0, 1),
+                }
+            }
+            _ => return Ok(atom),
+        };
+        if max < min {
+            return Err("invalid repetition bounds".to_string());
+        }
+        let greedy = !self.eat(b'?');
+        Ok(Node::Repeat(Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+            greedy,
+        }))
+    }
+
+    // Function to parse bounds such as {3}, {2,} or {1,4} and step past them. A { that does not
+    // start any is left alone, to be read as a literal.
+    fn bounds(&mut self) -> Option<(usize, usize)> {
+        let rest = &self.pattern[self.at + 1..];
+        let end = rest.iter().position(|&b| b == b'}')?;
+        let inner = std::str::from_utf8(&rest[..end]).ok()?;
+        let number = |s: &str| match s.bytes().all(|b| b.is_ascii_digit()) {
+            true => s.parse::<usize>().ok(),
+            false => None,
+        };
+        let bounds = match inner.split_once(',') {
+            None => (number(inner)?, number(inner)?),
+            Some((min, "")) => (number(min)?, usize::MAX),
+            Some((min, max)) => (number(min)?, number(max)?),
+        };
+        self.at += end + 2;
+        Some(bounds)
+    }
+
+    // Function to add the other case of every letter in `set` when matching case-insensitively.
+    fn fold(&self, mut set: [bool; 256]) -> [bool; 256] {
+        if self.insensitive {
+            for b in 0..=255u8 {
+                if set[usize::from(b)] {
+                    set[usize::from(b.to_ascii_lowercase())] = true;
+                    set[usize::from(b.to_ascii_uppercase())] = true;
+                }
+            }
+        }
+        set
+    }
+}
+
+// Function to get the set of `byte` alone.
+fn single(byte: u8) -> [bool; 256] {
+    let mut set = [false; 256];
+    set[usize::from(byte)] = true;
+    set
+}
+
+// State of one search: the text and the steps it has left.
+struct Matcher<'t> {
+    text: &'t [u8],
+    steps: Cell<usize>,
+    depth: Cell<usize>,
+}
+
+impl Matcher<'_> {
+    // Function to match `node` at `at`, then hand the end of it to `next` for the rest of the
+    // pattern. Backtracks into the other ways `node` can match while `next` fails.
+    fn run(
+        &self,
+        node: &Node,
+        at: usize,
+        spans: &mut Spans,
+        next: &mut dyn FnMut(usize, &mut Spans) -> bool,
+    ) -> bool {
+        let steps = self.steps.get();
+        if steps == 0 {
+            return false;
+        }
+        if self.depth.get() == DEPTH {
+            self.steps.set(0);
+            return false;
+        }
+        self.steps.set(steps - 1);
+        self.depth.set(self.depth.get() + 1);
+        let found = self.step(node, at, spans, next);
+        self.depth.set(self.depth.get() - 1);
+        found
+    }
+
+    // Function to match `node` itself, for `run`.
+    fn step(
+        &self,
+        node: &Node,
+        at: usize,
+        spans: &mut Spans,
+        next: &mut dyn FnMut(usize, &mut Spans) -> bool,
+    ) -> bool {
+        match node {
+            Node::Byte(set) => match self.text.get(at) {
+                Some(&b) if set[usize::from(b)] => next(at + 1, spans),
+                _ => false,
+            },
+            Node::Start => at == 0 && next(at, spans),
+            Node::End => at == self.text.len() && next(at, spans),
+            Node::Group(inner, None) => self.run(inner, at, spans, next),
+            Node::Group(inner, Some(index)) => self.run(inner, at, spans, &mut |end, spans| {
+                let saved = spans[*index];
+                spans[*index] = Some((at, end));
+                if next(end, spans) {
+                    return true;
+                }
+                spans[*index] = saved;
+                false
+            }),
+            Node::Sequence(items) => self.sequence(items, at, spans, next),
+            Node::Either(alternatives) => alternatives
+                .iter()
+                .any(|alternative| self.run(alternative, at, spans, next)),
+            Node::Repeat(repeat) => match &*repeat.node {
+                // Runs of single bytes, as in .* or [\w.]+, are counted rather than recursed into.
+                Node::Byte(set) => {
+                    let run = self.text[at..]
+                        .iter()
+                        .take(repeat.max)
+                        .take_while(|&&b| set[usize::from(b)])
+                        .count();
+                    let mut counts = repeat.min..=run;
+                    match repeat.greedy {
+                        true => counts.rev().any(|n| next(at + n, spans)),
+                        false => counts.any(|n| next(at + n, spans)),
+                    }
+                }
+                _ => self.repeat(repeat, 0, at, spans, next),
+            },
+        }
+    }
+
+    // Function to match `items` one after the other from `at`.
+    fn sequence(
+        &self,
+        items: &[Node],
+        at: usize,
+        spans: &mut Spans,
+        next: &mut dyn FnMut(usize, &mut Spans) -> bool,
+    ) -> bool {
+        match items.split_first() {
+            None => next(at, spans),
+            Some((first, rest)) => self.run(first, at, spans, &mut |at, spans| {
+                self.sequence(rest, at, spans, next)
+            }),
+        }
+    }
+
+    // Function to match more rounds of `repeat` after `count` of them. A round that matches
+    // nothing only counts towards the minimum, so empty groups cannot loop forever.
+    fn repeat(
+        &self,
+        repeat: &Repeat,
+        count: usize,
+        at: usize,
+        spans: &mut Spans,
+        next: &mut dyn FnMut(usize, &mut Spans) -> bool,
+    ) -> bool {
+        let more = |spans: &mut Spans, next: &mut dyn FnMut(usize, &mut Spans) -> bool| {
+            count < repeat.max
+                && self.run(&repeat.node, at, spans, &mut |end, spans| {
+                    (end != at || count < repeat.min)
+                        && self.repeat(repeat, count + 1, end, spans, next)
+                })
+        };
+        match repeat.greedy {
+            true => more(spans, next) || (count >= repeat.min && next(at, spans)),
+            false => (count >= repeat.min && next(at, spans)) || more(spans, next),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Function to get the groups of the first match of `pattern` as strings.
+    fn groups(pattern: &str, text: &str) -> Option<Vec<Option<String>>> {
+        let regex = Regex::new(pattern).unwrap();
+        regex.captures(text.as_bytes()).map(|groups| {
+            groups
+                .into_iter()
+                .map(|group| group.map(|g| String::from_utf8_lossy(g).into_owned()))
+                .collect()
+        })
+    }
+
+    // Function to get group `n` of the first match.
+    fn group(pattern: &str, text: &str, n: usize) -> Option<String> {
+        groups(pattern, text)?.into_iter().nth(n).flatten()
+    }
+
+    #[test]
+    fn matches_literals_and_classes() {
+        assert_eq!(
+            group(
+                r"^SSH-([\d.]+)-OpenSSH_([\w.]+)",
+                "SSH-2.0-OpenSSH_9.6p1 Ubuntu",
+                2
+            )
+            .as_deref(),
+            Some("9.6p1")
+        );
+        assert_eq!(group(r"b+", "aabbbc", 0).as_deref(), Some("bbb"));
+        assert_eq!(
+            group(r"[^\r\n]+", "\r\nline\r\n", 0).as_deref(),
+            Some("line")
+        );
+        assert_eq!(group(r"[a-c-]+", "x-ab-cd", 0).as_deref(), Some("-ab-c"));
+        assert_eq!(group(r"\x41\.", "xA.", 0).as_deref(), Some("A."));
+        assert!(groups(r"^abc", "xabc").is_none());
+        assert!(groups(r"abc$", "abcx").is_none());
+        assert!(groups(r"a{2,3}", "a").is_none());
+        assert_eq!(group(r"a{2,3}", "aaaa", 0).as_deref(), Some("aaa"));
+        assert_eq!(group(r"x{a", "x{a", 0).as_deref(), Some("x{a"));
+        assert_eq!(group(r"}*x", "}}x", 0).as_deref(), Some("}}x"));
+    }
+
+    #[test]
+    fn backtracks_through_groups() {
+        assert_eq!(
+            group(
+                r"(?s)^HTTP/1\.[01] (\d+).*\r\nServer: ([^\r\n]+)",
+                "HTTP/1.1 200 OK\r\nDate: x\r\nServer: nginx/1.24.0\r\n\r\n",
+                2
+            )
+            .as_deref(),
+            Some("nginx/1.24.0")
+        );
+        assert_eq!(group(r"(?:ab)+c", "abababc", 0).as_deref(), Some("abababc"));
+        assert_eq!(group(r"(cat|dog)s?$", "hotdogs", 1).as_deref(), Some("dog"));
+        assert_eq!(group(r"<(.+?)>", "<a><b>", 1).as_deref(), Some("a"));
+        assert_eq!(group(r"(a|ab)(c|bcd)(d*)", "abcd", 3).as_deref(), Some(""));
+        // A group that took no part in the match has no text.
+        assert_eq!(groups(r"x(y)?z", "xz").unwrap()[1], None);
+        assert!(groups(r"(a*)*b", "aaaa").is_none());
+    }
+
+    #[test]
+    fn gives_up_on_deep_repeats() {
+        // A long response against a repeated group ends the search rather than the stack.
+        let long = "abcd".repeat(4096);
+        assert!(groups(r"^(?:.{4})*?$", &long).is_none());
+        assert!(groups(r"^((?:ab|cd)+)*x", &long).is_none());
+        assert_eq!(
+            group(r"^(?:.{4}){0,64}?(abcd)$", &long[..256], 1).as_deref(),
+            Some("abcd")
+        );
+    }
+
+    #[test]
+    fn applies_flags() {
+        assert_eq!(
+            group(r"(?i)server: (\w+)", "SERVER: Caddy", 1).as_deref(),
+            Some("Caddy")
+        );
+        assert!(groups(r"a.b", "a\nb").is_none());
+        assert!(groups(r"(?s)a.b", "a\nb").is_some());
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        for pattern in [
+            r"(ab", r"ab)", r"*a", r"a**", r"[abc", r"\q", r"[z-a]", r"a{3,1}", r"\x4",
+        ] {
+            assert!(Regex::new(pattern).is_err(), "{}", pattern);
+        }
+    }
+}
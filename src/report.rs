@@ -0,0 +1,746 @@
+use crate::advertised::Advert; // Import the services hosts advertised on a local network.
+use crate::containers::Container; // Import what container platform APIs answered.
+use crate::datastore::Datastore; // Import what data stores answered clients without a login.
+use crate::ftp::Ftp; // Import what anonymous FTP logins gave.
+use crate::handshakes::Tls; // Import what TLS handshakes with open ports showed.
+use crate::ics::Ics; // Import what industrial devices told about themselves.
+use crate::mqtt::Mqtt; // Import what MQTT brokers answered anonymous clients.
+use crate::nameserver::Nameserver; // Import what DNS servers told.
+use crate::ntp::Ntp; // Import what NTP servers answered.
+use crate::rdp::Rdp; // Import what RDP servers agreed to.
+use crate::scanner::{PortResult, Protocol, Reason}; // Import the probe results to report.
+use crate::services; // Import the service table to annotate open ports.
+use crate::smb::Smb; // Import what SMB servers negotiated.
+use crate::smtp::Smtp; // Import what SMTP servers offered.
+use crate::snmp::Snmp; // Import what SNMP agents gave away.
+use crate::ssh::Ssh; // Import what SSH servers offered.
+use crate::targets::{End, Route, Target}; // Import the scanned hosts and the routes traced to them.
+use crate::versions::Service; // Import the services identified on open ports.
+use crate::web::Page; // Import what open web ports answered.
+use serde::Serialize; // Import `Serialize` to write the structured report.
+use std::collections::BTreeSet; // Import `BTreeSet` to compare sorted port sets.
+use std::fs; // Import filesystem access to write the structured report.
+use std::net::IpAddr; // Import `IpAddr` to name the family that answered.
+
+// Totals shown at the end of a scan and stored in the structured report.
+#[derive(Debug, Clone, Serialize)]
+pub struct Stats {
+    pub ports_scanned: usize,
+    pub hosts_scanned: usize,
+    pub ports_excluded: usize,
+    // Probes planned (hosts times ports) and probes that finished.
+    pub probes_total: usize,
+    pub probes_completed: usize,
+    // Set when --max-duration stopped the scan before every probe was sent.
+    pub truncated: bool,
+    // Set when the scan was stopped with Ctrl-C or SIGTERM.
+    pub interrupted: bool,
+    // Filtered ports to probe again with --verify-filtered, how many were, and how many answered.
+    pub verify_total: usize,
+    pub verify_completed: usize,
+    pub verify_flipped: usize,
+}
+
+// One port in the structured report.
+#[derive(Debug, Serialize)]
+struct JsonPort<'a> {
+    port: u16,
+    protocol: &'static str,
+    state: &'static str,
+    // Why the port got its state, e.g. `syn-ack`.
+    reason: &'static str,
+    service: Option<&'static str>,
+    attempts: u32,
+    // The address that answered a port raced over IPv6 and IPv4 (--happy-eyeballs).
+    answered_by: Option<String>,
+    // Set when the port only answered the second pass of --verify-filtered.
+    second_pass: bool,
+    // What the service sent first (--banners).
+    banner: Option<&'a str>,
+    // The service and version identified (--service-version).
+    detected: Option<&'a Service>,
+    // What a TLS handshake showed (--tls).
+    tls: Option<&'a Tls>,
+    // What the port answered a `GET /` (--http).
+    http: Option<&'a Page>,
+    // What an SSH server offered for its key exchange (--ssh).
+    ssh: Option<&'a Ssh>,
+    // What an anonymous login to an FTP server gave (--ftp-anon).
+    ftp: Option<&'a Ftp>,
+    // What an SMTP server offered and whether it relays (--smtp).
+    smtp: Option<&'a Smtp>,
+    // What a DNS server told about its version and recursion (--dns).
+    dns: Option<&'a Nameserver>,
+    // What an SNMP agent gave away to the communities tried (--snmp).
+    snmp: Option<&'a Snmp>,
+    // What an SMB server negotiated and named itself (--smb).
+    smb: Option<&'a Smb>,
+    // What an RDP server agreed to and whether it enforces NLA (--rdp).
+    rdp: Option<&'a Rdp>,
+    // What an MQTT broker answered a client without credentials (--mqtt).
+    mqtt: Option<&'a Mqtt>,
+    // What a Redis, Memcached or MongoDB server answered without a login (--datastores).
+    datastore: Option<&'a Datastore>,
+    // What a Docker or Kubernetes API answered without credentials (--containers).
+    container: Option<&'a Container>,
+    // What a Modbus, S7 or DNP3 device told about itself (--ics).
+    ics: Option<&'a Ics>,
+    // What an NTP server answered, amplification queries included (--ntp).
+    ntp: Option<&'a Ntp>,
+}
+
+// One host in the structured report.
+#[derive(Debug, Serialize)]
+struct JsonHost<'a> {
+    address: String,
+    hostnames: &'a [String],
+    ptr: Option<&'a str>,
+    tags: &'a [String],
+    timed_out: bool,
+    // The IPv4 address raced against `address` with --happy-eyeballs.
+    fallback: Option<String>,
+    // The MAC address of a host on a local network that answered ARP (--ping-first).
+    mac: Option<String>,
+    // The services the host advertised on the local network (--local-discovery).
+    advertised: &'a [Advert],
+    ports: Vec<JsonPort<'a>>,
+    // The route to an open port of the host (--traceroute).
+    traceroute: Option<JsonRoute>,
+}
+
+// One hop of a traced route in the structured report.
+#[derive(Debug, Serialize)]
+struct JsonHop {
+    ttl: u8,
+    address: Option<String>,
+    rtt_ms: Option<f64>,
+}
+
+// A traced route in the structured report.
+#[derive(Debug, Serialize)]
+struct JsonRoute {
+    port: u16,
+    end: &'static str,
+    hops: Vec<JsonHop>,
+}
+
+// The whole structured report.
+#[derive(Debug, Serialize)]
+struct JsonReport<'a> {
+    hosts: Vec<JsonHost<'a>>,
+    stats: &'a Stats,
+}
+
+// Function to collect the ports kept for one host, in ascending order: the open (or
+// open|filtered) ones, and with --verbose the closed and filtered ones too.
+fn host_ports(results: &[PortResult], host: usize) -> Vec<&PortResult> {
+    let mut ports: Vec<&PortResult> = results.iter().filter(|r| r.host == host).collect();
+    ports.sort_unstable_by_key(|r| r.port);
+    ports
+}
+
+// Function to collect the open (or open|filtered) ports found on one host, in ascending order.
+fn open_ports(results: &[PortResult], host: usize) -> Vec<&PortResult> {
+    let mut ports = host_ports(results, host);
+    ports.retain(|r| r.state.reported());
+    ports
+}
+
+// Function to look up the well-known service of a port, or the name of an IP protocol.
+fn service(r: &PortResult) -> Option<&'static str> {
+    match r.protocol {
+        Protocol::Ip => services::name_by_protocol(r.port as u8),
+        protocol => services::name_by_port(r.port, protocol.name()),
+    }
+}
+
+// Function to print the target list of a list scan.
+pub fn print_target_list(hosts: &[Target]) {
+    for target in hosts {
+        println!("{}", target);
+    }
+    println!("{} host(s) would be scanned", hosts.len());
+}
+
+// Function to print the hosts a ping scan found up, with the MAC address of those on a local
+// network, out of the `total` targets given.
+pub fn print_up_hosts(hosts: &[Target], total: usize) {
+    for target in hosts {
+        match target.mac {
+            Some(mac) => println!("{} is up (MAC address {})", target, mac),
+            None => println!("{} is up", target),
+        }
+    }
+    println!("{} host(s) up, {} target(s) given", hosts.len(), total);
+}
+
+// Function to describe a service a host advertised, e.g. `mDNS: _ipp._tcp on 631 (Office Printer)`.
+fn describe_advert(advert: &Advert) -> String {
+    let mut line = format!("{}: {}", advert.via, advert.service);
+    if let Some(port) = advert.port {
+        line.push_str(&format!(" on {}", port));
+    }
+    if let Some(name) = &advert.name {
+        line.push_str(&format!(" ({})", name));
+    }
+    line
+}
+
+// Function to describe why a port got its state, counting the retries of silent ports.
+fn reason(r: &PortResult) -> String {
+    match (r.reason, r.attempts) {
+        (Reason::NoResponse, attempts) if attempts > 1 => {
+            format!("no-response after {} retries", attempts - 1)
+        }
+        (reason, _) => reason.name().to_string(),
+    }
+}
+
+// Function to print the ports found grouped per host, skipping hosts where nothing was found
+// and that advertised nothing.
+// Hosts abandoned by --host-timeout are always listed, as their results are incomplete.
+// With `reasons`, each port says why it got its state.
+pub fn print_hosts(hosts: &[Target], results: &[PortResult], show_services: bool, reasons: bool) {
+    for (host, target) in hosts.iter().enumerate() {
+        let ports = host_ports(results, host);
+        if ports.is_empty() && target.advertised.is_empty() {
+            if target.timed_out {
+                println!("{}: timed out", target);
+            }
+            continue;
+        }
+
+        println!("{}:", target); // Display the host, with its resolved IP for hostnames.
+        if let Some(mac) = target.mac {
+            println!("MAC address {}", mac);
+        }
+        for advert in &target.advertised {
+            println!("advertised via {}", describe_advert(advert));
+        }
+        for r in ports {
+            // Display the open port, with its service name if asked for and known,
+            // and the family that answered when both were raced.
+            let mut notes = vec![];
+            if reasons {
+                notes.push(reason(r));
+            }
+            // An identified service replaces the well-known name, and protocol numbers always get
+            // their name, as they mean little without it.
+            if let Some(detected) = &r.findings.detected {
+                notes.push(detected.describe());
+            } else if let Some(name) =
+                service(r).filter(|_| show_services || r.protocol == Protocol::Ip)
+            {
+                notes.push(name.to_string());
+            }
+            match r.answered {
+                Some(IpAddr::V4(_)) => notes.push("over IPv4".to_string()),
+                Some(IpAddr::V6(_)) => notes.push("over IPv6".to_string()),
+                None => {}
+            }
+            if r.second_pass {
+                notes.push("only on the second pass".to_string());
+            }
+            // TCP ports are shown bare, other protocols get a suffix such as `53/udp`.
+            let port = match r.protocol {
+                Protocol::Tcp => r.port.to_string(),
+                Protocol::Ip => format!("protocol {}", r.port),
+                protocol => format!("{}/{}", r.port, protocol.name()),
+            };
+            let mut line = format!("{} is {}", port, r.state.name());
+            if !notes.is_empty() {
+                line.push_str(&format!(" ({})", notes.join(", ")));
+            }
+            if let Some(banner) = &r.findings.banner {
+                line.push_str(&format!(" \u{2014} {}", banner));
+            }
+            println!("{}", line);
+            if let Some(page) = &r.findings.http {
+                print_page(page);
+            }
+            if let Some(tls) = &r.findings.tls {
+                print_tls(tls);
+            }
+            if let Some(ssh) = &r.findings.ssh {
+                print_ssh(ssh);
+            }
+            if let Some(ftp) = &r.findings.ftp {
+                let verdict = if ftp.anonymous { "allowed" } else { "refused" };
+                println!("  FTP anonymous login: {} ({})", verdict, ftp.reply);
+            }
+            if let Some(smtp) = &r.findings.smtp {
+                print_smtp(smtp);
+            }
+            if let Some(nameserver) = &r.findings.dns {
+                print_nameserver(nameserver);
+            }
+            if let Some(snmp) = &r.findings.snmp {
+                println!(
+                    "  WARNING: SNMP readable with the community {}",
+                    snmp.communities.join(", ")
+                );
+                if let Some(name) = &snmp.name {
+                    println!("  sysName: {}", name);
+                }
+                if let Some(descr) = &snmp.descr {
+                    println!("  sysDescr: {}", descr);
+                }
+            }
+            if let Some(smb) = &r.findings.smb {
+                print_smb(smb);
+            }
+            if let Some(rdp) = &r.findings.rdp {
+                println!("  RDP security: {}", rdp.security);
+                if !rdp.nla_required {
+                    println!("  WARNING: Network Level Authentication is not required");
+                }
+            }
+            if let Some(mqtt) = &r.findings.mqtt {
+                let over = if mqtt.tls { " over TLS" } else { "" };
+                match mqtt.anonymous {
+                    true => println!(
+                        "  WARNING: MQTT broker accepts clients without credentials{} ({})",
+                        over, mqtt.reply
+                    ),
+                    false => println!("  MQTT anonymous connect{}: refused ({})", over, mqtt.reply),
+                }
+            }
+            if let Some(datastore) = &r.findings.datastore {
+                let mut name = datastore.service.to_string();
+                if let Some(version) = &datastore.version {
+                    name.push_str(&format!(" {}", version));
+                }
+                match datastore.unauthenticated {
+                    true => println!(
+                        "  WARNING: {} answers without authentication ({})",
+                        name, datastore.reply
+                    ),
+                    false => println!("  {} asks for authentication ({})", name, datastore.reply),
+                }
+            }
+            if let Some(container) = &r.findings.container {
+                let mut name = container.api.to_string();
+                if let Some(version) = &container.version {
+                    name.push_str(&format!(" {}", version));
+                }
+                if container.tls {
+                    name.push_str(" over TLS");
+                }
+                match container.unauthenticated {
+                    true => println!(
+                        "  WARNING: {} answers without authentication ({})",
+                        name, container.reply
+                    ),
+                    false => println!("  {} asks for authentication ({})", name, container.reply),
+                }
+            }
+            if let Some(ics) = &r.findings.ics {
+                println!("  {} device", ics.protocol);
+                for field in &ics.identification {
+                    println!("  {}: {}", field.name, field.value);
+                }
+            }
+            if let Some(ntp) = &r.findings.ntp {
+                print_ntp(ntp);
+            }
+        }
+        if target.timed_out {
+            println!("timed out, other ports were not probed");
+        }
+        if let Some(route) = &target.route {
+            print_route(route);
+        }
+    }
+}
+
+// Function to print what a port answered a `GET /`, below the port, e.g.
+// `HTTP 302 → /login (Apache-Coyote/1.1)`.
+fn print_page(page: &Page) {
+    let mut line = format!(
+        "  {} {}",
+        if page.https { "HTTPS" } else { "HTTP" },
+        page.status
+    );
+    if let Some(location) = &page.location {
+        line.push_str(&format!(" \u{2192} {}", location));
+    }
+    if let Some(title) = &page.title {
+        line.push_str(&format!(" \"{}\"", title));
+    }
+    if let Some(server) = &page.server {
+        line.push_str(&format!(" ({})", server));
+    }
+    println!("{}", line);
+    match page.issues.as_deref() {
+        Some([]) => println!("  security headers: all set"),
+        Some(issues) => println!("  security headers: {}", issues.join(", ")),
+        None => {}
+    }
+    if let Some(paths) = page.websockets.as_deref().filter(|paths| !paths.is_empty()) {
+        println!("  WebSocket: {}", paths.join(", "));
+    }
+}
+
+// Function to print what a DNS server told, below the port.
+fn print_nameserver(nameserver: &Nameserver) {
+    if let Some(version) = &nameserver.version {
+        println!("  DNS version.bind: {}", version);
+    }
+    let code = nameserver.recursion.as_deref().unwrap_or("");
+    match nameserver.open_resolver {
+        Some(true) => println!(
+            "  WARNING: open resolver, resolved an outside name ({})",
+            code
+        ),
+        Some(false) => println!("  not an open resolver ({})", code),
+        None => println!("  recursion test: no answer"),
+    }
+}
+
+// Function to print what an SMTP server offered and the verdict of the relay test, below the
+// port.
+fn print_smtp(smtp: &Smtp) {
+    println!("  SMTP {}", smtp.greeting);
+    if !smtp.capabilities.is_empty() {
+        println!("  capabilities: {}", smtp.capabilities.join(", "));
+    }
+    if !smtp.starttls {
+        println!("  WARNING: STARTTLS is not offered");
+    }
+    let reply = smtp.relay_reply.as_deref().unwrap_or("no answer");
+    match smtp.open_relay {
+        Some(true) => println!(
+            "  WARNING: open relay, accepted mail between outside domains ({})",
+            reply
+        ),
+        Some(false) => println!("  relay: refused ({})", reply),
+        None => println!("  relay: inconclusive ({})", reply),
+    }
+}
+
+// Function to print what an SMB server negotiated and the names it gave, below the port.
+fn print_smb(smb: &Smb) {
+    let mut line = format!(
+        "  SMB dialect: {}",
+        smb.dialect.as_deref().unwrap_or("SMB1 only")
+    );
+    if let Some(signing) = &smb.signing {
+        line.push_str(&format!(", signing {}", signing));
+    }
+    println!("{}", line);
+    let names = [
+        ("NetBIOS name", &smb.netbios_name),
+        ("NetBIOS domain", &smb.netbios_domain),
+        ("DNS name", &smb.dns_name),
+        ("DNS domain", &smb.dns_domain),
+        ("DNS forest", &smb.dns_forest),
+        ("OS version", &smb.os_version),
+    ];
+    for (name, value) in names {
+        if let Some(value) = value {
+            println!("  {}: {}", name, value);
+        }
+    }
+    if smb.smb1 {
+        println!("  WARNING: SMB1 is enabled");
+    }
+    if smb.signing.as_deref() != Some("required") {
+        println!("  WARNING: signing is not required");
+    }
+}
+
+// Function to print what an NTP server answered, below the port.
+fn print_ntp(ntp: &Ntp) {
+    if let (Some(version), Some(stratum)) = (ntp.version, ntp.stratum) {
+        println!("  NTP version {}, stratum {}", version, stratum);
+    }
+    if let Some(daemon) = &ntp.daemon {
+        println!("  NTP daemon: {}", daemon);
+    }
+    if let Some(system) = &ntp.system {
+        println!("  system: {}", system);
+    }
+    if let Some(bytes) = ntp.control_bytes {
+        println!(
+            "  WARNING: mode 6 queries are answered ({} bytes for a 12-byte request)",
+            bytes
+        );
+    }
+    if let Some(bytes) = ntp.monlist_bytes {
+        println!(
+            "  WARNING: monlist (mode 7) is answered ({} bytes for a 48-byte request)",
+            bytes
+        );
+    }
+}
+
+// Function to print what an SSH server offered, below the port.
+fn print_ssh(ssh: &Ssh) {
+    match &ssh.comments {
+        Some(comments) => println!("  SSH {} {} {}", ssh.protocol, ssh.software, comments),
+        None => println!("  SSH {} {}", ssh.protocol, ssh.software),
+    }
+    let lists = [
+        ("key exchange", &ssh.kex),
+        ("host keys", &ssh.host_keys),
+        ("ciphers", &ssh.ciphers),
+        ("MACs", &ssh.macs),
+        ("compression", &ssh.compression),
+    ];
+    for (name, list) in lists.into_iter().filter(|(_, list)| !list.is_empty()) {
+        println!("  {}: {}", name, list.join(", "));
+    }
+    if !ssh.deprecated.is_empty() {
+        println!(
+            "  WARNING: offers the deprecated {}",
+            ssh.deprecated.join(", ")
+        );
+    }
+}
+
+// Function to print what a TLS handshake with a port showed, below the port.
+fn print_tls(tls: &Tls) {
+    match &tls.sni {
+        Some(name) => println!("  {} with {} for {}", tls.version, tls.cipher, name),
+        None => println!("  {} with {}", tls.version, tls.cipher),
+    }
+    if let Some(protocol) = &tls.alpn {
+        println!("  ALPN: {}", protocol);
+    }
+    if let Some(ja3s) = &tls.ja3s {
+        println!("  JA3S: {} ({})", ja3s.hash, ja3s.string);
+    }
+    if !tls.versions.is_empty() {
+        println!("  accepts: {}", tls.versions.join(", "));
+    }
+    let deprecated = tls.deprecated();
+    if !deprecated.is_empty() {
+        println!(
+            "  WARNING: accepts the deprecated {}",
+            deprecated.join(" and ")
+        );
+    }
+    let mut version = None;
+    for suite in &tls.ciphers {
+        if version != Some(&suite.version) {
+            println!("  {} cipher suites:", suite.version);
+            version = Some(&suite.version);
+        }
+        match suite.weak.is_empty() {
+            true => println!("    {}", suite.name),
+            false => println!("    {} (weak: {})", suite.name, suite.weak.join(", ")),
+        }
+    }
+    let weak = tls.ciphers.iter().filter(|s| !s.weak.is_empty()).count();
+    if weak > 0 {
+        println!("  WARNING: accepts {} weak cipher suite(s)", weak);
+    }
+    if let Some(certificate) = &tls.certificate {
+        println!("  certificate: {}", certificate.subject);
+        match certificate.self_signed {
+            true => println!("  issuer: the subject itself"),
+            false => println!("  issuer: {}", certificate.issuer),
+        }
+        println!(
+            "  valid: {} to {}",
+            certificate.not_before.date(),
+            certificate.not_after.date()
+        );
+        match tls.expiring {
+            Some(left) if left < 0 => {
+                println!("  WARNING: the certificate expired {} day(s) ago", -left)
+            }
+            Some(0) => println!("  WARNING: the certificate expires today"),
+            Some(left) => println!("  WARNING: the certificate expires in {} day(s)", left),
+            None => {}
+        }
+    }
+    match &tls.untrusted {
+        Some(reason) => println!("  not trusted: {}", reason),
+        None => println!("  trusted"),
+    }
+}
+
+// Function to print a traced route hop by hop, with how it ends.
+fn print_route(route: &Route) {
+    println!("Route to port {}:", route.port);
+    for hop in &route.hops {
+        match (hop.addr, hop.rtt) {
+            (Some(addr), Some(rtt)) => println!(
+                "{:>3}  {}  {:.1} ms",
+                hop.ttl,
+                addr,
+                rtt.as_secs_f64() * 1000.0
+            ),
+            _ => println!("{:>3}  *", hop.ttl),
+        }
+    }
+    let last = route.hops.last();
+    match (route.end, last.and_then(|hop| hop.addr)) {
+        (End::Reached, _) => println!("reached the host in {} hop(s)", route.hops.len()),
+        (End::Unreachable, Some(addr)) => {
+            println!(
+                "{} reported the host unreachable, a filter rejects the probes",
+                addr
+            )
+        }
+        (_, _) => match last {
+            Some(hop) => println!(
+                "no answer beyond hop {}, a filter drops the probes",
+                hop.ttl
+            ),
+            None => println!("no hop answered"),
+        },
+    }
+}
+
+// Function to compare the IPv4 and IPv6 results of hostnames that resolved to both families,
+// pointing out ports that are only reachable over one of them.
+pub fn print_dual_stack(hosts: &[Target], results: &[PortResult]) {
+    // Hostnames in the order they were first seen.
+    let mut names: Vec<&String> = vec![];
+    for name in hosts.iter().flat_map(|t| t.hostnames.iter()) {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    for name in names {
+        // Union of the open ports over all addresses of one family.
+        let family_ports = |v4: bool| -> Option<BTreeSet<u16>> {
+            let indices: Vec<usize> = hosts
+                .iter()
+                .enumerate()
+                .filter(|(_, t)| t.hostnames.contains(name) && t.addr.is_ipv4() == v4)
+                .map(|(i, _)| i)
+                .collect();
+            if indices.is_empty() {
+                return None;
+            }
+            Some(
+                indices
+                    .iter()
+                    .flat_map(|&i| open_ports(results, i))
+                    .map(|r| r.port)
+                    .collect(),
+            )
+        };
+
+        // Only hostnames with both A and AAAA records can be compared.
+        let (Some(v4), Some(v6)) = (family_ports(true), family_ports(false)) else {
+            continue;
+        };
+        for port in v4.difference(&v6) {
+            println!("{}: {} is open on IPv4 only", name, port);
+        }
+        for port in v6.difference(&v4) {
+            println!("{}: {} is open on IPv6 only", name, port);
+        }
+    }
+}
+
+// Function to print the scan statistics.
+pub fn print_stats(stats: &Stats) {
+    println!(
+        "Scanned {} port(s) on {} host(s), {} port(s) excluded",
+        stats.ports_scanned, stats.hosts_scanned, stats.ports_excluded
+    );
+    if stats.verify_total > 0 {
+        println!(
+            "Second pass probed {} of {} filtered port(s), {} answered",
+            stats.verify_completed, stats.verify_total, stats.verify_flipped
+        );
+    }
+    let reason = match (stats.truncated, stats.interrupted) {
+        (true, _) => "truncated by --max-duration",
+        (_, true) => "interrupted",
+        _ => return,
+    };
+    // Show how much of the target range was covered before the scan stopped.
+    println!(
+        "Scan {} after {} of {} probes ({:.1}%), results are incomplete",
+        reason,
+        stats.probes_completed,
+        stats.probes_total,
+        percent(stats.probes_completed, stats.probes_total)
+    );
+}
+
+// Function to compute how many of the probes are done, in percent.
+pub fn percent(completed: usize, total: usize) -> f64 {
+    match total {
+        0 => 100.0,
+        total => completed as f64 * 100.0 / total as f64,
+    }
+}
+
+// Function to write the structured (JSON) report, listing every scanned host with its tags.
+pub fn write_json(
+    path: &str,
+    hosts: &[Target],
+    results: &[PortResult],
+    stats: &Stats,
+) -> Result<(), String> {
+    let report = JsonReport {
+        hosts: hosts
+            .iter()
+            .enumerate()
+            .map(|(i, target)| JsonHost {
+                address: target.address(),
+                hostnames: &target.hostnames,
+                ptr: target.ptr.as_deref(),
+                tags: &target.tags,
+                timed_out: target.timed_out,
+                fallback: target.fallback.map(|addr| addr.to_string()),
+                mac: target.mac.map(|mac| mac.to_string()),
+                advertised: &target.advertised,
+                ports: host_ports(results, i)
+                    .into_iter()
+                    .map(|r| JsonPort {
+                        port: r.port,
+                        protocol: r.protocol.name(),
+                        state: r.state.name(),
+                        reason: r.reason.name(),
+                        service: service(r),
+                        attempts: r.attempts,
+                        answered_by: r.answered.map(|addr| addr.to_string()),
+                        second_pass: r.second_pass,
+                        banner: r.findings.banner.as_deref(),
+                        detected: r.findings.detected.as_ref(),
+                        tls: r.findings.tls.as_ref(),
+                        http: r.findings.http.as_ref(),
+                        ssh: r.findings.ssh.as_ref(),
+                        ftp: r.findings.ftp.as_ref(),
+                        smtp: r.findings.smtp.as_ref(),
+                        dns: r.findings.dns.as_ref(),
+                        snmp: r.findings.snmp.as_ref(),
+                        smb: r.findings.smb.as_ref(),
+                        rdp: r.findings.rdp.as_ref(),
+                        mqtt: r.findings.mqtt.as_ref(),
+                        datastore: r.findings.datastore.as_ref(),
+                        container: r.findings.container.as_ref(),
+                        ics: r.findings.ics.as_ref(),
+                        ntp: r.findings.ntp.as_ref(),
+                    })
+                    .collect(),
+                traceroute: target.route.as_ref().map(|route| JsonRoute {
+                    port: route.port,
+                    end: route.end.name(),
+                    hops: route
+                        .hops
+                        .iter()
+                        .map(|hop| JsonHop {
+                            ttl: hop.ttl,
+                            address: hop.addr.map(|addr| addr.to_string()),
+                            rtt_ms: hop.rtt.map(|rtt| rtt.as_secs_f64() * 1000.0),
+                        })
+                        .collect(),
+                }),
+            })
+            .collect(),
+        stats,
+    };
+
+    let json = serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?;
+    fs::write(path, json + "\n").map_err(|e| format!("Failed to write {}: {}", path, e))
+}
@@ -0,0 +1,350 @@
+use crate::dns; // Import the DNS wire-format client.
+use crate::{http, tls}; // Import the HTTP and TLS helpers for DNS over HTTPS.
+#[cfg(unix)]
+use std::ffi::{CStr, CString}; // Import C string types to talk to getaddrinfo and getnameinfo.
+use std::fmt; // Import formatting traits to display lookup errors.
+use std::io; // Import I/O types for query errors.
+#[cfg(not(unix))]
+use std::net::ToSocketAddrs; // Import the resolver of the standard library for systems without getaddrinfo.
+use std::net::{IpAddr, SocketAddr}; // Import address types for resolution results.
+#[cfg(unix)]
+use std::net::{Ipv4Addr, Ipv6Addr}; // Import the address types getaddrinfo results are read into.
+use tokio::net::{lookup_host, TcpStream}; // Import the system resolver and TCP streams.
+
+// getaddrinfo codes meaning the name has no addresses, as opposed to the resolver failing.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const GAI_NOT_FOUND: [libc::c_int; 2] = [libc::EAI_NONAME, libc::EAI_NODATA];
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+const GAI_NOT_FOUND: [libc::c_int; 1] = [libc::EAI_NONAME];
+
+// Why a hostname could not be resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LookupError {
+    // The name does not exist or has no addresses, which is an answer about that name only.
+    NotFound(String),
+    // The resolver itself failed (timeout, unreachable server, TLS error, SERVFAIL, ...),
+    // so no answer at all was obtained.
+    Resolver(String),
+}
+
+impl fmt::Display for LookupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LookupError::NotFound(e) | LookupError::Resolver(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+// Where hostnames are resolved. New backends slot in here without touching target expansion or scanning.
+#[derive(Debug, Clone)]
+pub enum Resolver {
+    // The operating system resolver (getaddrinfo).
+    System,
+    // A specific DNS server queried directly over UDP/TCP port 53.
+    Server(SocketAddr),
+    // A DNS-over-HTTPS endpoint (RFC 8484), so lookups never travel as cleartext UDP,
+    // together with the address its name was bootstrapped to.
+    Doh(http::Url, SocketAddr),
+}
+
+impl Resolver {
+    // Function to build a resolver from a `--dns-server` value: an IP, optionally with a port.
+    pub fn server(spec: &str) -> Result<Resolver, String> {
+        if let Ok(addr) = spec.parse::<SocketAddr>() {
+            return Ok(Resolver::Server(addr));
+        }
+        spec.parse::<IpAddr>()
+            .map(|ip| Resolver::Server(SocketAddr::new(ip, 53)))
+            .map_err(|_| format!("{} is not a valid DNS server address", spec))
+    }
+
+    // Function to build a resolver from a `--doh` URL.
+    // The endpoint is resolved and sent one probe query up front, so an unreachable or broken
+    // server is reported once here instead of as a failure for every hostname.
+    pub async fn doh(spec: &str) -> Result<Resolver, String> {
+        let url = http::parse_url(spec)?;
+        if !url.https {
+            return Err("--doh needs an https:// URL".to_string());
+        }
+
+        // The DoH server's own name is bootstrapped through the system resolver.
+        let addr = lookup_host((url.host.as_str(), url.port))
+            .await
+            .map_err(|e| format!("Failed to resolve the DoH server {}: {}", url.host, e))?
+            .next()
+            .ok_or_else(|| format!("The DoH server {} has no address", url.host))?;
+
+        // Ask for the root zone, which every recursive resolver can answer.
+        doh_query(&url, addr, ".", dns::TYPE_A)
+            .await
+            .map_err(|e| format!("The DoH server {} is not usable: {}", spec, e))?;
+        Ok(Resolver::Doh(url, addr))
+    }
+
+    // Function to send one DNS query through the wire-format backends (plain DNS or DoH).
+    async fn query(&self, name: &str, rtype: u16) -> io::Result<dns::Message> {
+        match self {
+            Resolver::Server(server) => dns::query(*server, name, rtype, dns::CLASS_IN).await,
+            Resolver::Doh(url, addr) => doh_query(url, *addr, name, rtype).await,
+            Resolver::System => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the system resolver has no wire-format queries",
+            )),
+        }
+    }
+
+    // Function to resolve a hostname into its addresses, each with its IPv6 scope ID (0 if none).
+    pub async fn lookup(&self, name: &str) -> Result<Vec<(IpAddr, u32)>, LookupError> {
+        let mut addrs: Vec<(IpAddr, u32)> = vec![];
+        match self {
+            Resolver::System => {
+                // getaddrinfo blocks, so keep it off the async worker threads.
+                let owned = name.to_string();
+                addrs = tokio::task::spawn_blocking(move || system_lookup(&owned))
+                    .await
+                    .map_err(|e| LookupError::Resolver(e.to_string()))??;
+            }
+            Resolver::Server(_) | Resolver::Doh(..) => {
+                // Ask for both families at once so dual-stack hosts are found.
+                let (v4, v6) = tokio::join!(
+                    self.query(name, dns::TYPE_A),
+                    self.query(name, dns::TYPE_AAAA)
+                );
+                // Keep whatever one family returned even if the other query failed,
+                // e.g. a server that drops AAAA queries or answers them with SERVFAIL.
+                let mut failure = None;
+                let mut nxdomain = false;
+                for response in [v4, v6] {
+                    let message = match response {
+                        Ok(message) => message,
+                        Err(e) => {
+                            failure = Some(e.to_string());
+                            continue;
+                        }
+                    };
+                    match message.rcode() {
+                        0 => {}
+                        3 => {
+                            nxdomain = true;
+                            continue;
+                        }
+                        rcode => {
+                            failure = Some(format!("server answered with {}", rcode_name(rcode)));
+                            continue;
+                        }
+                    }
+                    // Recursive servers include the CNAME chain; only the addresses matter here.
+                    for record in message.answers {
+                        if let dns::RData::Ip(ip) = record.data {
+                            addrs.push((ip, 0));
+                        }
+                    }
+                }
+                if addrs.is_empty() {
+                    if let Some(e) = failure {
+                        return Err(LookupError::Resolver(format!(
+                            "Failed to resolve {}: {}",
+                            name, e
+                        )));
+                    }
+                    if nxdomain {
+                        return Err(LookupError::NotFound(format!(
+                            "Failed to resolve {}: no such domain",
+                            name
+                        )));
+                    }
+                }
+            }
+        }
+
+        if addrs.is_empty() {
+            return Err(LookupError::NotFound(format!(
+                "{} did not resolve to any address",
+                name
+            )));
+        }
+
+        // A hostname can come back with the same IP several times (once per socket type).
+        let mut unique: Vec<(IpAddr, u32)> = vec![];
+        for addr in addrs {
+            if !unique.contains(&addr) {
+                unique.push(addr);
+            }
+        }
+        Ok(unique)
+    }
+
+    // Function to look up the name registered for an address (PTR record), if any.
+    pub async fn reverse(&self, ip: IpAddr) -> Option<String> {
+        match self {
+            Resolver::System => {
+                // getnameinfo blocks, so keep it off the async worker threads.
+                tokio::task::spawn_blocking(move || system_reverse(ip))
+                    .await
+                    .ok()
+                    .flatten()
+            }
+            Resolver::Server(_) | Resolver::Doh(..) => {
+                let message = self
+                    .query(&dns::reverse_name(ip), dns::TYPE_PTR)
+                    .await
+                    .ok()?;
+                message
+                    .answers
+                    .into_iter()
+                    .find_map(|record| match record.data {
+                        dns::RData::Name(name) => Some(name),
+                        _ => None,
+                    })
+            }
+        }
+    }
+}
+
+// Function to send a DNS query as an RFC 8484 POST to a DoH endpoint at `addr`.
+// Every query opens its own TLS connection on purpose: lookups run in parallel, and the
+// HTTP client sends one request per connection (`Connection: close`) instead of pooling.
+async fn doh_query(
+    url: &http::Url,
+    addr: SocketAddr,
+    name: &str,
+    rtype: u16,
+) -> io::Result<dns::Message> {
+    // DoH uses ID 0 so responses stay cacheable; HTTPS already ties answers to the request.
+    let query = dns::build_query(0, name, rtype, dns::CLASS_IN, true);
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nAccept: application/dns-message\r\n\
+         Content-Type: application/dns-message\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        url.path,
+        url.authority(),
+        query.len()
+    )
+    .into_bytes();
+    request.extend_from_slice(&query);
+
+    let tcp = TcpStream::connect(addr).await?;
+    let mut stream = tls::connect_verified(tcp, &url.host).await?;
+    let response = http::send(&mut stream, &request).await?;
+    if response.status != 200 {
+        return Err(io::Error::other(format!(
+            "DoH server answered with HTTP {}",
+            response.status
+        )));
+    }
+    dns::parse_message(&response.body)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed DNS response"))
+}
+
+// Function to name a DNS response code in error messages.
+fn rcode_name(rcode: u8) -> String {
+    match rcode {
+        1 => "FORMERR".to_string(),
+        2 => "SERVFAIL".to_string(),
+        4 => "NOTIMP".to_string(),
+        5 => "REFUSED".to_string(),
+        rcode => format!("response code {}", rcode),
+    }
+}
+
+// Function to resolve a name through getaddrinfo, keeping the scope ID of link-local results.
+#[cfg(unix)]
+fn system_lookup(name: &str) -> Result<Vec<(IpAddr, u32)>, LookupError> {
+    let c_name = CString::new(name)
+        .map_err(|_| LookupError::NotFound(format!("{} is not a valid hostname", name)))?;
+    // SAFETY: an all-zero `addrinfo` is a valid set of hints (any family, any protocol).
+    let mut hints: libc::addrinfo = unsafe { std::mem::zeroed() };
+    // One entry per address instead of one per socket type.
+    hints.ai_socktype = libc::SOCK_STREAM;
+    let mut list: *mut libc::addrinfo = std::ptr::null_mut();
+    // SAFETY: `c_name` and `hints` outlive the call and `list` receives the result list.
+    let rc = unsafe { libc::getaddrinfo(c_name.as_ptr(), std::ptr::null(), &hints, &mut list) };
+    if rc != 0 {
+        let detail = if rc == libc::EAI_SYSTEM {
+            io::Error::last_os_error().to_string()
+        } else {
+            // SAFETY: gai_strerror returns a static NUL-terminated string.
+            unsafe { CStr::from_ptr(libc::gai_strerror(rc)) }
+                .to_string_lossy()
+                .into_owned()
+        };
+        let message = format!("Failed to resolve {}: {}", name, detail);
+        return Err(if GAI_NOT_FOUND.contains(&rc) {
+            LookupError::NotFound(message)
+        } else {
+            LookupError::Resolver(message)
+        });
+    }
+
+    let mut addrs = vec![];
+    let mut entry = list;
+    while !entry.is_null() {
+        // SAFETY: `entry` is a node of the list returned by getaddrinfo, freed only below.
+        let info = unsafe { &*entry };
+        // SAFETY: `ai_addr` points to a socket address of the family given in `ai_family`.
+        match info.ai_family {
+            libc::AF_INET if !info.ai_addr.is_null() => {
+                let v4 = unsafe { &*(info.ai_addr as *const libc::sockaddr_in) };
+                let ip = Ipv4Addr::from(u32::from_be(v4.sin_addr.s_addr));
+                addrs.push((IpAddr::V4(ip), 0));
+            }
+            libc::AF_INET6 if !info.ai_addr.is_null() => {
+                let v6 = unsafe { &*(info.ai_addr as *const libc::sockaddr_in6) };
+                let ip = Ipv6Addr::from(v6.sin6_addr.s6_addr);
+                // Link-local results carry the interface they were resolved on.
+                addrs.push((IpAddr::V6(ip), v6.sin6_scope_id));
+            }
+            _ => {}
+        }
+        entry = info.ai_next;
+    }
+    // SAFETY: `list` came from a successful getaddrinfo call and is not used afterwards.
+    unsafe { libc::freeaddrinfo(list) };
+    Ok(addrs)
+}
+
+// Function to ask the system resolver for the name of an address.
+#[cfg(unix)]
+fn system_reverse(ip: IpAddr) -> Option<String> {
+    let addr = socket2::SockAddr::from(SocketAddr::new(ip, 0));
+    let mut host = [0 as libc::c_char; libc::NI_MAXHOST as usize];
+    // SAFETY: `addr` points to a valid sockaddr of the given length and `host` is NI_MAXHOST bytes long.
+    let rc = unsafe {
+        libc::getnameinfo(
+            addr.as_ptr(),
+            addr.len(),
+            host.as_mut_ptr(),
+            host.len() as libc::socklen_t,
+            std::ptr::null_mut(),
+            0,
+            libc::NI_NAMEREQD,
+        )
+    };
+    if rc != 0 {
+        return None;
+    }
+    // SAFETY: on success getnameinfo wrote a NUL-terminated string into `host`.
+    let name = unsafe { CStr::from_ptr(host.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+// Function to resolve a name through the resolver of the standard library on systems without
+// getaddrinfo. It does not tell a name without addresses from a failing resolver, so every
+// failure counts as the former.
+#[cfg(not(unix))]
+fn system_lookup(name: &str) -> Result<Vec<(IpAddr, u32)>, LookupError> {
+    let addrs = (name, 0)
+        .to_socket_addrs()
+        .map_err(|e| LookupError::NotFound(format!("Failed to resolve {}: {}", name, e)))?;
+    Ok(addrs
+        .map(|addr| match addr {
+            SocketAddr::V4(v4) => (IpAddr::V4(*v4.ip()), 0),
+            SocketAddr::V6(v6) => (IpAddr::V6(*v6.ip()), v6.scope_id()),
+        })
+        .collect())
+}
+
+// Function to ask the system resolver for the name of an address, which only getaddrinfo
+// systems answer here.
+#[cfg(not(unix))]
+fn system_reverse(_ip: IpAddr) -> Option<String> {
+    None
+}
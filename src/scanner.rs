@@ -0,0 +1,1413 @@
+use crate::checkpoint::Progress; // Import the progress of a resumed scan.
+use crate::containers::Container; // Import what container platform APIs answered.
+use crate::datastore::Datastore; // Import what data stores answered clients without a login.
+use crate::ftp::Ftp; // Import what anonymous FTP logins gave.
+use crate::handshakes::Tls; // Import what TLS handshakes with open ports showed.
+use crate::ics::Ics; // Import what industrial devices told about themselves.
+#[cfg(target_os = "linux")]
+use crate::idle::Zombie; // Import the zombie of idle scans.
+use crate::mqtt::Mqtt; // Import what MQTT brokers answered anonymous clients.
+use crate::nameserver::Nameserver; // Import what DNS servers told.
+use crate::ntp::Ntp; // Import what NTP servers answered.
+#[cfg(target_os = "linux")]
+use crate::protocols::Prober; // Import the prober of IP protocol scans.
+#[cfg(target_os = "linux")]
+use crate::raw::{self, Raw, Reply}; // Import raw sockets for half-open and stealth probes.
+use crate::rdp::Rdp; // Import what RDP servers agreed to.
+use crate::smb::Smb; // Import what SMB servers negotiated.
+use crate::smtp::Smtp; // Import what SMTP servers offered.
+use crate::snmp::Snmp; // Import what SNMP agents gave away.
+use crate::ssh::Ssh; // Import what SSH servers offered.
+use crate::targets::Target; // Import the hosts to probe.
+use crate::udp; // Import the payloads of UDP probes.
+use crate::versions::Service; // Import the services identified on open ports.
+use crate::web::Page; // Import what open web ports answered.
+use futures::stream::{self, Stream, StreamExt}; // Import streams to run a bounded number of probes at once.
+use rand::Rng; // Import `Rng` to draw the random delays of --jitter.
+use serde::{Deserialize, Serialize}; // Import serde to store the protocol in checkpoints.
+use socket2::{Domain, Socket, Type}; // Import socket2 to bind probe sockets before connecting.
+use std::collections::{HashMap, VecDeque}; // Import `HashMap` for per-port timeouts and `VecDeque` for host turns.
+use std::future::Future; // Import `Future` for the checks run on the ports after the scan.
+use std::io; // Import I/O error kinds to tell refusals from other failures.
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr}; // Import socket address types for the probed ports.
+use std::pin::pin; // Import `pin!` to race two connects in place.
+use std::sync::{Arc, Mutex}; // Import shared state for round-trip estimates and the socket throttle.
+use std::time::Duration; // Import `Duration` for connection timeouts.
+use tokio::io::Interest; // Import readiness interests to wait for the errors of UDP probes.
+use tokio::net::{TcpSocket, UdpSocket}; // Import asynchronous sockets for connect and UDP probes.
+use tokio::sync::{watch, Semaphore, SemaphorePermit}; // Import `Semaphore` to cap the sockets open at once and `watch` to pause probing.
+use tokio::time::{sleep, sleep_until, timeout, timeout_at, Instant}; // Import timers to give up on silent ports and pace probes.
+
+// The transport protocol of a scan.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    #[default]
+    Tcp,
+    Udp,
+    Sctp,
+    // IP protocols rather than ports (--ip-protocols); the port number is the protocol number.
+    Ip,
+}
+
+impl Protocol {
+    // Function to get the name of the protocol as used in service tables, e.g. `tcp`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+            Protocol::Sctp => "sctp",
+            Protocol::Ip => "ip",
+        }
+    }
+}
+
+// How probes are sent.
+#[derive(Debug)]
+pub enum Engine {
+    // Regular sockets: a full connect (or SCTP association), or a UDP datagram.
+    Sockets,
+    // Packets crafted on raw sockets, of the given scan type.
+    #[cfg(target_os = "linux")]
+    Raw(Raw, RawScan),
+    // IPv4 packets of each protocol, to find the protocols a host speaks (--ip-protocols).
+    #[cfg(target_os = "linux")]
+    Protocols(Prober),
+    // SYNs sent in the name of a zombie, read off its IP IDs (--idle-zombie).
+    #[cfg(target_os = "linux")]
+    Idle(Zombie),
+}
+
+// The scan types sent from raw sockets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawScan {
+    // A SYN, answered like a connect (--syn).
+    Syn,
+    // Segments that open ports silently drop and closed ones answer with a RST, as RFC 793
+    // asks: a lone FIN (--fin), no flags at all (--null), or FIN, PSH and URG (--xmas).
+    Fin,
+    Null,
+    Xmas,
+    // An ACK out of the blue (--ack), which every port answers with a RST unless a filter
+    // drops it; it tells filtered ports from unfiltered ones, not open from closed.
+    Ack,
+    // The same ACK (--window), telling open from closed by the window of the RST, which some
+    // stacks leave above zero on open ports only.
+    Window,
+    // A FIN/ACK (--maimon), which BSD-derived stacks drop on open ports instead of resetting.
+    Maimon,
+    // An SCTP INIT (--sctp), answered by an INIT-ACK or an ABORT, never completing the
+    // association.
+    Sctp,
+}
+
+impl RawScan {
+    // Function to get the option selecting the scan type, e.g. `--syn`.
+    pub fn option(self) -> &'static str {
+        match self {
+            RawScan::Syn => "--syn",
+            RawScan::Fin => "--fin",
+            RawScan::Null => "--null",
+            RawScan::Xmas => "--xmas",
+            RawScan::Ack => "--ack",
+            RawScan::Window => "--window",
+            RawScan::Maimon => "--maimon",
+            RawScan::Sctp => "--sctp",
+        }
+    }
+
+    // Function to get the transport protocol of the scan type.
+    #[cfg(target_os = "linux")]
+    pub fn transport(self) -> raw::Transport {
+        match self {
+            RawScan::Sctp => raw::Transport::Sctp,
+            _ => raw::Transport::Tcp,
+        }
+    }
+
+    // Function to get the TCP flags sent by the scan type, or `None` for an SCTP INIT.
+    #[cfg(target_os = "linux")]
+    fn flags(self) -> Option<u8> {
+        match self {
+            RawScan::Syn => Some(raw::SYN),
+            RawScan::Fin => Some(raw::FIN),
+            RawScan::Null => Some(0),
+            RawScan::Xmas => Some(raw::FIN | raw::PSH | raw::URG),
+            RawScan::Ack | RawScan::Window => Some(raw::ACK),
+            RawScan::Maimon => Some(raw::FIN | raw::ACK),
+            RawScan::Sctp => None,
+        }
+    }
+
+    // Function to get the state of a port that never answered the scan type.
+    #[cfg(target_os = "linux")]
+    fn silence(self) -> PortState {
+        match self {
+            RawScan::Fin | RawScan::Null | RawScan::Xmas | RawScan::Maimon => {
+                PortState::OpenFiltered
+            }
+            RawScan::Syn | RawScan::Ack | RawScan::Window | RawScan::Sctp => PortState::Filtered,
+        }
+    }
+}
+
+// What a probe learned about a single port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PortState {
+    // The port accepted the connection.
+    Open,
+    // The host answered but refused the connection (RST).
+    Closed,
+    // Nothing came back before the timeout, or the network rejected the probe.
+    Filtered,
+    // Nothing came back to a probe that open ports ignore (--fin, --null, --xmas, --maimon), so the
+    // port is open unless a filter dropped the probe. Also what a silent IP protocol is.
+    #[serde(rename = "open|filtered")]
+    OpenFiltered,
+    // A RST came back to --ack, so no filter is in the way; open or closed is unknown.
+    Unfiltered,
+    // The zombie of an idle scan saw no answer of the port, which is closed or filtered.
+    #[serde(rename = "closed|filtered")]
+    ClosedFiltered,
+}
+
+impl PortState {
+    // Function to get the name of the state as shown in reports, e.g. `open|filtered`.
+    pub fn name(self) -> &'static str {
+        match self {
+            PortState::Open => "open",
+            PortState::Closed => "closed",
+            PortState::Filtered => "filtered",
+            PortState::OpenFiltered => "open|filtered",
+            PortState::Unfiltered => "unfiltered",
+            PortState::ClosedFiltered => "closed|filtered",
+        }
+    }
+
+    // Function to check whether the port may be open, or got past the filters of an --ack scan,
+    // which is what gets reported.
+    pub fn reported(self) -> bool {
+        matches!(
+            self,
+            PortState::Open | PortState::OpenFiltered | PortState::Unfiltered
+        )
+    }
+}
+
+// Why a port got its state, as shown by --reason.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reason {
+    // The connect went through, or a SYN/ACK came back to a raw SYN.
+    SynAck,
+    // The connect was refused with a RST.
+    ConnRefused,
+    // A raw probe was answered with a RST.
+    Reset,
+    // An SCTP INIT was answered with an INIT-ACK, or the system's stack set up an association.
+    InitAck,
+    // An SCTP INIT was answered with an ABORT, or the system's stack was refused.
+    Abort,
+    // The UDP port sent a datagram back.
+    UdpResponse,
+    // The host answered in the probed IP protocol.
+    ProtoResponse,
+    // An ICMP port unreachable came back.
+    PortUnreach,
+    // An ICMP protocol unreachable came back.
+    ProtoUnreach,
+    // The network reported the host or its network unreachable.
+    HostUnreach,
+    NetUnreach,
+    // A filter on the way, or of this host, turned the probe away.
+    AdminProhibited,
+    // The IP ID of the zombie of an idle scan went up by two, or by one.
+    #[serde(rename = "ip-id+2")]
+    IpIdUp2,
+    #[serde(rename = "ip-id+1")]
+    IpIdUp1,
+    // Something came back that says nothing about the port.
+    UnexpectedReply,
+    // Nothing came back in time.
+    NoResponse,
+    // The probe could not be sent.
+    Error,
+    // The result was saved by a checkpoint without its reason.
+    Unknown,
+}
+
+impl Reason {
+    // Function to get the name of the reason as shown in reports, e.g. `syn-ack`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Reason::SynAck => "syn-ack",
+            Reason::ConnRefused => "conn-refused",
+            Reason::Reset => "reset",
+            Reason::InitAck => "init-ack",
+            Reason::Abort => "abort",
+            Reason::UdpResponse => "udp-response",
+            Reason::ProtoResponse => "proto-response",
+            Reason::PortUnreach => "port-unreach",
+            Reason::ProtoUnreach => "proto-unreach",
+            Reason::HostUnreach => "host-unreach",
+            Reason::NetUnreach => "net-unreach",
+            Reason::AdminProhibited => "admin-prohibited",
+            Reason::IpIdUp2 => "ip-id+2",
+            Reason::IpIdUp1 => "ip-id+1",
+            Reason::UnexpectedReply => "unexpected-reply",
+            Reason::NoResponse => "no-response",
+            Reason::Error => "error",
+            Reason::Unknown => "unknown",
+        }
+    }
+
+    // Function to tell why a socket failed to reach a port, from the error the kernel made of
+    // the ICMP message that came back (a filter rejecting the probe mostly sends host unreachable).
+    #[cfg(unix)]
+    fn of_error(e: &io::Error) -> Reason {
+        match e.raw_os_error() {
+            Some(libc::EHOSTUNREACH | libc::EHOSTDOWN) => Reason::HostUnreach,
+            Some(libc::ENETUNREACH) => Reason::NetUnreach,
+            // The firewall of this host dropped the probe on its way out.
+            Some(libc::EPERM | libc::EACCES) => Reason::AdminProhibited,
+            _ => Reason::Error,
+        }
+    }
+
+    // Function to tell why a socket failed to reach a port on systems whose error codes are not
+    // those of Unix, from the kind the standard library makes of the error.
+    #[cfg(not(unix))]
+    fn of_error(e: &io::Error) -> Reason {
+        match e.kind() {
+            io::ErrorKind::HostUnreachable => Reason::HostUnreach,
+            io::ErrorKind::NetworkUnreachable => Reason::NetUnreach,
+            io::ErrorKind::PermissionDenied => Reason::AdminProhibited,
+            _ => Reason::Error,
+        }
+    }
+}
+
+// How long to wait before the first retry of a timed-out probe; doubled for every further retry.
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+// The outcome of probing one port of one host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortResult {
+    // Index of the host in the target list.
+    pub host: usize,
+    pub port: u16,
+    pub protocol: Protocol,
+    pub state: PortState,
+    pub reason: Reason,
+    // Number of probes sent before the port answered (or the retries ran out).
+    pub attempts: u32,
+    // The address that answered, when the port was raced over IPv6 and IPv4 (--happy-eyeballs).
+    pub answered: Option<IpAddr>,
+    // Set when the port was filtered at first and answered when probed again (--verify-filtered).
+    pub second_pass: bool,
+    // What the checks after the scan found on the port.
+    pub findings: Findings,
+}
+
+// What the checks run after the scan found on a port, each left out unless its option was given
+// and the port answered it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Findings {
+    // The first line the service sent after a connect (--banners).
+    pub banner: Option<String>,
+    // The service and version identified by --service-version.
+    pub detected: Option<Service>,
+    // What a TLS handshake with the port showed (--tls).
+    pub tls: Option<Tls>,
+    // What the port answered a `GET /` (--http).
+    pub http: Option<Page>,
+    // What an SSH server offered for its key exchange (--ssh).
+    pub ssh: Option<Ssh>,
+    // What an anonymous login to an FTP server gave (--ftp-anon).
+    pub ftp: Option<Ftp>,
+    // What an SMTP server offered and whether it relays (--smtp).
+    pub smtp: Option<Smtp>,
+    // What a DNS server told about its version and recursion (--dns).
+    pub dns: Option<Nameserver>,
+    // What an SNMP agent gave away to the communities tried (--snmp).
+    pub snmp: Option<Snmp>,
+    // What an SMB server negotiated and named itself (--smb).
+    pub smb: Option<Smb>,
+    // What an RDP server agreed to and whether it enforces NLA (--rdp).
+    pub rdp: Option<Rdp>,
+    // What an MQTT broker answered a client without credentials (--mqtt).
+    pub mqtt: Option<Mqtt>,
+    // What a Redis, Memcached or MongoDB server answered without a login (--datastores).
+    pub datastore: Option<Datastore>,
+    // What a Docker or Kubernetes API answered without credentials (--containers).
+    pub container: Option<Container>,
+    // What a Modbus, S7 or DNP3 device told about itself (--ics).
+    pub ics: Option<Ics>,
+    // What an NTP server answered, amplification queries included (--ntp).
+    pub ntp: Option<Ntp>,
+}
+
+// Lower bound for adapted timeouts, so a burst of fast replies cannot shrink them to nothing.
+pub const MIN_TIMEOUT: Duration = Duration::from_millis(100);
+
+// Smoothed round-trip time of one host, tracked like TCP does (RFC 6298), and how long it has been probed.
+#[derive(Debug, Default)]
+struct HostTiming {
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    // When the first probe of the host started.
+    started: Option<Instant>,
+    // Set once `--host-timeout` gave up on the host.
+    timed_out: bool,
+}
+
+// Per-host timeouts that follow the round-trip times measured so far: fast LAN hosts soon get
+// short timeouts, while slow hosts keep up to the configured maximum. With a host budget, hosts
+// are also abandoned once they have been probed for that long.
+#[derive(Debug)]
+pub struct Timing {
+    min: Duration,
+    max: Duration,
+    budget: Option<Duration>,
+    // Ports with a fixed timeout of their own (--timeout-override), e.g. for slow services.
+    overrides: HashMap<u16, Duration>,
+    hosts: Vec<Mutex<HostTiming>>,
+}
+
+impl Timing {
+    // Function to track `hosts` hosts, starting every one of them at `max`, giving each host
+    // at most `budget` from its first probe. Ports in `overrides` always get their own timeout.
+    pub fn new(
+        hosts: usize,
+        min: Duration,
+        max: Duration,
+        budget: Option<Duration>,
+        overrides: HashMap<u16, Duration>,
+    ) -> Timing {
+        Timing {
+            min: min.min(max),
+            max,
+            budget,
+            overrides,
+            hosts: (0..hosts).map(|_| Mutex::default()).collect(),
+        }
+    }
+
+    // Function to get the current timeout for a port of a host: the smoothed RTT plus four
+    // deviations, unless the port has a timeout of its own.
+    pub fn timeout(&self, host: usize, port: u16) -> Duration {
+        if let Some(&timeout) = self.overrides.get(&port) {
+            return timeout;
+        }
+        let rtt = self.hosts[host].lock().unwrap();
+        match rtt.srtt {
+            Some(srtt) => (srtt + 4 * rtt.rttvar).clamp(self.min, self.max),
+            None => self.max,
+        }
+    }
+
+    // Function to feed one measured round-trip time of a host into its estimate.
+    // Ports with their own timeout are left out, as their slowness says nothing about the host.
+    fn record(&self, host: usize, port: u16, sample: Duration) {
+        if self.overrides.contains_key(&port) {
+            return;
+        }
+        let mut rtt = self.hosts[host].lock().unwrap();
+        match rtt.srtt {
+            None => {
+                rtt.srtt = Some(sample);
+                rtt.rttvar = sample / 2;
+            }
+            Some(srtt) => {
+                let deviation = srtt.abs_diff(sample);
+                rtt.rttvar = (rtt.rttvar * 3 + deviation) / 4;
+                rtt.srtt = Some((srtt * 7 + sample) / 8);
+            }
+        }
+    }
+
+    // Function to get the moment a host runs out of budget, starting its clock on the first call.
+    fn deadline(&self, host: usize) -> Option<Instant> {
+        let budget = self.budget?;
+        let mut timing = self.hosts[host].lock().unwrap();
+        Some(*timing.started.get_or_insert_with(Instant::now) + budget)
+    }
+
+    // Function to give up on a host.
+    fn expire(&self, host: usize) {
+        self.hosts[host].lock().unwrap().timed_out = true;
+    }
+
+    // Function to check whether `--host-timeout` gave up on a host.
+    pub fn timed_out(&self, host: usize) -> bool {
+        self.hosts[host].lock().unwrap().timed_out
+    }
+}
+
+// What a single connect attempt ran into.
+enum Attempt {
+    // A definite answer, and what gave it.
+    Answer(PortState, Reason),
+    // Nothing came back in time.
+    TimedOut,
+    // No socket could be created because the process or system ran out of file descriptors,
+    // which says nothing about the port.
+    NoSockets,
+}
+
+// How long a probe that ran out of file descriptors waits before trying again.
+const NO_SOCKETS_PAUSE: Duration = Duration::from_millis(50);
+
+// Lowers the number of sockets open at once below `--concurrency` once the process has run
+// out of file descriptors, so the rest of the scan stays within the limit.
+#[derive(Debug)]
+struct Throttle {
+    permits: Semaphore,
+    state: Mutex<ThrottleState>,
+}
+
+#[derive(Debug)]
+struct ThrottleState {
+    // The number of sockets allowed at once.
+    limit: usize,
+    // Permits still held by probes that must not go back once those probes finish.
+    debt: usize,
+}
+
+// A socket slot, given back (or retired, after a shrink) when dropped.
+struct Slot<'a> {
+    throttle: &'a Throttle,
+    permit: Option<SemaphorePermit<'a>>,
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        let mut state = self.throttle.state.lock().unwrap();
+        if let Some(permit) = self.permit.take() {
+            if state.debt > 0 {
+                state.debt -= 1;
+                permit.forget();
+            }
+        }
+    }
+}
+
+impl Throttle {
+    // Function to allow up to `limit` sockets at once.
+    fn new(limit: usize) -> Throttle {
+        Throttle {
+            permits: Semaphore::new(limit),
+            state: Mutex::new(ThrottleState { limit, debt: 0 }),
+        }
+    }
+
+    // Function to wait for a free socket slot.
+    async fn acquire(&self) -> Slot<'_> {
+        Slot {
+            throttle: self,
+            // The semaphore is never closed.
+            permit: Some(self.permits.acquire().await.unwrap()),
+        }
+    }
+
+    // Function to shrink the limit to three quarters of the sockets open right now.
+    // Several probes failing at the same moment agree on the same limit instead of
+    // shrinking it once each.
+    fn shrink(&self) {
+        let mut state = self.state.lock().unwrap();
+        let in_flight = state.limit + state.debt - self.permits.available_permits();
+        let target = (in_flight * 3 / 4).max(1);
+        if target < state.limit {
+            // Idle permits go away now, the ones in use as their probes finish.
+            let excess = state.limit - target;
+            state.debt += excess - self.permits.forget_permits(excess);
+            state.limit = target;
+            eprintln!(
+                "Ran out of file descriptors, lowering the concurrency to {}",
+                target
+            );
+        }
+    }
+}
+
+// Where the probes leave from: a local address per family (--source-ip), an interface
+// (--interface) and a local port (--source-port), and how many hops they may travel (--ttl).
+// Unset parts are left to the kernel's routing and defaults.
+#[derive(Debug, Clone, Default)]
+pub struct Source {
+    v4: Option<Ipv4Addr>,
+    v6: Option<Ipv6Addr>,
+    interface: Option<String>,
+    port: Option<u16>,
+    ttl: Option<u8>,
+}
+
+impl Source {
+    // Function to parse the comma-separated --source-ip list (at most one address per family)
+    // and take the --interface name, the --source-port and the --ttl, checking that sockets can
+    // actually be bound to them.
+    pub fn new(
+        ips: Option<&str>,
+        interface: Option<String>,
+        port: Option<u16>,
+        ttl: Option<u8>,
+    ) -> Result<Source, String> {
+        if ttl == Some(0) {
+            return Err("--ttl must be from 1 to 255".to_string());
+        }
+        let mut source = Source {
+            interface,
+            port,
+            ttl,
+            ..Source::default()
+        };
+        for ip in ips
+            .into_iter()
+            .flat_map(|ips| ips.split(','))
+            .map(str::trim)
+        {
+            let parsed = ip
+                .parse::<IpAddr>()
+                .map_err(|_| format!("--source-ip {} is not an IP address", ip))?;
+            let taken = match parsed {
+                IpAddr::V4(v4) => source.v4.replace(v4).is_some(),
+                IpAddr::V6(v6) => source.v6.replace(v6).is_some(),
+            };
+            if taken {
+                return Err(format!(
+                    "--source-ip has more than one address of the family of {}",
+                    ip
+                ));
+            }
+        }
+
+        // Try each configured family once, so a wrong address or interface fails right away.
+        let mut locals: Vec<IpAddr> = [source.v4.map(IpAddr::V4), source.v6.map(IpAddr::V6)]
+            .into_iter()
+            .flatten()
+            .collect();
+        if locals.is_empty() && (source.interface.is_some() || source.port.is_some()) {
+            locals.push(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        }
+        for local in locals {
+            let from = match (&source.interface, local.is_unspecified()) {
+                (Some(interface), true) => interface.clone(),
+                (Some(interface), false) => format!("{} on {}", local, interface),
+                (None, true) => "this host".to_string(),
+                (None, false) => local.to_string(),
+            };
+            let from = match source.port {
+                Some(port) => format!("port {} of {}", port, from),
+                None => from,
+            };
+            source
+                .socket(SocketAddr::new(local, 0), Type::STREAM, None)
+                .map_err(|e| format!("Cannot send probes from {}: {}", from, e))?;
+        }
+        Ok(source)
+    }
+
+    // Function to check whether probes to `addr` can leave from the given source addresses,
+    // which only fails when --source-ip names the other family only.
+    pub fn covers(&self, addr: IpAddr) -> bool {
+        match addr {
+            IpAddr::V4(_) => self.v4.is_some() || self.v6.is_none(),
+            IpAddr::V6(_) => self.v6.is_some() || self.v4.is_none(),
+        }
+    }
+
+    // Function to get the local port the probes leave from, if --source-port fixes it.
+    #[cfg(target_os = "linux")]
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    // Function to create a non-blocking socket for a probe of `addr`. With a fixed source port,
+    // every probe socket shares it, which connects to different ports of a host allow.
+    pub fn socket(
+        &self,
+        addr: SocketAddr,
+        kind: Type,
+        protocol: Option<socket2::Protocol>,
+    ) -> io::Result<Socket> {
+        let socket = Socket::new(Domain::for_address(addr), kind, protocol)?;
+        socket.set_nonblocking(true)?;
+        self.limit(&socket, addr.ip())?;
+        if self.port.is_some() {
+            socket.set_reuse_address(true)?;
+        }
+        self.bind(&socket, addr.ip(), self.port.unwrap_or(0))?;
+        Ok(socket)
+    }
+
+    // Function to set the TTL (or IPv6 hop limit) of the packets a socket sends to `remote`, if
+    // --ttl gives one.
+    pub fn limit(&self, socket: &Socket, remote: IpAddr) -> io::Result<()> {
+        match (self.ttl, remote) {
+            (Some(ttl), IpAddr::V4(_)) => socket.set_ttl(u32::from(ttl)),
+            (Some(ttl), IpAddr::V6(_)) => socket.set_unicast_hops_v6(u32::from(ttl)),
+            (None, _) => Ok(()),
+        }
+    }
+
+    // Function to write the TTL (or IPv6 hop limit) into a packet built with its IP header for a
+    // socket of protocol IPPROTO_RAW, if --ttl gives one.
+    #[cfg(target_os = "linux")]
+    pub fn limit_packet(&self, packet: &mut [u8]) {
+        let at = match packet.first().map(|byte| byte >> 4) {
+            Some(4) => 8,
+            Some(6) => 7,
+            _ => return,
+        };
+        if let (Some(ttl), Some(field)) = (self.ttl, packet.get_mut(at)) {
+            *field = ttl;
+        }
+    }
+
+    // Function to bind a socket to the interface, if set.
+    #[cfg(target_os = "linux")]
+    pub fn bind_interface(&self, socket: &Socket) -> io::Result<()> {
+        match &self.interface {
+            Some(interface) => socket.bind_device(Some(interface.as_bytes())),
+            None => Ok(()),
+        }
+    }
+
+    // Function to bind a socket to the interface, if set, which only Linux binds sockets to by
+    // name.
+    #[cfg(not(target_os = "linux"))]
+    pub fn bind_interface(&self, _socket: &Socket) -> io::Result<()> {
+        match &self.interface {
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "binding to an interface needs Linux",
+            )),
+            None => Ok(()),
+        }
+    }
+
+    // Function to bind a socket for probes of `remote` to the interface and the local address
+    // of its family, if set. Raw and ICMP sockets have no ports, so the source port is left out.
+    #[cfg(target_os = "linux")]
+    pub fn bind_to(&self, socket: &Socket, remote: IpAddr) -> io::Result<()> {
+        self.bind(socket, remote, 0)
+    }
+
+    // Function to bind a socket for probes of `remote` to the interface, and to the local address
+    // of its family and `port`, if either is set.
+    fn bind(&self, socket: &Socket, remote: IpAddr, port: u16) -> io::Result<()> {
+        self.bind_interface(socket)?;
+        let local = match remote {
+            IpAddr::V4(_) => self.v4.map(IpAddr::V4),
+            IpAddr::V6(_) => self.v6.map(IpAddr::V6),
+        };
+        if local.is_some() || port != 0 {
+            let unspecified = match remote {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            };
+            socket.bind(&SocketAddr::new(local.unwrap_or(unspecified), port).into())?;
+        }
+        Ok(())
+    }
+}
+
+// Function to connect a socket through tokio, giving `None` if it takes longer than `wait`.
+async fn tokio_connect(socket: Socket, addr: SocketAddr, wait: Duration) -> Option<io::Result<()>> {
+    let socket = TcpSocket::from_std_stream(socket.into());
+    timeout(wait, socket.connect(addr))
+        .await
+        .ok()
+        .map(|result| result.map(drop))
+}
+
+// Function to probe a port with a full TCP connect, waiting at most `wait` for an answer.
+// With `protocol` set to SCTP, the system's SCTP stack sets up a full association instead.
+// Built with the `io-uring` feature, connects go through io_uring where the kernel allows it.
+async fn connect_once(
+    addr: SocketAddr,
+    wait: Duration,
+    source: &Source,
+    protocol: Option<socket2::Protocol>,
+) -> Attempt {
+    let sctp = protocol.is_some();
+    let result = match source.socket(addr, Type::STREAM, protocol) {
+        Err(e) => Some(Err(e)),
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        Ok(socket) => match crate::uring::ring() {
+            Some(ring) => match ring.connect(socket, addr, wait).await {
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => None,
+                result => Some(result),
+            },
+            None => tokio_connect(socket, addr, wait).await,
+        },
+        #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+        Ok(socket) => tokio_connect(socket, addr, wait).await,
+    };
+
+    match result {
+        Some(Ok(())) if sctp => Attempt::Answer(PortState::Open, Reason::InitAck),
+        Some(Ok(())) => Attempt::Answer(PortState::Open, Reason::SynAck),
+        Some(Err(e)) if e.kind() == io::ErrorKind::ConnectionRefused => {
+            let reason = if sctp {
+                Reason::Abort
+            } else {
+                Reason::ConnRefused
+            };
+            Attempt::Answer(PortState::Closed, reason)
+        }
+        Some(Err(e)) if out_of_sockets(&e) => Attempt::NoSockets,
+        // Unreachable hosts or networks and the like: no proof the port is closed.
+        Some(Err(e)) => Attempt::Answer(PortState::Filtered, Reason::of_error(&e)),
+        // The connect timed out, which is what a dropped SYN looks like.
+        None => Attempt::TimedOut,
+    }
+}
+
+// Function to check whether opening a socket failed for want of file descriptors.
+#[cfg(unix)]
+fn out_of_sockets(e: &io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(libc::EMFILE | libc::ENFILE))
+}
+
+// Function to check whether opening a socket failed for want of file descriptors, which only
+// Unix systems limit.
+#[cfg(not(unix))]
+fn out_of_sockets(_e: &io::Error) -> bool {
+    false
+}
+
+// Function to check whether a host answers a TCP connect to `addr` at all, accepting or refusing
+// it, which host discovery takes as a sign of life.
+pub async fn answers(addr: SocketAddr, wait: Duration, source: &Source) -> bool {
+    matches!(
+        connect_once(addr, wait, source, None).await,
+        Attempt::Answer(PortState::Open | PortState::Closed, _)
+    )
+}
+
+// Function to probe a port once with a packet sent from a raw socket. A RST means closed
+// (unfiltered for --ack, open if its window is above zero for --window); a SYN/ACK to a SYN
+// means open, without completing the handshake (the kernel resets the connection it knows
+// nothing of). An SCTP INIT is answered by an INIT-ACK if the port is open and an ABORT if it
+// is closed. Anything else, or a failure to send, counts as filtered.
+#[cfg(target_os = "linux")]
+async fn raw_once(addr: SocketAddr, wait: Duration, raw: &Raw, scan: RawScan) -> Attempt {
+    let reply = match scan.flags() {
+        Some(flags) => raw.probe(addr, flags, wait).await,
+        None => raw.init(addr, wait).await,
+    };
+    let (state, reason) = match reply {
+        Ok(None) => return Attempt::TimedOut,
+        Ok(Some(Reply::Segment { flags, window })) if flags & raw::RST != 0 => match scan {
+            RawScan::Ack => (PortState::Unfiltered, Reason::Reset),
+            RawScan::Window if window > 0 => (PortState::Open, Reason::Reset),
+            _ => (PortState::Closed, Reason::Reset),
+        },
+        Ok(Some(Reply::Segment { flags, .. }))
+            if scan == RawScan::Syn && flags & (raw::SYN | raw::ACK) == raw::SYN | raw::ACK =>
+        {
+            (PortState::Open, Reason::SynAck)
+        }
+        Ok(Some(Reply::Chunk(raw::INIT_ACK))) => (PortState::Open, Reason::InitAck),
+        Ok(Some(Reply::Chunk(raw::ABORT))) => (PortState::Closed, Reason::Abort),
+        Ok(Some(_)) => (PortState::Filtered, Reason::UnexpectedReply),
+        Err(e) => (PortState::Filtered, Reason::of_error(&e)),
+    };
+    Attempt::Answer(state, reason)
+}
+
+// Function to probe an IP protocol of a host once, the protocol number standing in for the port.
+#[cfg(target_os = "linux")]
+async fn protocol_once(addr: SocketAddr, wait: Duration, prober: &Prober) -> Attempt {
+    let IpAddr::V4(host) = addr.ip() else {
+        return Attempt::Answer(PortState::Filtered, Reason::Error);
+    };
+    match prober.probe(host, addr.port() as u8, wait).await {
+        Ok(Some((state, reason))) => Attempt::Answer(state, reason),
+        Ok(None) => Attempt::TimedOut,
+        Err(e) => Attempt::Answer(PortState::Filtered, Reason::of_error(&e)),
+    }
+}
+
+// Function to probe a port of an IPv4 host once through the zombie of an idle scan.
+#[cfg(target_os = "linux")]
+async fn idle_once(addr: SocketAddr, wait: Duration, zombie: &Zombie) -> Attempt {
+    let SocketAddr::V4(target) = addr else {
+        return Attempt::Answer(PortState::Filtered, Reason::Error);
+    };
+    match zombie.probe(target, wait).await {
+        Ok(Some(state @ PortState::Open)) => Attempt::Answer(state, Reason::IpIdUp2),
+        Ok(Some(state)) => Attempt::Answer(state, Reason::IpIdUp1),
+        Ok(None) => Attempt::TimedOut,
+        Err(e) => Attempt::Answer(PortState::Filtered, Reason::of_error(&e)),
+    }
+}
+
+// Function to probe a UDP port once: send it a datagram (with a payload the service is likely to
+// answer, see `udp::payload`) and wait at most `wait` for a reply. A reply means open, an ICMP
+// port unreachable (reported by the kernel as a refused connection) closed, and silence
+// either open or filtered, which is reported as filtered.
+async fn udp_once(addr: SocketAddr, wait: Duration, source: &Source) -> Attempt {
+    let exchange = async {
+        let socket = source.socket(addr, Type::DGRAM, None)?;
+        let socket = UdpSocket::from_std(socket.into())?;
+        socket.connect(addr).await?;
+        socket.send(&udp::payload(addr.port())).await?;
+        // An ICMP port unreachable only raises an error event, which a plain `recv` never wakes for.
+        let mut reply = [0u8; 512];
+        loop {
+            let ready = socket.ready(Interest::READABLE | Interest::ERROR).await?;
+            if ready.is_error() {
+                return Err(socket
+                    .take_error()?
+                    .unwrap_or_else(|| io::ErrorKind::Other.into()));
+            }
+            match socket.try_recv(&mut reply) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                result => return result.map(drop),
+            }
+        }
+    };
+    match timeout(wait, exchange).await {
+        Ok(Ok(())) => Attempt::Answer(PortState::Open, Reason::UdpResponse),
+        Ok(Err(e)) if e.kind() == io::ErrorKind::ConnectionRefused => {
+            Attempt::Answer(PortState::Closed, Reason::PortUnreach)
+        }
+        Ok(Err(e)) if out_of_sockets(&e) => Attempt::NoSockets,
+        Ok(Err(e)) => Attempt::Answer(PortState::Filtered, Reason::of_error(&e)),
+        Err(_) => Attempt::TimedOut,
+    }
+}
+
+// Function to check whether the system has an SCTP stack to set up associations with.
+pub fn sctp_available() -> bool {
+    Socket::new(Domain::IPV4, Type::STREAM, Some(sctp())).is_ok()
+}
+
+// Function to get the socket protocol of SCTP, by its IANA number, as not every libc names it.
+fn sctp() -> socket2::Protocol {
+    socket2::Protocol::from(132)
+}
+
+// Function to probe a port once over `protocol` and the engine of the scan. UDP ports always
+// get a datagram, as the raw engines only probe TCP and SCTP ports.
+async fn probe_once(
+    addr: SocketAddr,
+    protocol: Protocol,
+    wait: Duration,
+    settings: &Settings,
+) -> Attempt {
+    let source = &settings.source;
+    match (&settings.engine, protocol) {
+        #[cfg(target_os = "linux")]
+        (Engine::Raw(..), Protocol::Udp) => udp_once(addr, wait, source).await,
+        #[cfg(target_os = "linux")]
+        (Engine::Raw(raw, scan), _) => raw_once(addr, wait, raw, *scan).await,
+        #[cfg(target_os = "linux")]
+        (Engine::Protocols(prober), _) => protocol_once(addr, wait, prober).await,
+        #[cfg(target_os = "linux")]
+        (Engine::Idle(zombie), _) => idle_once(addr, wait, zombie).await,
+        (Engine::Sockets, Protocol::Tcp) => connect_once(addr, wait, source, None).await,
+        (Engine::Sockets, Protocol::Udp) => udp_once(addr, wait, source).await,
+        (Engine::Sockets, Protocol::Sctp) => connect_once(addr, wait, source, Some(sctp())).await,
+        // Protocol scans always go through `Engine::Protocols`.
+        (Engine::Sockets, Protocol::Ip) => Attempt::Answer(PortState::Filtered, Reason::Error),
+    }
+}
+
+// How long the IPv6 connect of a raced probe gets before the IPv4 one starts, the
+// Connection Attempt Delay recommended by RFC 8305.
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+// Function to rank what a connect ran into, most conclusive first, to pick the answer of a race.
+fn rank(attempt: &Attempt) -> u8 {
+    match attempt {
+        Attempt::Answer(PortState::Open, _) => 0,
+        Attempt::Answer(PortState::Closed | PortState::Unfiltered, _) => 1,
+        Attempt::NoSockets => 2,
+        Attempt::Answer(
+            PortState::Filtered | PortState::OpenFiltered | PortState::ClosedFiltered,
+            _,
+        ) => 3,
+        Attempt::TimedOut => 4,
+    }
+}
+
+// Function to probe a port over two addresses of a dual-stack host, Happy Eyeballs style
+// (RFC 8305): `first` gets a head start of `ATTEMPT_DELAY`, or less if it fails sooner, and an
+// open connection on either address ends the race. The probe of `second` waits for the rate too.
+// Returns the outcome and the address it came from.
+async fn race_once(
+    first: SocketAddr,
+    second: SocketAddr,
+    protocol: Protocol,
+    wait: Duration,
+    settings: &Settings,
+) -> (Attempt, SocketAddr) {
+    let mut first_probe = pin!(probe_once(first, protocol, wait, settings));
+    let second_probe = async {
+        settings.pace().await;
+        probe_once(second, protocol, wait, settings).await
+    };
+    let early = tokio::select! {
+        attempt = &mut first_probe => Some(attempt),
+        _ = sleep(ATTEMPT_DELAY) => None,
+    };
+    let (a, b) = match early {
+        Some(a @ Attempt::Answer(PortState::Open, _)) => return (a, first),
+        Some(a) => (a, second_probe.await),
+        None => {
+            let mut second_probe = pin!(second_probe);
+            tokio::select! {
+                a = &mut first_probe => match a {
+                    Attempt::Answer(PortState::Open, _) => return (a, first),
+                    a => (a, second_probe.await),
+                },
+                b = &mut second_probe => match b {
+                    Attempt::Answer(PortState::Open, _) => return (b, second),
+                    b => (first_probe.await, b),
+                },
+            }
+        }
+    };
+    if rank(&b) < rank(&a) {
+        (b, second)
+    } else {
+        (a, first)
+    }
+}
+
+// Function to probe a port of host number `host`, sending up to `settings.retries` more probes (with
+// exponential backoff) when one times out. Refusals and other definite answers are never retried.
+// Every answer updates the host's round-trip estimate. A probe that finds no free file descriptor
+// lowers the throttle and goes back in line without counting as an attempt. Every probe sent again
+// waits for the rate like the first one did in `probe_all`. With a `fallback`
+// address each probe races both addresses. Returns the state and why, the number of probes sent
+// and, for raced probes that got an answer, the address it came from.
+async fn probe_port(
+    addr: SocketAddr,
+    fallback: Option<SocketAddr>,
+    protocol: Protocol,
+    host: usize,
+    timing: &Timing,
+    settings: &Settings,
+    throttle: &Throttle,
+) -> (PortState, Reason, u32, Option<IpAddr>) {
+    let mut backoff = RETRY_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        let slot = throttle.acquire().await;
+        let started = Instant::now();
+        let wait = timing.timeout(host, addr.port());
+        let (outcome, answered) = match fallback {
+            Some(fallback) => {
+                let (outcome, from) = race_once(addr, fallback, protocol, wait, settings).await;
+                (outcome, Some(from.ip()))
+            }
+            None => (probe_once(addr, protocol, wait, settings).await, None),
+        };
+        match outcome {
+            Attempt::Answer(state, reason) => {
+                timing.record(host, addr.port(), started.elapsed());
+                return (state, reason, attempt, answered);
+            }
+            Attempt::NoSockets => {
+                throttle.shrink();
+                drop(slot);
+                sleep(NO_SOCKETS_PAUSE).await;
+                settings.pace().await;
+            }
+            Attempt::TimedOut if attempt <= settings.retries => {
+                drop(slot);
+                sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+                settings.pace().await;
+            }
+            Attempt::TimedOut => {
+                return (
+                    settings.silence(protocol),
+                    Reason::NoResponse,
+                    attempt,
+                    None,
+                )
+            }
+        }
+    }
+}
+
+// The values bundled by a timing template (-T0 to -T5).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Template {
+    pub timeout: Duration,
+    pub concurrency: usize,
+    pub retries: u32,
+    // Pause between the start of two probes, if any.
+    pub delay: Option<Duration>,
+}
+
+// Function to get a timing template, from 0 (paranoid, one probe every five minutes) to 5 (insane).
+// Level 3 holds the regular defaults.
+pub fn template(level: u8) -> Template {
+    let (timeout_ms, concurrency, retries, delay_ms) = match level {
+        0 => (5000, 1, 2, Some(300_000)),
+        1 => (5000, 1, 2, Some(15_000)),
+        2 => (3000, 1, 2, Some(400)),
+        3 => (3000, 500, 0, None),
+        4 => (1250, 1000, 2, None),
+        _ => (300, 5000, 1, None),
+    };
+    Template {
+        timeout: Duration::from_millis(timeout_ms),
+        concurrency,
+        retries,
+        delay: delay_ms.map(Duration::from_millis),
+    }
+}
+
+// Spaces probes out evenly so no more than a given number start per second, optionally adding
+// a random delay between two probes so they do not go out at a perfectly regular pace.
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    // Upper bound (exclusive) of the random delay added to each interval.
+    jitter: Duration,
+    // Start time of the next probe.
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    // Function to build a limiter allowing `per_second` probes per second.
+    pub fn new(per_second: f64) -> RateLimiter {
+        RateLimiter::every(Duration::from_secs_f64(1.0 / per_second))
+    }
+
+    // Function to build a limiter starting one probe every `interval`.
+    pub fn every(interval: Duration) -> RateLimiter {
+        RateLimiter {
+            interval,
+            jitter: Duration::ZERO,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    // Function to add a random delay of up to `jitter` between two probes.
+    pub fn jittered(self, jitter: Duration) -> RateLimiter {
+        RateLimiter { jitter, ..self }
+    }
+
+    // Function to wait until the next probe may start.
+    pub async fn wait(&self) {
+        // Reserve a start time, then sleep until it outside of the lock.
+        let slot = {
+            let mut next = self.next.lock().unwrap();
+            // Time spent idle (e.g. while every slot was busy) is not saved up as a burst.
+            let slot = (*next).max(Instant::now());
+            *next = slot + self.interval;
+            if !self.jitter.is_zero() {
+                *next += rand::thread_rng().gen_range(Duration::ZERO..self.jitter);
+            }
+            slot
+        };
+        sleep_until(slot).await;
+    }
+}
+
+// Sends probes in bursts of `size`, with a quiet period of `delay` between the start of the
+// last probe of one burst and the first of the next.
+#[derive(Debug)]
+pub struct Batches {
+    size: usize,
+    delay: Duration,
+    // Probes started in the current burst, and when the burst may start.
+    state: Mutex<(usize, Instant)>,
+}
+
+impl Batches {
+    // Function to build bursts of `size` probes separated by `delay`.
+    pub fn new(size: usize, delay: Duration) -> Batches {
+        Batches {
+            size,
+            delay,
+            state: Mutex::new((0, Instant::now())),
+        }
+    }
+
+    // Function to wait until the probe may start with the current or the next burst.
+    async fn wait(&self) {
+        let slot = {
+            let mut state = self.state.lock().unwrap();
+            let (sent, start) = &mut *state;
+            let slot = (*start).max(Instant::now());
+            *sent += 1;
+            if *sent == self.size {
+                *sent = 0;
+                *start = slot + self.delay;
+            }
+            slot
+        };
+        sleep_until(slot).await;
+    }
+}
+
+// Lets a running scan be suspended: while paused no new probe starts, and probes already
+// in flight finish normally.
+#[derive(Debug)]
+pub struct Pause {
+    paused: watch::Sender<bool>,
+}
+
+impl Default for Pause {
+    fn default() -> Pause {
+        Pause {
+            paused: watch::channel(false).0,
+        }
+    }
+}
+
+impl Pause {
+    // Function to pause or resume the scan.
+    pub fn set(&self, paused: bool) {
+        self.paused.send_replace(paused);
+    }
+
+    // Function to check whether the scan is paused.
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    // Function to wait until the scan is not paused.
+    async fn wait(&self) {
+        // The sender lives as long as `self`, so waiting cannot fail.
+        let _ = self.paused.subscribe().wait_for(|paused| !paused).await;
+    }
+}
+
+// Settings shared by every probe of a scan.
+#[derive(Debug)]
+pub struct Settings {
+    pub retries: u32,
+    // Number of probes in flight at the same time.
+    pub concurrency: usize,
+    pub rate: Option<RateLimiter>,
+    pub batches: Option<Batches>,
+    pub source: Source,
+    pub engine: Engine,
+    pub pause: Pause,
+    // Probes already done by the scan being resumed, which are not sent again.
+    pub done: Progress,
+    // Number of hosts probed at the same time, taking turns between their ports.
+    pub host_parallelism: usize,
+    // Most probes in flight to a single host, if capped.
+    pub per_host: Option<usize>,
+}
+
+impl Settings {
+    // Function to get the state of a port of `protocol` that never answered.
+    fn silence(&self, protocol: Protocol) -> PortState {
+        // Every engine sends UDP probes as plain datagrams.
+        if protocol == Protocol::Udp {
+            return PortState::Filtered;
+        }
+        match &self.engine {
+            #[cfg(target_os = "linux")]
+            Engine::Raw(_, scan) => scan.silence(),
+            #[cfg(target_os = "linux")]
+            Engine::Protocols(_) => PortState::OpenFiltered,
+            // The zombie never showed the port answering.
+            #[cfg(target_os = "linux")]
+            Engine::Idle(_) => PortState::ClosedFiltered,
+            Engine::Sockets => PortState::Filtered,
+        }
+    }
+
+    // Function to wait until `rate` lets the next probe go out, if it is capped.
+    async fn pace(&self) {
+        if let Some(rate) = &self.rate {
+            rate.wait().await;
+        }
+    }
+
+    // Function to open a socket of the source to `addr` for the checks after the scan, once the
+    // rate lets it go out.
+    pub async fn socket(&self, addr: SocketAddr, kind: Type) -> io::Result<Socket> {
+        self.pace().await;
+        self.source.socket(addr, kind, None)
+    }
+}
+
+// Iterator over the probes of a scan that goes round-robin over `parallel` hosts at a time,
+// each host's ports in order; once a host has had all its ports the next one takes its turn.
+struct Interleave<'a> {
+    hosts: std::slice::Iter<'a, usize>,
+    ports: &'a [u16],
+    // The hosts taking turns, with the index of their next port.
+    active: VecDeque<(usize, usize)>,
+}
+
+impl<'a> Interleave<'a> {
+    // Function to start going over `ports` on the hosts of `host_order`.
+    fn new(host_order: &'a [usize], ports: &'a [u16], parallel: usize) -> Interleave<'a> {
+        let mut hosts = host_order.iter();
+        let active = match ports.is_empty() {
+            true => VecDeque::new(),
+            false => hosts
+                .by_ref()
+                .take(parallel)
+                .map(|&host| (host, 0))
+                .collect(),
+        };
+        Interleave {
+            hosts,
+            ports,
+            active,
+        }
+    }
+}
+
+impl Iterator for Interleave<'_> {
+    type Item = (usize, u16);
+
+    fn next(&mut self) -> Option<(usize, u16)> {
+        let (host, i) = self.active.pop_front()?;
+        if i + 1 < self.ports.len() {
+            self.active.push_back((host, i + 1));
+        } else if let Some(&next) = self.hosts.next() {
+            self.active.push_back((next, 0));
+        }
+        Some((host, self.ports[i]))
+    }
+}
+
+// Function to probe `ports` of `protocol` on the hosts of `targets`, in the order of `host_order` (indices into `targets`).
+// At most `settings.concurrency` probes run at once (and at most `settings.per_host` per host) and
+// the next one is only created when a slot frees up, so memory stays flat however many hosts and
+// ports there are. Results are yielded in the order they complete; ports of hosts that ran out of
+// time are left out.
+pub fn scan<'a>(
+    targets: &'a [Target],
+    host_order: &'a [usize],
+    ports: &'a [u16],
+    protocol: Protocol,
+    timing: &'a Timing,
+    settings: &'a Settings,
+) -> impl Stream<Item = PortResult> + 'a {
+    let probes = Interleave::new(host_order, ports, settings.host_parallelism)
+        .filter(move |&(host, port)| !settings.done.contains(host, port, protocol))
+        .map(move |(host, port)| (host, port, protocol));
+    probe_all(targets, probes, false, timing, settings)
+}
+
+// Function to probe the given host and port pairs again (--verify-filtered), the same way as
+// `scan` but ignoring `settings.done`. Results are marked as coming from the second pass.
+pub fn verify<'a>(
+    targets: &'a [Target],
+    probes: &'a [(usize, u16, Protocol)],
+    timing: &'a Timing,
+    settings: &'a Settings,
+) -> impl Stream<Item = PortResult> + 'a {
+    probe_all(targets, probes.iter().copied(), true, timing, settings)
+}
+
+// Function to run the probes of `scan` and `verify`.
+fn probe_all<'a>(
+    targets: &'a [Target],
+    probes: impl Iterator<Item = (usize, u16, Protocol)> + 'a,
+    second_pass: bool,
+    timing: &'a Timing,
+    settings: &'a Settings,
+) -> impl Stream<Item = PortResult> + 'a {
+    let throttle = Arc::new(Throttle::new(settings.concurrency));
+    let per_host: Arc<Vec<Semaphore>> = Arc::new(match settings.per_host {
+        Some(n) => targets.iter().map(|_| Semaphore::new(n)).collect(),
+        None => vec![],
+    });
+    stream::iter(probes)
+        .map(move |(host, port, protocol)| {
+            let throttle = throttle.clone();
+            let per_host = per_host.clone();
+            async move {
+                // Skip the remaining ports of an abandoned host without waiting for a slot.
+                if timing.timed_out(host) {
+                    return None;
+                }
+                settings.pause.wait().await;
+                let _host_slot = match per_host.get(host) {
+                    Some(cap) => Some(cap.acquire().await.ok()?),
+                    None => None,
+                };
+                settings.pace().await;
+                if let Some(batches) = &settings.batches {
+                    batches.wait().await;
+                }
+                let addr = targets[host].socket_addr(port);
+                let fallback = targets[host].fallback_addr(port);
+                let probe = probe_port(addr, fallback, protocol, host, timing, settings, &throttle);
+                // Probes still running when the host's budget runs out are cut short.
+                let outcome = match timing.deadline(host) {
+                    Some(deadline) => timeout_at(deadline, probe).await.ok(),
+                    None => Some(probe.await),
+                };
+                let Some((state, reason, attempts, answered)) = outcome else {
+                    timing.expire(host);
+                    return None;
+                };
+                Some(PortResult {
+                    host,
+                    port,
+                    protocol,
+                    state,
+                    reason,
+                    attempts,
+                    answered,
+                    second_pass,
+                    findings: Findings::default(),
+                })
+            }
+        })
+        .buffer_unordered(settings.concurrency)
+        .filter_map(|result| async move { result })
+}
+
+// Function to run `check` after the scan on every port of `results` that `pick` takes, with
+// what `pick` took from the port and its host, and hand what it found to `keep` with the result.
+// A raced port is checked over the family that answered. At most `parallel` ports are checked
+// at the same time.
+pub async fn check_ports<'h, P, T, C, F>(
+    hosts: &'h [Target],
+    results: &mut [PortResult],
+    parallel: usize,
+    pick: impl Fn(&PortResult, &'h Target) -> Option<P>,
+    check: C,
+    mut keep: impl FnMut(&mut PortResult, T),
+) where
+    C: Fn(SocketAddr, P) -> F,
+    F: Future<Output = T>,
+{
+    let picked: Vec<(usize, SocketAddr, P)> = results
+        .iter()
+        .enumerate()
+        .filter_map(|(i, r)| {
+            let host = &hosts[r.host];
+            let taken = pick(r, host)?;
+            let addr = match r.answered {
+                Some(ip) => SocketAddr::new(ip, r.port),
+                None => host.socket_addr(r.port),
+            };
+            Some((i, addr, taken))
+        })
+        .collect();
+    let found: Vec<(usize, T)> = stream::iter(picked)
+        .map(|(i, addr, taken)| {
+            let found = check(addr, taken);
+            async move { (i, found.await) }
+        })
+        .buffer_unordered(parallel.max(1))
+        .collect()
+        .await;
+    for (i, found) in found {
+        keep(&mut results[i], found);
+    }
+}
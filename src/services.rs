@@ -0,0 +1,188 @@
+use std::fs; // Import filesystem access to read user services files.
+use std::sync::OnceLock; // Import `OnceLock` to parse the embedded table only once.
+
+// Port to service name mapping in `/etc/services` format, embedded at build time. It is
+// Debian's netbase table, under the GPL version 2 as the notice at its top says.
+const SERVICES: &str = include_str!("../data/services");
+
+// Names of the IP protocols most hosts know, by number, as in /etc/protocols.
+const PROTOCOLS: &[(u8, &str)] = &[
+    (0, "hopopt"),
+    (1, "icmp"),
+    (2, "igmp"),
+    (4, "ipip"),
+    (6, "tcp"),
+    (8, "egp"),
+    (17, "udp"),
+    (41, "ipv6"),
+    (46, "rsvp"),
+    (47, "gre"),
+    (50, "esp"),
+    (51, "ah"),
+    (58, "ipv6-icmp"),
+    (88, "eigrp"),
+    (89, "ospf"),
+    (94, "ipip-nos"),
+    (97, "etherip"),
+    (103, "pim"),
+    (108, "ipcomp"),
+    (112, "vrrp"),
+    (115, "l2tp"),
+    (124, "isis"),
+    (132, "sctp"),
+    (136, "udplite"),
+    (137, "mpls-in-ip"),
+];
+
+// One line of the services table.
+#[derive(Debug, Clone)]
+struct Entry {
+    name: &'static str,
+    port: u16,
+    proto: &'static str,
+    aliases: Vec<&'static str>,
+}
+
+// Function to get the parsed services table.
+fn table() -> &'static [Entry] {
+    static TABLE: OnceLock<Vec<Entry>> = OnceLock::new();
+    TABLE.get_or_init(|| SERVICES.lines().filter_map(parse_line).collect())
+}
+
+// Function to get the entries of the user's services file (--services-file), if one was loaded.
+fn custom() -> &'static [Entry] {
+    CUSTOM.get().map_or(&[], |entries| entries)
+}
+
+// The entries of the user's services file, which win over the embedded table.
+static CUSTOM: OnceLock<Vec<Entry>> = OnceLock::new();
+
+// Function to load a services file of the user's, whose names then override or extend the
+// embedded table. Its lines take the same form, with the protocol defaulting to TCP, e.g.
+// `billing 8081` or `billing 8081/udp`.
+pub fn load(path: &str) -> Result<(), String> {
+    let contents =
+        fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    // The names are kept for the whole run, like those of the embedded table.
+    let entries = parse_custom(Box::leak(contents.into_boxed_str()))
+        .map_err(|e| format!("Invalid services file {}: {}", path, e))?;
+    let _ = CUSTOM.set(entries);
+    Ok(())
+}
+
+// Function to parse a user services file, refusing lines that are not `name port[/proto]`
+// rather than quietly dropping the names in them.
+fn parse_custom(contents: &'static str) -> Result<Vec<Entry>, String> {
+    let mut entries = vec![];
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        let Some(name) = fields.next() else {
+            continue;
+        };
+        let invalid = || format!("line {} is not `name port[/protocol]`", number + 1);
+        let field = fields.next().ok_or_else(invalid)?;
+        let (port, proto) = field.split_once('/').unwrap_or((field, "tcp"));
+        let port = port.parse().map_err(|_| invalid())?;
+        if !matches!(proto, "tcp" | "udp" | "sctp") {
+            return Err(format!(
+                "line {} has protocol {}, which is not tcp, udp or sctp",
+                number + 1,
+                proto
+            ));
+        }
+        entries.push(Entry {
+            name,
+            port,
+            proto,
+            aliases: fields.collect(),
+        });
+    }
+    Ok(entries)
+}
+
+// Function to parse a line such as `smtp 25/tcp mail # comment`.
+fn parse_line(line: &'static str) -> Option<Entry> {
+    let line = line.split('#').next()?;
+    let mut fields = line.split_whitespace();
+    let name = fields.next()?;
+    let (port, proto) = fields.next()?.split_once('/')?;
+    Some(Entry {
+        name,
+        port: port.parse().ok()?,
+        proto,
+        aliases: fields.collect(),
+    })
+}
+
+// Function to translate a service name (or alias) into its port number.
+// Entries for `proto` win, but a name only registered for another protocol is still accepted.
+pub fn port_by_name(name: &str, proto: &str) -> Option<u16> {
+    let matches = |e: &&Entry| {
+        e.name.eq_ignore_ascii_case(name) || e.aliases.iter().any(|a| a.eq_ignore_ascii_case(name))
+    };
+    [custom(), table()]
+        .iter()
+        .find_map(|entries| {
+            entries
+                .iter()
+                .filter(matches)
+                .find(|e| e.proto == proto)
+                .or_else(|| entries.iter().find(matches))
+        })
+        .map(|e| e.port)
+}
+
+// Function to look up the service name registered for a port. Few services are listed for SCTP
+// of their own, so SCTP ports without an entry take the TCP name: a service keeps its name on
+// every transport it runs over (RFC 6335), e.g. `80/sctp` is http.
+pub fn name_by_port(port: u16, proto: &str) -> Option<&'static str> {
+    let find = |proto: &str| {
+        custom()
+            .iter()
+            .chain(table())
+            .find(|e| e.port == port && e.proto == proto)
+            .map(|e| e.name)
+    };
+    find(proto).or_else(|| find("tcp").filter(|_| proto == "sctp"))
+}
+
+// Function to get the name of an IP protocol, e.g. `gre` for 47.
+pub fn name_by_protocol(protocol: u8) -> Option<&'static str> {
+    PROTOCOLS
+        .iter()
+        .find(|(number, _)| *number == protocol)
+        .map(|(_, name)| *name)
+}
+
+// Function to get the number of an IP protocol by name, e.g. 47 for `gre`.
+pub fn protocol_by_name(name: &str) -> Option<u8> {
+    PROTOCOLS
+        .iter()
+        .find(|(_, known)| known.eq_ignore_ascii_case(name))
+        .map(|(number, _)| *number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_custom_files() {
+        let entries =
+            parse_custom("# ours\nbilling\t8081\n\nmetrics 9102/udp stats # scraped\n").unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            (entries[0].name, entries[0].port, entries[0].proto),
+            ("billing", 8081, "tcp")
+        );
+        assert_eq!(
+            (entries[1].name, entries[1].port, entries[1].proto),
+            ("metrics", 9102, "udp")
+        );
+        assert_eq!(entries[1].aliases, ["stats"]);
+        assert!(parse_custom("billing\n").is_err());
+        assert!(parse_custom("billing 80a\n").is_err());
+        assert!(parse_custom("billing 8081/icmp\n").is_err());
+    }
+}
@@ -0,0 +1,480 @@
+use crate::scanner::{self, PortResult, PortState, Protocol, Settings}; // Import the open ports to negotiate with and the scan settings to connect with.
+use crate::targets::Target; // Import the hosts the ports belong to.
+use crate::x509::der; // Import the DER writer for the SPNEGO token.
+use serde::Serialize; // Import `Serialize` to report the servers in the structured output.
+use socket2::Type; // Import socket types for the connects.
+use std::io; // Import I/O errors for socket failures.
+use std::net::SocketAddr; // Import `SocketAddr` for the ports negotiated with.
+use std::time::Duration; // Import `Duration` for the connect and read timeouts.
+use tokio::io::{AsyncReadExt, AsyncWriteExt}; // Import reads and writes on the connected streams.
+use tokio::net::{TcpSocket, TcpStream}; // Import sockets of the source for the connects.
+use tokio::time::{timeout_at, Instant}; // Import deadlines to give up on slow servers.
+
+// Define the longest message read from a server.
+const MAX_MESSAGE: usize = 64 * 1024;
+
+// Define the SMB2 dialects offered, oldest first: 2.0.2, 2.1, 3.0, 3.0.2 and 3.1.1.
+const DIALECTS: [u16; 5] = [0x0202, 0x0210, 0x0300, 0x0302, 0x0311];
+
+// Define the SMB2 commands and the status that asks for the next authentication leg.
+const NEGOTIATE: u16 = 0;
+const SESSION_SETUP: u16 = 1;
+const MORE_PROCESSING_REQUIRED: u32 = 0xc000_0016;
+
+// Define the flags of an NTLM NEGOTIATE_MESSAGE: Unicode, OEM, request target, NTLM, always
+// sign, extended session security, target info, version, 128-bit, key exchange and 56-bit.
+const NTLM_FLAGS: u32 = 0xe288_8207;
+
+// Define the encoded OIDs of SPNEGO (1.3.6.1.5.5.2) and NTLMSSP (1.3.6.1.4.1.311.2.2.10).
+const SPNEGO: &[u8] = &[0x2b, 6, 1, 5, 5, 2];
+const NTLMSSP: &[u8] = &[0x2b, 6, 1, 4, 1, 0x82, 0x37, 2, 2, 10];
+
+// What an SMB server negotiated and told about itself before any authentication.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Smb {
+    // Set when it still speaks SMB1 (the NT LM 0.12 dialect).
+    pub smb1: bool,
+    // The newest SMB2 dialect it agreed on, e.g. `3.1.1`.
+    pub dialect: Option<String>,
+    // Whether it signs messages: `required`, `enabled` or `disabled`.
+    pub signing: Option<String>,
+    // The names its NTLM challenge carried.
+    pub netbios_name: Option<String>,
+    pub netbios_domain: Option<String>,
+    pub dns_name: Option<String>,
+    pub dns_domain: Option<String>,
+    pub dns_forest: Option<String>,
+    // The Windows version in the challenge, e.g. `10.0.20348`.
+    pub os_version: Option<String>,
+}
+
+// What an NTLM CHALLENGE_MESSAGE named.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Challenge {
+    netbios_name: Option<String>,
+    netbios_domain: Option<String>,
+    dns_name: Option<String>,
+    dns_domain: Option<String>,
+    dns_forest: Option<String>,
+    os_version: Option<String>,
+}
+
+// Function to negotiate with every open TCP port in `results` that may run SMB: ports 139 and
+// 445 and ports --service-version named microsoft-ds or netbios-ssn. Each gets `wait` for the
+// connect and the exchanges of each of its two connections.
+pub async fn negotiate_all(
+    hosts: &[Target],
+    results: &mut [PortResult],
+    settings: &Settings,
+    wait: Duration,
+) {
+    scanner::check_ports(
+        hosts,
+        results,
+        settings.concurrency,
+        |r, _| {
+            if r.protocol != Protocol::Tcp || r.state != PortState::Open {
+                return None;
+            }
+            // Port 139 takes a NetBIOS session request first.
+            match (
+                r.port,
+                r.findings.detected.as_ref().map(|s| s.name.as_str()),
+            ) {
+                (139, _) | (_, Some("netbios-ssn")) => Some(true),
+                (445, _) | (_, Some("microsoft-ds")) => Some(false),
+                _ => None,
+            }
+        },
+        |addr, netbios| negotiate(addr, netbios, settings, wait),
+        |r, smb| r.findings.smb = smb,
+    )
+    .await;
+}
+
+// Function to find out what the server at `addr` speaks: one connection offers SMB1 alone, the
+// other the SMB2 dialects and then starts an NTLM login to read the challenge, which names the
+// server. Gives `None` if it answered neither like an SMB server.
+async fn negotiate(
+    addr: SocketAddr,
+    netbios: bool,
+    settings: &Settings,
+    wait: Duration,
+) -> Option<Smb> {
+    let smb1 = async {
+        let deadline = Instant::now() + wait;
+        let mut stream = connect(addr, netbios, settings, deadline).await?;
+        let response = exchange(&mut stream, &smb1_negotiate(), deadline).await?;
+        io::Result::Ok(smb1_response(&response))
+    };
+    let smb1 = smb1.await.ok().flatten();
+
+    let smb2 = async {
+        let deadline = Instant::now() + wait;
+        let mut stream = connect(addr, netbios, settings, deadline).await?;
+        let response = exchange(&mut stream, &smb2_negotiate(), deadline).await?;
+        let Some((dialect, security)) = negotiate_response(&response) else {
+            return Ok(None);
+        };
+        let challenge = match exchange(&mut stream, &session_setup(), deadline).await {
+            Ok(response) => challenge(&response),
+            Err(_) => None,
+        };
+        io::Result::Ok(Some((dialect, security, challenge)))
+    };
+    let smb2 = smb2.await.ok().flatten();
+
+    let signing = |enabled: bool, required: bool| match (enabled, required) {
+        (_, true) => "required".to_string(),
+        (true, false) => "enabled".to_string(),
+        _ => "disabled".to_string(),
+    };
+    let mut smb = Smb {
+        smb1: smb1.is_some(),
+        dialect: None,
+        signing: smb1.map(|mode| signing(mode & 0x04 != 0, mode & 0x08 != 0)),
+        netbios_name: None,
+        netbios_domain: None,
+        dns_name: None,
+        dns_domain: None,
+        dns_forest: None,
+        os_version: None,
+    };
+    match smb2 {
+        Some((dialect, security, challenge)) => {
+            smb.dialect = Some(dialect_name(dialect));
+            smb.signing = Some(signing(security & 0x01 != 0, security & 0x02 != 0));
+            let challenge = challenge.unwrap_or_default();
+            smb.netbios_name = challenge.netbios_name;
+            smb.netbios_domain = challenge.netbios_domain;
+            smb.dns_name = challenge.dns_name;
+            smb.dns_domain = challenge.dns_domain;
+            smb.dns_forest = challenge.dns_forest;
+            smb.os_version = challenge.os_version;
+        }
+        None if smb1.is_none() => return None,
+        None => {}
+    }
+    Some(smb)
+}
+
+// Function to connect to `addr` before `deadline`, opening a NetBIOS session for *SMBSERVER
+// first if `netbios` is set.
+async fn connect(
+    addr: SocketAddr,
+    netbios: bool,
+    settings: &Settings,
+    deadline: Instant,
+) -> io::Result<TcpStream> {
+    let timed_out = |_| io::Error::from(io::ErrorKind::TimedOut);
+    let socket = TcpSocket::from_std_stream(settings.socket(addr, Type::STREAM).await?.into());
+    let mut stream = timeout_at(deadline, socket.connect(addr))
+        .await
+        .map_err(timed_out)??;
+    if netbios {
+        let mut request = vec![0x81, 0, 0, 68];
+        request.extend_from_slice(&netbios_name(b"*SMBSERVER"));
+        request.extend_from_slice(&netbios_name(b"PORTSNIFFER"));
+        stream.write_all(&request).await?;
+        let mut answer = [0u8; 4];
+        timeout_at(deadline, stream.read_exact(&mut answer))
+            .await
+            .map_err(timed_out)??;
+        // 0x82 is a positive session response.
+        if answer[0] != 0x82 {
+            return Err(io::Error::from(io::ErrorKind::ConnectionRefused));
+        }
+    }
+    Ok(stream)
+}
+
+// Function to encode a NetBIOS name as the session service wants it: padded with spaces to 16
+// bytes, each half byte turned into a letter from A, and framed as one label.
+fn netbios_name(name: &[u8]) -> Vec<u8> {
+    let mut padded = name.to_vec();
+    padded.resize(16, b' ');
+    let mut encoded = vec![32];
+    for byte in padded {
+        encoded.extend_from_slice(&[b'A' + (byte >> 4), b'A' + (byte & 0x0f)]);
+    }
+    encoded.push(0);
+    encoded
+}
+
+// Function to send `message` in a session message and read the answer, before `deadline`.
+async fn exchange(
+    stream: &mut TcpStream,
+    message: &[u8],
+    deadline: Instant,
+) -> io::Result<Vec<u8>> {
+    let timed_out = |_| io::Error::from(io::ErrorKind::TimedOut);
+    let mut framed = (message.len() as u32).to_be_bytes().to_vec();
+    framed.extend_from_slice(message);
+    stream.write_all(&framed).await?;
+    let mut header = [0u8; 4];
+    timeout_at(deadline, stream.read_exact(&mut header))
+        .await
+        .map_err(timed_out)??;
+    let length = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+    if header[0] != 0 || length > MAX_MESSAGE {
+        return Err(io::ErrorKind::InvalidData.into());
+    }
+    let mut message = vec![0u8; length];
+    timeout_at(deadline, stream.read_exact(&mut message))
+        .await
+        .map_err(timed_out)??;
+    Ok(message)
+}
+
+// Function to build an SMB1 NEGOTIATE that offers NT LM 0.12 alone, the dialect of every SMB1
+// server since Windows NT.
+fn smb1_negotiate() -> Vec<u8> {
+    let mut message = b"\xffSMB\x72".to_vec();
+    message.extend_from_slice(&[0; 4]); // Status.
+    message.push(0x18); // Flags: canonical, case-insensitive paths.
+    message.extend_from_slice(&0xc001u16.to_le_bytes()); // Flags2: Unicode, NT status, long names.
+    message.extend_from_slice(&[0; 12]); // PID high, security features, reserved.
+    message.extend_from_slice(&[0, 0, 0x50, 0x53, 0, 0, 0, 0]); // TID, PID, UID, MID.
+    message.push(0); // No parameter words.
+    let dialects = b"\x02NT LM 0.12\x00";
+    message.extend_from_slice(&(dialects.len() as u16).to_le_bytes());
+    message.extend_from_slice(dialects);
+    message
+}
+
+// Function to read an SMB1 NEGOTIATE response that agreed on the dialect offered. Gives its
+// security mode.
+fn smb1_response(message: &[u8]) -> Option<u8> {
+    if !message.starts_with(b"\xffSMB\x72") || message.get(5..9)? != [0; 4] {
+        return None;
+    }
+    // 17 parameter words, starting with the index of the dialect agreed on.
+    match (message.get(32)?, u16_at(message, 33)?) {
+        (17, 0) => message.get(35).copied(),
+        _ => None,
+    }
+}
+
+// Function to build the 64-byte header of an SMB2 request.
+fn header(command: u16, message_id: u64) -> Vec<u8> {
+    let mut header = b"\xfeSMB".to_vec();
+    header.extend_from_slice(&64u16.to_le_bytes());
+    header.extend_from_slice(&[0; 6]); // Credit charge and status.
+    header.extend_from_slice(&command.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes()); // Credits asked for.
+    header.extend_from_slice(&[0; 8]); // Flags and next command.
+    header.extend_from_slice(&message_id.to_le_bytes());
+    header.extend_from_slice(&[0; 32]); // Process, tree and session IDs and the signature.
+    header
+}
+
+// Function to build an SMB2 NEGOTIATE offering `DIALECTS`, with the preauth integrity context
+// that 3.1.1 needs.
+fn smb2_negotiate() -> Vec<u8> {
+    let mut message = header(NEGOTIATE, 0);
+    message.extend_from_slice(&36u16.to_le_bytes());
+    message.extend_from_slice(&(DIALECTS.len() as u16).to_le_bytes());
+    message.extend_from_slice(&1u16.to_le_bytes()); // Signing enabled, not required.
+    message.extend_from_slice(&[0; 6]); // Reserved and capabilities.
+    message.extend_from_slice(&rand::random::<[u8; 16]>()); // Client GUID.
+                                                            // The contexts follow the dialects, 8-byte aligned.
+    let contexts = (100 + 2 * DIALECTS.len()).next_multiple_of(8);
+    message.extend_from_slice(&(contexts as u32).to_le_bytes());
+    message.extend_from_slice(&[1, 0, 0, 0]); // One context.
+    for dialect in DIALECTS {
+        message.extend_from_slice(&dialect.to_le_bytes());
+    }
+    message.resize(contexts, 0);
+    // SMB2_PREAUTH_INTEGRITY_CAPABILITIES: SHA-512 with a 32-byte salt.
+    message.extend_from_slice(&[1, 0, 38, 0, 0, 0, 0, 0, 1, 0, 32, 0, 1, 0]);
+    message.extend_from_slice(&rand::random::<[u8; 32]>());
+    message
+}
+
+// Function to read an SMB2 NEGOTIATE response, giving the dialect it agreed on and its
+// security mode.
+fn negotiate_response(message: &[u8]) -> Option<(u16, u16)> {
+    if !message.starts_with(b"\xfeSMB") || u32_at(message, 8)? != 0 {
+        return None;
+    }
+    if u16_at(message, 12)? != NEGOTIATE || u16_at(message, 64)? != 65 {
+        return None;
+    }
+    Some((u16_at(message, 68)?, u16_at(message, 66)?))
+}
+
+// Function to build an SMB2 SESSION_SETUP carrying an NTLM NEGOTIATE_MESSAGE in an SPNEGO token,
+// which servers answer with the NTLM challenge.
+fn session_setup() -> Vec<u8> {
+    let mut ntlm = b"NTLMSSP\x00\x01\x00\x00\x00".to_vec();
+    ntlm.extend_from_slice(&NTLM_FLAGS.to_le_bytes());
+    ntlm.extend_from_slice(&[0; 16]); // No domain or workstation.
+    ntlm.extend_from_slice(&[10, 0, 0x63, 0x45, 0, 0, 0, 15]); // Version 10.0.17763, NTLM 15.
+    let mechanisms = der(0xa0, &der(0x30, &der(0x06, NTLMSSP)));
+    let token = der(0xa2, &der(0x04, &ntlm));
+    let init = der(0xa0, &der(0x30, &[mechanisms, token].concat()));
+    let spnego = der(0x60, &[der(0x06, SPNEGO), init].concat());
+
+    let mut message = header(SESSION_SETUP, 1);
+    message.extend_from_slice(&25u16.to_le_bytes());
+    message.extend_from_slice(&[0, 1]); // No flags, signing enabled.
+    message.extend_from_slice(&[0; 8]); // Capabilities and channel.
+    message.extend_from_slice(&88u16.to_le_bytes()); // The token follows the 24 fixed bytes.
+    message.extend_from_slice(&(spnego.len() as u16).to_le_bytes());
+    message.extend_from_slice(&[0; 8]); // No previous session.
+    message.extend_from_slice(&spnego);
+    message
+}
+
+// Function to read the NTLM CHALLENGE_MESSAGE out of the answer to `session_setup`, with the
+// names of its target info and the version of its server.
+fn challenge(message: &[u8]) -> Option<Challenge> {
+    if !message.starts_with(b"\xfeSMB") || u32_at(message, 8)? != MORE_PROCESSING_REQUIRED {
+        return None;
+    }
+    let start = message
+        .windows(12)
+        .position(|w| w == b"NTLMSSP\x00\x02\x00\x00\x00")?;
+    let ntlm = &message[start..];
+    let info_length = usize::from(u16_at(ntlm, 40)?);
+    let info_offset = u32_at(ntlm, 44)? as usize;
+    let mut info = ntlm.get(info_offset..info_offset + info_length)?;
+    let mut challenge = Challenge::default();
+    if u32_at(ntlm, 20)? & 0x0200_0000 != 0 && info_offset >= 56 {
+        challenge.os_version = Some(format!("{}.{}.{}", ntlm[48], ntlm[49], u16_at(ntlm, 50)?));
+    }
+    // AV_PAIRs: an ID, a length and a UTF-16 value, up to the ID 0.
+    while let (Some(id), Some(length)) = (u16_at(info, 0), u16_at(info, 2)) {
+        let value = info.get(4..4 + usize::from(length))?;
+        let units: Vec<u16> = value
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        let text = Some(String::from_utf16_lossy(&units)).filter(|text| !text.is_empty());
+        match id {
+            0 => break,
+            1 => challenge.netbios_name = text,
+            2 => challenge.netbios_domain = text,
+            3 => challenge.dns_name = text,
+            4 => challenge.dns_domain = text,
+            5 => challenge.dns_forest = text,
+            _ => {}
+        }
+        info = &info[4 + usize::from(length)..];
+    }
+    Some(challenge)
+}
+
+// Function to name an SMB2 dialect, e.g. `3.1.1` for 0x0311.
+fn dialect_name(dialect: u16) -> String {
+    match dialect {
+        0x0202 => "2.0.2".to_string(),
+        0x0210 => "2.1".to_string(),
+        0x0300 => "3.0".to_string(),
+        0x0302 => "3.0.2".to_string(),
+        0x0311 => "3.1.1".to_string(),
+        other => format!("{:#06x}", other),
+    }
+}
+
+// Function to read a little-endian u16 at `pos`.
+fn u16_at(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+// Function to read a little-endian u32 at `pos`.
+fn u32_at(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Function to build an SMB2 response header for `command` with `status`.
+    fn response_header(command: u16, status: u32) -> Vec<u8> {
+        let mut header = header(command, 0);
+        header[8..12].copy_from_slice(&status.to_le_bytes());
+        header
+    }
+
+    #[test]
+    fn builds_requests() {
+        let negotiate = smb2_negotiate();
+        assert_eq!(&negotiate[..4], b"\xfeSMB");
+        assert_eq!(u16_at(&negotiate, 64), Some(36));
+        assert_eq!(u32_at(&negotiate, 64 + 28), Some(112));
+        assert_eq!(u16_at(&negotiate, 100), Some(0x0202));
+        assert_eq!(u16_at(&negotiate, 108), Some(0x0311));
+        assert_eq!(u16_at(&negotiate, 112), Some(1));
+        assert_eq!(negotiate.len(), 112 + 8 + 38);
+
+        let setup = session_setup();
+        assert_eq!(u16_at(&setup, 12), Some(SESSION_SETUP));
+        let length = usize::from(u16_at(&setup, 78).unwrap());
+        assert_eq!(setup.len(), 88 + length);
+        assert_eq!(setup[88], 0x60);
+        assert!(setup.windows(8).any(|w| w == b"NTLMSSP\x00"));
+
+        assert_eq!(smb1_negotiate()[32..], *b"\x00\x0c\x00\x02NT LM 0.12\x00");
+        let name = netbios_name(b"*SMBSERVER");
+        assert_eq!(name.len(), 34);
+        assert_eq!(&name[..5], b"\x20CKFD");
+    }
+
+    #[test]
+    fn reads_negotiate_responses() {
+        let mut response = response_header(NEGOTIATE, 0);
+        response.extend_from_slice(&65u16.to_le_bytes());
+        response.extend_from_slice(&3u16.to_le_bytes());
+        response.extend_from_slice(&0x0311u16.to_le_bytes());
+        response.extend_from_slice(&[0; 58]);
+        assert_eq!(negotiate_response(&response), Some((0x0311, 3)));
+        response[8] = 0x22;
+        assert_eq!(negotiate_response(&response), None);
+
+        let mut smb1 = smb1_negotiate()[..32].to_vec();
+        smb1.extend_from_slice(&[17, 0, 0, 0x03]);
+        assert_eq!(smb1_response(&smb1), Some(0x03));
+        smb1[33] = 0xff;
+        assert_eq!(smb1_response(&smb1), None);
+    }
+
+    #[test]
+    fn reads_challenges() {
+        let utf16 =
+            |text: &str| -> Vec<u8> { text.encode_utf16().flat_map(u16::to_le_bytes).collect() };
+        let mut info = vec![];
+        for (id, text) in [(2, "CORP"), (1, "FILES1"), (3, "files1.corp.test"), (0, "")] {
+            info.extend_from_slice(&(id as u16).to_le_bytes());
+            info.extend_from_slice(&(utf16(text).len() as u16).to_le_bytes());
+            info.extend_from_slice(&utf16(text));
+        }
+        let mut ntlm = b"NTLMSSP\x00\x02\x00\x00\x00".to_vec();
+        ntlm.extend_from_slice(&[0; 8]); // Target name.
+        ntlm.extend_from_slice(&0x0200_0000u32.to_le_bytes());
+        ntlm.extend_from_slice(&[0; 16]); // Challenge and reserved.
+        ntlm.extend_from_slice(&(info.len() as u16).to_le_bytes());
+        ntlm.extend_from_slice(&(info.len() as u16).to_le_bytes());
+        ntlm.extend_from_slice(&56u32.to_le_bytes());
+        ntlm.extend_from_slice(&[10, 0, 0x24, 0x4f, 0, 0, 0, 15]);
+        ntlm.extend_from_slice(&info);
+        let mut response = response_header(SESSION_SETUP, MORE_PROCESSING_REQUIRED);
+        response.extend_from_slice(&[9, 0, 0, 0, 72, 0, 0, 0]);
+        response.extend_from_slice(&ntlm);
+
+        assert_eq!(
+            challenge(&response),
+            Some(Challenge {
+                netbios_name: Some("FILES1".to_string()),
+                netbios_domain: Some("CORP".to_string()),
+                dns_name: Some("files1.corp.test".to_string()),
+                dns_domain: None,
+                dns_forest: None,
+                os_version: Some("10.0.20260".to_string()),
+            })
+        );
+        assert_eq!(challenge(&response[..response.len() - 10]), None);
+        assert_eq!(dialect_name(0x0302), "3.0.2");
+    }
+}
@@ -0,0 +1,155 @@
+use crate::ftp; // Import the reply reader, as SMTP replies take the form of FTP ones.
+use crate::scanner::{self, PortResult, PortState, Protocol, Settings}; // Import the open ports to probe and the scan settings to connect with.
+use crate::targets::Target; // Import the hosts the ports belong to.
+use serde::Serialize; // Import `Serialize` to report the servers in the structured output.
+use socket2::Type; // Import socket types for the connects.
+use std::io; // Import I/O errors for socket failures.
+use std::net::{IpAddr, SocketAddr}; // Import addresses for the ports probed and the EHLO greeting.
+use std::time::Duration; // Import `Duration` for the connect and read timeouts.
+use tokio::io::AsyncWriteExt; // Import writes on the connected streams.
+use tokio::net::{TcpSocket, TcpStream}; // Import sockets of the source for the connects.
+use tokio::time::{timeout_at, Instant}; // Import deadlines to give up on slow servers.
+
+// Define the sender and the recipient of the relay test, in domains reserved for examples
+// (RFC 2606), so no mail could ever reach anyone.
+const RELAY_FROM: &str = "relay-test@example.com";
+const RELAY_TO: &str = "relay-test@example.org";
+
+// What an SMTP server said about itself and whether it relays.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Smtp {
+    // The last line of its greeting, e.g. `220 mail.example.com ESMTP Postfix`.
+    pub greeting: String,
+    // The extensions its EHLO answer listed, e.g. `SIZE 10240000` and `STARTTLS`.
+    pub capabilities: Vec<String>,
+    pub starttls: bool,
+    // `true` when it accepted a recipient of an outside domain from an outside sender, `false`
+    // when it refused the recipient and `None` when the test said neither, e.g. because the
+    // sender was refused or the answer only said to try again later.
+    pub open_relay: Option<bool>,
+    // The answer the verdict comes from.
+    pub relay_reply: Option<String>,
+}
+
+// Function to probe every open TCP port in `results` that may run SMTP: ports 25, 587 and 2525,
+// ports --service-version named smtp and ports whose banner is an SMTP greeting. Each gets
+// `wait` for the connect and the whole conversation together.
+pub async fn probe_all(
+    hosts: &[Target],
+    results: &mut [PortResult],
+    settings: &Settings,
+    wait: Duration,
+) {
+    scanner::check_ports(
+        hosts,
+        results,
+        settings.concurrency,
+        |r, _| {
+            let smtp = matches!(r.port, 25 | 587 | 2525)
+                || r.findings
+                    .detected
+                    .as_ref()
+                    .is_some_and(|s| s.name == "smtp")
+                || r.findings.banner.as_ref().is_some_and(|b| {
+                    b.starts_with("220") && b.to_ascii_lowercase().contains("smtp")
+                });
+            (r.protocol == Protocol::Tcp && r.state == PortState::Open && smtp).then_some(())
+        },
+        |addr, ()| async move { probe(addr, settings, wait).await.ok().flatten() },
+        |r, smtp| r.findings.smtp = smtp,
+    )
+    .await;
+}
+
+// Function to connect to `addr`, greet it with EHLO and test whether it relays: a MAIL FROM and
+// a RCPT TO for outside domains, then RSET and QUIT. No DATA is ever sent, so nothing gets
+// delivered. Gives `None` if the port does not greet like an SMTP server.
+async fn probe(addr: SocketAddr, settings: &Settings, wait: Duration) -> io::Result<Option<Smtp>> {
+    let deadline = Instant::now() + wait;
+    let socket = TcpSocket::from_std_stream(settings.socket(addr, Type::STREAM).await?.into());
+    let mut stream = timeout_at(deadline, socket.connect(addr))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    let mut received = vec![];
+    let (code, mut greeting) = ftp::answer(&mut stream, &mut received, deadline).await?;
+    if code != 220 {
+        return Ok(None);
+    }
+    // The address literal of our end is a valid EHLO name without any DNS behind it.
+    let name = match stream.local_addr()?.ip() {
+        IpAddr::V4(ip) => format!("[{}]", ip),
+        IpAddr::V6(ip) => format!("[IPv6:{}]", ip),
+    };
+    let (code, lines) = command(
+        &mut stream,
+        &mut received,
+        deadline,
+        &format!("EHLO {}", name),
+    )
+    .await?;
+    let capabilities: Vec<String> = match code {
+        250 => lines
+            .iter()
+            .skip(1)
+            .map(|line| line.get(4..).unwrap_or("").trim().to_string())
+            .filter(|capability| !capability.is_empty())
+            .collect(),
+        // Servers without ESMTP only know HELO.
+        _ => {
+            command(
+                &mut stream,
+                &mut received,
+                deadline,
+                &format!("HELO {}", name),
+            )
+            .await?;
+            vec![]
+        }
+    };
+    let starttls = capabilities
+        .iter()
+        .any(|capability| capability.eq_ignore_ascii_case("STARTTLS"));
+    let mut smtp = Smtp {
+        greeting: greeting.pop().unwrap_or_default(),
+        capabilities,
+        starttls,
+        open_relay: None,
+        relay_reply: None,
+    };
+
+    let mail = format!("MAIL FROM:<{}>", RELAY_FROM);
+    let (code, mut lines) = command(&mut stream, &mut received, deadline, &mail).await?;
+    if code == 250 {
+        let rcpt = format!("RCPT TO:<{}>", RELAY_TO);
+        let (rcpt_code, rcpt_lines) = command(&mut stream, &mut received, deadline, &rcpt).await?;
+        smtp.open_relay = relay_verdict(rcpt_code);
+        lines = rcpt_lines;
+        // Some servers hang up after refusing a recipient.
+        let _ = command(&mut stream, &mut received, deadline, "RSET").await;
+    }
+    smtp.relay_reply = lines.pop();
+    // Leave politely; the answer does not matter.
+    let _ = stream.write_all(b"QUIT\r\n").await;
+    Ok(Some(smtp))
+}
+
+// Function to send `line` and read the answer to it.
+async fn command(
+    stream: &mut TcpStream,
+    received: &mut Vec<u8>,
+    deadline: Instant,
+    line: &str,
+) -> io::Result<(u16, Vec<String>)> {
+    stream.write_all(format!("{}\r\n", line).as_bytes()).await?;
+    ftp::answer(stream, received, deadline).await
+}
+
+// Function to tell from the answer code to the outside RCPT TO whether the server relays. A
+// temporary failure, e.g. greylisting, says neither.
+fn relay_verdict(code: u16) -> Option<bool> {
+    match code {
+        250 | 251 => Some(true),
+        500..=599 => Some(false),
+        _ => None,
+    }
+}
@@ -0,0 +1,290 @@
+use crate::scanner::{self, PortResult, PortState, Protocol, Reason, Settings}; // Import the UDP ports to probe and the scan settings to probe with.
+use crate::targets::Target; // Import the hosts the ports belong to.
+use crate::x509::{der, Der}; // Import the BER writer and reader of the messages.
+use serde::Serialize; // Import `Serialize` to report the agents in the structured output.
+use socket2::Type; // Import socket types for the probes.
+use std::io; // Import I/O errors for socket failures.
+use std::net::SocketAddr; // Import `SocketAddr` for the ports probed.
+use std::time::Duration; // Import `Duration` for the response timeouts.
+use tokio::net::UdpSocket; // Import sockets of the source for the probes.
+use tokio::time::{timeout_at, Instant}; // Import deadlines to stop waiting for answers.
+
+// Define the BER tags of SNMP messages.
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const OID: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const GET_REQUEST: u8 = 0xa0;
+const GET_RESPONSE: u8 = 0xa2;
+
+// Define the encoded OIDs of sysDescr.0 (1.3.6.1.2.1.1.1.0) and sysName.0 (1.3.6.1.2.1.1.5.0).
+const SYS_DESCR: &[u8] = &[0x2b, 6, 1, 2, 1, 1, 1, 0];
+const SYS_NAME: &[u8] = &[0x2b, 6, 1, 2, 1, 1, 5, 0];
+
+// Define the request ID of the first community; the others count up from it.
+const FIRST_ID: u32 = 0x5053_0000;
+
+// What an SNMP agent gave away to the communities tried.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Snmp {
+    // The communities it answered, in the order they were tried.
+    pub communities: Vec<String>,
+    // Its sysDescr and sysName, e.g. `Cisco IOS Software, C2960 ...` and `core-sw1`.
+    pub descr: Option<String>,
+    pub name: Option<String>,
+}
+
+// A GetResponse, with the values of the variables that are strings.
+#[derive(Debug, PartialEq, Eq)]
+struct Response {
+    id: u32,
+    error: u32,
+    values: Vec<(Vec<u8>, String)>,
+}
+
+// Function to read a comma-separated list of --snmp-communities, or give `public` alone.
+pub fn communities(spec: Option<&str>) -> Result<Vec<String>, String> {
+    let Some(spec) = spec else {
+        return Ok(vec!["public".to_string()]);
+    };
+    let communities: Vec<String> = spec.split(',').map(str::to_string).collect();
+    match communities.iter().find(|c| c.is_empty() || c.len() > 255) {
+        Some(_) => Err(format!(
+            "SNMP communities {:?} have to be between 1 and 255 characters each",
+            spec
+        )),
+        None => Ok(communities),
+    }
+}
+
+// Function to ask every UDP port 161 in `results` for sysDescr and sysName with each of the
+// `communities`, waiting `wait` for the answers. Open and filtered ports are both asked:
+// agents stay silent to communities they do not know, so the scan may have found no answer.
+// Filtered ports that got one are open.
+pub async fn probe_all(
+    hosts: &[Target],
+    results: &mut [PortResult],
+    communities: &[String],
+    settings: &Settings,
+    wait: Duration,
+) {
+    let keep = |r: &mut PortResult, snmp: Option<Snmp>| {
+        if snmp.is_some() && r.state == PortState::Filtered {
+            r.state = PortState::Open;
+            r.reason = Reason::UdpResponse;
+        }
+        r.findings.snmp = snmp;
+    };
+    scanner::check_ports(
+        hosts,
+        results,
+        settings.concurrency,
+        |r, _| {
+            let asked = matches!(r.state, PortState::Open | PortState::Filtered);
+            (r.protocol == Protocol::Udp && r.port == 161 && asked).then_some(())
+        },
+        |addr, ()| async move {
+            probe(addr, communities, settings, wait)
+                .await
+                .ok()
+                .flatten()
+        },
+        keep,
+    )
+    .await;
+}
+
+// Function to send a get-request with each community to `addr` at once and collect the answers
+// until all came or `wait` is over. Gives `None` if no community was answered.
+async fn probe(
+    addr: SocketAddr,
+    communities: &[String],
+    settings: &Settings,
+    wait: Duration,
+) -> io::Result<Option<Snmp>> {
+    let socket = UdpSocket::from_std(settings.socket(addr, Type::DGRAM).await?.into())?;
+    socket.connect(addr).await?;
+    for (id, community) in (FIRST_ID..).zip(communities) {
+        socket.send(&request(community, id)).await?;
+    }
+    let deadline = Instant::now() + wait;
+    let mut answered = vec![false; communities.len()];
+    let mut snmp = Snmp {
+        communities: vec![],
+        descr: None,
+        name: None,
+    };
+    let mut buffer = vec![0u8; 4096];
+    while answered.contains(&false) {
+        let Ok(read) = timeout_at(deadline, socket.recv(&mut buffer)).await else {
+            break;
+        };
+        let Some(response) = response(&buffer[..read?]) else {
+            continue;
+        };
+        let Some(index) = response.id.checked_sub(FIRST_ID).map(|i| i as usize) else {
+            continue;
+        };
+        if index >= communities.len() || answered[index] || response.error != 0 {
+            continue;
+        }
+        answered[index] = true;
+        for (oid, value) in response.values {
+            match oid.as_slice() {
+                SYS_DESCR => snmp.descr.get_or_insert(value),
+                SYS_NAME => snmp.name.get_or_insert(value),
+                _ => continue,
+            };
+        }
+    }
+    snmp.communities = communities
+        .iter()
+        .zip(&answered)
+        .filter(|(_, &answered)| answered)
+        .map(|(community, _)| community.clone())
+        .collect();
+    Ok((!snmp.communities.is_empty()).then_some(snmp))
+}
+
+// Function to build an SNMP version 1 get-request for sysDescr.0 and sysName.0.
+fn request(community: &str, id: u32) -> Vec<u8> {
+    let bindings: Vec<u8> = [SYS_DESCR, SYS_NAME]
+        .iter()
+        .flat_map(|oid| der(SEQUENCE, &[der(OID, oid), der(NULL, &[])].concat()))
+        .collect();
+    let pdu = [
+        integer(id),
+        integer(0),
+        integer(0),
+        der(SEQUENCE, &bindings),
+    ]
+    .concat();
+    let message = [
+        integer(0),
+        der(OCTET_STRING, community.as_bytes()),
+        der(GET_REQUEST, &pdu),
+    ]
+    .concat();
+    der(SEQUENCE, &message)
+}
+
+// Function to encode a non-negative INTEGER in the fewest bytes.
+fn integer(number: u32) -> Vec<u8> {
+    let bytes = number.to_be_bytes();
+    let skip = bytes.iter().take(3).take_while(|&&b| b == 0).count();
+    let mut contents = bytes[skip..].to_vec();
+    // A set top bit would make it negative.
+    if contents[0] & 0x80 != 0 {
+        contents.insert(0, 0);
+    }
+    der(INTEGER, &contents)
+}
+
+// Function to read a non-negative INTEGER of up to four bytes, past the zero byte that keeps a
+// set top bit from making it negative.
+fn read_integer(contents: &[u8]) -> Option<u32> {
+    if contents.first().is_none_or(|b| b & 0x80 != 0) {
+        return None;
+    }
+    let contents = match contents {
+        [0, rest @ ..] if !rest.is_empty() => rest,
+        _ => contents,
+    };
+    (contents.len() <= 4).then(|| {
+        contents
+            .iter()
+            .fold(0u32, |number, &b| number << 8 | u32::from(b))
+    })
+}
+
+// Function to read a GetResponse of any SNMP version, keeping the variables whose values are
+// strings with the control characters left out.
+fn response(datagram: &[u8]) -> Option<Response> {
+    let mut message = Der {
+        data: Der { data: datagram }.expect(SEQUENCE).ok()?,
+    };
+    message.expect(INTEGER).ok()?;
+    message.expect(OCTET_STRING).ok()?;
+    let mut pdu = Der {
+        data: message.expect(GET_RESPONSE).ok()?,
+    };
+    let id = read_integer(pdu.expect(INTEGER).ok()?)?;
+    let error = read_integer(pdu.expect(INTEGER).ok()?)?;
+    pdu.expect(INTEGER).ok()?;
+    let mut bindings = Der {
+        data: pdu.expect(SEQUENCE).ok()?,
+    };
+    let mut values = vec![];
+    while !bindings.data.is_empty() {
+        let mut binding = Der {
+            data: bindings.expect(SEQUENCE).ok()?,
+        };
+        let oid = binding.expect(OID).ok()?;
+        if let Ok(value) = binding.expect(OCTET_STRING) {
+            let text: String = String::from_utf8_lossy(value)
+                .chars()
+                .map(|c| if c.is_control() { ' ' } else { c })
+                .collect();
+            values.push((oid.to_vec(), text.trim().to_string()));
+        }
+    }
+    Some(Response { id, error, values })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_requests() {
+        // The same request as the sysDescr payload of the UDP scan, with sysName added.
+        let packet = request("public", 0x5053_4e46);
+        assert_eq!(
+            &packet[..27],
+            b"\x30\x37\x02\x01\x00\x04\x06public\xa0\x2a\x02\x04\x50\x53\x4e\x46\x02\x01\x00\x02\x01\x00"
+        );
+        assert_eq!(packet.len(), 0x39);
+        assert_eq!(integer(0x80), b"\x02\x02\x00\x80");
+        assert_eq!(der(OCTET_STRING, &[b'a'; 200])[..3], [0x04, 0x81, 200]);
+        assert_eq!(communities(None).unwrap(), ["public"]);
+        assert_eq!(
+            communities(Some("public,private")).unwrap(),
+            ["public", "private"]
+        );
+        assert!(communities(Some("public,")).is_err());
+    }
+
+    #[test]
+    fn reads_responses() {
+        let descr = der(
+            SEQUENCE,
+            &[der(OID, SYS_DESCR), der(OCTET_STRING, b"Linux gw 6.1\r\n")].concat(),
+        );
+        let name = der(SEQUENCE, &[der(OID, SYS_NAME), der(0x80, &[])].concat());
+        let pdu = [
+            integer(FIRST_ID + 1),
+            integer(0),
+            integer(0),
+            der(SEQUENCE, &[descr, name].concat()),
+        ]
+        .concat();
+        let message = [
+            integer(1),
+            der(OCTET_STRING, b"private"),
+            der(GET_RESPONSE, &pdu),
+        ]
+        .concat();
+        assert_eq!(
+            response(&der(SEQUENCE, &message)),
+            Some(Response {
+                id: FIRST_ID + 1,
+                error: 0,
+                values: vec![(SYS_DESCR.to_vec(), "Linux gw 6.1".to_string())],
+            })
+        );
+        assert_eq!(response(&request("public", 1)), None);
+        assert_eq!(read_integer(&[0x00, 0xff]), Some(255));
+        assert_eq!(read_integer(&[0xff]), None);
+    }
+}
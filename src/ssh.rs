@@ -0,0 +1,337 @@
+use crate::scanner::{self, PortResult, PortState, Protocol, Settings}; // Import the open ports to audit and the scan settings to connect with.
+use crate::targets::Target; // Import the hosts the ports belong to.
+use serde::Serialize; // Import `Serialize` to report the algorithms in the structured output.
+use socket2::Type; // Import socket types for the connects.
+use std::io; // Import I/O errors for socket failures.
+use std::net::SocketAddr; // Import `SocketAddr` for the ports audited.
+use std::time::Duration; // Import `Duration` for the connect and read timeouts.
+use tokio::io::{AsyncReadExt, AsyncWriteExt}; // Import reads and writes on the connected streams.
+use tokio::net::{TcpSocket, TcpStream}; // Import sockets of the source for the connects.
+use tokio::time::{timeout_at, Instant}; // Import deadlines to give up on slow servers.
+
+// Define how much a server may send before its identification string and how long a key
+// exchange packet may be, as RFC 4253 bounds them.
+const MAX_PREAMBLE: usize = 8 * 1024;
+const MAX_PACKET: usize = 35_000;
+
+// Define the message number of SSH_MSG_KEXINIT.
+const KEXINIT: u8 = 20;
+
+// What an SSH server told about itself before any authentication.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Ssh {
+    // The protocol version of its identification string, e.g. `2.0`, or `1.99` for a server
+    // speaking both versions.
+    pub protocol: String,
+    // The software version, e.g. `OpenSSH_9.6p1`.
+    pub software: String,
+    // What follows it, e.g. `Ubuntu-3ubuntu13.5`.
+    pub comments: Option<String>,
+    // The key exchange methods, host key types, ciphers, MACs and compressions it offers, in its
+    // order of preference, each direction put together.
+    pub kex: Vec<String>,
+    pub host_keys: Vec<String>,
+    pub ciphers: Vec<String>,
+    pub macs: Vec<String>,
+    pub compression: Vec<String>,
+    // The deprecated algorithms of the lists, and `SSH-1` if it still speaks version 1.
+    pub deprecated: Vec<String>,
+}
+
+// Function to audit every open TCP port in `results` that may run SSH: port 22, ports whose
+// banner is an SSH identification and ports --service-version named ssh. Each gets `wait` for
+// the connect, the identification and the key exchange offer together.
+pub async fn audit_all(
+    hosts: &[Target],
+    results: &mut [PortResult],
+    settings: &Settings,
+    wait: Duration,
+) {
+    scanner::check_ports(
+        hosts,
+        results,
+        settings.concurrency,
+        |r, _| {
+            let ssh = r.port == 22
+                || r.findings
+                    .banner
+                    .as_ref()
+                    .is_some_and(|b| b.starts_with("SSH-"))
+                || r.findings
+                    .detected
+                    .as_ref()
+                    .is_some_and(|s| s.name == "ssh");
+            (r.protocol == Protocol::Tcp && r.state == PortState::Open && ssh).then_some(())
+        },
+        |addr, ()| async move { audit(addr, settings, wait).await.ok().flatten() },
+        |r, ssh| r.findings.ssh = ssh,
+    )
+    .await;
+}
+
+// Function to connect to `addr`, swap identification strings and read the key exchange offer
+// of the server, which it sends before knowing ours. Gives `None` if the port does not speak
+// SSH; servers of version 1 only have no offer to read.
+async fn audit(addr: SocketAddr, settings: &Settings, wait: Duration) -> io::Result<Option<Ssh>> {
+    let deadline = Instant::now() + wait;
+    let timed_out = |_| io::Error::from(io::ErrorKind::TimedOut);
+    let socket = TcpSocket::from_std_stream(settings.socket(addr, Type::STREAM).await?.into());
+    let mut stream = timeout_at(deadline, socket.connect(addr))
+        .await
+        .map_err(timed_out)??;
+    stream.write_all(b"SSH-2.0-port_sniffer\r\n").await?;
+    let mut received = vec![];
+    let (line, rest) = loop {
+        if let Some(found) = identification_line(&received) {
+            break found;
+        }
+        if received.len() > MAX_PREAMBLE || !read_more(&mut stream, &mut received, deadline).await?
+        {
+            return Ok(None);
+        }
+    };
+    let Some((protocol, software, comments)) = identification(&line) else {
+        return Ok(None);
+    };
+    let mut ssh = Ssh {
+        protocol,
+        software,
+        comments,
+        kex: vec![],
+        host_keys: vec![],
+        ciphers: vec![],
+        macs: vec![],
+        compression: vec![],
+        deprecated: vec![],
+    };
+    if ssh.protocol.starts_with('1') {
+        ssh.deprecated.push("SSH-1".to_string());
+    }
+    if ssh.protocol == "2.0" || ssh.protocol == "1.99" {
+        let mut received = rest;
+        let payload = loop {
+            match packet(&received) {
+                Ok(Some(payload)) => break payload,
+                Ok(None)
+                    if read_more(&mut stream, &mut received, deadline)
+                        .await
+                        .unwrap_or(false) => {}
+                _ => return Ok(Some(ssh)),
+            }
+        };
+        if let Some(lists) = name_lists(&payload) {
+            let both = |a: usize, b: usize| -> Vec<String> {
+                let mut names = lists[a].clone();
+                names.extend(lists[b].iter().filter(|n| !lists[a].contains(n)).cloned());
+                names
+            };
+            ssh.kex = lists[0].clone();
+            ssh.host_keys = lists[1].clone();
+            ssh.ciphers = both(2, 3);
+            ssh.macs = both(4, 5);
+            ssh.compression = both(6, 7);
+        }
+        let offered = [&ssh.kex, &ssh.host_keys, &ssh.ciphers, &ssh.macs];
+        let deprecated: Vec<String> = offered
+            .into_iter()
+            .flatten()
+            .filter(|name| deprecated(name))
+            .cloned()
+            .collect();
+        ssh.deprecated.extend(deprecated);
+    }
+    Ok(Some(ssh))
+}
+
+// Function to read what the server sends next onto `received` before `deadline`. Gives `false`
+// once it hung up.
+async fn read_more(
+    stream: &mut TcpStream,
+    received: &mut Vec<u8>,
+    deadline: Instant,
+) -> io::Result<bool> {
+    let mut buffer = [0u8; 4096];
+    let read = timeout_at(deadline, stream.read(&mut buffer))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    received.extend_from_slice(&buffer[..read]);
+    Ok(read > 0)
+}
+
+// Function to find the identification string in what a server sent so far, skipping the other
+// lines it may send first. Gives the line without its line break and what came after it.
+fn identification_line(received: &[u8]) -> Option<(String, Vec<u8>)> {
+    let mut start = 0;
+    while let Some(end) = received[start..].iter().position(|&b| b == b'\n') {
+        let line = &received[start..start + end];
+        if line.starts_with(b"SSH-") {
+            let line = String::from_utf8_lossy(line.strip_suffix(b"\r").unwrap_or(line));
+            return Some((line.into_owned(), received[start + end + 1..].to_vec()));
+        }
+        start += end + 1;
+    }
+    None
+}
+
+// Function to split an identification string such as `SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13.5`
+// into its protocol version, software version and comments.
+fn identification(line: &str) -> Option<(String, String, Option<String>)> {
+    let rest = line.strip_prefix("SSH-")?;
+    let (protocol, rest) = rest.split_once('-')?;
+    let (software, comments) = match rest.split_once(' ') {
+        Some((software, comments)) => (software, Some(comments.trim().to_string())),
+        None => (rest, None),
+    };
+    let printable = |text: &str| text.chars().all(|c| c.is_ascii_graphic() || c == ' ');
+    (printable(protocol) && printable(rest) && !protocol.is_empty()).then(|| {
+        (
+            protocol.to_string(),
+            software.to_string(),
+            comments.filter(|c| !c.is_empty()),
+        )
+    })
+}
+
+// Function to take the payload of the first binary packet out of `received`: a 4-byte length,
+// a padding length, the payload and the padding, unencrypted before the first key exchange.
+// Gives `None` while the packet is incomplete and an error for a malformed one.
+fn packet(received: &[u8]) -> Result<Option<Vec<u8>>, ()> {
+    let Some(header) = received.get(..5) else {
+        return Ok(None);
+    };
+    let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let padding = usize::from(header[4]);
+    if !(padding + 1..=MAX_PACKET).contains(&length) {
+        return Err(());
+    }
+    Ok(received
+        .get(5..4 + length - padding)
+        .map(|payload| payload.to_vec()))
+}
+
+// Function to read the ten name-lists of an SSH_MSG_KEXINIT payload, after its 16-byte cookie:
+// key exchange, host key, then ciphers, MACs, compressions and languages for each direction.
+fn name_lists(payload: &[u8]) -> Option<Vec<Vec<String>>> {
+    if payload.first() != Some(&KEXINIT) {
+        return None;
+    }
+    let mut rest = payload.get(17..)?;
+    let mut lists = vec![];
+    for _ in 0..10 {
+        let length = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+        let names = std::str::from_utf8(rest.get(4..4 + length)?).ok()?;
+        lists.push(
+            names
+                .split(',')
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect(),
+        );
+        rest = &rest[4 + length..];
+    }
+    Some(lists)
+}
+
+// Function to tell whether an algorithm is deprecated: key exchanges over SHA-1 or 1024-bit
+// groups, DSA keys and RSA signatures over SHA-1, CBC, RC4 and 64-bit block ciphers, MACs over
+// MD5, SHA-1 or RIPEMD-160 and truncated ones, and no cipher or MAC at all.
+fn deprecated(name: &str) -> bool {
+    let base = name.split('@').next().unwrap_or(name);
+    base.contains("sha1")
+        || base.starts_with("diffie-hellman-group1-")
+        || base.starts_with("rsa1024")
+        || base.starts_with("ssh-dss")
+        || base == "ssh-rsa"
+        || base == "ssh-rsa-cert-v01"
+        || base.contains("cbc")
+        || base.starts_with("arcfour")
+        || base.starts_with("3des")
+        || base.starts_with("des")
+        || base.starts_with("blowfish")
+        || base.starts_with("cast128")
+        || base.contains("md5")
+        || base.contains("ripemd160")
+        || base.ends_with("-96")
+        || base.starts_with("umac-64")
+        || base == "none"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_identification_strings() {
+        let (line, rest) =
+            identification_line(b"Welcome\r\nSSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13.5\r\n\x00\x00")
+                .unwrap();
+        assert_eq!(line, "SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13.5");
+        assert_eq!(rest, b"\x00\x00");
+        assert_eq!(identification_line(b"SSH-2.0-dropbear"), None);
+        assert_eq!(
+            identification(&line),
+            Some((
+                "2.0".to_string(),
+                "OpenSSH_9.6p1".to_string(),
+                Some("Ubuntu-3ubuntu13.5".to_string())
+            ))
+        );
+        assert_eq!(
+            identification("SSH-1.99-Cisco-1.25"),
+            Some(("1.99".to_string(), "Cisco-1.25".to_string(), None))
+        );
+        assert_eq!(identification("HTTP/1.1 400"), None);
+    }
+
+    #[test]
+    fn reads_key_exchange_offers() {
+        let mut payload = vec![KEXINIT];
+        payload.extend_from_slice(&[7; 16]);
+        let lists = [
+            "curve25519-sha256,diffie-hellman-group14-sha1",
+            "ssh-ed25519,ssh-rsa",
+            "aes128-ctr,aes256-cbc",
+            "aes128-ctr",
+            "hmac-sha2-256,hmac-sha1",
+            "hmac-sha2-256",
+            "none,zlib@openssh.com",
+            "none",
+            "",
+            "",
+        ];
+        for list in lists {
+            payload.extend_from_slice(&(list.len() as u32).to_be_bytes());
+            payload.extend_from_slice(list.as_bytes());
+        }
+        payload.extend_from_slice(&[0, 0, 0, 0, 0]);
+        let mut received = ((payload.len() + 5) as u32).to_be_bytes().to_vec();
+        received.push(4);
+        received.extend_from_slice(&payload);
+        assert_eq!(packet(&received[..received.len() - 1]), Ok(None));
+        received.extend_from_slice(&[0; 4]);
+        assert_eq!(packet(&received), Ok(Some(payload.clone())));
+        assert_eq!(packet(b"SSH-2.0-"), Err(()));
+
+        let lists = name_lists(&payload).unwrap();
+        assert_eq!(lists[1], ["ssh-ed25519", "ssh-rsa"]);
+        assert_eq!(lists[7], ["none"]);
+        assert!(lists[8].is_empty());
+        let flagged: Vec<&String> = lists[..6]
+            .iter()
+            .flatten()
+            .filter(|n| deprecated(n))
+            .collect();
+        assert_eq!(
+            flagged,
+            [
+                "diffie-hellman-group14-sha1",
+                "ssh-rsa",
+                "aes256-cbc",
+                "hmac-sha1"
+            ]
+        );
+        assert!(!deprecated("hmac-sha2-256-etm@openssh.com"));
+        assert!(!deprecated("rsa-sha2-512"));
+        assert!(name_lists(&[21, 0]).is_none());
+    }
+}
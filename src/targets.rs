@@ -0,0 +1,825 @@
+use crate::advertised::Advert; // Import `Advert` for the services hosts advertise on a local network.
+use crate::resolver::{LookupError, Resolver}; // Import the resolver used for hostnames.
+use std::collections::HashMap; // Import `HashMap` to merge duplicate addresses.
+#[cfg(unix)]
+use std::ffi::{CStr, CString}; // Import C string types to talk to the interface-name APIs.
+use std::fmt; // Import formatting traits to display targets in reports.
+use std::fs; // Import filesystem access to read target lists.
+use std::io::{self, Read}; // Import stdin access to read piped target lists.
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6}; // Import IP address types for network operations.
+use std::str::FromStr; // Import `FromStr` to parse --input-format.
+use std::sync::Arc; // Import `Arc` to share the resolver between lookups.
+use std::time::Duration; // Import `Duration` for the round-trip times of traced hops.
+use tokio::sync::Semaphore; // Import `Semaphore` to bound concurrent DNS lookups.
+
+// Upper bound on the number of hosts a single CIDR block or octet range may expand to (a /12 for IPv4).
+const MAX_CIDR_HOSTS: u128 = 1 << 20;
+
+// Number of target specifications resolved at the same time (e.g. for long subdomain lists).
+const MAX_PARALLEL_LOOKUPS: usize = 64;
+
+// A MAC address, shown as six colon-separated hex bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mac(pub [u8; 6]);
+
+impl fmt::Display for Mac {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            a, b, c, d, e, g
+        )
+    }
+}
+
+// One hop of a route: the address that answered the probe with this TTL and how long it took,
+// or nothing if no answer came back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hop {
+    pub ttl: u8,
+    pub addr: Option<IpAddr>,
+    pub rtt: Option<Duration>,
+}
+
+// How a traced route ends. Only traceroutes, which need Linux, build routes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub enum End {
+    // The host answered the connect itself.
+    Reached,
+    // The last hop reported the host unreachable, as filters rejecting the probe do.
+    Unreachable,
+    // Nothing answered beyond the last hop, as filters dropping the probe do.
+    Lost,
+}
+
+impl End {
+    // Function to name the end of a route in reports.
+    pub fn name(self) -> &'static str {
+        match self {
+            End::Reached => "reached",
+            End::Unreachable => "unreachable",
+            End::Lost => "lost",
+        }
+    }
+}
+
+// The route to an open port of a host, hop by hop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub port: u16,
+    pub hops: Vec<Hop>,
+    pub end: End,
+}
+
+// A single host to be scanned, remembering the hostnames that resolved to it (if any).
+// `scope_id` is the interface index of IPv6 link-local addresses (the `%eth0` zone), 0 otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub addr: IpAddr,
+    pub scope_id: u32,
+    pub hostnames: Vec<String>,
+    // Name found by a reverse (PTR) lookup when --resolve is used.
+    pub ptr: Option<String>,
+    // User-supplied labels (e.g. environment or role) carried into structured output.
+    pub tags: Vec<String>,
+    // Set when --host-timeout gave up on the host before every port was probed.
+    pub timed_out: bool,
+    // IPv4 address of the same hostname, raced against this IPv6 one with --happy-eyeballs.
+    pub fallback: Option<IpAddr>,
+    // MAC address that answered the ARP request of --ping-first, for hosts on a local network.
+    pub mac: Option<Mac>,
+    // Route to an open port of the host, traced after the scan with --traceroute.
+    pub route: Option<Route>,
+    // Services the host advertised on the local network, found with --local-discovery.
+    pub advertised: Vec<Advert>,
+}
+
+impl Target {
+    // Function to build a target for `addr` in the zone `scope_id`, found under `hostnames`, with
+    // nothing learned about it yet.
+    pub fn new(addr: IpAddr, scope_id: u32, hostnames: Vec<String>) -> Target {
+        Target {
+            addr,
+            scope_id,
+            hostnames,
+            ptr: None,
+            tags: vec![],
+            timed_out: false,
+            fallback: None,
+            mac: None,
+            route: None,
+            advertised: vec![],
+        }
+    }
+
+    // Function to build the socket address of a port on this host.
+    // Using `SocketAddr` instead of string formatting keeps IPv6 addresses bracketed correctly.
+    pub fn socket_addr(&self, port: u16) -> SocketAddr {
+        match self.addr {
+            IpAddr::V4(v4) => SocketAddr::new(IpAddr::V4(v4), port),
+            IpAddr::V6(v6) => SocketAddr::V6(SocketAddrV6::new(v6, port, 0, self.scope_id)),
+        }
+    }
+
+    // Function to build the socket address of a port on the fallback address, if there is one.
+    pub fn fallback_addr(&self, port: u16) -> Option<SocketAddr> {
+        self.fallback.map(|addr| SocketAddr::new(addr, port))
+    }
+
+    // Function to format the IP address, with its zone for link-local IPv6 addresses.
+    pub fn address(&self) -> String {
+        match self.scope_id {
+            0 => self.addr.to_string(),
+            scope_id => format!("{}%{}", self.addr, zone_name(scope_id)),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    // Show the hostname next to its resolved IP so the user knows what was actually scanned.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let addr = self.address();
+        // Hostnames can resolve to both families, so label which one this address belongs to.
+        // Several hostnames (e.g. subdomains) can share one address. Paired addresses show both.
+        match (self.hostnames.join(", "), self.fallback) {
+            (names, _) if names.is_empty() => write!(f, "{}", addr)?,
+            (names, Some(v4)) => write!(f, "{} ({}, {})", names, addr, v4)?,
+            (names, None) if self.addr.is_ipv4() => write!(f, "{} ({}, IPv4)", names, addr)?,
+            (names, None) => write!(f, "{} ({}, IPv6)", names, addr)?,
+        }
+        // The reverse DNS name, unless it just repeats a hostname the user gave.
+        match &self.ptr {
+            Some(ptr) if !self.hostnames.contains(ptr) => write!(f, " [{}]", ptr),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Function to split a comma-separated target list into individual specifications.
+pub fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+// The layout of a target list file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    // Targets separated by newlines, commas or whitespace.
+    List,
+    // One `target,tag1;tag2` row per line.
+    Csv,
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<InputFormat, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "list" => Ok(InputFormat::List),
+            "csv" => Ok(InputFormat::Csv),
+            _ => Err(format!("{} is not an input format (list or csv)", s)),
+        }
+    }
+}
+
+// Function to read target specifications from a file (like nmap's -iL), or from stdin if `path` is `-`.
+// Targets are separated by newlines, commas or whitespace; a `#` at the start of a line or after
+// whitespace starts a comment, while `10.0.0.5#prod#web` attaches the tags `prod` and `web`.
+// CSV lists instead hold one `target,tag1;tag2` row per line. Without an explicit `format`,
+// files ending in `.csv` are read as CSV and everything else (including stdin) as a plain list.
+pub fn read_list(path: &str, format: Option<InputFormat>) -> Result<Vec<String>, String> {
+    let contents = if path == "-" {
+        let mut contents = String::new();
+        io::stdin()
+            .read_to_string(&mut contents)
+            .map_err(|e| format!("Failed to read targets from stdin: {}", e))?;
+        contents
+    } else {
+        fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?
+    };
+
+    let format = format.unwrap_or(if path.to_ascii_lowercase().ends_with(".csv") {
+        InputFormat::Csv
+    } else {
+        InputFormat::List
+    });
+    if format == InputFormat::Csv {
+        return Ok(contents.lines().filter_map(csv_row).collect());
+    }
+
+    Ok(contents
+        .lines()
+        .map(strip_comment)
+        .flat_map(|line| line.split(|c: char| c == ',' || c.is_whitespace()))
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+// Function to cut a trailing comment off a line, keeping `#` that is glued to a target as a tag separator.
+fn strip_comment(line: &str) -> &str {
+    let mut prev = ' ';
+    for (i, c) in line.char_indices() {
+        if c == '#' && prev.is_whitespace() {
+            return &line[..i];
+        }
+        prev = c;
+    }
+    line
+}
+
+// Function to turn a CSV row `target,tag1;tag2` into the inline form `target#tag1#tag2`.
+fn csv_row(line: &str) -> Option<String> {
+    let line = strip_comment(line);
+    let mut fields = line.split(',').map(str::trim);
+    let target = fields.next().filter(|t| !t.is_empty())?;
+    let tags: Vec<&str> = fields
+        .next()
+        .unwrap_or("")
+        .split(';')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .collect();
+
+    let mut spec = target.to_string();
+    for tag in tags {
+        spec.push('#');
+        spec.push_str(tag);
+    }
+    Some(spec)
+}
+
+// Function to split inline tags off a specification: `10.0.0.5#prod#web` gives `10.0.0.5` and [prod, web].
+fn split_tags(spec: &str) -> (&str, Vec<String>) {
+    let mut parts = spec.split('#');
+    let target = parts.next().unwrap_or("").trim();
+    let tags = parts
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(String::from)
+        .collect();
+    (target, tags)
+}
+
+// Function to expand a list of target specifications into individual hosts.
+// Hostnames that do not exist or have no address are reported and skipped, so one dead entry
+// in a subdomain list does not abort the scan; malformed addresses and resolver failures
+// (timeouts, unreachable DNS servers, ...) still do, as every later lookup would fail too.
+pub async fn expand_all(specs: &[String], resolver: &Resolver) -> Result<Vec<Target>, String> {
+    // Resolve the specifications concurrently, remembering their original order.
+    let limit = Arc::new(Semaphore::new(MAX_PARALLEL_LOOKUPS));
+    let mut lookups = tokio::task::JoinSet::new();
+    for (i, spec) in specs.iter().enumerate() {
+        let (spec, resolver, limit) = (spec.clone(), resolver.clone(), limit.clone());
+        lookups.spawn(async move {
+            let _permit = limit.acquire_owned().await;
+            let (target, tags) = split_tags(&spec);
+            let result = if is_hostname(target) {
+                match resolve_hostname(target, &resolver).await {
+                    Err(LookupError::NotFound(e)) => {
+                        eprintln!("Skipping {}: {}", target, e);
+                        Ok(vec![])
+                    }
+                    result => result.map_err(|e| e.to_string()),
+                }
+            } else {
+                expand(target, &resolver).await
+            };
+            let result = result.map(|mut hosts| {
+                for host in &mut hosts {
+                    host.tags = tags.clone();
+                }
+                hosts
+            });
+            (i, result)
+        });
+    }
+    let mut expanded: Vec<Option<Vec<Target>>> = vec![None; specs.len()];
+    while let Some(joined) = lookups.join_next().await {
+        let (i, result) = joined.map_err(|e| e.to_string())?;
+        expanded[i] = Some(result?);
+    }
+
+    // Hosts are scanned in the order given; an address listed twice is only scanned once,
+    // keeping every hostname and tag attached to it. Link-local addresses on different
+    // interfaces (fe80::1%eth0 and fe80::1%eth1) are different hosts.
+    let mut targets: Vec<Target> = vec![];
+    let mut sources: Vec<&str> = vec![];
+    let mut seen: HashMap<(IpAddr, u32), usize> = HashMap::new();
+    for (spec, hosts) in specs.iter().zip(expanded) {
+        let (source, _) = split_tags(spec);
+        for target in hosts.into_iter().flatten() {
+            let Some(&i) = seen.get(&(target.addr, target.scope_id)) else {
+                seen.insert((target.addr, target.scope_id), targets.len());
+                targets.push(target);
+                sources.push(source);
+                continue;
+            };
+            // Merged tags end up on every name of the host, so say so when they disagree.
+            if !target.tags.is_empty()
+                && !targets[i].tags.is_empty()
+                && target.tags != targets[i].tags
+            {
+                eprintln!(
+                    "Warning: {} gets the tags {} from {} and {} from {}",
+                    target.address(),
+                    targets[i].tags.join("#"),
+                    sources[i],
+                    target.tags.join("#"),
+                    source
+                );
+            }
+            for name in target.hostnames {
+                if !targets[i].hostnames.contains(&name) {
+                    targets[i].hostnames.push(name);
+                }
+            }
+            for tag in target.tags {
+                if !targets[i].tags.contains(&tag) {
+                    targets[i].tags.push(tag);
+                }
+            }
+        }
+    }
+
+    if targets.is_empty() {
+        return Err("No targets left to scan".to_string());
+    }
+    Ok(targets)
+}
+
+// Function to tell whether a specification is a hostname rather than an address, block or range.
+fn is_hostname(spec: &str) -> bool {
+    let spec = spec.trim();
+    !spec.contains('/')
+        && !spec.contains('%')
+        && spec.parse::<IpAddr>().is_err()
+        && parse_octet_ranges(spec).is_none()
+}
+
+// Function to turn exclusion specifications (IPs, CIDR blocks, hostnames) into networks to skip.
+pub async fn exclusions(specs: &[String], resolver: &Resolver) -> Result<Vec<Network>, String> {
+    let mut networks = vec![];
+    for spec in specs {
+        // Tags mean nothing for exclusions.
+        let (spec, _) = split_tags(spec);
+        if spec.contains('/') {
+            // CIDR blocks are matched as a whole instead of being expanded.
+            networks.push(Network::parse(spec)?);
+        } else {
+            // Single IPs and hostnames exclude each address they stand for.
+            for target in expand(spec, resolver).await? {
+                networks.push(Network::host(target.addr));
+            }
+        }
+    }
+    Ok(networks)
+}
+
+// Function to drop every target that falls inside one of the excluded networks.
+pub fn exclude(targets: &mut Vec<Target>, excluded: &[Network]) {
+    targets.retain(|t| !excluded.iter().any(|n| n.contains(t.addr)));
+}
+
+// Function to pair up the IPv6 and IPv4 addresses of hostnames that resolved to both families
+// (--happy-eyeballs): each IPv6 target takes the first unpaired IPv4 target sharing a hostname
+// as its fallback, along with that target's hostnames and tags.
+pub fn pair_families(targets: &mut Vec<Target>) {
+    let mut i = 0;
+    while i < targets.len() {
+        let target = &targets[i];
+        let partner = (target.addr.is_ipv6() && !target.hostnames.is_empty())
+            .then(|| {
+                targets.iter().position(|t| {
+                    t.addr.is_ipv4()
+                        && t.hostnames
+                            .iter()
+                            .any(|name| target.hostnames.contains(name))
+                })
+            })
+            .flatten();
+        if let Some(j) = partner {
+            let v4 = targets.remove(j);
+            // Removing an earlier target moves this one down by one.
+            if j < i {
+                i -= 1;
+            }
+            let target = &mut targets[i];
+            target.fallback = Some(v4.addr);
+            for name in v4.hostnames {
+                if !target.hostnames.contains(&name) {
+                    target.hostnames.push(name);
+                }
+            }
+            for tag in v4.tags {
+                if !target.tags.contains(&tag) {
+                    target.tags.push(tag);
+                }
+            }
+        }
+        i += 1;
+    }
+}
+
+// Function to expand a target specification (IP, CIDR block or hostname) into individual hosts.
+pub async fn expand(spec: &str, resolver: &Resolver) -> Result<Vec<Target>, String> {
+    let spec = spec.trim();
+
+    // A CIDR block such as 192.168.1.0/24.
+    if spec.contains('/') {
+        return Network::parse(spec)?
+            .hosts()
+            .map(|addrs| addrs.into_iter().map(ip_target).collect())
+            .map_err(|e| format!("{}: {}", spec, e));
+    }
+
+    // An IP literal needs no DNS lookup.
+    if let Ok(ip) = spec.parse::<IpAddr>() {
+        return Ok(vec![ip_target(ip)]);
+    }
+
+    // An nmap-style octet range such as 192.168.1.1-50 or 10.0.0-3.1.
+    if let Some(octets) = parse_octet_ranges(spec) {
+        return octet_hosts(&octets?)
+            .map(|addrs| addrs.into_iter().map(ip_target).collect())
+            .map_err(|e| format!("{}: {}", spec, e));
+    }
+
+    // An IPv6 address with a zone ID, such as fe80::1%eth0.
+    if let Some((ip, zone)) = spec.split_once('%') {
+        let ip: Ipv6Addr = ip
+            .parse()
+            .map_err(|_| format!("{} is not a valid IPv6 address", spec))?;
+        let scope_id =
+            zone_index(zone).ok_or_else(|| format!("{}: unknown zone {}", spec, zone))?;
+        return Ok(vec![Target::new(IpAddr::V6(ip), scope_id, vec![])]);
+    }
+
+    // Otherwise it is a hostname.
+    resolve_hostname(spec, resolver)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// Function to resolve a hostname into one target per address.
+async fn resolve_hostname(name: &str, resolver: &Resolver) -> Result<Vec<Target>, LookupError> {
+    Ok(resolver
+        .lookup(name)
+        .await?
+        .into_iter()
+        .map(|(addr, scope_id)| Target::new(addr, scope_id, vec![name.to_string()]))
+        .collect())
+}
+
+// Function to parse the four octets of an octet-range specification into inclusive ranges.
+// Returns `None` when the specification does not look like one (e.g. it is a hostname).
+fn parse_octet_ranges(spec: &str) -> Option<Result<Vec<(u8, u8)>, String>> {
+    let parts: Vec<&str> = spec.split('.').collect();
+    let looks_numeric = |p: &&str| {
+        !p.is_empty()
+            && p.chars()
+                .all(|c| c.is_ascii_digit() || c == '-' || c == '*')
+    };
+    if parts.len() != 4 || !parts.iter().all(looks_numeric) {
+        return None;
+    }
+
+    let octet = |s: &str| {
+        s.parse::<u8>()
+            .map_err(|_| format!("{} is not a valid octet in {}", s, spec))
+    };
+    let ranges = parts
+        .iter()
+        .map(|part| match part.split_once('-') {
+            // `*` stands for the whole octet.
+            _ if *part == "*" => Ok((0, 255)),
+            // An open-ended range such as `10-` runs up to 255, `-10` starts at 0.
+            Some((start, end)) => {
+                let start = if start.is_empty() { 0 } else { octet(start)? };
+                let end = if end.is_empty() { 255 } else { octet(end)? };
+                if start > end {
+                    return Err(format!("Octet range {} in {} is reversed", part, spec));
+                }
+                Ok((start, end))
+            }
+            None => octet(part).map(|v| (v, v)),
+        })
+        .collect();
+    Some(ranges)
+}
+
+// Function to list every address matched by a set of octet ranges.
+fn octet_hosts(ranges: &[(u8, u8)]) -> Result<Vec<IpAddr>, String> {
+    let count: u128 = ranges
+        .iter()
+        .map(|(start, end)| (*end - *start) as u128 + 1)
+        .product();
+    if count > MAX_CIDR_HOSTS {
+        return Err(format!(
+            "range is larger than the maximum of {} hosts",
+            MAX_CIDR_HOSTS
+        ));
+    }
+
+    let mut addrs = Vec::with_capacity(count as usize);
+    for a in ranges[0].0..=ranges[0].1 {
+        for b in ranges[1].0..=ranges[1].1 {
+            for c in ranges[2].0..=ranges[2].1 {
+                for d in ranges[3].0..=ranges[3].1 {
+                    addrs.push(IpAddr::V4(Ipv4Addr::new(a, b, c, d)));
+                }
+            }
+        }
+    }
+    Ok(addrs)
+}
+
+// Function to wrap a bare IP address into a target.
+pub fn ip_target(addr: IpAddr) -> Target {
+    Target::new(addr, 0, vec![])
+}
+
+// Function to turn an IPv6 zone (an interface name or a numeric index) into an interface index.
+fn zone_index(zone: &str) -> Option<u32> {
+    if let Ok(index) = zone.parse::<u32>() {
+        return Some(index);
+    }
+    interface_index(zone)
+}
+
+// Function to look up the index of the interface named `name`.
+#[cfg(unix)]
+fn interface_index(name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    // SAFETY: `name` is a valid NUL-terminated string that outlives the call.
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => None,
+        index => Some(index),
+    }
+}
+
+// Function to look up the index of an interface by name, which only Unix systems offer, so
+// other systems take numeric zones only.
+#[cfg(not(unix))]
+fn interface_index(_name: &str) -> Option<u32> {
+    None
+}
+
+// Function to turn an interface index back into its name for display, falling back to the number.
+#[cfg(unix)]
+fn zone_name(scope_id: u32) -> String {
+    let mut buf = [0 as libc::c_char; libc::IF_NAMESIZE];
+    // SAFETY: `buf` is IF_NAMESIZE bytes long, as `if_indextoname` requires.
+    let name = unsafe { libc::if_indextoname(scope_id, buf.as_mut_ptr()) };
+    if name.is_null() {
+        return scope_id.to_string();
+    }
+    // SAFETY: on success `if_indextoname` wrote a NUL-terminated string into `buf`.
+    unsafe { CStr::from_ptr(buf.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+// Function to show an interface index, which only Unix systems turn back into a name.
+#[cfg(not(unix))]
+fn zone_name(scope_id: u32) -> String {
+    scope_id.to_string()
+}
+
+// Function to convert an address into a 128-bit integer plus its width in bits,
+// so IPv4 and IPv6 share the same arithmetic.
+fn to_bits(addr: IpAddr) -> (u128, u8) {
+    match addr {
+        IpAddr::V4(v4) => (u32::from(v4) as u128, 32),
+        IpAddr::V6(v6) => (u128::from(v6), 128),
+    }
+}
+
+// A CIDR block such as 192.168.1.0/24.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Network {
+    pub base: IpAddr,
+    pub prefix: u8,
+}
+
+impl Network {
+    // Function to parse `address/prefix` notation.
+    pub fn parse(spec: &str) -> Result<Network, String> {
+        let (base, prefix) = spec
+            .split_once('/')
+            .ok_or_else(|| format!("{} is not in CIDR notation", spec))?;
+        let base: IpAddr = base
+            .trim()
+            .parse()
+            .map_err(|_| format!("{} is not a valid CIDR network address", spec))?;
+        let prefix: u8 = prefix
+            .trim()
+            .parse()
+            .map_err(|_| format!("{} has an invalid prefix length", spec))?;
+
+        let (_, bits) = to_bits(base);
+        if prefix > bits {
+            return Err(format!("{}: prefix length must be at most {}", spec, bits));
+        }
+        Ok(Network { base, prefix })
+    }
+
+    // Function to build a network holding exactly one address.
+    pub fn host(addr: IpAddr) -> Network {
+        Network {
+            base: addr,
+            prefix: to_bits(addr).1,
+        }
+    }
+
+    // Function to compute the mask selecting the network part of an address.
+    fn mask(&self) -> u128 {
+        let (_, bits) = to_bits(self.base);
+        let host_bits = (bits - self.prefix) as u32;
+        if host_bits >= 128 {
+            0
+        } else {
+            !((1u128 << host_bits) - 1)
+        }
+    }
+
+    // Function to check whether an address falls inside this network.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        // An IPv4 address is never part of an IPv6 network and vice versa.
+        if self.base.is_ipv4() != addr.is_ipv4() {
+            return false;
+        }
+        let mask = self.mask();
+        (to_bits(self.base).0 & mask) == (to_bits(addr).0 & mask)
+    }
+
+    // Function to list the usable hosts of the network.
+    pub fn hosts(&self) -> Result<Vec<IpAddr>, String> {
+        let (value, bits) = to_bits(self.base);
+
+        // Number of addresses in the block, rejecting blocks too large to hold in memory.
+        let host_bits = (bits - self.prefix) as u32;
+        if host_bits >= 128 || (1u128 << host_bits) > MAX_CIDR_HOSTS {
+            return Err(format!(
+                "block is larger than the maximum of {} hosts",
+                MAX_CIDR_HOSTS
+            ));
+        }
+        let size = 1u128 << host_bits;
+        let network = value & self.mask();
+
+        // Skip the network and broadcast addresses of IPv4 blocks that have them (/30 and larger).
+        let (first, last) = if bits == 32 && host_bits >= 2 {
+            (network + 1, network + size - 2)
+        } else {
+            (network, network + size - 1)
+        };
+
+        Ok((first..=last)
+            .map(|v| match self.base {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(v as u32)),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(v)),
+            })
+            .collect())
+    }
+}
+
+// Function to fill in the reverse DNS names of the selected hosts, looking them up concurrently.
+pub async fn reverse_lookup(targets: &mut [Target], selected: &[usize], resolver: &Resolver) {
+    let mut lookups = tokio::task::JoinSet::new();
+    for &i in selected {
+        let resolver = resolver.clone();
+        let addr = targets[i].addr;
+        lookups.spawn(async move { (i, resolver.reverse(addr).await) });
+    }
+    while let Some(Ok((i, ptr))) = lookups.join_next().await {
+        targets[i].ptr = ptr;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Function to get the addresses of a block as strings.
+    fn block(spec: &str) -> Result<Vec<String>, String> {
+        let hosts = Network::parse(spec)?.hosts()?;
+        Ok(hosts.iter().map(IpAddr::to_string).collect())
+    }
+
+    // Function to get the addresses of an octet range as strings.
+    fn range(spec: &str) -> Option<Result<Vec<String>, String>> {
+        let ranges = parse_octet_ranges(spec)?;
+        Some(ranges.and_then(|ranges| {
+            let hosts = octet_hosts(&ranges)?;
+            Ok(hosts.iter().map(IpAddr::to_string).collect())
+        }))
+    }
+
+    #[test]
+    fn expands_octet_ranges() {
+        assert_eq!(
+            range("10.0.0.1-3"),
+            Some(Ok(vec![
+                "10.0.0.1".to_string(),
+                "10.0.0.2".to_string(),
+                "10.0.0.3".to_string()
+            ]))
+        );
+        assert_eq!(
+            range("10.0-1.0.7"),
+            Some(Ok(vec!["10.0.0.7".to_string(), "10.1.0.7".to_string()]))
+        );
+        assert_eq!(
+            parse_octet_ranges("10.0.0.250-"),
+            Some(Ok(vec![(10, 10), (0, 0), (0, 0), (250, 255)]))
+        );
+        assert_eq!(
+            parse_octet_ranges("10.0.0.-2"),
+            Some(Ok(vec![(10, 10), (0, 0), (0, 0), (0, 2)]))
+        );
+        assert_eq!(
+            parse_octet_ranges("10.0.0.*"),
+            Some(Ok(vec![(10, 10), (0, 0), (0, 0), (0, 255)]))
+        );
+        assert_eq!(range("10.0.0.5-5").map(|r| r.unwrap().len()), Some(1));
+        assert!(matches!(range("10.0.0.5-1"), Some(Err(_))));
+        assert!(matches!(range("10.0.0.256"), Some(Err(_))));
+        assert!(matches!(range("10.*.*.*"), Some(Err(_))));
+        // Anything else is not an octet range at all.
+        for other in [
+            "10.0.0",
+            "10.0.0.1.2",
+            "example.com",
+            "10.0.0.a",
+            "10..0.1",
+            "::1",
+        ] {
+            assert_eq!(parse_octet_ranges(other), None, "{}", other);
+        }
+    }
+
+    #[test]
+    fn expands_blocks() {
+        assert_eq!(
+            block("192.168.1.0/30").unwrap(),
+            ["192.168.1.1", "192.168.1.2"]
+        );
+        // Blocks without room for a network and broadcast address keep every address.
+        assert_eq!(
+            block("192.168.1.6/31").unwrap(),
+            ["192.168.1.6", "192.168.1.7"]
+        );
+        assert_eq!(block("192.168.1.7/32").unwrap(), ["192.168.1.7"]);
+        assert_eq!(block("10.1.2.3/24").unwrap().len(), 254);
+        assert_eq!(
+            block("2001:db8::5/126").unwrap(),
+            ["2001:db8::4", "2001:db8::5", "2001:db8::6", "2001:db8::7"]
+        );
+        assert_eq!(block("::1/128").unwrap(), ["::1"]);
+        // Blocks too large to list, and prefixes too long for the family, are refused.
+        for bad in [
+            "0.0.0.0/0",
+            "::/0",
+            "10.0.0.0/8",
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0.0",
+            "10.0.0.0/x",
+            "x/24",
+        ] {
+            assert!(block(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn excludes_networks() {
+        let any = Network::parse("0.0.0.0/0").unwrap();
+        assert!(any.contains("203.0.113.9".parse().unwrap()));
+        assert!(!any.contains("::1".parse().unwrap()));
+        assert!(Network::parse("::/0")
+            .unwrap()
+            .contains("2001:db8::1".parse().unwrap()));
+        let block = Network::parse("10.0.0.0/30").unwrap();
+        assert!(block.contains("10.0.0.3".parse().unwrap()));
+        assert!(!block.contains("10.0.0.4".parse().unwrap()));
+
+        let mut targets: Vec<Target> = ["10.0.0.1", "10.0.0.5", "10.0.0.9", "::1"]
+            .iter()
+            .map(|addr| ip_target(addr.parse().unwrap()))
+            .collect();
+        let excluded = [
+            block,
+            Network::host("10.0.0.9".parse().unwrap()),
+            Network::parse("::1/128").unwrap(),
+        ];
+        exclude(&mut targets, &excluded);
+        let left: Vec<String> = targets.iter().map(Target::address).collect();
+        assert_eq!(left, ["10.0.0.5"]);
+    }
+}
@@ -0,0 +1,216 @@
+use std::io; // Import I/O types for handshake errors.
+use std::sync::{Arc, OnceLock}; // Import shared ownership for the TLS configuration.
+use tokio::net::TcpStream; // Import the TCP stream TLS runs on top of.
+use tokio_rustls::client::TlsStream; // Import the TLS stream type.
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+}; // Import the verifier interface, to look at certificates without trusting them.
+use tokio_rustls::rustls::client::WebPkiServerVerifier; // Import the verifier of certificate chains.
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime}; // Import the certificate and SNI name types.
+use tokio_rustls::rustls::{
+    AlertDescription, CertificateError, ClientConfig, DigitallySignedStruct, Error, RootCertStore,
+    SignatureScheme,
+}; // Import the client configuration, alerts and certificate errors.
+use tokio_rustls::TlsConnector; // Import the connector that performs handshakes.
+
+// Function to get the Mozilla roots.
+fn roots() -> Arc<RootCertStore> {
+    static ROOTS: OnceLock<Arc<RootCertStore>> = OnceLock::new();
+    ROOTS
+        .get_or_init(|| {
+            Arc::new(RootCertStore::from_iter(
+                webpki_roots::TLS_SERVER_ROOTS.iter().cloned(),
+            ))
+        })
+        .clone()
+}
+
+// Function to get the client configuration that validates certificates against the Mozilla roots.
+fn verified_config() -> Arc<ClientConfig> {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            Arc::new(
+                ClientConfig::builder()
+                    .with_root_certificates(roots())
+                    .with_no_client_auth(),
+            )
+        })
+        .clone()
+}
+
+// Function to get the verifier that checks certificate chains against the Mozilla roots.
+fn chain_verifier() -> Arc<WebPkiServerVerifier> {
+    static VERIFIER: OnceLock<Arc<WebPkiServerVerifier>> = OnceLock::new();
+    VERIFIER
+        .get_or_init(|| {
+            WebPkiServerVerifier::builder(roots())
+                .build()
+                .expect("the Mozilla roots make a valid verifier")
+        })
+        .clone()
+}
+
+// Verifier of TLS probes, which takes any certificate so that it can be looked at after the
+// handshake, but still checks that the server holds its key.
+#[derive(Debug)]
+struct AnyCertificate(Arc<WebPkiServerVerifier>);
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.0.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.0.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_verify_schemes()
+    }
+}
+
+// The application protocols TLS probes offer (ALPN), for the server to pick the one it speaks.
+// ACME servers answer acme-tls/1 with a challenge certificate, so it is offered last.
+const ALPN: &[&str] = &[
+    "h2",
+    "http/1.1",
+    "http/1.0",
+    "spdy/3.1",
+    "dot",
+    "imap",
+    "pop3",
+    "managesieve",
+    "xmpp-client",
+    "xmpp-server",
+    "mqtt",
+    "postgresql",
+    "ntske/1",
+    "stun.turn",
+    "coap",
+    "smb",
+    "irc",
+    "ftp",
+    "sunrpc",
+    "acme-tls/1",
+];
+
+// Function to get the client configuration of TLS probes, which takes any certificate, with
+// `alpn` offering the application protocols.
+fn probe_config(alpn: bool) -> Arc<ClientConfig> {
+    static CONFIGS: [OnceLock<Arc<ClientConfig>>; 2] = [OnceLock::new(), OnceLock::new()];
+    CONFIGS[usize::from(alpn)]
+        .get_or_init(|| {
+            let mut config = ClientConfig::builder()
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(AnyCertificate(chain_verifier())))
+                .with_no_client_auth();
+            if alpn {
+                config.alpn_protocols = ALPN.iter().map(|p| p.as_bytes().to_vec()).collect();
+            }
+            Arc::new(config)
+        })
+        .clone()
+}
+
+// Function to check that `name` can be sent as the SNI name of a handshake.
+pub fn server_name(name: &str) -> Result<ServerName<'static>, String> {
+    ServerName::try_from(name.to_string())
+        .map_err(|_| format!("{} is not a valid server name", name))
+}
+
+// Function to run a TLS handshake over an established TCP connection that takes any
+// certificate, to look at it rather than trust it, offering the common application protocols
+// if `alpn` is set.
+pub async fn connect_any(
+    stream: TcpStream,
+    server_name: ServerName<'static>,
+    alpn: bool,
+) -> io::Result<TlsStream<TcpStream>> {
+    TlsConnector::from(probe_config(alpn))
+        .connect(server_name, stream)
+        .await
+}
+
+// Function to check whether a handshake failed because the server speaks none of the
+// application protocols offered.
+pub fn no_common_protocol(error: &io::Error) -> bool {
+    error
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<Error>())
+        .is_some_and(|e| *e == Error::AlertReceived(AlertDescription::NoApplicationProtocol))
+}
+
+// Function to check whether a handshake or the first read after it failed because the server
+// wants a client certificate, which TLS 1.3 servers only say after the handshake.
+pub fn certificate_required(error: &io::Error) -> bool {
+    error
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<Error>())
+        .is_some_and(|e| {
+            matches!(
+                e,
+                Error::AlertReceived(
+                    AlertDescription::CertificateRequired | AlertDescription::BadCertificate
+                )
+            )
+        })
+}
+
+// Function to check a certificate chain, leaf first, against the Mozilla roots for
+// `server_name`, saying why it is not trusted if it is not.
+pub fn verify(chain: &[CertificateDer<'_>], server_name: &ServerName<'_>) -> Result<(), String> {
+    let (leaf, intermediates) = chain.split_first().ok_or("no certificate")?;
+    chain_verifier()
+        .verify_server_cert(leaf, intermediates, server_name, &[], UnixTime::now())
+        .map(|_| ())
+        .map_err(|e| match e {
+            Error::InvalidCertificate(CertificateError::UnknownIssuer) => {
+                "issued by an unknown authority".to_string()
+            }
+            Error::InvalidCertificate(
+                CertificateError::Expired | CertificateError::ExpiredContext { .. },
+            ) => "expired".to_string(),
+            Error::InvalidCertificate(
+                CertificateError::NotValidYet | CertificateError::NotValidYetContext { .. },
+            ) => "not valid yet".to_string(),
+            Error::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. },
+            ) => format!("not valid for {}", server_name.to_str()),
+            Error::InvalidCertificate(e) => e.to_string(),
+            e => e.to_string(),
+        })
+}
+
+// Function to run a certificate-validated TLS handshake over an established TCP connection.
+pub async fn connect_verified(
+    stream: TcpStream,
+    server_name: &str,
+) -> io::Result<TlsStream<TcpStream>> {
+    let name = ServerName::try_from(server_name.to_string())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    TlsConnector::from(verified_config())
+        .connect(name, stream)
+        .await
+}
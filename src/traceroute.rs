@@ -0,0 +1,353 @@
+use crate::raw; // Import the description of missing privileges for raw sockets.
+use crate::scanner::{PortResult, PortState, Protocol, Settings, Source}; // Import the open ports to trace towards and the settings and source to send with.
+use crate::targets::{End, Hop, Route, Target}; // Import the hosts whose routes are traced and the routes to them.
+use futures::stream::{self, FuturesUnordered, StreamExt}; // Import streams to trace hosts and wait for connects concurrently.
+use socket2::{Domain, Protocol as SocketProtocol, Socket, Type}; // Import socket2 to open the probes and the ICMP sockets.
+use std::collections::HashMap; // Import `HashMap` to tie ICMP errors to the probes that caused them.
+use std::io; // Import I/O errors for socket failures.
+use std::mem::MaybeUninit; // Import `MaybeUninit` for the receive buffer.
+use std::net::{IpAddr, SocketAddr}; // Import address types for the hops and the traced ports.
+use std::time::Duration; // Import `Duration` for reply timeouts and round-trip times.
+use tokio::io::unix::AsyncFd; // Import `AsyncFd` to wait for ICMP errors without blocking.
+use tokio::net::TcpStream; // Import `TcpStream` to wait for the connects of the probes.
+use tokio::time::{timeout_at, Instant}; // Import `timeout_at` to stop waiting for the hops.
+
+// Define the furthest hop a route is traced to.
+const MAX_HOPS: u8 = 30;
+
+// What came back for the probe with one TTL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Answer {
+    // A router on the way dropped the probe as its TTL ran out.
+    Router(IpAddr),
+    // A router, or the host itself, reported the host or port unreachable.
+    Unreachable(IpAddr),
+    // The host accepted or refused the connect.
+    Host,
+}
+
+// Function to check up front that the ICMP errors of the hops can be read, which needs root or
+// the CAP_NET_RAW capability.
+pub fn check(source: &Source) -> Result<(), String> {
+    icmp_socket(Domain::IPV4, source, IpAddr::from([0u8; 4]))
+        .map(drop)
+        .map_err(|e| raw::denied("--traceroute", e))
+}
+
+// Function to trace the route to the first open TCP port of every host in `results`, giving
+// each hop `wait` to answer, and keep it on the host.
+pub async fn trace_all(
+    hosts: &mut [Target],
+    results: &[PortResult],
+    settings: &Settings,
+    wait: Duration,
+) {
+    let mut traced: Vec<(usize, SocketAddr)> = vec![];
+    for r in results {
+        let open = r.protocol == Protocol::Tcp && r.state == PortState::Open;
+        if open && traced.last().is_none_or(|(host, _)| *host != r.host) {
+            traced.push((r.host, hosts[r.host].socket_addr(r.port)));
+        }
+    }
+    // Each trace has a socket in flight per hop, so fewer hosts than ports share the concurrency.
+    let parallel = (settings.concurrency / usize::from(MAX_HOPS)).max(1);
+    let routes: Vec<io::Result<Route>> = stream::iter(&traced)
+        .map(|&(_, addr)| trace(addr, settings, wait))
+        .buffered(parallel)
+        .collect()
+        .await;
+    for ((host, addr), route) in traced.into_iter().zip(routes) {
+        match route {
+            Ok(route) => hosts[host].route = Some(route),
+            Err(e) => eprintln!("\nWarning: --traceroute cannot trace {}: {}", addr, e),
+        }
+    }
+}
+
+// Function to trace the route to `addr`: connect to it once with every TTL up to `MAX_HOPS` at
+// the same time, and read which router dropped each connect from the ICMP time exceeded
+// errors they send back. The connects that make it all the way are answered by the host.
+pub async fn trace(addr: SocketAddr, settings: &Settings, wait: Duration) -> io::Result<Route> {
+    let icmp = AsyncFd::new(icmp_socket(
+        Domain::for_address(addr),
+        &settings.source,
+        addr.ip(),
+    )?)?;
+
+    // The probes by their local port, which the ICMP errors quote.
+    let mut ports: HashMap<u16, u8> = HashMap::new();
+    let connects = FuturesUnordered::new();
+    let sent = Instant::now();
+    for ttl in 1..=MAX_HOPS {
+        let socket = settings.socket(addr, Type::STREAM).await?;
+        match addr {
+            SocketAddr::V4(_) => socket.set_ttl(u32::from(ttl))?,
+            SocketAddr::V6(_) => socket.set_unicast_hops_v6(u32::from(ttl))?,
+        }
+        match socket.connect(&addr.into()) {
+            Err(e) if e.raw_os_error() != Some(libc::EINPROGRESS) => return Err(e),
+            _ => {}
+        }
+        if let Some(local) = socket.local_addr()?.as_socket() {
+            ports.insert(local.port(), ttl);
+        }
+        connects.push(async move { (ttl, reaches(socket).await) });
+    }
+
+    let mut answers: HashMap<u8, (Answer, Duration)> = HashMap::new();
+    let collect = async {
+        let mut connects = connects;
+        let mut buffer = [MaybeUninit::<u8>::uninit(); 1500];
+        while !complete(&answers) {
+            tokio::select! {
+                Some((ttl, reached)) = connects.next() => {
+                    if reached {
+                        answers.entry(ttl).or_insert((Answer::Host, sent.elapsed()));
+                    }
+                }
+                ready = icmp.readable() => {
+                    let mut ready = ready?;
+                    let Ok(received) = ready.try_io(|socket| socket.get_ref().recv_from(&mut buffer))
+                    else {
+                        continue;
+                    };
+                    let (length, from) = received?;
+                    let Some(from) = from.as_socket() else {
+                        continue;
+                    };
+                    // SAFETY: `recv_from` initialized the first `length` bytes.
+                    let packet = unsafe {
+                        std::slice::from_raw_parts(buffer.as_ptr().cast::<u8>(), length)
+                    };
+                    let answer = error(packet, from.ip(), addr);
+                    if let Some((ttl, answer)) =
+                        answer.and_then(|(port, answer)| Some((*ports.get(&port)?, answer)))
+                    {
+                        answers.entry(ttl).or_insert((answer, sent.elapsed()));
+                    }
+                }
+            }
+        }
+        io::Result::Ok(())
+    };
+    timeout_at(sent + wait, collect).await.unwrap_or(Ok(()))?;
+    Ok(route(addr, &answers))
+}
+
+// Function to check whether the route is known: the host (or a router turning the probe away)
+// answered some TTL, and every TTL below it answered as well.
+fn complete(answers: &HashMap<u8, (Answer, Duration)>) -> bool {
+    let end = (1..=MAX_HOPS).find(|ttl| {
+        answers
+            .get(ttl)
+            .is_some_and(|(answer, _)| !matches!(answer, Answer::Router(_)))
+    });
+    end.is_some_and(|end| (1..end).all(|ttl| answers.contains_key(&ttl)))
+}
+
+// Function to put the answers of the probes together into the route to `addr`, which ends at
+// the first hop that was not a router passing the probe on.
+fn route(addr: SocketAddr, answers: &HashMap<u8, (Answer, Duration)>) -> Route {
+    let end = (1..=MAX_HOPS).find_map(|ttl| match answers.get(&ttl) {
+        Some((Answer::Host, _)) => Some((ttl, End::Reached)),
+        Some((Answer::Unreachable(_), _)) => Some((ttl, End::Unreachable)),
+        _ => None,
+    });
+    let (last, end) = end.unwrap_or_else(|| {
+        let last = answers.keys().copied().max().unwrap_or(0);
+        (last, End::Lost)
+    });
+    let hops = (1..=last)
+        .map(|ttl| {
+            let answer = answers.get(&ttl);
+            Hop {
+                ttl,
+                addr: answer.map(|(answer, _)| match answer {
+                    Answer::Router(ip) | Answer::Unreachable(ip) => *ip,
+                    Answer::Host => addr.ip(),
+                }),
+                rtt: answer.map(|(_, rtt)| *rtt),
+            }
+        })
+        .collect();
+    Route {
+        port: addr.port(),
+        hops,
+        end,
+    }
+}
+
+// Function to wait for the connect of a probe to finish, telling whether the host answered it,
+// accepting or refusing the connection.
+async fn reaches(socket: Socket) -> bool {
+    let Ok(stream) = TcpStream::from_std(socket.into()) else {
+        return false;
+    };
+    if stream.writable().await.is_err() {
+        return false;
+    }
+    match stream.take_error() {
+        Ok(None) => stream.peer_addr().is_ok(),
+        Ok(Some(e)) => e.kind() == io::ErrorKind::ConnectionRefused,
+        Err(_) => false,
+    }
+}
+
+// Function to open a raw socket that receives the ICMP (or ICMPv6) errors sent to this host.
+fn icmp_socket(domain: Domain, source: &Source, remote: IpAddr) -> io::Result<Socket> {
+    let protocol = match domain {
+        Domain::IPV6 => SocketProtocol::ICMPV6,
+        _ => SocketProtocol::ICMPV4,
+    };
+    let socket = Socket::new(domain, Type::RAW, Some(protocol))?;
+    socket.set_nonblocking(true)?;
+    source.bind_to(&socket, remote)?;
+    Ok(socket)
+}
+
+// Function to read an ICMP error sent by `from` about a TCP segment to `addr`: the local port
+// the segment came from, which tells the probe, and what the error means. Raw IPv4 sockets
+// deliver the IP header in front of the message, ICMPv6 ones do not. Errors quote the IP
+// header of the dropped packet and the first 8 bytes of its segment, which hold the ports.
+fn error(packet: &[u8], from: IpAddr, addr: SocketAddr) -> Option<(u16, Answer)> {
+    let (kind, quoted, segment) = match from {
+        IpAddr::V4(_) => {
+            let icmp = packet.get(usize::from(packet.first()? & 0x0f) * 4..)?;
+            let kind = match icmp.first()? {
+                11 => Answer::Router(from),
+                3 => Answer::Unreachable(from),
+                _ => return None,
+            };
+            let quoted = icmp.get(8..)?;
+            let header = usize::from(quoted.first()? & 0x0f) * 4;
+            if *quoted.get(9)? != libc::IPPROTO_TCP as u8 {
+                return None;
+            }
+            let to: [u8; 4] = quoted.get(16..20)?.try_into().ok()?;
+            (kind, IpAddr::from(to), quoted.get(header..)?)
+        }
+        IpAddr::V6(_) => {
+            let kind = match packet.first()? {
+                3 => Answer::Router(from),
+                1 => Answer::Unreachable(from),
+                _ => return None,
+            };
+            let quoted = packet.get(8..)?;
+            if *quoted.get(6)? != libc::IPPROTO_TCP as u8 {
+                return None;
+            }
+            let to: [u8; 16] = quoted.get(24..40)?.try_into().ok()?;
+            (kind, IpAddr::from(to), quoted.get(40..)?)
+        }
+    };
+    let half = |at: usize| {
+        Some(u16::from_be_bytes(
+            segment.get(at..at + 2)?.try_into().ok()?,
+        ))
+    };
+    if quoted != addr.ip() || half(2)? != addr.port() {
+        return None;
+    }
+    Some((half(0)?, kind))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Function to build an ICMP error of type `kind` quoting a segment from port 40000 to
+    // 10.0.0.9:80, as a raw IPv4 socket delivers it.
+    fn icmp_error(kind: u8) -> Vec<u8> {
+        let mut packet = vec![
+            0x45, 0, 0, 0, 0, 0, 0, 0, 64, 1, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+        ];
+        packet.extend_from_slice(&[kind, 0, 0, 0, 0, 0, 0, 0]);
+        packet.extend_from_slice(&[
+            0x45, 0, 0, 44, 0, 0, 0, 0, 1, 6, 0, 0, 10, 0, 0, 2, 10, 0, 0, 9,
+        ]);
+        packet.extend_from_slice(&[0x9c, 0x40, 0, 80, 0, 0, 0, 1]);
+        packet
+    }
+
+    #[test]
+    fn reads_icmp_errors() {
+        let router = IpAddr::from([10, 0, 0, 1]);
+        let addr: SocketAddr = "10.0.0.9:80".parse().unwrap();
+        assert_eq!(
+            error(&icmp_error(11), router, addr),
+            Some((40000, Answer::Router(router)))
+        );
+        assert_eq!(
+            error(&icmp_error(3), router, addr),
+            Some((40000, Answer::Unreachable(router)))
+        );
+        // Echo replies and errors about other ports or hosts are not ours.
+        assert_eq!(error(&icmp_error(0), router, addr), None);
+        assert_eq!(
+            error(&icmp_error(11), router, "10.0.0.9:81".parse().unwrap()),
+            None
+        );
+        assert_eq!(
+            error(&icmp_error(11), router, "10.0.0.8:80".parse().unwrap()),
+            None
+        );
+        assert_eq!(error(&icmp_error(11)[..40], router, addr), None);
+
+        // ICMPv6 time exceeded, quoting the 40-byte IPv6 header.
+        let router = IpAddr::from([0xfd00, 0, 0, 0, 0, 0, 0, 1]);
+        let addr: SocketAddr = "[fd01::9]:443".parse().unwrap();
+        let mut packet = vec![3, 0, 0, 0, 0, 0, 0, 0];
+        packet.extend_from_slice(&[0x60, 0, 0, 0, 0, 20, 6, 1]);
+        packet.extend_from_slice(&[0; 16]);
+        packet.extend_from_slice(&"fd01::9".parse::<std::net::Ipv6Addr>().unwrap().octets());
+        packet.extend_from_slice(&[0x9c, 0x41, 0x01, 0xbb, 0, 0, 0, 1]);
+        assert_eq!(
+            error(&packet, router, addr),
+            Some((40001, Answer::Router(router)))
+        );
+    }
+
+    #[test]
+    fn builds_routes() {
+        let addr: SocketAddr = "10.0.0.9:80".parse().unwrap();
+        let router = IpAddr::from([10, 0, 0, 1]);
+        let ms = Duration::from_millis;
+        let mut answers = HashMap::from([(1, (Answer::Router(router), ms(1)))]);
+        assert!(!complete(&answers));
+        // Every TTL from 3 on reaches the host, the second hop stays silent.
+        answers.insert(3, (Answer::Host, ms(3)));
+        answers.insert(4, (Answer::Host, ms(3)));
+        assert!(!complete(&answers));
+        let reached = route(addr, &answers);
+        assert_eq!(reached.end, End::Reached);
+        assert_eq!(
+            reached.hops,
+            vec![
+                Hop {
+                    ttl: 1,
+                    addr: Some(router),
+                    rtt: Some(ms(1))
+                },
+                Hop {
+                    ttl: 2,
+                    addr: None,
+                    rtt: None
+                },
+                Hop {
+                    ttl: 3,
+                    addr: Some(addr.ip()),
+                    rtt: Some(ms(3))
+                },
+            ]
+        );
+        answers.insert(2, (Answer::Router(router), ms(2)));
+        assert!(complete(&answers));
+
+        // Without an answer from the host, the route ends at the last hop that answered.
+        let answers = HashMap::from([(2, (Answer::Router(router), ms(2)))]);
+        let lost = route(addr, &answers);
+        assert_eq!((lost.end, lost.hops.len()), (End::Lost, 2));
+        let answers = HashMap::from([(1, (Answer::Unreachable(router), ms(1)))]);
+        assert_eq!(route(addr, &answers).end, End::Unreachable);
+    }
+}
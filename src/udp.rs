@@ -0,0 +1,49 @@
+use crate::dns; // Import the DNS message builder for the DNS payload.
+
+// Function to pick the datagram to send to a UDP port. Many UDP services ignore anything they
+// cannot parse, so well-known ports get a real request that makes the service answer;
+// other ports get an empty datagram.
+pub fn payload(port: u16) -> Vec<u8> {
+    match port {
+        // DNS: a recursive query for the root name servers.
+        53 => dns::build_query(0x5053, ".", dns::TYPE_NS, dns::CLASS_IN, true),
+        // mDNS: a query for the services announced on the link.
+        5353 => dns::build_query(
+            0,
+            "_services._dns-sd._udp.local",
+            dns::TYPE_PTR,
+            dns::CLASS_IN,
+            false,
+        ),
+        // TFTP: a read request for a file that most likely does not exist, answered with an error.
+        69 => b"\x00\x01sniff\x00octet\x00".to_vec(),
+        // NTP: a version 3 client request.
+        123 => {
+            let mut packet = vec![0u8; 48];
+            packet[0] = 0x1b;
+            packet
+        }
+        // NetBIOS name service: a node status request for the wildcard name `*`.
+        137 => {
+            let mut packet = b"\x50\x53\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x20CK".to_vec();
+            packet.extend_from_slice(&[b'A'; 30]);
+            packet.extend_from_slice(b"\x00\x00\x21\x00\x01");
+            packet
+        }
+        // SNMP: a version 1 get-request for sysDescr.0 with the community `public`.
+        161 => b"\x30\x29\x02\x01\x00\x04\x06public\xa0\x1c\x02\x04\x50\x53\x4e\x46\x02\x01\x00\x02\x01\x00\x30\x0e\x30\x0c\x06\x08\x2b\x06\x01\x02\x01\x01\x01\x00\x05\x00".to_vec(),
+        // QUIC (HTTP/3): an Initial packet of a version reserved to force version negotiation,
+        // padded to the 1200 bytes servers require, which they answer with the versions they
+        // speak.
+        443 => {
+            let mut packet = b"\xc0\x1a\x2a\x3a\x4a\x08portsnif\x08portsnif".to_vec();
+            packet.resize(1200, 0);
+            packet
+        }
+        // SSDP: a discovery request for every device.
+        1900 => b"M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\nMX: 1\r\nST: ssdp:all\r\n\r\n".to_vec(),
+        // Memcached: a `stats` command with the UDP frame header.
+        11211 => b"\x00\x01\x00\x00\x00\x01\x00\x00stats\r\n".to_vec(),
+        _ => vec![],
+    }
+}
@@ -0,0 +1,222 @@
+use io_uring::{opcode, squeue, types, IoUring}; // Import the io_uring bindings.
+use socket2::{SockAddr, Socket}; // Import socket2 types for the sockets to connect.
+use std::collections::HashMap; // Import `HashMap` to track the connects in flight.
+use std::io; // Import I/O errors for connect results.
+use std::net::SocketAddr; // Import socket address types for the probed ports.
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd}; // Import raw descriptors to hand to the kernel.
+use std::sync::atomic::{AtomicUsize, Ordering}; // Import atomics to skip redundant wake-ups.
+use std::sync::{mpsc, OnceLock}; // Import channels to pass connects to the ring thread.
+use std::time::Duration; // Import `Duration` for connect timeouts.
+use tokio::sync::oneshot; // Import oneshot channels to hand back each connect result.
+
+// Define how many entries the submission queue holds; each connect takes two (connect and timeout).
+const ENTRIES: u32 = 4096;
+
+// Define the user data marking the completion of the wake-up read.
+const WAKE: u64 = u64::MAX;
+
+// A connect waiting to be submitted to the ring.
+struct Request {
+    socket: Socket,
+    addr: SockAddr,
+    wait: Duration,
+    done: oneshot::Sender<io::Result<()>>,
+}
+
+// A connect submitted to the ring. The address and timeout are read by the kernel,
+// so they stay in place (boxed) until the connect completes.
+struct InFlight {
+    // Kept open until the connect completes; closed when this is dropped.
+    _socket: Socket,
+    _addr: Box<SockAddr>,
+    _timeout: Box<types::Timespec>,
+    done: oneshot::Sender<io::Result<()>>,
+}
+
+// A thread owning an io_uring instance, which submits queued connects in batches.
+pub struct Ring {
+    requests: mpsc::Sender<Request>,
+    // Written to wake the ring thread when connects are queued.
+    wake: OwnedFd,
+    // Connects queued since the ring thread last looked, so only the first one wakes it.
+    queued: &'static AtomicUsize,
+}
+
+// Function to get the shared ring, set up on first use.
+// Returns `None` (after a warning) if the kernel does not allow io_uring.
+pub fn ring() -> Option<&'static Ring> {
+    static RING: OnceLock<Option<Ring>> = OnceLock::new();
+    RING.get_or_init(|| match Ring::start() {
+        Ok(ring) => Some(ring),
+        Err(e) => {
+            eprintln!(
+                "Warning: io_uring is not available ({}), using regular connects",
+                e
+            );
+            None
+        }
+    })
+    .as_ref()
+}
+
+impl Ring {
+    // Function to set up the ring and start its thread.
+    fn start() -> io::Result<Ring> {
+        let ring = IoUring::new(ENTRIES)?;
+        // SAFETY: eventfd has no memory-safety preconditions.
+        let wake = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+        if wake < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `wake` is a freshly created descriptor owned by nobody else.
+        let wake = unsafe { OwnedFd::from_raw_fd(wake) };
+        let queued: &'static AtomicUsize = Box::leak(Box::new(AtomicUsize::new(0)));
+        let (tx, rx) = mpsc::channel();
+        let wake_fd = wake.as_raw_fd();
+        std::thread::Builder::new()
+            .name("io_uring".to_string())
+            .spawn(move || {
+                if let Err(e) = run(ring, rx, wake_fd, queued) {
+                    eprintln!("Error: io_uring stopped working: {}", e);
+                }
+            })?;
+        Ok(Ring {
+            requests: tx,
+            wake,
+            queued,
+        })
+    }
+
+    // Function to connect a non-blocking socket to `addr`, waiting at most `wait` for an answer.
+    // A connect that times out fails with `ErrorKind::TimedOut`.
+    pub async fn connect(
+        &self,
+        socket: Socket,
+        addr: SocketAddr,
+        wait: Duration,
+    ) -> io::Result<()> {
+        let (done, result) = oneshot::channel();
+        let request = Request {
+            socket,
+            addr: addr.into(),
+            wait,
+            done,
+        };
+        if self.requests.send(request).is_err() {
+            return Err(io::Error::other("the io_uring thread stopped"));
+        }
+        // Only the first connect queued since the ring thread last looked needs to wake it.
+        if self.queued.fetch_add(1, Ordering::AcqRel) == 0 {
+            let one = 1u64;
+            // SAFETY: `one` is 8 readable bytes, as an eventfd write expects.
+            unsafe { libc::write(self.wake.as_raw_fd(), (&one as *const u64).cast(), 8) };
+        }
+        result
+            .await
+            .unwrap_or_else(|_| Err(io::Error::other("the io_uring thread stopped")))
+    }
+}
+
+// Function run by the ring thread: submits the queued connects, each linked to its timeout,
+// and hands back the results as they complete.
+fn run(
+    mut ring: IoUring,
+    requests: mpsc::Receiver<Request>,
+    wake: i32,
+    queued: &AtomicUsize,
+) -> io::Result<()> {
+    let mut in_flight: HashMap<u64, InFlight> = HashMap::new();
+    let mut next_id = 0u64;
+    let mut wake_buf = Box::new(0u64);
+    let mut armed = false;
+    loop {
+        // Wait for the next wake-up, unless that read is still pending.
+        if !armed {
+            let read = opcode::Read::new(types::Fd(wake), (&mut *wake_buf as *mut u64).cast(), 8)
+                .build()
+                .user_data(WAKE);
+            // SAFETY: `wake_buf` stays in place until the read completes.
+            while unsafe { ring.submission().push(&read) }.is_err() {
+                ring.submit()?;
+            }
+            armed = true;
+        }
+
+        match ring.submit_and_wait(1) {
+            Err(e) if e.kind() != io::ErrorKind::Interrupted => return Err(e),
+            _ => {}
+        }
+        let mut woken = false;
+        for entry in ring.completion() {
+            match entry.user_data() {
+                WAKE => {
+                    armed = false;
+                    woken = true;
+                }
+                // The timeout half of a connect; its connect reports the outcome.
+                id if id & 1 == 1 => {}
+                id => {
+                    let Some(connect) = in_flight.remove(&id) else {
+                        continue;
+                    };
+                    let result = match entry.result() {
+                        0 => Ok(()),
+                        e if -e == libc::ECANCELED => Err(io::ErrorKind::TimedOut.into()),
+                        e => Err(io::Error::from_raw_os_error(-e)),
+                    };
+                    // The prober may have given up on the port already.
+                    let _ = connect.done.send(result);
+                }
+            }
+        }
+        if !woken {
+            continue;
+        }
+
+        // Submit everything queued since the last wake-up.
+        queued.store(0, Ordering::Release);
+        while let Ok(request) = requests.try_recv() {
+            let addr = Box::new(request.addr);
+            let timeout = Box::new(
+                types::Timespec::new()
+                    .sec(request.wait.as_secs())
+                    .nsec(request.wait.subsec_nanos()),
+            );
+            let id = next_id << 1;
+            next_id += 1;
+            let connect = opcode::Connect::new(
+                types::Fd(request.socket.as_raw_fd()),
+                addr.as_ptr().cast(),
+                addr.len(),
+            )
+            .build()
+            .flags(squeue::Flags::IO_LINK)
+            .user_data(id);
+            let timeout_entry = opcode::LinkTimeout::new(&*timeout)
+                .build()
+                .user_data(id | 1);
+            // The connect and its timeout have to go into the queue together.
+            while {
+                let queue = ring.submission();
+                queue.capacity() - queue.len() < 2
+            } {
+                ring.submit()?;
+            }
+            // SAFETY: the socket, address and timeout live in `in_flight` until the connect completes.
+            unsafe {
+                let mut queue = ring.submission();
+                let _ = queue.push(&connect);
+                let _ = queue.push(&timeout_entry);
+            }
+            in_flight.insert(
+                id,
+                InFlight {
+                    _socket: request.socket,
+                    _addr: addr,
+                    _timeout: timeout,
+                    done: request.done,
+                },
+            );
+        }
+    }
+}
@@ -0,0 +1,491 @@
+use crate::regex::Regex; // Import the regular expressions of the match rules.
+use crate::scanner::{self, PortResult, PortState, Protocol, Settings}; // Import the open ports to probe and the scan settings to connect with.
+use crate::targets::Target; // Import the hosts the ports belong to.
+use crate::web; // Import the plain and TLS connects of the web probes.
+use serde::{Deserialize, Serialize}; // Import serde to read the probe database and report what was found.
+use socket2::Type; // Import socket types for the probes.
+use std::fs; // Import filesystem access to read the user's probes.
+use std::io; // Import I/O errors for socket failures.
+use std::net::SocketAddr; // Import `SocketAddr` for the ports probed.
+use std::sync::OnceLock; // Import `OnceLock` to compile the probe database only once.
+use std::time::Duration; // Import `Duration` for the connect and read timeouts.
+use tokio::io::{AsyncReadExt, AsyncWriteExt}; // Import reads and writes on the connected streams.
+use tokio::net::UdpSocket; // Import sockets of the source for the UDP probes.
+use tokio::time::{timeout, timeout_at, Instant}; // Import timeouts to give up on silent services.
+
+// The built-in probe database, embedded at build time.
+const PROBES: &str = include_str!("../data/probes.toml");
+
+// The user's probes (--probe-rules), tried before the built-in ones.
+static CUSTOM: OnceLock<Vec<Probe>> = OnceLock::new();
+
+// Define how much of a response is read at most.
+const MAX_RESPONSE: usize = 16 * 1024;
+
+// What a probe database file holds.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    #[serde(default)]
+    probes: Vec<ProbeRule>,
+}
+
+// A probe as written in a probe database file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProbeRule {
+    name: String,
+    protocol: String,
+    #[serde(default)]
+    ports: Vec<u16>,
+    #[serde(default)]
+    send: String,
+    // The bytes to send written in hex instead, e.g. `0a 1b ff`.
+    #[serde(default)]
+    hex: String,
+    // The length zero bytes pad `send` up to, for services that drop short datagrams.
+    #[serde(default)]
+    pad: usize,
+    // Set to speak to the service over TLS.
+    #[serde(default)]
+    tls: bool,
+    #[serde(default)]
+    matches: Vec<MatchRule>,
+}
+
+// A rule that names the service from a response, as written in a probe database file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MatchRule {
+    pattern: String,
+    service: String,
+    product: Option<String>,
+    version: Option<String>,
+    info: Option<String>,
+}
+
+// A compiled probe: what to send to which ports, and how to read the response.
+#[derive(Debug)]
+struct Probe {
+    protocol: Protocol,
+    // The ports the probe is sent to, or every port when empty.
+    ports: Vec<u16>,
+    payload: Vec<u8>,
+    // Set when the probe goes over TLS.
+    tls: bool,
+    matches: Vec<Match>,
+}
+
+// A compiled match rule.
+#[derive(Debug)]
+struct Match {
+    regex: Regex,
+    service: String,
+    product: Option<String>,
+    version: Option<String>,
+    info: Option<String>,
+}
+
+// The service found on a port, e.g. `ssh` served by `OpenSSH` version `9.6p1`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Service {
+    pub name: String,
+    pub product: Option<String>,
+    pub version: Option<String>,
+    // More about it, such as the OS it was built for or the host name it gave.
+    pub info: Option<String>,
+}
+
+impl Service {
+    // Function to describe the service in one line, e.g. `ssh OpenSSH 9.6p1 Ubuntu-3ubuntu13`.
+    pub fn describe(&self) -> String {
+        let mut parts = vec![self.name.as_str()];
+        parts.extend(
+            [&self.product, &self.version, &self.info]
+                .into_iter()
+                .flatten()
+                .map(String::as_str),
+        );
+        parts.join(" ")
+    }
+}
+
+// Function to get the compiled built-in probes. A test makes sure they compile.
+fn database() -> &'static [Probe] {
+    static DATABASE: OnceLock<Vec<Probe>> = OnceLock::new();
+    DATABASE.get_or_init(|| parse(PROBES).expect("the built-in probe database is valid"))
+}
+
+// Function to load a probe file of the user's in the form of data/probes.toml, whose probes are
+// then tried before the built-in ones.
+pub fn load(path: &str) -> Result<(), String> {
+    let contents =
+        fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let probes = parse(&contents).map_err(|e| format!("Invalid probe file {}: {}", path, e))?;
+    let _ = CUSTOM.set(probes);
+    Ok(())
+}
+
+// Function to read and compile a probe database in the form of data/probes.toml.
+fn parse(contents: &str) -> Result<Vec<Probe>, String> {
+    let file: File = toml::from_str(contents).map_err(|e| e.to_string())?;
+    file.probes.into_iter().map(compile).collect()
+}
+
+// Function to compile a probe as written in a database file, naming it in errors.
+fn compile(rule: ProbeRule) -> Result<Probe, String> {
+    let failed = |e: String| format!("probe {}: {}", rule.name, e);
+    let protocol = match rule.protocol.as_str() {
+        "tcp" => Protocol::Tcp,
+        "udp" => Protocol::Udp,
+        other => return Err(failed(format!("protocol {} is not tcp or udp", other))),
+    };
+    let mut payload = match (rule.send.is_empty(), rule.hex.is_empty()) {
+        (_, true) => unescape(&rule.send).map_err(failed)?,
+        (true, false) => unhex(&rule.hex).map_err(failed)?,
+        (false, false) => return Err(failed("send and hex cannot both be given".to_string())),
+    };
+    if protocol == Protocol::Udp && rule.tls {
+        return Err(failed("only TCP probes can go over TLS".to_string()));
+    }
+    if payload.len() < rule.pad {
+        payload.resize(rule.pad, 0);
+    }
+    if protocol == Protocol::Udp && payload.is_empty() {
+        return Err(failed("UDP probes need something to send".to_string()));
+    }
+    let matches = rule
+        .matches
+        .into_iter()
+        .map(|m| {
+            Ok(Match {
+                regex: Regex::new(&m.pattern)?,
+                service: m.service,
+                product: m.product,
+                version: m.version,
+                info: m.info,
+            })
+        })
+        .collect::<Result<_, String>>()
+        .map_err(failed)?;
+    Ok(Probe {
+        protocol,
+        ports: rule.ports,
+        payload,
+        tls: rule.tls,
+        matches,
+    })
+}
+
+// Function to decode the escapes \r \n \t \0 \\ and \xNN of a probe payload.
+fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+    let mut rest = text.as_bytes();
+    while let Some((&byte, after)) = rest.split_first() {
+        rest = after;
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        let (&escape, after) = rest.split_first().ok_or("send ends in a lone \\")?;
+        rest = after;
+        bytes.push(match escape {
+            b'r' => b'\r',
+            b'n' => b'\n',
+            b't' => b'\t',
+            b'0' => 0,
+            b'\\' => b'\\',
+            b'x' => {
+                let digits = rest.get(..2).ok_or("send has a short \\x escape")?;
+                rest = &rest[2..];
+                std::str::from_utf8(digits)
+                    .ok()
+                    .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                    .ok_or("send has an invalid \\x escape")?
+            }
+            other => {
+                return Err(format!(
+                    "send has an unknown escape \\{}",
+                    char::from(other)
+                ))
+            }
+        });
+    }
+    Ok(bytes)
+}
+
+// Function to decode the hex digits of a probe payload, which white space may separate.
+fn unhex(text: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<u8> = text.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err("hex has an odd number of digits".to_string());
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| "hex has a character that is not a hex digit".to_string())
+        })
+        .collect()
+}
+
+// Function to identify the service and its version on every open TCP and UDP port in
+// `results` and keep it on the result. Each probe gets `wait` for its connect and `wait` for
+// the response. Ports that match no rule stay as they are.
+pub async fn detect_all(
+    hosts: &[Target],
+    results: &mut [PortResult],
+    settings: &Settings,
+    wait: Duration,
+) {
+    scanner::check_ports(
+        hosts,
+        results,
+        settings.concurrency,
+        |r, host| {
+            let transport = matches!(r.protocol, Protocol::Tcp | Protocol::Udp);
+            let name = host.hostnames.first().map(String::as_str);
+            (r.state == PortState::Open && transport).then_some((name, r.protocol))
+        },
+        |addr, (name, protocol)| detect(addr, name, protocol, settings, wait),
+        |r, service| r.findings.detected = service,
+    )
+    .await;
+}
+
+// Function to send the probes meant for the port of `addr`, those of the user first, one after
+// the other, until the response to one of them names the service. TLS probes go to the host
+// `name`, or else the address.
+async fn detect(
+    addr: SocketAddr,
+    name: Option<&str>,
+    protocol: Protocol,
+    settings: &Settings,
+    wait: Duration,
+) -> Option<Service> {
+    let probes = CUSTOM
+        .get()
+        .into_iter()
+        .flatten()
+        .chain(database())
+        .filter(|probe| probe.protocol == protocol)
+        .filter(|probe| probe.ports.is_empty() || probe.ports.contains(&addr.port()));
+    for probe in probes {
+        match probe.send(addr, name, settings, wait).await {
+            Ok(Some(service)) => return Some(service),
+            Ok(None) => {}
+            // A port that does not speak TLS may still answer the other probes.
+            Err(_) if probe.tls => {}
+            // A port that cannot be reached any more will not answer the next probes either.
+            Err(_) => return None,
+        }
+    }
+    None
+}
+
+impl Probe {
+    // Function to send the probe to `addr` and read the response until a rule matches it, the
+    // service hangs up or `wait` is over. Fails only if the port cannot be reached, or for TLS
+    // probes, the handshake fails.
+    async fn send(
+        &self,
+        addr: SocketAddr,
+        name: Option<&str>,
+        settings: &Settings,
+        wait: Duration,
+    ) -> io::Result<Option<Service>> {
+        let mut buffer = vec![0u8; MAX_RESPONSE];
+        if self.protocol == Protocol::Udp {
+            let socket = UdpSocket::from_std(settings.socket(addr, Type::DGRAM).await?.into())?;
+            socket.connect(addr).await?;
+            socket.send(&self.payload).await?;
+            return Ok(match timeout(wait, socket.recv(&mut buffer)).await {
+                Ok(Ok(read)) => self.identify(&buffer[..read]),
+                _ => None,
+            });
+        }
+
+        let mut stream = web::connect(addr, name, self.tls, settings, wait).await?;
+        stream.write_all(&self.payload).await?;
+        let deadline = Instant::now() + wait;
+        let mut read = 0;
+        while read < MAX_RESPONSE {
+            match timeout_at(deadline, stream.read(&mut buffer[read..])).await {
+                Ok(Ok(0)) | Ok(Err(_)) | Err(_) => break,
+                Ok(Ok(n)) => read += n,
+            }
+            if let Some(service) = self.identify(&buffer[..read]) {
+                return Ok(Some(service));
+            }
+        }
+        Ok(None)
+    }
+
+    // Function to name the service from `response` with the first rule that matches it.
+    fn identify(&self, response: &[u8]) -> Option<Service> {
+        self.matches.iter().find_map(|rule| {
+            let groups = rule.regex.captures(response)?;
+            let fill = |template: &Option<String>| {
+                template
+                    .as_deref()
+                    .map(|template| expand(template, &groups))
+                    .filter(|text| !text.is_empty())
+            };
+            Some(Service {
+                name: rule.service.clone(),
+                product: fill(&rule.product),
+                version: fill(&rule.version),
+                info: fill(&rule.info),
+            })
+        })
+    }
+}
+
+// Function to put the text of the groups of a match in for `$1` to `$9` in `template`, leaving
+// out control characters, which a service should not get onto the terminal.
+fn expand(template: &str, groups: &[Option<&[u8]>]) -> String {
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match chars.peek().and_then(|next| next.to_digit(10)) {
+            Some(n) if c == '$' => {
+                chars.next();
+                if let Some(Some(group)) = groups.get(n as usize) {
+                    text.extend(
+                        String::from_utf8_lossy(group)
+                            .chars()
+                            .filter(|c| !c.is_control()),
+                    );
+                }
+            }
+            _ => text.push(c),
+        }
+    }
+    text.trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Function to identify `response` to the probe `name` of the built-in database.
+    fn identify(name: &str, response: &[u8]) -> Option<String> {
+        let rules: File = toml::from_str(PROBES).unwrap();
+        let index = rules.probes.iter().position(|p| p.name == name).unwrap();
+        database()[index].identify(response).map(|s| s.describe())
+    }
+
+    #[test]
+    fn compiles_the_builtin_database() {
+        assert!(!database().is_empty());
+    }
+
+    #[test]
+    fn identifies_services() {
+        assert_eq!(
+            identify("banner", b"SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13.5\r\n").as_deref(),
+            Some("ssh OpenSSH 9.6p1 Ubuntu-3ubuntu13.5")
+        );
+        assert_eq!(
+            identify("banner", b"220 (vsFTPd 3.0.5)\r\n").as_deref(),
+            Some("ftp vsftpd 3.0.5")
+        );
+        assert_eq!(
+            identify(
+                "banner",
+                b"J\x00\x00\x00\x0a8.0.36-0ubuntu0.22.04.1\x00\x08\x00\x00\x00"
+            )
+            .as_deref(),
+            Some("mysql MySQL 8.0.36")
+        );
+        assert_eq!(
+            identify(
+                "http",
+                b"HTTP/1.1 301 Moved Permanently\r\nContent-Type: text/html\r\nServer: nginx/1.24.0\r\n\r\n"
+            )
+            .as_deref(),
+            Some("http nginx 1.24.0")
+        );
+        assert_eq!(
+            identify(
+                "http",
+                b"HTTP/1.0 200 OK\r\nserver: Apache/2.4.58 (Ubuntu)\r\n\r\n"
+            )
+            .as_deref(),
+            Some("http Apache httpd 2.4.58 Ubuntu")
+        );
+        assert_eq!(
+            identify("http", b"HTTP/1.1 404 Not Found\r\n\r\n").as_deref(),
+            Some("http")
+        );
+        assert_eq!(identify("http", b"SSH-2.0-OpenSSH_9.6\r\n"), None);
+        assert_eq!(
+            identify(
+                "quic",
+                b"\xa3\x00\x00\x00\x00\x08portsnif\x08portsnif\xca\xfa\xba\xda\x00\x00\x00\x01"
+            )
+            .as_deref(),
+            Some("quic v1")
+        );
+        assert_eq!(
+            identify(
+                "quic",
+                b"\x80\x00\x00\x00\x00\x08portsnif\x08portsnif\x00\x01\x00\x00\xff\x00\x00\x1d"
+            )
+            .as_deref(),
+            Some("quic")
+        );
+        // A reply padded to the largest response read still comes out as QUIC.
+        let mut padded = b"\x80\x00\x00\x00\x00\x08portsnif\x08portsnif".to_vec();
+        padded.resize(MAX_RESPONSE, 0xff);
+        assert_eq!(identify("quic", &padded).as_deref(), Some("quic"));
+    }
+
+    #[test]
+    fn unescapes_payloads() {
+        assert_eq!(unescape(r"GET /\r\n\x00\\").unwrap(), b"GET /\r\n\x00\\");
+        assert!(unescape(r"\q").is_err());
+        assert!(unescape(r"\x4").is_err());
+        assert!(unescape("\\").is_err());
+    }
+
+    #[test]
+    fn reads_user_probes() {
+        let file = r#"
+            [[probes]]
+            name = "billing"
+            protocol = "tcp"
+            ports = [7100]
+            hex = "42 49 4c 0d 0a"
+            tls = true
+
+            [[probes.matches]]
+            pattern = '^BIL ([\d.]+)'
+            service = "billing"
+            version = "$1"
+        "#;
+        let probes = parse(file).unwrap();
+        assert_eq!(probes[0].payload, b"BIL\r\n");
+        assert!(probes[0].tls);
+        assert_eq!(
+            probes[0].identify(b"BIL 2.1.0 ready").map(|s| s.describe()),
+            Some("billing 2.1.0".to_string())
+        );
+
+        // A rule nesting unbounded repeats gives up on a long response rather than crash.
+        let nested = file.replace(r"^BIL ([\d.]+)", r"^BIL ((?:\d+\.?)*)+x");
+        let long = format!("BIL {}", "1.".repeat(MAX_RESPONSE / 2));
+        assert!(parse(&nested).unwrap()[0]
+            .identify(long.as_bytes())
+            .is_none());
+
+        let both = "[[probes]]\nname = 'x'\nprotocol = 'tcp'\nsend = 'a'\nhex = '61'";
+        assert!(parse(both).is_err());
+        let udp_tls = "[[probes]]\nname = 'x'\nprotocol = 'udp'\nhex = '61'\ntls = true";
+        assert!(parse(udp_tls).is_err());
+        assert!(unhex("616").is_err());
+        assert!(unhex("6g").is_err());
+    }
+}
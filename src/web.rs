@@ -0,0 +1,416 @@
+use crate::http::{self, Response, Url}; // Import the HTTP client the pages are requested with.
+use crate::scanner::{self, PortResult, PortState, Protocol, Settings}; // Import the open ports to request and the scan settings to connect with.
+use crate::targets::Target; // Import the hosts the ports belong to.
+use crate::tls; // Import TLS handshakes for HTTPS.
+use crate::x509; // Import base64 for the WebSocket keys.
+use rand::RngCore; // Import random bytes for the WebSocket keys.
+use ring::digest; // Import SHA-1 to check the WebSocket accept keys.
+use serde::Serialize; // Import `Serialize` to report pages in the structured output.
+use socket2::Type; // Import socket types for the connects.
+use std::io; // Import I/O errors for socket failures.
+use std::net::SocketAddr; // Import `SocketAddr` for the ports requested.
+use std::time::Duration; // Import `Duration` for the connect and response timeouts.
+use tokio::io::{AsyncRead, AsyncWrite}; // Import the stream traits plain and TLS connections share.
+use tokio::net::TcpSocket; // Import `TcpSocket` to connect the sockets of the source.
+use tokio::time::timeout; // Import `timeout` to give up on ports that do not answer.
+use tokio_rustls::rustls::pki_types::ServerName; // Import the name HTTPS handshakes are run for.
+
+// Define the longest page title kept.
+const MAX_TITLE: usize = 100;
+
+// Define the shortest HSTS max-age not flagged, 180 days in seconds.
+const MIN_HSTS_AGE: u64 = 180 * 86_400;
+
+// Define the GUID a WebSocket server appends to the key of a handshake before hashing it (RFC 6455).
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// A plain or TLS connection to a web or MQTT port.
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Connection for S {}
+
+// What an open port answered a `GET /`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Page {
+    // Set when the page came over TLS.
+    pub https: bool,
+    pub status: u16,
+    // The Server header, e.g. `nginx/1.24.0`.
+    pub server: Option<String>,
+    // Where a redirect points, from its Location header.
+    pub location: Option<String>,
+    // The text of the <title> of the page.
+    pub title: Option<String>,
+    // What the security headers and cookies of the response lack, e.g. `no Content-Security-Policy`
+    // or `cookie sid without HttpOnly/SameSite`, with --http-headers.
+    pub issues: Option<Vec<String>>,
+    // The paths that accepted a WebSocket upgrade, with --websocket.
+    pub websockets: Option<Vec<String>>,
+}
+
+// Function to request `/` from every open TCP port in `results` that may be a web server and
+// keep what it answered on the result, giving each connect and response `wait`. Ports that sent
+// a banner or were identified as another service speak something else; the others get a plain
+// request, then one over TLS if the answer was no HTTP. With `audit`, the security headers of
+// the answers are checked as well, and given `websocket` paths, each of them is asked for a
+// WebSocket upgrade over the connection the page came over.
+pub async fn probe_all(
+    hosts: &[Target],
+    results: &mut [PortResult],
+    audit: bool,
+    websocket: Option<&[String]>,
+    settings: &Settings,
+    wait: Duration,
+) {
+    scanner::check_ports(
+        hosts,
+        results,
+        settings.concurrency,
+        |r, host| {
+            let web = r.findings.banner.is_none()
+                && r.findings
+                    .detected
+                    .as_ref()
+                    .is_none_or(|s| s.name.starts_with("http"));
+            let name = host.hostnames.first().map(String::as_str);
+            (r.protocol == Protocol::Tcp && r.state == PortState::Open && web)
+                .then_some((name, r.findings.tls.is_some()))
+        },
+        |addr, (name, tls)| async move {
+            let response = match tls {
+                true => request(addr, name, true, settings, wait).await,
+                false => match request(addr, name, false, settings, wait).await {
+                    Ok(response) => Ok(response),
+                    Err(_) => request(addr, name, true, settings, wait).await,
+                },
+            };
+            let (response, https) = response.ok()?;
+            let mut page = page(&response, https, audit);
+            if let Some(paths) = websocket {
+                let mut upgraded = vec![];
+                for path in paths {
+                    if upgrades(addr, name, https, path, settings, wait).await {
+                        upgraded.push(path.clone());
+                    }
+                }
+                page.websockets = Some(upgraded);
+            }
+            Some(page)
+        },
+        |r, page| r.findings.http = page,
+    )
+    .await;
+}
+
+// Function to connect to `addr` and request `/` for the host `name`, or else the address, over
+// TLS if `https` is set, within `wait` for the connect, the handshake and the response each.
+// Gives the response with whether it came over TLS.
+async fn request(
+    addr: SocketAddr,
+    name: Option<&str>,
+    https: bool,
+    settings: &Settings,
+    wait: Duration,
+) -> io::Result<(Response, bool)> {
+    let mut stream = connect(addr, name, https, settings, wait).await?;
+    let request = format!(
+        "GET / HTTP/1.1\r\nHost: {}\r\nUser-Agent: port_sniffer\r\nAccept: */*\r\nConnection: close\r\n\r\n",
+        authority(addr, name, https)
+    );
+    let response = timeout(wait, http::send(&mut stream, request.as_bytes()))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    Ok((response, https))
+}
+
+// Function to ask `path` on `addr` for a WebSocket upgrade, the way `request` asks for `/`. Only
+// a 101 answer carrying the accept key of the handshake counts.
+async fn upgrades(
+    addr: SocketAddr,
+    name: Option<&str>,
+    https: bool,
+    path: &str,
+    settings: &Settings,
+    wait: Duration,
+) -> bool {
+    let Ok(mut stream) = connect(addr, name, https, settings, wait).await else {
+        return false;
+    };
+    let mut nonce = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let key = x509::base64(&nonce);
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: port_sniffer\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        path,
+        authority(addr, name, https),
+        key
+    );
+    match timeout(wait, http::send_head(&mut stream, request.as_bytes())).await {
+        Ok(Ok(response)) => {
+            response.status == 101
+                && response
+                    .header("sec-websocket-accept")
+                    .is_some_and(|accept| accept.trim() == accept_key(&key))
+        }
+        _ => false,
+    }
+}
+
+// Function to connect to `addr`, over TLS for the host `name`, or else the address, if `https` is
+// set, within `wait` for the connect and the handshake each.
+pub async fn connect(
+    addr: SocketAddr,
+    name: Option<&str>,
+    https: bool,
+    settings: &Settings,
+    wait: Duration,
+) -> io::Result<Box<dyn Connection>> {
+    let timed_out = |_| io::Error::from(io::ErrorKind::TimedOut);
+    let socket = TcpSocket::from_std_stream(settings.socket(addr, Type::STREAM).await?.into());
+    let stream = timeout(wait, socket.connect(addr))
+        .await
+        .map_err(timed_out)??;
+    if !https {
+        return Ok(Box::new(stream));
+    }
+    let server_name = name
+        .and_then(|name| tls::server_name(name).ok())
+        .unwrap_or(ServerName::from(addr.ip()));
+    let stream = timeout(wait, tls::connect_any(stream, server_name, false))
+        .await
+        .map_err(timed_out)??;
+    Ok(Box::new(stream))
+}
+
+// Function to give the Host header of requests to `addr` for the host `name`, or else the
+// address.
+pub fn authority(addr: SocketAddr, name: Option<&str>, https: bool) -> String {
+    Url {
+        https,
+        host: name.map_or(addr.ip().to_string(), str::to_string),
+        port: addr.port(),
+        path: "/".to_string(),
+    }
+    .authority()
+}
+
+// Function to compute the Sec-WebSocket-Accept a server answers the handshake key `key` with.
+fn accept_key(key: &str) -> String {
+    let hash = digest::digest(
+        &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        format!("{}{}", key, WEBSOCKET_GUID).as_bytes(),
+    );
+    x509::base64(hash.as_ref())
+}
+
+// Function to read a comma-separated list of --websocket-paths, each starting with `/`, with `/`
+// itself first.
+pub fn paths(spec: Option<&str>) -> Result<Vec<String>, String> {
+    let mut paths = vec!["/".to_string()];
+    for path in spec.into_iter().flat_map(|spec| spec.split(',')) {
+        let path = path.trim();
+        if !path.starts_with('/') || path.contains(char::is_whitespace) {
+            return Err(format!(
+                "WebSocket path {:?} has to start with / and hold no spaces",
+                path
+            ));
+        }
+        if !paths.iter().any(|p| p == path) {
+            paths.push(path.to_string());
+        }
+    }
+    Ok(paths)
+}
+
+// Function to sum up a response as a page, checking its security headers if `audit` is set.
+fn page(response: &Response, https: bool, audit: bool) -> Page {
+    let header = |name| response.header(name).map(str::to_string);
+    Page {
+        https,
+        status: response.status,
+        server: header("server"),
+        location: header("location").filter(|_| (300..400).contains(&response.status)),
+        title: title(&response.body),
+        issues: audit.then(|| issues(response, https)),
+        websockets: None,
+    }
+}
+
+// Function to list what the security headers and cookies of a response lack. HSTS and the
+// Secure flag of cookies only matter over TLS.
+fn issues(response: &Response, https: bool) -> Vec<String> {
+    let mut issues = vec![];
+    if https {
+        let max_age = response
+            .header("strict-transport-security")
+            .map(|value| directive(value, "max-age").and_then(|age| age.parse::<u64>().ok()));
+        match max_age {
+            None => issues.push("no Strict-Transport-Security".to_string()),
+            Some(age) if age.unwrap_or(0) < MIN_HSTS_AGE => {
+                issues.push("Strict-Transport-Security max-age under 180 days".to_string())
+            }
+            Some(_) => {}
+        }
+    }
+    let policy = response.header("content-security-policy");
+    if policy.is_none() {
+        issues.push("no Content-Security-Policy".to_string());
+    }
+    // frame-ancestors in the policy takes the place of X-Frame-Options.
+    let framed = policy.is_some_and(|policy| directive(policy, "frame-ancestors").is_some());
+    if response.header("x-frame-options").is_none() && !framed {
+        issues.push("no X-Frame-Options".to_string());
+    }
+    if !response
+        .header("x-content-type-options")
+        .is_some_and(|value| value.eq_ignore_ascii_case("nosniff"))
+    {
+        issues.push("no X-Content-Type-Options: nosniff".to_string());
+    }
+    if response.header("referrer-policy").is_none() {
+        issues.push("no Referrer-Policy".to_string());
+    }
+    for (_, cookie) in response
+        .headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("set-cookie"))
+    {
+        let mut parts = cookie.split(';').map(str::trim);
+        let name = parts.next().unwrap_or("").split('=').next().unwrap_or("");
+        let flags: Vec<String> = parts
+            .map(|part| part.split('=').next().unwrap_or("").to_ascii_lowercase())
+            .collect();
+        let lacking: Vec<&str> = [("Secure", https), ("HttpOnly", true), ("SameSite", true)]
+            .into_iter()
+            .filter(|(flag, wanted)| *wanted && !flags.contains(&flag.to_ascii_lowercase()))
+            .map(|(flag, _)| flag)
+            .collect();
+        if !lacking.is_empty() {
+            issues.push(format!("cookie {} without {}", name, lacking.join("/")));
+        }
+    }
+    issues
+}
+
+// Function to find the value of a directive such as `max-age=31536000` in a header value whose
+// directives are separated by `;`, e.g. HSTS and CSP.
+fn directive<'a>(value: &'a str, name: &str) -> Option<&'a str> {
+    value.split(';').map(str::trim).find_map(|part| {
+        let (key, rest) = part
+            .split_once(|c: char| c == '=' || c.is_whitespace())
+            .unwrap_or((part, ""));
+        key.eq_ignore_ascii_case(name)
+            .then(|| rest.trim().trim_matches('"'))
+    })
+}
+
+// Function to find the title of an HTML page, with its entities decoded, runs of white space
+// made one space and cut to `MAX_TITLE` characters.
+fn title(body: &[u8]) -> Option<String> {
+    let html = String::from_utf8_lossy(body);
+    let lower = html.to_ascii_lowercase();
+    let open = lower.find("<title")?;
+    let start = open + lower[open..].find('>')? + 1;
+    let end = start + lower[start..].find("</title")?;
+    let text = html[start..end]
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&");
+    let title: String = text
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_TITLE)
+        .collect();
+    (!title.is_empty()).then_some(title)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_titles() {
+        assert_eq!(
+            title(b"<html><HEAD><Title>\n  Welcome &amp; hello\n</TITLE>").as_deref(),
+            Some("Welcome & hello")
+        );
+        assert_eq!(
+            title(b"<title id=\"t\">Login</title>").as_deref(),
+            Some("Login")
+        );
+        assert_eq!(title(b"<title>   </title>"), None);
+        assert_eq!(title(b"<title>never closed"), None);
+        assert_eq!(title(b"no html"), None);
+        let long = [b"<title>".as_slice(), &[b'a'; 300], b"</title>"].concat();
+        assert_eq!(title(&long).map(|t| t.len()), Some(MAX_TITLE));
+    }
+
+    #[test]
+    fn sums_up_responses() {
+        let response = http::parse_response(
+            b"HTTP/1.1 302 Found\r\nLocation: /login\r\nServer: Apache-Coyote/1.1\r\n\r\n",
+        )
+        .unwrap();
+        let page = page(&response, false, false);
+        assert_eq!(page.status, 302);
+        assert_eq!(page.location.as_deref(), Some("/login"));
+        assert_eq!(page.server.as_deref(), Some("Apache-Coyote/1.1"));
+        assert_eq!(page.title, None);
+        assert_eq!(page.issues, None);
+    }
+
+    #[test]
+    fn audits_security_headers() {
+        let response = http::parse_response(
+            b"HTTP/1.1 200 OK\r\nStrict-Transport-Security: max-age=300; includeSubDomains\r\n\
+              Content-Security-Policy: default-src 'self'; frame-ancestors 'none'\r\n\
+              X-Content-Type-Options: nosniff\r\nReferrer-Policy: no-referrer\r\n\
+              Set-Cookie: sid=abc; Path=/; HttpOnly\r\n\
+              Set-Cookie: pref=1; Secure; HttpOnly; SameSite=Lax\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            issues(&response, true),
+            [
+                "Strict-Transport-Security max-age under 180 days",
+                "cookie sid without Secure/SameSite"
+            ]
+        );
+        assert_eq!(issues(&response, false), ["cookie sid without SameSite"]);
+
+        let bare = http::parse_response(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+        assert_eq!(
+            issues(&bare, true),
+            [
+                "no Strict-Transport-Security",
+                "no Content-Security-Policy",
+                "no X-Frame-Options",
+                "no X-Content-Type-Options: nosniff",
+                "no Referrer-Policy"
+            ]
+        );
+        assert_eq!(
+            directive("max-age=\"31536000\"; preload", "MAX-AGE"),
+            Some("31536000")
+        );
+    }
+
+    #[test]
+    fn computes_websocket_accept_keys() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert_eq!(paths(None).unwrap(), ["/"]);
+        assert_eq!(
+            paths(Some("/ws, /,/socket.io/")).unwrap(),
+            ["/", "/ws", "/socket.io/"]
+        );
+        assert!(paths(Some("ws")).is_err());
+    }
+}
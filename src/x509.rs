@@ -0,0 +1,465 @@
+use serde::{Serialize, Serializer}; // Import `Serialize` to report certificates in the structured output.
+use std::fmt; // Import formatting to show certificate times.
+use std::net::IpAddr; // Import `IpAddr` to show the addresses a certificate is valid for.
+use std::time::{SystemTime, UNIX_EPOCH}; // Import the clock to compare certificate times with.
+
+// DER tags of the parts of a certificate read here.
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+const OID: u8 = 0x06;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+const OCTET_STRING: u8 = 0x04;
+// The explicit [0] tag around the version of a certificate, and [3] around its extensions.
+const VERSION: u8 = 0xa0;
+const EXTENSIONS: u8 = 0xa3;
+// The OID of the subject alternative name extension, 2.5.29.17.
+const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+// The implicit tags of the alternative names kept, dNSName [2] and iPAddress [7].
+const DNS_NAME: u8 = 0x82;
+const IP_ADDRESS: u8 = 0x87;
+
+// The alphabet of base64, as used by PEM and WebSocket handshakes.
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// What a certificate says about itself, as shown after a TLS handshake.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Summary {
+    // The subject and issuer as RFC 4514 strings, e.g. `CN=example.com, O=Example, C=US`.
+    pub subject: String,
+    pub issuer: String,
+    pub not_before: Time,
+    pub not_after: Time,
+    // Set when the issuer is the subject itself.
+    pub self_signed: bool,
+    // The hostnames and addresses of the subject alternative names, e.g. `www.example.com`.
+    pub names: Vec<String>,
+}
+
+// A point in time of a certificate, in seconds since the Unix epoch. Shown and serialized as
+// `2025-01-31T12:00:00Z`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Time(pub i64);
+
+impl Time {
+    // Function to get the current time.
+    pub fn now() -> Time {
+        let elapsed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Time(elapsed.as_secs() as i64)
+    }
+
+    // Function to get the date of the time, e.g. `2025-01-31`.
+    pub fn date(self) -> String {
+        let (year, month, day) = civil(self.0.div_euclid(86_400));
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.0.rem_euclid(86_400);
+        write!(
+            f,
+            "{}T{:02}:{:02}:{:02}Z",
+            self.date(),
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    }
+}
+
+impl Serialize for Time {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+// A reader over DER-encoded values, which also reads the definite-length BER of SNMP.
+pub struct Der<'a> {
+    pub data: &'a [u8],
+}
+
+impl<'a> Der<'a> {
+    // Function to read the next value, giving its tag and contents.
+    pub fn read(&mut self) -> Result<(u8, &'a [u8]), String> {
+        let short = || "truncated DER value".to_string();
+        let (&tag, rest) = self.data.split_first().ok_or_else(short)?;
+        let (&first, rest) = rest.split_first().ok_or_else(short)?;
+        let (length, rest) = match first {
+            0..=0x7f => (usize::from(first), rest),
+            0x81..=0x84 => {
+                let size = usize::from(first & 0x7f);
+                let bytes = rest.get(..size).ok_or_else(short)?;
+                let length = bytes
+                    .iter()
+                    .fold(0usize, |length, &b| length << 8 | usize::from(b));
+                (length, &rest[size..])
+            }
+            _ => return Err("unsupported DER length".to_string()),
+        };
+        let contents = rest.get(..length).ok_or_else(short)?;
+        self.data = &rest[length..];
+        Ok((tag, contents))
+    }
+
+    // Function to read the next value, which has to carry `tag`.
+    pub fn expect(&mut self, tag: u8) -> Result<&'a [u8], String> {
+        match self.read()? {
+            (found, contents) if found == tag => Ok(contents),
+            (found, _) => Err(format!(
+                "expected DER tag {:#04x}, found {:#04x}",
+                tag, found
+            )),
+        }
+    }
+
+    // Function to look at the tag of the next value.
+    fn peek(&self) -> Option<u8> {
+        self.data.first().copied()
+    }
+}
+
+// Function to encode a DER value with its tag and length, as the SNMP and SPNEGO messages sent
+// to other services are.
+pub fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut value = vec![tag];
+    match contents.len() {
+        length @ 0..=0x7f => value.push(length as u8),
+        length @ 0x80..=0xff => value.extend_from_slice(&[0x81, length as u8]),
+        length => {
+            value.push(0x82);
+            value.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+    }
+    value.extend_from_slice(contents);
+    value
+}
+
+// Function to read the parts of a DER-encoded X.509 certificate shown after a handshake.
+pub fn parse(certificate: &[u8]) -> Result<Summary, String> {
+    let mut outer = Der { data: certificate };
+    let mut tbs = Der {
+        data: Der {
+            data: outer.expect(SEQUENCE)?,
+        }
+        .expect(SEQUENCE)?,
+    };
+    if tbs.peek() == Some(VERSION) {
+        tbs.read()?;
+    }
+    tbs.read()?; // serial number
+    tbs.expect(SEQUENCE)?; // signature algorithm
+    let issuer = tbs.expect(SEQUENCE)?;
+    let mut validity = Der {
+        data: tbs.expect(SEQUENCE)?,
+    };
+    let not_before = time(validity.read()?)?;
+    let not_after = time(validity.read()?)?;
+    let subject = tbs.expect(SEQUENCE)?;
+    tbs.expect(SEQUENCE)?; // public key
+                           // The unique identifiers of version 2 may come before the extensions.
+    let mut names = vec![];
+    while tbs.peek().is_some() {
+        if let (EXTENSIONS, contents) = tbs.read()? {
+            names = alternative_names(contents)?;
+        }
+    }
+    Ok(Summary {
+        subject: name(subject)?,
+        issuer: name(issuer)?,
+        not_before,
+        not_after,
+        self_signed: subject == issuer,
+        names,
+    })
+}
+
+// Function to find the subject alternative names among the extensions of a certificate.
+fn alternative_names(contents: &[u8]) -> Result<Vec<String>, String> {
+    let mut extensions = Der {
+        data: Der { data: contents }.expect(SEQUENCE)?,
+    };
+    while extensions.peek().is_some() {
+        let mut extension = Der {
+            data: extensions.expect(SEQUENCE)?,
+        };
+        if extension.expect(OID)? != SUBJECT_ALT_NAME {
+            continue;
+        }
+        // Skip the critical flag, if there is one.
+        let mut value = extension.read()?;
+        if value.0 != OCTET_STRING {
+            value = extension.read()?;
+        }
+        let mut general = Der {
+            data: Der { data: value.1 }.expect(SEQUENCE)?,
+        };
+        let mut names = vec![];
+        while general.peek().is_some() {
+            match general.read()? {
+                (DNS_NAME, name) => names.push(text(DNS_NAME, name)),
+                (IP_ADDRESS, &[a, b, c, d]) => names.push(IpAddr::from([a, b, c, d]).to_string()),
+                (IP_ADDRESS, address) => {
+                    if let Ok(octets) = <[u8; 16]>::try_from(address) {
+                        names.push(IpAddr::from(octets).to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+        return Ok(names);
+    }
+    Ok(vec![])
+}
+
+// Function to encode a DER certificate as PEM, in base64 lines of 64 characters.
+pub fn pem(certificate: &[u8]) -> String {
+    let encoded = base64(certificate);
+    let mut pem = "-----BEGIN CERTIFICATE-----\n".to_string();
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(&String::from_utf8_lossy(line));
+        pem.push('\n');
+    }
+    pem.push_str("-----END CERTIFICATE-----\n");
+    pem
+}
+
+// Function to encode `data` in base64, with padding.
+pub fn base64(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (i, &b)| bits | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            encoded.push(match i <= chunk.len() {
+                true => char::from(BASE64[(bits >> (18 - 6 * i) & 0x3f) as usize]),
+                false => '=',
+            });
+        }
+    }
+    encoded
+}
+
+// Function to turn a distinguished name into an RFC 4514 string, its most specific part first.
+fn name(contents: &[u8]) -> Result<String, String> {
+    let mut parts = vec![];
+    let mut sets = Der { data: contents };
+    while sets.peek().is_some() {
+        let mut set = Der {
+            data: sets.expect(SET)?,
+        };
+        while set.peek().is_some() {
+            let mut attribute = Der {
+                data: set.expect(SEQUENCE)?,
+            };
+            let kind = attribute.expect(OID)?;
+            let (tag, value) = attribute.read()?;
+            parts.push(format!("{}={}", attribute_name(kind), text(tag, value)));
+        }
+    }
+    parts.reverse();
+    Ok(parts.join(", "))
+}
+
+// Function to get the short name of an attribute type, or its dotted OID if it has none.
+fn attribute_name(oid: &[u8]) -> String {
+    match oid {
+        [0x55, 0x04, 0x03] => "CN".to_string(),
+        [0x55, 0x04, 0x06] => "C".to_string(),
+        [0x55, 0x04, 0x07] => "L".to_string(),
+        [0x55, 0x04, 0x08] => "ST".to_string(),
+        [0x55, 0x04, 0x0a] => "O".to_string(),
+        [0x55, 0x04, 0x0b] => "OU".to_string(),
+        [0x55, 0x04, 0x05] => "serialNumber".to_string(),
+        [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x01] => "emailAddress".to_string(),
+        oid => dotted(oid),
+    }
+}
+
+// Function to write an OID in dotted form, e.g. `2.5.4.97`.
+fn dotted(oid: &[u8]) -> String {
+    let mut arcs = vec![];
+    let mut value = 0u64;
+    for &byte in oid {
+        value = value << 7 | u64::from(byte & 0x7f);
+        if byte & 0x80 == 0 {
+            if arcs.is_empty() {
+                let first = (value / 40).min(2);
+                arcs.push(first);
+                arcs.push(value - first * 40);
+            } else {
+                arcs.push(value);
+            }
+            value = 0;
+        }
+    }
+    arcs.iter()
+        .map(u64::to_string)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+// Function to decode the string value of an attribute, leaving out control characters.
+fn text(tag: u8, value: &[u8]) -> String {
+    let decoded = match tag {
+        // BMPString, in UTF-16.
+        0x1e => String::from_utf16_lossy(
+            &value
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect::<Vec<_>>(),
+        ),
+        _ => String::from_utf8_lossy(value).into_owned(),
+    };
+    decoded.chars().filter(|c| !c.is_control()).collect()
+}
+
+// Function to read a UTCTime or GeneralizedTime such as `250131120000Z`.
+fn time((tag, value): (u8, &[u8])) -> Result<Time, String> {
+    let invalid = || "invalid certificate time".to_string();
+    let text = std::str::from_utf8(value).map_err(|_| invalid())?;
+    let digits = text.strip_suffix('Z').ok_or_else(invalid)?;
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let (year, rest) = match (tag, digits.len()) {
+        (UTC_TIME, 12) => {
+            let year: i64 = digits[..2].parse().map_err(|_| invalid())?;
+            (
+                if year < 50 { 2000 + year } else { 1900 + year },
+                &digits[2..],
+            )
+        }
+        (GENERALIZED_TIME, 14) => (digits[..4].parse().map_err(|_| invalid())?, &digits[4..]),
+        _ => return Err(invalid()),
+    };
+    let field = |at: usize| rest[at..at + 2].parse::<i64>().map_err(|_| invalid());
+    let (month, day) = (field(0)?, field(2)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+    Ok(Time(
+        days(year, month, day) * 86_400 + field(4)? * 3600 + field(6)? * 60 + field(8)?,
+    ))
+}
+
+// Function to count the days from 1970-01-01 to a date of the proleptic Gregorian calendar.
+fn days(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let of_era = year - era * 400;
+    let of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let of_cycle = of_era * 365 + of_era / 4 - of_era / 100 + of_year;
+    era * 146_097 + of_cycle - 719_468
+}
+
+// Function to get the date of a count of days since 1970-01-01, the inverse of `days`.
+fn civil(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let of_era = days - era * 146_097;
+    let of_cycle = (of_era - of_era / 1460 + of_era / 36_524 - of_era / 146_096) / 365;
+    let of_year = of_era - (365 * of_cycle + of_cycle / 4 - of_cycle / 100);
+    let shifted = (5 * of_year + 2) / 153;
+    let day = of_year - (153 * shifted + 2) / 5 + 1;
+    let month = if shifted < 10 {
+        shifted + 3
+    } else {
+        shifted - 9
+    };
+    let year = of_cycle + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A self-signed certificate made with `openssl req -x509 -newkey ec -pkeyopt
+    // ec_paramgen_curve:P-256 -subj /C=US/O=Example/CN=example.test -not_before 20250101000000Z
+    // -not_after 20250131120000Z -addext subjectAltName=DNS:example.test,DNS:www.example.test,IP:192.0.2.7`.
+    const SELF_SIGNED: &str = "\
+        308201f43082019aa00302010202144758ae20e572bcb6da71bec02043179f40426bbe300a06082a8648ce3d\
+        0403023036310b30090603550406130255533110300e060355040a0c074578616d706c653115301306035504\
+        030c0c6578616d706c652e74657374301e170d3235303130313030303030305a170d32353031333131323030\
+        30305a3036310b30090603550406130255533110300e060355040a0c074578616d706c653115301306035504\
+        030c0c6578616d706c652e746573743059301306072a8648ce3d020106082a8648ce3d03010703420004915d\
+        b0477e8783694037d25b653704192a5918c36cc247e0dd521d2e3163ff7bb53eba3a243365cc58636d33db03\
+        a0c3b1ff2fdfbce12080e0b97565d0f0723da38185308182301d0603551d0e041604147ffd4a014c9e80c4c2\
+        5e2f4d51e15f20ace2288c301f0603551d230418301680147ffd4a014c9e80c4c25e2f4d51e15f20ace2288c\
+        300f0603551d130101ff040530030101ff302f0603551d1104283026820c6578616d706c652e746573748210\
+        7777772e6578616d706c652e746573748704c0000207300a06082a8648ce3d0403020348003045022100c627\
+        ac3213563beccfde62198f10ef09fe92db4ec84ce19e53fb1ae8a2ecc05f02201f0dd8e282de405192dc1a2c\
+        712f4b3d67943bcde925a219f5f589decad79be5";
+
+    // Function to decode a hex string.
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn converts_dates() {
+        assert_eq!(days(1970, 1, 1), 0);
+        assert_eq!(days(2000, 3, 1), 11_017);
+        assert_eq!(civil(11_017), (2000, 3, 1));
+        assert_eq!(civil(days(2024, 2, 29)), (2024, 2, 29));
+        assert_eq!(Time(1_738_324_800).to_string(), "2025-01-31T12:00:00Z");
+    }
+
+    #[test]
+    fn reads_times() {
+        assert_eq!(time((UTC_TIME, b"250131120000Z")), Ok(Time(1_738_324_800)));
+        assert_eq!(
+            time((GENERALIZED_TIME, b"20250131120000Z")),
+            Ok(Time(1_738_324_800))
+        );
+        assert_eq!(
+            time((UTC_TIME, b"991231235959Z")).unwrap().date(),
+            "1999-12-31"
+        );
+        assert!(time((UTC_TIME, b"251331120000Z")).is_err());
+        assert!(time((UTC_TIME, b"2501311200Z")).is_err());
+    }
+
+    #[test]
+    fn names_oids() {
+        assert_eq!(dotted(&[0x55, 0x04, 0x61]), "2.5.4.97");
+        assert_eq!(
+            dotted(&[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d]),
+            "1.2.840.113549"
+        );
+    }
+
+    #[test]
+    fn reads_certificates() {
+        let summary = parse(&hex(SELF_SIGNED)).unwrap();
+        assert_eq!(summary.subject, "CN=example.test, O=Example, C=US");
+        assert_eq!(summary.issuer, summary.subject);
+        assert!(summary.self_signed);
+        assert_eq!(summary.not_before.to_string(), "2025-01-01T00:00:00Z");
+        assert_eq!(summary.not_after, Time(1_738_324_800));
+        assert_eq!(
+            summary.names,
+            ["example.test", "www.example.test", "192.0.2.7"]
+        );
+        assert!(parse(&hex(SELF_SIGNED)[..100]).is_err());
+    }
+
+    #[test]
+    fn writes_pem() {
+        assert_eq!(
+            pem(b"fooba"),
+            "-----BEGIN CERTIFICATE-----\nZm9vYmE=\n-----END CERTIFICATE-----\n"
+        );
+        let lines: Vec<usize> = pem(&[0; 100]).lines().map(str::len).collect();
+        assert_eq!(lines, [27, 64, 64, 8, 25]);
+    }
+}